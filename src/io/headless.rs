@@ -0,0 +1,504 @@
+use std::fs;
+use std::io::{self, Write};
+
+use crate::isa::assembly::assemble_program;
+use crate::isa::operand::Register;
+use crate::isa::Instruction;
+use crate::simulator::replay::format_commit;
+use crate::simulator::state::State;
+use crate::simulator::{print_deadlock, run_headless, step_cycle, StopReason, HEADLESS_CYCLE_LIMIT};
+use crate::util::config::{parse_address, Config};
+use crate::util::loader::{executable_sections, write_elf};
+
+///////////////////////////////////////////////////////////////////////////////
+//// CONST/STATIC
+
+/// The number of bytes shown on each line of a memory dump.
+const DUMP_WIDTH: usize = 16;
+
+///////////////////////////////////////////////////////////////////////////////
+//// FUNCTIONS
+
+/// Entry point for the `disasm` subcommand. Prints every instruction in the
+/// executable sections of the elf file, as its address, the raw word and the
+/// disassembled instruction. If symbols are loaded, the functions and labels
+/// are named above their first instruction.
+pub fn run_disasm(config: &Config) {
+    let state = State::new(config);
+    for (name, start, end) in executable_sections(config) {
+        println!("{}:", name);
+        for addr in (start..end).step_by(4) {
+            if let Some(symbol) = state.symbols.get(&addr) {
+                println!("{:08x} <{}>:", addr, symbol);
+            }
+            let word = state.memory.read_i32(addr).word;
+            match Instruction::decode(word) {
+                Some(i) => println!("{:08x}:  {:08x}  {}", addr, word, i.disassemble()),
+                None => println!("{:08x}:  {:08x}  <illegal>", addr, word),
+            }
+        }
+    }
+}
+
+/// Entry point for the `asm` subcommand. Assembles the source file at the
+/// configured origin and writes it out as an elf file, with its labels as
+/// symbols, so that it can be run like any other program.
+pub fn run_asm(config: &Config) {
+    let source = match fs::read_to_string(&config.asm_file) {
+        Ok(s) => s,
+        Err(e) => error!(format!("Could not read {}: {}", config.asm_file, e)),
+    };
+    let program = match assemble_program(&source, config.origin) {
+        Ok(p) => p,
+        Err(e) => error!(format!("Could not assemble {}: {}", config.asm_file, e)),
+    };
+    if program.text_size == 0 {
+        error!(format!("{} has no instructions to assemble.", config.asm_file));
+    }
+    if let Err(e) = fs::write(&config.elf_file, write_elf(&program)) {
+        error!(format!("Could not write {}: {}", config.elf_file, e));
+    }
+    println!(
+        "Assembled {} bytes of text and {} bytes of data at 0x{:x} into {}.",
+        program.text_size,
+        program.bytes.len() - program.text_size,
+        program.origin,
+        config.elf_file
+    );
+}
+
+/// Entry point for the `trace` subcommand. Runs the simulation without the
+/// display, printing every instruction in the order that they commit, along
+/// with the cycle they committed in (unless asked not to) and the result they
+/// wrote back.
+///
+/// Returns the exit code of the program, if it exited.
+pub fn run_trace(config: &Config) -> Option<i32> {
+    let mut front = 0;
+    let (state, stop) = run_headless(config, |state| {
+        let cycle = state.stats.cycles - 1;
+        for line in committed_this_cycle(state, &mut front) {
+            if config.trace_cycles {
+                println!("{:>8}  {}", cycle, line);
+            } else {
+                println!("{}", line);
+            }
+        }
+    });
+    print_program_output(&state, stop);
+    if let Some(replay) = &state.replay {
+        if replay.diverged {
+            error!("The simulation diverged from the replayed trace.");
+        }
+        if replay.pos < replay.count() {
+            println!("Replayed the first {} of {} instructions of the trace.", replay.pos, replay.count());
+        } else {
+            println!("Replayed all {} instructions of the trace.", replay.count());
+        }
+    }
+    state.exit_code
+}
+
+/// Formats every instruction committed in the cycle that has just finished,
+/// given where the front of the reorder buffer was before it, which is then
+/// moved on.
+fn committed_this_cycle(state: &State, front: &mut usize) -> Vec<String> {
+    // Everything committed this cycle lies from where the front of the
+    // reorder buffer was, as the commit stage takes from the front
+    let rob = &state.reorder_buffer;
+    let cycle = state.stats.cycles - 1;
+    let mut lines = vec![];
+    for i in 0..rob.capacity {
+        if rob[*front + i].cycle_committed != Some(cycle) {
+            break;
+        }
+        lines.push(format_commit(state, *front + i));
+    }
+    *front = rob.front_fin;
+    lines
+}
+
+/// Entry point for the `bench` subcommand. Runs the simulation without the
+/// display, and prints a table of how the simulated processor performed. If
+/// a parameter is being swept, the simulation is run from scratch for each of
+/// its values, with one row in the table each.
+pub fn run_bench(config: &Config) {
+    if let Some(path) = &config.compare {
+        run_compare(config, path, &mut io::stdout()).expect("Failed to print comparison.");
+        return;
+    }
+    let runs = match &config.sweep {
+        Some((param, values)) => values
+            .iter()
+            .map(|value| {
+                let mut run = config.clone();
+                run.set_param(param, value).unwrap();
+                (param.as_str(), value.as_str(), run)
+            })
+            .collect(),
+        None => vec![("", "", config.clone())],
+    };
+
+    let label_width = runs.iter().map(|(p, v, _)| p.len().max(v.len())).max().unwrap_or(0);
+    println!(
+        "{:>w$} {:>10} {:>10} {:>8} {:>10} {:>10}",
+        runs[0].0, "cycles", "executed", "ipc", "stalls", "bp_rate",
+        w = label_width
+    );
+    for (_, value, run) in runs.iter() {
+        let (state, stop) = run_headless(run, |_| ());
+        let stats = &state.stats;
+        let branches = stats.bp_success + stats.bp_failure;
+        println!(
+            "{:>w$} {:>10} {:>10} {:>8.3} {:>10} {:>10}{}",
+            value,
+            stats.cycles,
+            stats.executed,
+            stats.executed as f32 / stats.measured_cycles() as f32,
+            stats.stalls,
+            if branches == 0 {
+                String::from("-")
+            } else {
+                format!("{:.3}", stats.bp_success as f32 / branches as f32)
+            },
+            match stop {
+                StopReason::Finished => String::new(),
+                StopReason::Breakpoint(pc) => format!(" (breakpoint at {:08x})", pc),
+                StopReason::CycleLimit => String::from(" (did not finish)"),
+            },
+            w = label_width
+        );
+    }
+}
+
+/// Runs the simulation without the display with the given config, and again
+/// with the options in the file at the given path instead, then prints every
+/// statistic of both side by side, with the percentage change from the first
+/// to the second.
+fn run_compare<W: Write>(config: &Config, path: &str, out: &mut W) -> io::Result<()> {
+    let other = Config::create_from_file(path, &config.elf_file);
+    let (state_a, stop_a) = run_headless(config, |_| ());
+    let (state_b, stop_b) = run_headless(&other, |_| ());
+
+    writeln!(out, "{:<22} {:>14} {:>14} {:>9}", "", "options", path, "change")?;
+    for ((stat, a), (_, b)) in state_a.stats.summary().into_iter().zip(state_b.stats.summary()) {
+        let change = if a == b {
+            String::from("-")
+        } else if a == 0.0 || a.is_nan() || b.is_nan() {
+            String::from("n/a")
+        } else {
+            format!("{:+.1}%", (b - a) / a * 100.0)
+        };
+        if a.fract() == 0.0 && b.fract() == 0.0 {
+            writeln!(out, "{:<22} {:>14} {:>14} {:>9}", stat, a, b, change)?;
+        } else {
+            writeln!(out, "{:<22} {:>14.3} {:>14.3} {:>9}", stat, a, b, change)?;
+        }
+    }
+    for (label, stop) in [("options", stop_a), (path, stop_b)].iter() {
+        match stop {
+            StopReason::Finished => (),
+            StopReason::Breakpoint(pc) => {
+                writeln!(out, "The run with {} stopped at breakpoint at {:08x}.", label, pc)?
+            }
+            StopReason::CycleLimit => writeln!(out, "The run with {} did not finish.", label)?,
+        }
+    }
+    Ok(())
+}
+
+/// Entry point for the headless command prompt. Runs the simulation without
+/// the display, reading commands from stdin line by line to advance it and
+/// inspect its state:
+///
+/// - `step [N]` advances N cycles, or the `--interactive-step` default.
+/// - `regs` prints the register file.
+/// - `mem ADDR LEN` prints LEN bytes of memory from ADDR.
+/// - `rob` prints the entries in use in the reorder buffer.
+/// - `continue` runs until the program finishes or stops at a breakpoint.
+/// - `quit` (or `exit`) stops the simulation.
+///
+/// Returns the exit code of the program, if it exited.
+pub fn headless_interactive(config: &Config) -> Option<i32> {
+    let default_step = config.interactive_step.unwrap_or(1);
+    let mut state = State::new(config);
+    prompt();
+
+    // Lines are read one at a time without keeping stdin locked, as the
+    // program reads from it too, through the read system call
+    let stdin = io::stdin();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        match stdin.read_line(&mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => (),
+        }
+        match run_command(&mut state, &line, default_step, &mut io::stdout()) {
+            Ok(false) => prompt(),
+            Ok(true) | Err(_) => break,
+        }
+    }
+
+    print_program_output(&state, StopReason::Finished);
+    if state.deadlocked {
+        print_deadlock(&state);
+    }
+    state.exit_code
+}
+
+/// Runs a single command typed at the interactive prompt, writing what it
+/// prints to the given output. Stepping moves by the given number of cycles
+/// when not told otherwise.
+///
+/// Returns whether the session is over, as the program finished or it was
+/// quit.
+fn run_command<W: Write>(
+    state: &mut State,
+    line: &str,
+    default_step: u64,
+    out: &mut W,
+) -> io::Result<bool> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.as_slice() {
+        [] => (),
+        ["quit"] | ["exit"] => return Ok(true),
+        ["step"] => return step_cycles(state, default_step, out),
+        ["step", n] => match n.parse::<u64>() {
+            Ok(n) => return step_cycles(state, n, out),
+            Err(_) => writeln!(out, "error: `{}` is not a valid number of cycles.", n)?,
+        },
+        ["continue"] => return step_cycles(state, HEADLESS_CYCLE_LIMIT, out),
+        ["regs"] => write_registers(state, out)?,
+        ["mem", addr, len] => match (parse_address(addr), parse_address(len)) {
+            (Some(addr), Some(len)) => write_memory(state, addr, len, out)?,
+            _ => writeln!(out, "error: usage is `mem ADDR LEN`.")?,
+        },
+        ["rob"] => write_reorder_buffer(state, out)?,
+        _ => writeln!(out, "error: unknown command `{}`.", line.trim())?,
+    }
+    Ok(false)
+}
+
+/// Advances the simulation by up to the given number of cycles, stopping
+/// early if the program finishes or stops at a breakpoint, and then reports
+/// where it got to.
+///
+/// Returns whether or not the program finished.
+fn step_cycles<W: Write>(state: &mut State, cycles: u64, out: &mut W) -> io::Result<bool> {
+    let mut finished = false;
+    for _ in 0..cycles {
+        let logged = state.log.total;
+        finished = step_cycle(state);
+        // There is no display for the log, so it goes to stderr instead
+        for (cycle, level, message) in state.log.since(logged) {
+            eprintln!("[{:>6} {:<5}] {}", cycle, level, message);
+        }
+        if finished {
+            break;
+        }
+        if let Some(pc) = state.breakpoint.take() {
+            writeln!(out, "Stopped at breakpoint at {:08x}.", pc)?;
+            break;
+        }
+    }
+    writeln!(
+        out,
+        "Cycle {}, pc {:08x}{}",
+        state.stats.cycles,
+        state.register[Register::PC].data,
+        if finished { ", finished." } else { "." }
+    )?;
+    Ok(finished)
+}
+
+/// Writes every register in the register file, with its value in hex and
+/// decimal, and the reorder buffer entry it is renamed to, if any.
+fn write_registers<W: Write>(state: &State, out: &mut W) -> io::Result<()> {
+    for (name, are) in state.register.file.iter().enumerate() {
+        writeln!(
+            out,
+            "{n:>#04}-{n:<03} ({rn}) :: {v:08x} - {v}",
+            n = Register::from(name as i32),
+            v = are.data,
+            rn = match are.rename {
+                Some(entry) => format!("{:02}", entry),
+                None => String::from("  "),
+            },
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes a hexdump of the given range of memory, with the address of the
+/// first byte at the start of each line. Memory out of range reads as 0.
+fn write_memory<W: Write>(state: &State, addr: usize, len: usize, out: &mut W) -> io::Result<()> {
+    let last = addr.saturating_add(len);
+    for start in (addr..last).step_by(DUMP_WIDTH) {
+        let end = start.saturating_add(DUMP_WIDTH).min(last);
+        let bytes: Vec<String> = (start..end)
+            .map(|a| format!("{:02x}", state.memory.read_u8(a).word))
+            .collect();
+        writeln!(out, "{:08x}: {}", start, bytes.join(" "))?;
+    }
+    Ok(())
+}
+
+/// Writes every entry in use in the reorder buffer, from the oldest, with
+/// its index in the buffer.
+fn write_reorder_buffer<W: Write>(state: &State, out: &mut W) -> io::Result<()> {
+    let rob = &state.reorder_buffer;
+    for i in 0..rob.count {
+        let n = (rob.front + i) % rob.capacity;
+        writeln!(out, "{:>3}: {}", n, rob[n])?;
+    }
+    Ok(())
+}
+
+/// Prints the prompt, flushing stdout as there is no trailing newline.
+fn prompt() {
+    print!("> ");
+    #[allow(unused_must_use)]
+    {
+        io::stdout().flush();
+    }
+}
+
+/// Prints what the simulated program has output and its exit code, or why it
+/// stopped if it did not finish.
+fn print_program_output(state: &State, stop: StopReason) {
+    for line in program_output(state, stop) {
+        println!("{}", line);
+    }
+}
+
+/// Returns the lines printed by `print_program_output`.
+fn program_output(state: &State, stop: StopReason) -> Vec<String> {
+    let mut lines = vec![String::from("Program output:")];
+    lines.extend(state.out.iter().cloned());
+    if let Some(code) = state.exit_code {
+        lines.push(format!("Program exited with code {}.", code));
+    }
+    match stop {
+        StopReason::Finished => (),
+        StopReason::Breakpoint(pc) => lines.push(format!("Stopped at breakpoint at {:08x}.", pc)),
+        StopReason::CycleLimit => lines.push(format!(
+            "Program did not finish within {} cycles.",
+            HEADLESS_CYCLE_LIMIT
+        )),
+    }
+    lines
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// TESTS
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::builder::StateBuilder;
+    use std::fs;
+
+    /// The machines that every program is traced on, as for `make check`.
+    const GOLDEN_CONFIGS: [&[&str]; 2] = [
+        &[],
+        &[
+            "--alu=4",
+            "--blu=1",
+            "--mcu=4",
+            "--rsv=32",
+            "--rob=64",
+            "-n=4",
+            "-i=6",
+            "-b=twolevel",
+            "-r",
+        ],
+    ];
+
+    #[test]
+    fn matches_golden_traces() {
+        for file in fs::read_dir("resources/golden").unwrap() {
+            let path = file.unwrap().path();
+            let program = path.file_stem().unwrap().to_str().unwrap().to_string();
+            let golden = fs::read_to_string(&path).unwrap();
+            let elf_file = format!("resources/programs/{}/a.out", program);
+            for options in GOLDEN_CONFIGS.iter() {
+                let mut args = vec!["daybreak", "trace"];
+                args.extend(options.iter());
+                args.push(&elf_file);
+                let config = Config::create_from(args);
+
+                let mut front = 0;
+                let mut lines = vec![];
+                let (state, stop) = run_headless(&config, |state| {
+                    lines.extend(committed_this_cycle(state, &mut front));
+                });
+                assert_eq!(stop, StopReason::Finished);
+                lines.extend(program_output(&state, stop));
+                let expected: Vec<&str> = golden.lines().collect();
+                assert_eq!(lines, expected, "{} {:?}", program, options);
+            }
+        }
+    }
+
+    /// Runs each of the given commands at the interactive prompt, returning
+    /// everything that they printed.
+    fn run_commands(state: &mut State, commands: &[&str]) -> String {
+        let mut out = vec![];
+        for command in commands.iter() {
+            run_command(state, command, 1, &mut out).unwrap();
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn steps_and_prints_registers() {
+        let mut state = StateBuilder::new()
+            .asm("addi x10 x0 42")
+            .asm("jalr x0 x1 0")
+            .build();
+        let out = run_commands(&mut state, &["step 1", "regs"]);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[0], "Cycle 1, pc 00000000.");
+        assert_eq!(lines.len(), 1 + state.register.file.len());
+        assert_eq!(lines[1 + 2], "  sp-x2  (  ) :: 000f4230 - 999984");
+
+        // Once finished, the result is in the registers printed
+        let out = run_commands(&mut state, &["bogus", "continue", "regs"]);
+        assert!(out.starts_with("error: unknown command `bogus`.\n"));
+        assert!(out.contains(", finished.\n"));
+        assert!(out.contains("  a0-x10 (  ) :: 0000002a - 42\n"));
+        assert!(run_command(&mut state, "quit", 1, &mut vec![]).unwrap());
+    }
+
+    #[test]
+    fn compares_wider_machine() {
+        let path = std::env::temp_dir().join("daybreak_compare_wide");
+        fs::write(&path, "# Twice as wide\n-n 2\n").unwrap();
+        let path = path.to_str().unwrap();
+        let config = Config::create_from(vec![
+            "daybreak",
+            "bench",
+            "--compare",
+            path,
+            "resources/programs/fib_non_recursive/a.out",
+        ]);
+        assert_eq!(config.n_way, 1);
+        assert_eq!(Config::create_from_file(path, &config.elf_file).n_way, 2);
+
+        let mut out = vec![];
+        run_compare(&config, path, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let ipc: Vec<f64> = out
+            .lines()
+            .find(|l| l.starts_with("ipc "))
+            .unwrap()
+            .split_whitespace()
+            .skip(1)
+            .take(2)
+            .map(|v| v.parse().unwrap())
+            .collect();
+        assert!(ipc[1] >= ipc[0], "{}", out);
+        assert!(!out.contains("did not finish"));
+    }
+}
@@ -0,0 +1,174 @@
+use std::collections::VecDeque;
+use std::mem;
+
+use crate::simulator::memory::{Memory, MemoryDiff};
+use crate::simulator::state::State;
+
+///////////////////////////////////////////////////////////////////////////////
+//// STRUCTS
+
+/// The past states of the simulation, for rewinding the display. Only the
+/// newest state is kept whole. Every older state is kept as the difference
+/// back to it from the state after it, which avoids keeping a copy of the
+/// whole of memory for every cycle.
+pub struct History {
+    /// The number of states to keep, including the newest.
+    capacity: usize,
+    /// The newest state, if any have been added.
+    newest: Option<State>,
+    /// The differences back from each state to the one before it, from the
+    /// newest.
+    diffs: VecDeque<StateDiff>,
+}
+
+/// The difference back from a state to the state of the cycle before it.
+struct StateDiff {
+    /// The older state, apart from its memory, which is left empty. The rest
+    /// of the state is small next to memory, so is kept whole.
+    state: State,
+    /// The bytes of the older state's memory that differ from the newer.
+    memory: MemoryDiff,
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// IMPLEMENTATIONS
+
+impl History {
+    /// Creates an empty history, that keeps up to the given number of states.
+    pub fn new(capacity: usize) -> History {
+        History {
+            capacity,
+            newest: None,
+            diffs: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Returns the number of states in the history.
+    pub fn len(&self) -> usize {
+        match self.newest {
+            Some(_) => self.diffs.len() + 1,
+            None => 0,
+        }
+    }
+
+    /// Adds a state to the front of the history, as the newest, dropping the
+    /// oldest if the history is full.
+    pub fn push(&mut self, state: State) {
+        if let Some(mut older) = self.newest.take() {
+            let memory = mem::replace(&mut older.memory, Memory::create_empty(0));
+            self.diffs.push_front(StateDiff {
+                memory: memory.diff_from(&state.memory),
+                state: older,
+            });
+        }
+        self.newest = Some(state);
+        self.diffs.truncate(self.capacity.saturating_sub(1));
+    }
+
+    /// Returns the newest state, if any.
+    pub fn newest(&self) -> Option<&State> {
+        self.newest.as_ref()
+    }
+
+    /// Rebuilds the state that is `n` states older than the newest, where 0
+    /// is the newest, by restoring the newest state's memory back through
+    /// each difference in turn.
+    pub fn get(&self, n: usize) -> Option<State> {
+        let newest = self.newest.as_ref()?;
+        if n == 0 {
+            return Some(newest.clone());
+        }
+        let mut state = self.diffs.get(n - 1)?.state.clone();
+        let mut memory = newest.memory.clone();
+        for diff in self.diffs.iter().take(n) {
+            memory.undo(&diff.memory);
+        }
+        state.memory = memory;
+        Some(state)
+    }
+
+    /// Iterates over the states from the newest, where every state but the
+    /// newest has empty memory. This is much cheaper than rebuilding each
+    /// one, for looking at anything else in them.
+    pub fn iter_without_memory(&self) -> impl Iterator<Item = &State> {
+        self.newest.iter().chain(self.diffs.iter().map(|d| &d.state))
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// TESTS
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isa::operand::Register;
+    use crate::simulator::builder::StateBuilder;
+    use crate::simulator::memory::INIT_MEMORY_SIZE;
+    use crate::simulator::step_cycle;
+
+    /// Asserts that a state rebuilt from the history is the same as a whole
+    /// copy of the state it was built from.
+    fn assert_same(rebuilt: &State, whole: &State) {
+        assert_eq!(rebuilt.stats.cycles, whole.stats.cycles);
+        assert_eq!(*rebuilt.memory, *whole.memory);
+        assert_eq!(rebuilt.memory.high_water(), whole.memory.high_water());
+        let registers = |s: &State| s.register.file.iter().map(|e| e.data).collect::<Vec<_>>();
+        assert_eq!(registers(rebuilt), registers(whole));
+        assert_eq!(rebuilt.reorder_buffer.count, whole.reorder_buffer.count);
+        assert_eq!(rebuilt.reorder_buffer.front, whole.reorder_buffer.front);
+    }
+
+    #[test]
+    fn rebuilds_every_state_in_history() {
+        // Each store extends memory further, and the program is reset part
+        // way through, shrinking memory back down
+        let mut state = StateBuilder::new()
+            .reg(Register::X5, 7)
+            .asm("lui x6 0x100")
+            .asm("sw x0 x5 0x100")
+            .asm("sw x6 x5 0")
+            .asm("addi x5 x5 1")
+            .asm("sw x6 x5 0x400")
+            .asm("sw x0 x5 0x100")
+            .asm("jalr x0 x1 0")
+            .build();
+        let mut history = History::new(64);
+        let mut whole = vec![];
+        for cycle in 0..40 {
+            if cycle == 25 {
+                state.reset();
+            } else {
+                step_cycle(&mut state);
+            }
+            history.push(state.clone());
+            whole.push(state.clone());
+        }
+        assert_eq!(whole[24].memory.len(), 0x10_0404);
+        assert_eq!(whole[25].memory.len(), INIT_MEMORY_SIZE);
+        assert_eq!(whole[24].memory.read_i32(0x100).word, 8);
+
+        assert_eq!(history.len(), 40);
+        for (n, state) in whole.iter().rev().enumerate() {
+            assert_same(&history.get(n).unwrap(), state);
+        }
+        assert!(history.get(40).is_none());
+    }
+
+    #[test]
+    fn drops_oldest_states_when_full() {
+        let mut history = History::new(3);
+        let mut state = StateBuilder::new().asm("jalr x0 x1 0").build();
+        for _ in 0..5 {
+            state.stats.cycles += 1;
+            history.push(state.clone());
+        }
+        assert_eq!(history.len(), 3);
+        assert_eq!(history.get(2).unwrap().stats.cycles, 3);
+        assert!(history.get(3).is_none());
+        let cycles: Vec<u64> = history
+            .iter_without_memory()
+            .map(|s| s.stats.cycles)
+            .collect();
+        assert_eq!(cycles, vec![5, 4, 3]);
+    }
+}
@@ -1,5 +1,4 @@
 use std::cmp;
-use std::collections::VecDeque;
 use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
 use std::thread::{spawn, JoinHandle};
 
@@ -7,26 +6,38 @@ use termion::event::Key;
 use tui::layout::Rect;
 
 use crate::simulator::state::State;
-use crate::simulator::INITIALLY_PAUSED;
 
+use self::history::History;
 use self::input::spawn_input_thread;
 use self::output::{draw_state, new_terminal};
+use self::theme::{Theme, ThemeMode};
 
 ///////////////////////////////////////////////////////////////////////////////
 //// EXTERNAL MODULES
 
+/// Entry points for running without the display, printing to stdout.
+pub mod headless;
+
+/// The past states of the simulation, kept for rewinding the display.
+pub mod history;
+
 /// User Input event handler logic.
 pub mod input;
 
 /// Output display logic.
 pub mod output;
 
+/// The interactive assembly prompt, for executing single instructions.
+pub mod repl;
+
+/// The colour schemes of the display.
+pub mod theme;
+
 ///////////////////////////////////////////////////////////////////////////////
 //// CONST/STATIC
 
-/// The number of states to keep in memory.
-/// Each state uses approximately O(sim_mem_size) RAM, which is typically 1mb.
-pub const KEPT_STATES: usize = 250;
+/// The file that the `m` key dumps memory to, unless `--mem-dump` is given.
+const DEFAULT_MEM_DUMP: &str = "memory.bin";
 
 ///////////////////////////////////////////////////////////////////////////////
 //// ENUMS
@@ -35,6 +46,9 @@ pub const KEPT_STATES: usize = 250;
 pub enum IoEvent {
     /// Signal that the user has asked to exit the process.
     Exit,
+    /// Signal that the simulation has paused at a breakpoint, at the given
+    /// program counter.
+    Breakpoint(usize),
     /// Signal that the simulation has finished.
     Finish,
     /// Signal that a keypress has occured (from the input thread).
@@ -76,8 +90,12 @@ pub struct TuiApp {
     pub rx: Receiver<IoEvent>,
     /// Terminal size
     pub size: Rect,
-    /// History of the last `KEPT_STATES` states
-    pub states: VecDeque<State>,
+    /// History of the last `history` states. Only the newest is kept whole,
+    /// the rest only keep the memory that changed in each cycle.
+    pub states: History,
+    /// The state being shown when it is not the newest, rebuilt from the
+    /// history.
+    pub shown: Option<State>,
     /// Whether or not the simulator has finished
     pub finished: bool,
     /// Whether or not the simulator is paused
@@ -85,6 +103,20 @@ pub struct TuiApp {
     /// Which historical state we are showing.
     /// 0 is current, 1 is the state before, 2 is the state before 1, etc
     pub hist_display: usize,
+    /// The cycle number being typed into the goto-cycle prompt, if open.
+    pub goto: Option<String>,
+    /// A message to flash at the user, until the next key press.
+    pub flash: Option<String>,
+    /// The file to dump memory to when asked to.
+    pub mem_dump: String,
+    /// Whether the instruction memory is centred on the committed program
+    /// counter, rather than the load counter.
+    pub follow_pc: bool,
+    /// Whether the reorder buffer marks which entries are waiting on the
+    /// results of which others.
+    pub show_deps: bool,
+    /// The styles that the display is drawn in.
+    pub theme: Theme,
 }
 
 ///////////////////////////////////////////////////////////////////////////////
@@ -92,8 +124,17 @@ pub struct TuiApp {
 
 impl IoThread {
     /// Creates a new IoThread object, and spawns the input/out threads
-    /// to run in the background.
-    pub fn new() -> IoThread {
+    /// to run in the background. The display thread will keep the given
+    /// number of states in its history, dump memory to the given file (if
+    /// any) when asked to, and draw in the given colour scheme. It starts
+    /// paused if the simulator does.
+    pub fn new(
+        history: usize,
+        mem_dump: Option<String>,
+        follow_pc: bool,
+        paused: bool,
+        theme: ThemeMode,
+    ) -> IoThread {
         let (tx_m, rx_m) = channel(); // Channel from io to MAIN
         let (tx_i, rx_i) = channel(); // Channel from main to IO
         let input_tx = tx_i.clone();
@@ -101,7 +142,9 @@ impl IoThread {
         IoThread {
             tx: tx_i,
             rx: rx_m,
-            handle: spawn(move || display_thread(tx_m, rx_i)),
+            handle: spawn(move || {
+                display_thread(tx_m, rx_i, history, mem_dump, follow_pc, paused, theme)
+            }),
         }
     }
 }
@@ -127,17 +170,39 @@ impl TuiApp {
         }
     }
 
+    /// Returns the state being shown, if there is one yet.
+    pub fn shown_state(&self) -> Option<&State> {
+        match self.hist_display {
+            0 => self.states.newest(),
+            _ => self.shown.as_ref(),
+        }
+    }
+
     /// Adds a simulator state to the history in the TuiApp state.
     fn add_state(&mut self, state: State) {
-        self.states.push_front(state);
-        if self.states.len() > KEPT_STATES {
-            self.states.pop_back();
+        self.states.push(state);
+        if self.hist_display > 0 {
+            self.show(self.hist_display);
         }
     }
 
+    /// Shows the state in the history the given number of states before the
+    /// newest, rebuilding it if it is not the newest.
+    fn show(&mut self, hist_display: usize) {
+        self.hist_display = hist_display;
+        self.shown = match hist_display {
+            0 => None,
+            n => self.states.get(n),
+        };
+    }
+
     /// Process an IoEvent.
     fn process_event(&mut self, event: IoEvent) -> bool {
         match event {
+            IoEvent::Breakpoint(pc) => {
+                self.paused = true;
+                self.flash = Some(format!("Paused at breakpoint at {:08x}", pc));
+            }
             IoEvent::Exit => return false,
             IoEvent::Finish => self.finished = true,
             IoEvent::Input(k) => self.process_key(k),
@@ -148,34 +213,129 @@ impl TuiApp {
 
     /// Process a key input.
     fn process_key(&mut self, key: Key) {
+        self.flash = None;
+        if self.goto.is_some() {
+            self.process_goto_key(key);
+            return;
+        }
         match key {
             Key::Char(' ') => self.toggle_pause(),
+            Key::Char('d') => self.toggle_show_deps(),
+            Key::Char('f') => self.toggle_follow_pc(),
+            Key::Char('g') => self.goto = Some(String::new()),
+            Key::Char('m') => self.dump_memory(),
             Key::Left => self.state_backward(),
             Key::Right => self.state_forward(),
             _ => (),
         }
     }
 
+    /// Process a key input whilst the goto-cycle prompt is open. Digits are
+    /// typed into the prompt, `Enter` jumps to the typed cycle, and either `g`
+    /// or deleting past the start of the prompt closes it.
+    fn process_goto_key(&mut self, key: Key) {
+        let mut input = self.goto.take().unwrap_or_default();
+        match key {
+            Key::Char('\n') => {
+                self.goto_cycle(&input);
+                return;
+            }
+            Key::Char('g') => return,
+            Key::Char(c) if c.is_ascii_digit() => input.push(c),
+            Key::Backspace if input.is_empty() => return,
+            Key::Backspace => {
+                input.pop();
+            }
+            _ => (),
+        }
+        self.goto = Some(input);
+    }
+
+    /// Jumps to the state in the history with the given absolute cycle
+    /// number, flashing an error if it is not within the kept history.
+    fn goto_cycle(&mut self, input: &str) {
+        let cycle = match input.parse::<u64>() {
+            Ok(c) => c,
+            Err(_) => {
+                self.flash = Some(String::from("No cycle given"));
+                return;
+            }
+        };
+        let index = self.states.iter_without_memory().position(|s| s.stats.cycles == cycle);
+        match index {
+            Some(index) => {
+                if index != 0 && self.hist_display == 0 && (!self.paused || self.finished) {
+                    self.toggle_pause();
+                }
+                self.show(index);
+            }
+            None => {
+                let newest = self.states.newest().map_or(0, |s| s.stats.cycles);
+                let oldest = self.states.iter_without_memory().last().map_or(0, |s| s.stats.cycles);
+                self.flash = Some(format!(
+                    "Cycle {} is not in the history ({} to {})",
+                    cycle, oldest, newest
+                ));
+            }
+        }
+    }
+
+    /// Dumps the memory of the state being shown to the memory dump file,
+    /// flashing the outcome at the user.
+    fn dump_memory(&mut self) {
+        let state = match self.shown_state() {
+            Some(s) => s,
+            None => return,
+        };
+        self.flash = Some(match state.memory.dump(&self.mem_dump) {
+            Ok(()) => format!(
+                "Dumped memory at cycle {} to {}",
+                state.stats.cycles, self.mem_dump
+            ),
+            Err(e) => format!("Failed to dump memory: {}", e),
+        });
+    }
+
     /// Rewinds the state to the last one in the history.
     fn state_backward(&mut self) {
         if self.hist_display == 0 && (!self.paused || self.finished) {
             self.toggle_pause();
         }
-        self.hist_display = cmp::min(
-            cmp::min(self.hist_display + 1, KEPT_STATES - 1),
-            self.states.len() - 1,
-        );
+        let hist_display = cmp::min(self.hist_display + 1, self.states.len() - 1);
+        self.show(hist_display);
     }
 
     /// Rewinds the state to the last one in the history.
     fn state_forward(&mut self) {
         if self.hist_display > 0 {
-            self.hist_display -= 1;
+            self.show(self.hist_display - 1);
         } else if !self.finished {
             self.tx.send(SimulatorEvent::Cycle).unwrap();
         }
     }
 
+    /// Toggles whether the instruction memory is centred on the committed
+    /// program counter or the load counter, flashing which at the user.
+    fn toggle_follow_pc(&mut self) {
+        self.follow_pc = !self.follow_pc;
+        self.flash = Some(String::from(if self.follow_pc {
+            "Following the committed program counter"
+        } else {
+            "Following the load counter"
+        }));
+    }
+
+    /// Toggles whether the reorder buffer marks the dependencies between its
+    /// entries, flashing which at the user.
+    fn toggle_show_deps(&mut self) {
+        self.show_deps = !self.show_deps;
+        self.flash = Some(String::from(if self.show_deps {
+            "Showing reorder buffer dependencies"
+        } else {
+            "Hiding reorder buffer dependencies"
+        }));
+    }
+
     /// Toggles whether or not the simulation is paused, instructing the
     /// simulator on what to do.
     fn toggle_pause(&mut self) {
@@ -191,17 +351,32 @@ impl TuiApp {
 
 /// Main entry point for the display thread that handles display updates and
 /// user input.
-fn display_thread(tx: Sender<SimulatorEvent>, rx: Receiver<IoEvent>) {
+fn display_thread(
+    tx: Sender<SimulatorEvent>,
+    rx: Receiver<IoEvent>,
+    history: usize,
+    mem_dump: Option<String>,
+    follow_pc: bool,
+    paused: bool,
+    theme: ThemeMode,
+) {
     // Initalise
     let mut terminal = new_terminal().expect("Could not start fancy UI.");
     let mut app = TuiApp {
         tx,
         rx,
         size: Rect::default(),
-        states: VecDeque::new(),
+        states: History::new(history),
+        shown: None,
         finished: false,
-        paused: INITIALLY_PAUSED,
+        paused,
         hist_display: 0,
+        goto: None,
+        flash: None,
+        mem_dump: mem_dump.unwrap_or_else(|| String::from(DEFAULT_MEM_DUMP)),
+        follow_pc,
+        show_deps: false,
+        theme: Theme::new(theme),
     };
 
     terminal.hide_cursor().unwrap();
@@ -239,3 +414,67 @@ fn display_thread(tx: Sender<SimulatorEvent>, rx: Receiver<IoEvent>) {
     // not reset. Explicit call to drop just in case.
     std::mem::drop(terminal)
 }
+
+///////////////////////////////////////////////////////////////////////////////
+//// TESTS
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a paused display with the given history depth, along with the
+    /// receiver of the messages it sends to the simulator, and the sender of
+    /// those it receives.
+    fn new_app(history: usize) -> (TuiApp, Receiver<SimulatorEvent>, Sender<IoEvent>) {
+        let (tx_m, rx_m) = channel();
+        let (tx_i, rx_i) = channel();
+        let app = TuiApp {
+            tx: tx_m,
+            rx: rx_i,
+            size: Rect::default(),
+            states: History::new(history),
+            shown: None,
+            finished: false,
+            paused: true,
+            hist_display: 0,
+            goto: None,
+            flash: None,
+            mem_dump: String::from(DEFAULT_MEM_DUMP),
+            follow_pc: false,
+            show_deps: false,
+            theme: Theme::new(ThemeMode::default()),
+        };
+        (app, rx_m, tx_i)
+    }
+
+    #[test]
+    fn rewinds_no_further_than_history() {
+        let (mut app, _rx, _tx) = new_app(3);
+        for cycle in 0..10 {
+            let mut state = State::default();
+            state.stats.cycles = cycle;
+            app.add_state(state);
+        }
+        for _ in 0..5 {
+            app.process_key(Key::Left);
+        }
+        assert_eq!(app.hist_display, 2);
+        assert_eq!(app.shown_state().unwrap().stats.cycles, 7);
+        app.process_key(Key::Right);
+        assert_eq!(app.shown_state().unwrap().stats.cycles, 8);
+    }
+
+    #[test]
+    fn toggles_following_committed_pc() {
+        let (mut app, _rx, _tx) = new_app(3);
+        app.process_key(Key::Char('f'));
+        assert!(app.follow_pc);
+        assert_eq!(
+            app.flash.as_ref().unwrap(),
+            "Following the committed program counter"
+        );
+        app.process_key(Key::Char('f'));
+        assert!(!app.follow_pc);
+        assert_eq!(app.flash.as_ref().unwrap(), "Following the load counter");
+    }
+}
@@ -1,3 +1,5 @@
+use std::cmp::min;
+use std::collections::HashSet;
 use std::io::{stdout, Error, Stdout};
 
 use byteorder::{LittleEndian, ReadBytesExt};
@@ -5,17 +7,18 @@ use either::{Left, Right};
 use termion::raw::{IntoRawMode, RawTerminal};
 use tui::backend::TermionBackend;
 use tui::layout::{Constraint, Direction, Layout, Rect};
-use tui::style::{Color, Modifier, Style};
+use tui::style::Style;
 use tui::widgets::{Block, Borders, List, Paragraph, Text, Widget};
 use tui::{Frame, Terminal as TuiTerminal};
 
-use crate::isa::Instruction;
+use crate::isa::decode_all;
 use crate::isa::operand::Register;
-use crate::simulator::branch::ReturnStackOp;
-use crate::simulator::execute::UnitType;
+use crate::simulator::reorder::ReorderBuffer;
 use crate::simulator::state::State;
+use crate::util::log::LogLevel;
 
 use super::TuiApp;
+use super::theme::Theme;
 
 ///////////////////////////////////////////////////////////////////////////////
 //// TYPES
@@ -26,6 +29,17 @@ pub type Backend = TermionBackend<RawTerminal<Stdout>>;
 /// Type alias for abbreviating the Terminal type
 pub type Terminal = TuiTerminal<Backend>;
 
+///////////////////////////////////////////////////////////////////////////////
+//// CONST/STATIC
+
+/// The narrowest terminal that the full layout is drawn in, as the stats
+/// column is a fifth of the width.
+const MIN_WIDTH: u16 = 100;
+
+/// The shortest terminal that the full layout is drawn in, as the stats and
+/// register file are stacked with fixed heights.
+const MIN_HEIGHT: u16 = 60;
+
 ///////////////////////////////////////////////////////////////////////////////
 //// FUNCTIONS
 
@@ -37,9 +51,15 @@ pub fn new_terminal() -> Result<Terminal, Error> {
     Ok(terminal)
 }
 
-/// Entry point for the drawing of the current stored simulate state.
+/// Entry point for the drawing of the current stored simulate state. If the
+/// terminal is too small for the layout, only a message saying so is drawn.
 pub fn draw_state(terminal: &mut Terminal, app: &TuiApp) -> std::io::Result<()> {
     terminal.draw(|mut f| {
+        if let Some(message) = too_small_message(app.size) {
+            draw_too_small(&mut f, app.size, message);
+            return;
+        }
+
         let default = State::default();
         let horz_chunks = Layout::default()
             .direction(Direction::Horizontal)
@@ -58,7 +78,7 @@ pub fn draw_state(terminal: &mut Terminal, app: &TuiApp) -> std::io::Result<()>
             .direction(Direction::Vertical)
             .constraints(
                 [
-                    Constraint::Length(16),
+                    Constraint::Length(27),
                     Constraint::Min(33),
                 ]
                 .as_ref()
@@ -101,8 +121,30 @@ pub fn draw_state(terminal: &mut Terminal, app: &TuiApp) -> std::io::Result<()>
             .split(centre_horz_split[0]);
         draw_latch_fetch(&mut f, fet_rsv_split[0], &app, &default);
         draw_reservation_station(&mut f, fet_rsv_split[1], &app, &default);
-        draw_reorder_buffer(&mut f, centre_horz_split[1], &app, &default);
-        draw_output(&mut f, centre_col[1], &app, &default);
+        let rob_timeline_split = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints(
+                [
+                    Constraint::Percentage(70),
+                    Constraint::Percentage(30),
+                ]
+                .as_ref()
+            )
+            .split(centre_horz_split[1]);
+        draw_reorder_buffer(&mut f, rob_timeline_split[0], &app, &default);
+        draw_timeline(&mut f, rob_timeline_split[1], &app, &default);
+        let out_log_split = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints(
+                [
+                    Constraint::Percentage(50),
+                    Constraint::Percentage(50),
+                ]
+                .as_ref()
+            )
+            .split(centre_col[1]);
+        draw_output(&mut f, out_log_split[0], &app, &default);
+        draw_log(&mut f, out_log_split[1], &app, &default);
 
         ////////////////////////////////////////////////////////// RIGHT COLUMN
         let right_col = Layout::default()
@@ -121,49 +163,127 @@ pub fn draw_state(terminal: &mut Terminal, app: &TuiApp) -> std::io::Result<()>
 }
 
 
+/// Returns the message shown in place of the layout when a terminal of the
+/// given size is too small for it, if it is.
+fn too_small_message(size: Rect) -> Option<String> {
+    if size.width >= MIN_WIDTH && size.height >= MIN_HEIGHT {
+        return None;
+    }
+    Some(format!(
+        "Terminal too small ({}x{}), need {}x{}.",
+        size.width, size.height, MIN_WIDTH, MIN_HEIGHT
+    ))
+}
+
+/// Draws the given message in place of the layout, wrapping as much of it as
+/// fits.
+fn draw_too_small(f: &mut Frame<Backend>, area: Rect, message: String) {
+    let message = [Text::raw(message)];
+    Paragraph::new(message.iter())
+        .wrap(true)
+        .render(f, area);
+}
+
 /// Draws the TuiApp state statistics on screen.
 fn draw_stats(f: &mut Frame<Backend>, area: Rect, app: &TuiApp, default: &State) {
-    let state = app.states.get(app.hist_display).unwrap_or(default);
+    let state = app.shown_state().unwrap_or(default);
     let tmp: Vec<Text> = vec![
         Text::raw(format!("executed: {}\n", state.stats.executed)),
         Text::raw(format!("cycles:   {}\n", state.stats.cycles)),
-        Text::raw(format!("ex/cycle: {:.3}\n", state.stats.executed as f32 / state.stats.cycles as f32)),
+        Text::raw(format!("ex/cycle: {:.3}\n", state.stats.executed as f32 / state.stats.measured_cycles() as f32)),
         Text::raw(format!("stalls:   {}\n", state.stats.stalls)),
-        Text::raw(format!("st/cycle: {:.4}\n", state.stats.stalls as f32 / state.stats.cycles as f32)),
+        Text::raw(format!("st/cycle: {:.4}\n", state.stats.stalls as f32 / state.stats.measured_cycles() as f32)),
+        Text::raw(format!("st_rsv:   {}\n", state.stats.stalls_rsv)),
+        Text::raw(format!("st_rob:   {}\n", state.stats.stalls_rob)),
+        Text::raw(format!("st_prf:   {}\n", state.stats.stalls_prf)),
+        Text::raw(format!("st_spec:  {}\n", state.stats.stalls_spec)),
+        Text::raw(format!("st_port:  {}\n", state.stats.mem_port_stalls)),
+        Text::raw(format!("st_bus:   {}\n", state.stats.bus_stalls)),
+        Text::raw(format!("ft_redir: {}\n", state.stats.fetch_redirects)),
+        Text::raw(format!("ft_trunc: {}\n", state.stats.fetch_truncations)),
         Text::raw(format!("bp_succ:  {}\n", state.stats.bp_success)),
         Text::raw(format!("bp_fail:  {}\n", state.stats.bp_failure)),
         Text::raw(format!("bp_rate:  {:.3}\n", state.stats.bp_success as f32 / (state.stats.bp_success + state.stats.bp_failure) as f32)),
+        Text::raw(format!("bp_orfb:  {}\n", state.stats.oracle_fallbacks)),
+        Text::raw(format!("ras_ovf:  {}\n", state.stats.ras_overflows)),
+        Text::raw(format!("ras_unf:  {}\n", state.stats.ras_underflows)),
+        Text::raw(format!("ld_viol:  {}\n", state.stats.load_violations)),
+        Text::raw(format!("ld_use:   {}\n", state.stats.load_uses)),
+        Text::raw(format!("ld_adj:   {}\n", state.stats.load_uses_adjacent)),
+        Text::raw(format!("ld_dist:  {:.2}\n", state.stats.load_use_cycles as f32 / state.stats.load_uses as f32)),
+        Text::raw(format!("faults:   {}\n", state.stats.faults_injected)),
+        Text::raw(format!("bubbles:  {}\n", state.stats.bubbles_injected)),
+        Text::raw(format!("illegal:  {}\n", state.stats.illegal_instructions)),
+        Text::raw(format!("acc_flt:  {}\n", state.stats.access_faults)),
+        Text::raw(format!("mis_aln:  {}\n", state.stats.misaligned_targets)),
+        Text::raw(format!("traps:    {}\n", state.stats.traps_taken)),
+        Text::raw(format!("fl_pen:   {}\n", state.stats.flush_penalty_cycles)),
+        Text::raw(format!("dc_wait:  {}\n", state.stats.decode_wait_cycles)),
         Text::raw(String::from("\n")),
         Text::raw(format!("bp_mode:  {:?}\n", state.branch_predictor.mode)),
         Text::raw(format!("bp_stack: {}\n", state.branch_predictor.return_stack_c.is_some())),
-        Text::raw(format!("alu_cnt:  {}\n", state.execute_units.iter().filter(|e| e.unit_type == UnitType::ALU).count())),
-        Text::raw(format!("blu_cnt:  {}\n", state.execute_units.iter().filter(|e| e.unit_type == UnitType::BLU).count())),
-        Text::raw(format!("mcu_cnt:  {}\n", state.execute_units.iter().filter(|e| e.unit_type == UnitType::MCU).count())),
+        Text::raw(format!("alu_cnt:  {}\n", state.execute_units.iter().filter(|e| e.name() == "ALU").count())),
+        Text::raw(format!("blu_cnt:  {}\n", state.execute_units.iter().filter(|e| e.name() == "BLU").count())),
+        Text::raw(format!("mcu_cnt:  {}\n", state.execute_units.iter().filter(|e| e.name() == "MCU").count())),
     ];
     Paragraph::new(tmp.iter())
-        .block(standard_block("Statistics"))
+        .block(standard_block("Statistics", &app.theme))
         .wrap(true)
         .render(f, area);
 }
 
 /// Draws the TuiApp state statistics on screen.
 fn draw_output(f: &mut Frame<Backend>, area: Rect, app: &TuiApp, default: &State) {
-    let state = app.states.get(app.hist_display).unwrap_or(default);
+    let state = app.shown_state().unwrap_or(default);
     let lines: Vec<Text> = state
         .out
         .iter()
         .map(|str| Text::raw(format!("{}\n", str)))
         .collect();
+    // The console title doubles as the goto-cycle prompt and message flash,
+    // and shows the exit code once the program has exited
+    let title = match (&app.goto, &app.flash, state.exit_code) {
+        (Some(input), _, _) => format!("Go to cycle: {}_", input),
+        (None, Some(message), _) => message.clone(),
+        (None, None, Some(code)) => format!("Console Output (exited with code {})", code),
+        (None, None, None) => String::from("Console Output"),
+    };
     Paragraph::new(lines.iter())
-        .block(standard_block("Console Output"))
+        .block(standard_block(&title, &app.theme))
         .wrap(true)
         .render(f, area);
 }
 
+/// Draws the most recent simulator log messages that fit on screen, coloured
+/// by their level.
+fn draw_log(f: &mut Frame<Backend>, area: Rect, app: &TuiApp, default: &State) {
+    let state = app.shown_state().unwrap_or(default);
+    let log = &state.log;
+    let shown = (area.height as usize).saturating_sub(2);
+    let messages = log
+        .entries
+        .iter()
+        .skip(log.entries.len().saturating_sub(shown))
+        .map(|(cycle, level, message)| {
+            Text::styled(
+                format!("{:>6} {:<5} {}", cycle, level, message),
+                match level {
+                    LogLevel::Warn => app.theme.warn,
+                    LogLevel::Info => app.theme.info,
+                    _ => Style::default(),
+                },
+            )
+        });
+    let title = format!("Log ({}, {} total)", log.level, log.total);
+    List::new(messages)
+        .block(standard_block(&title, &app.theme))
+        .render(f, area);
+}
+
 /// Draws the register file.
 fn draw_registers(f: &mut Frame<Backend>, area: Rect, app: &TuiApp, default: &State) {
-    let state_prev = app.states.get(app.hist_display + 1).unwrap_or(default);
-    let state = app.states.get(app.hist_display).unwrap_or(default);
+    let state_prev = app.states.iter_without_memory().nth(app.hist_display + 1).unwrap_or(default);
+    let state = app.shown_state().unwrap_or(default);
     let registers = state.register.file.iter().enumerate().map(|(name, are)| {
         let reg = Register::from(name as i32);
         let val = are.data;
@@ -180,40 +300,37 @@ fn draw_registers(f: &mut Frame<Backend>, area: Rect, app: &TuiApp, default: &St
                 },
             ),
             if reg == Register::PC {
-                Style::default().fg(Color::LightBlue)
+                app.theme.pc
             } else if val != val_prev {
-                Style::default().fg(Color::Black).bg(Color::LightYellow)
+                app.theme.changed
             } else {
-                Style::default().fg(Color::White)
+                app.theme.ready
             }
         )
     });
 
     List::new(registers)
-        .block(standard_block("Register File"))
+        .block(standard_block("Register File", &app.theme))
         .render(f, area);
 }
 
-/// Draws the fetch latch
+/// Draws the fetch queue
 fn draw_latch_fetch(f: &mut Frame<Backend>, area: Rect, app: &TuiApp, default: &State) {
-    let state = app.states.get(app.hist_display).unwrap_or(default);
+    let state = app.shown_state().unwrap_or(default);
     let lf = &state.latch_fetch;
-    let messages = lf.data.iter().enumerate().map(|(n, access)| {
-        let (rs_op, hist) = if n < lf.bp_data.len() {
-            lf.bp_data[n]
-        } else {
-            (ReturnStackOp::None, 0)
-        };
-        Text::raw(format!("{:08x}: {} - {:?} {:03b}", lf.pc + (4 * n), access, rs_op, hist))
+    let messages = lf.queue.iter().map(|e| {
+        let (rs_op, hist) = e.bp_data;
+        Text::raw(format!("{:08x}: {} - {:?} {:03b}", e.pc, e.access, rs_op, hist))
     });
+    let title = format!("Fetch Queue ({}/{})", lf.queue.len(), lf.capacity);
     List::new(messages)
-        .block(standard_block("Fetch Latch"))
+        .block(standard_block(&title, &app.theme))
         .render(f, area);
 }
 
 /// Draws the reservation station.
 fn draw_reservation_station(f: &mut Frame<Backend>, area: Rect, app: &TuiApp, default: &State) {
-    let state = app.states.get(app.hist_display).unwrap_or(default);
+    let state = app.shown_state().unwrap_or(default);
     let rsv = &state.resv_station;
     let rob = &state.reorder_buffer;
     let list = rsv.contents.iter().enumerate().map(|(n, e)| {
@@ -229,108 +346,224 @@ fn draw_reservation_station(f: &mut Frame<Backend>, area: Rect, app: &TuiApp, de
         Text::styled(
             format!("{:02}: {}", n, e),
             if ready {
-                Style::default().fg(Color::White)
+                app.theme.ready
             } else {
-                Style::default().fg(Color::DarkGray)
+                app.theme.not_ready
             }
         )
     });
 
     List::new(list)
-        .block(standard_block("Unified Reservation Station"))
+        .block(standard_block("Unified Reservation Station", &app.theme))
         .render(f, area);
 }
 
-/// Draws the reorder buffer.
+/// Returns whether the given entry of the reorder buffer is between its front
+/// and back, and so in use.
+fn rob_in_use(rob: &ReorderBuffer, n: usize) -> bool {
+    let (o, len) = (rob.count != 0, rob.capacity);
+    let front_n = if o && n < rob.front { n + len } else { n };
+    let front_b = if o && rob.back <= rob.front { rob.back + len } else { rob.back };
+    rob.front <= front_n && front_n < front_b
+}
+
+/// Returns the entries of the reorder buffer that the entries in use are
+/// waiting on the results of. Only entries in use have dependencies.
+fn rob_waited_on(rob: &ReorderBuffer) -> HashSet<usize> {
+    (0..rob.capacity)
+        .filter(|&n| rob_in_use(rob, n))
+        .flat_map(|n| vec![rob[n].rs1, rob[n].rs2])
+        .filter_map(|r| r.right())
+        .collect()
+}
+
+/// Draws the reorder buffer. When dependencies are shown, each entry that is
+/// being waited on is marked with a coloured `■` after its index, and the
+/// entries waiting on it have a `●` of the same colour for each operand.
 fn draw_reorder_buffer(f: &mut Frame<Backend>, area: Rect, app: &TuiApp, default: &State) {
-    let state = app.states.get(app.hist_display).unwrap_or(default);
+    let state = app.shown_state().unwrap_or(default);
     let rob = &state.reorder_buffer;
     let eus = &state.execute_units;
     let len = rob.capacity;
     let skip_amount = rob.front_fin.checked_sub((area.height as usize) / 4).unwrap_or(0);
-    let list = rob.rob.iter().enumerate().skip(skip_amount).map(|(n, e)| {
+
+    let o = rob.count != 0;
+    let in_use = |n: usize| rob_in_use(rob, n);
+    let waited_on = if app.show_deps { rob_waited_on(rob) } else { HashSet::new() };
+    let colours = &app.theme.dependencies;
+    let mark = |c: &'static str, name: Option<usize>| match name {
+        Some(_) if colours.is_empty() => Text::raw(c),
+        Some(name) => Text::styled(c, Style::default().fg(colours[name % colours.len()])),
+        None => Text::raw(" "),
+    };
+
+    let lines = rob.rob.iter().enumerate().skip(skip_amount).flat_map(|(n, e)| {
         // Find if any execute unit has this entry in it
         let unit = eus
             .iter()
-            .map(|eu| (eu, eu.executing.iter().find(|(r, _)| r.rob_entry == n)))
+            .map(|eu| (eu, eu.pipeline().executing.iter().find(|(r, _)| r.rob_entry == n)))
             .find(|(_, r)| r.is_some());
         let unit_str = if let Some((eu, Some(_))) = unit {
-            format!("{:#}", eu.unit_type)
+            eu.name().chars().take(1).collect()
         } else {
             String::from(" ")
         };
 
         // Move about pointers for the colour range checks below (cases where
-        // n or back are smaller than front/front_fin)
+        // n or back are smaller than front_fin)
+        let front_fin_n = if o && n < rob.front_fin { n + len } else { n };
+        let front_fin_b = if o && rob.back < rob.front_fin { rob.back + len } else { rob.back };
+
+        let style = if unit_str != " " {
+            app.theme.in_flight
+        } else if rob.front_fin <= front_fin_n && front_fin_n < front_fin_b {
+            app.theme.ready
+        } else if in_use(n) {
+            app.theme.finished
+        } else {
+            app.theme.not_ready
+        };
+        if app.show_deps {
+            let waiting = in_use(n);
+            vec![
+                Text::styled(format!("{} {:02} ", unit_str, n), style),
+                mark("■", Some(n).filter(|n| waited_on.contains(n))),
+                mark("●", e.rs1.right().filter(|_| waiting)),
+                mark("●", e.rs2.right().filter(|_| waiting)),
+                Text::styled(format!(": {}\n", e), style),
+            ]
+        } else {
+            vec![Text::styled(format!("{} {:02}: {}\n", unit_str, n, e), style)]
+        }
+    }).collect::<Vec<_>>();
+
+    let title = format!(
+        "Reorder Buffer ({}/{} physical registers)",
+        state.phys_regs.in_use(),
+        state.phys_regs.capacity
+    );
+    Paragraph::new(lines.iter())
+        .block(standard_block(&title, &app.theme))
+        .render(f, area);
+}
+
+/// Draws a timeline of the recent cycles alongside each reorder buffer entry,
+/// showing when the instruction was waiting in the reservation station (`r`),
+/// executing (`x`), finished and waiting to commit (`f`) and committed (`C`).
+fn draw_timeline(f: &mut Frame<Backend>, area: Rect, app: &TuiApp, default: &State) {
+    let state = app.shown_state().unwrap_or(default);
+    let rob = &state.reorder_buffer;
+    let len = rob.capacity;
+    let now = state.stats.cycles;
+    let width = (area.width as u64).saturating_sub(6);
+    let skip_amount = rob.front_fin.checked_sub((area.height as usize) / 4).unwrap_or(0);
+    let list = rob.rob.iter().enumerate().skip(skip_amount).map(|(n, e)| {
+        // Only entries between the front and back of the buffer are in use
         let o = rob.count != 0;
         let front_n = if o && n < rob.front { n + len } else { n };
         let front_b = if o && rob.back <= rob.front { rob.back + len } else { rob.back };
-        let front_fin_n = if o && n < rob.front_fin { n + len } else { n };
-        let front_fin_b = if o && rob.back < rob.front_fin { rob.back + len } else { rob.back };
+        let in_use = rob.front <= front_n && front_n < front_b;
+
+        let bar: String = if in_use {
+            (now.saturating_sub(width)..now)
+                .map(|c| {
+                    let after = |t: Option<u64>| t.map_or(false, |t| t <= c);
+                    if c < e.cycle_reserved || e.cycle_committed.map_or(false, |t| t < c) {
+                        ' '
+                    } else if after(e.cycle_committed) {
+                        'C'
+                    } else if after(e.cycle_finished) {
+                        'f'
+                    } else if after(e.cycle_issued) {
+                        'x'
+                    } else {
+                        'r'
+                    }
+                })
+                .collect()
+        } else {
+            String::new()
+        };
 
         Text::styled(
-            format!("{} {:02}: {}", unit_str, n, e),
-            if unit_str != " " {
-                Style::default().fg(Color::LightMagenta)
-            } else if rob.front_fin <= front_fin_n && front_fin_n < front_fin_b {
-                Style::default().fg(Color::White)
-            } else if rob.front <= front_n && front_n < front_b {
-                Style::default().fg(Color::Green)
+            format!("{:02}: {}", n, bar),
+            if in_use {
+                app.theme.ready
             } else {
-                Style::default().fg(Color::DarkGray)
-            }
+                app.theme.not_ready
+            },
         )
     });
 
     List::new(list)
-        .block(standard_block("Reorder Buffer"))
+        .block(standard_block("Timeline", &app.theme))
         .render(f, area);
 }
 
-/// Draws a section of the memory around the Load Counter.
+/// Draws a section of the memory around the Load Counter. If symbols are
+/// loaded, the functions and labels are named above their first instruction.
 fn draw_instr_memory(f: &mut Frame<Backend>, area: Rect, app: &TuiApp, default: &State) {
-    let state = app.states.get(app.hist_display).unwrap_or(default);
-    let pc = if state.latch_fetch.data.is_empty() { 0 } else { state.latch_fetch.pc };
+    let state = app.shown_state().unwrap_or(default);
     let lc = state.branch_predictor.lc;
-    let skip_amount = (lc.checked_sub((4 * area.height as usize) / 2).unwrap_or(0) / 4)
-        + ((state.n_way + 1) / 2);
-    let memory = state
-        .memory
-        .chunks(4)
+    // The committed program counter is -1 once the program has finished
+    let pc = match state.register[Register::PC].data {
+        pc if app.follow_pc && pc >= 0 => Some(pc as usize),
+        _ => None,
+    };
+    let skip_amount = match pc {
+        Some(pc) => pc.checked_sub((4 * area.height as usize) / 2).unwrap_or(0) / 4,
+        None => {
+            (lc.checked_sub((4 * area.height as usize) / 2).unwrap_or(0) / 4)
+                + ((state.fetch_width + 1) / 2)
+        }
+    };
+    // Only decode as many words as could fit in the area
+    let start = min(4 * skip_amount, state.memory.len());
+    let end = min(start + (4 * area.height as usize), state.memory.len());
+    let memory = decode_all(&state.memory[start..end])
+        .into_iter()
         .enumerate()
-        .skip(skip_amount)
-        .map(|(mut addr, mut value)| {
-            addr *= 4;
-            let word = value.read_i32::<LittleEndian>().unwrap();
-            Text::styled(
-                match Instruction::decode(word) {
-                    Some(i) => format!("{a:08x} :: {v:08x} - {i}", a = addr, v = word, i = i,),
+        .flat_map(|(n, instruction)| {
+            let addr = start + (4 * n);
+            let word = state.memory.read_i32(addr).word;
+            let label = state
+                .symbols
+                .get(&addr)
+                .map(|name| Text::styled(format!("<{}>:", name), app.theme.title));
+            label.into_iter().chain(Some(Text::styled(
+                match instruction {
+                    Some(i) => format!("{a:08x} :: {v:08x} - {i}", a = addr, v = word, i = i.disassemble(),),
                     None => format!("{a:08x} :: {v:08x} - {v}", a = addr, v = word,),
                 },
-                if lc <= addr && addr < lc + (4 * state.n_way) {
-                    Style::default()
-                        .fg(Color::LightBlue)
-                } else if pc <= addr && addr < pc + (4 * state.n_way) {
-                    Style::default()
-                        .fg(Color::LightCyan)
+                if pc == Some(addr) {
+                    app.theme.current_pc
+                } else if lc <= addr && addr < lc + (4 * state.fetch_width) {
+                    app.theme.pc
+                } else if state.latch_fetch.queue.iter().any(|e| e.pc == addr) {
+                    app.theme.queued
                 } else {
-                    Style::default().fg(Color::White)
+                    app.theme.ready
                 },
-            )
+            )))
         });
 
+    let title = if pc.is_some() {
+        "Memory (Centred on Committed PC)"
+    } else {
+        "Memory (Centred on Load Counter)"
+    };
     List::new(memory)
-        .block(standard_block("Memory (Centred on Load Counter)"))
+        .block(standard_block(title, &app.theme))
         .render(f, area);
 }
 
 /// Draws a section of the memory around the Load Counter.
 fn draw_stack_memory(f: &mut Frame<Backend>, area: Rect, app: &TuiApp, default: &State) {
-    let state = app.states.get(app.hist_display).unwrap_or(default);
+    let state = app.shown_state().unwrap_or(default);
     let sp_c = state.register[Register::X2].data;
     let last = app
         .states
-        .iter()
+        .iter_without_memory()
         .skip(1)
         .find(|s| s.register[Register::X2].data > sp_c)
         .unwrap_or(state);
@@ -350,27 +583,67 @@ fn draw_stack_memory(f: &mut Frame<Backend>, area: Rect, app: &TuiApp, default:
             let word = value.read_i32::<LittleEndian>().unwrap();
             Text::styled(
                 format!("{a:08x} :: {v:08x} - {v}", a = addr, v = word),
-                if sp_c <= (addr as i32) && (addr as i32) < sp_a {
-                    Style::default().fg(Color::White)
+                if state.in_stack_guard(addr, 4) {
+                    app.theme.guard
+                } else if sp_c <= (addr as i32) && (addr as i32) < sp_a {
+                    app.theme.ready
                 } else {
-                    Style::default().fg(Color::DarkGray)
+                    app.theme.not_ready
                 },
             )
         });
 
     List::new(memory)
-        .block(standard_block("Memory (Centred SP)"))
+        .block(standard_block("Memory (Centred SP)", &app.theme))
         .render(f, area);
 }
 
-/// Constructs a standardised Block widget with given title.
-pub fn standard_block(title: &str) -> Block {
+/// Constructs a standardised Block widget with given title, styled by the
+/// given theme.
+pub fn standard_block<'a>(title: &'a str, theme: &Theme) -> Block<'a> {
     Block::default()
         .borders(Borders::ALL)
-        .title_style(
-            Style::default()
-                .fg(Color::LightGreen)
-                .modifier(Modifier::Bold),
-        )
+        .title_style(theme.title)
         .title(title)
 }
+
+///////////////////////////////////////////////////////////////////////////////
+//// TESTS
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::builder::{run, StateBuilder};
+    use crate::simulator::step_cycle;
+
+    #[test]
+    fn marks_entries_waited_on() {
+        let mut state = StateBuilder::new()
+            .reg(Register::X5, 1)
+            .asm("div x10 x5 x5")
+            .asm("add x11 x10 x5")
+            .asm("add x12 x11 x10")
+            .asm("jalr x0 x1 0")
+            .build();
+        for _ in 0..6 {
+            step_cycle(&mut state);
+        }
+        let expected: HashSet<usize> = [0, 1].iter().cloned().collect();
+        assert_eq!(rob_waited_on(&state.reorder_buffer), expected);
+        assert!(!rob_in_use(&state.reorder_buffer, 4));
+        run(&mut state, 100);
+        assert!(rob_waited_on(&state.reorder_buffer).is_empty());
+    }
+
+    #[test]
+    fn only_draws_layout_when_it_fits() {
+        assert_eq!(
+            too_small_message(Rect::new(0, 0, 10, 5)).unwrap(),
+            "Terminal too small (10x5), need 100x60."
+        );
+        assert!(too_small_message(Rect::new(0, 0, 0, 0)).is_some());
+        assert!(too_small_message(Rect::new(0, 0, 99, 60)).is_some());
+        assert!(too_small_message(Rect::new(0, 0, 100, 59)).is_some());
+        assert!(too_small_message(Rect::new(0, 0, 100, 60)).is_none());
+    }
+}
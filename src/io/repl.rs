@@ -0,0 +1,169 @@
+use std::io::{self, Write};
+
+use crate::isa::assembly::parse_instruction;
+use crate::isa::op_code::Encodable;
+use crate::isa::operand::Register;
+use crate::simulator::state::State;
+use crate::simulator::step_cycle;
+use crate::util::config::Config;
+
+///////////////////////////////////////////////////////////////////////////////
+//// CONST/STATIC
+
+/// The commands that will exit the interactive prompt.
+const EXIT_COMMANDS: [&str; 2] = ["exit", "quit"];
+
+/// The command that resets the simulator back to its initial state.
+const RESET_COMMAND: &str = "reset";
+
+/// The maximum number of cycles to wait for an injected instruction to retire
+/// before giving up on it.
+const RETIRE_TIMEOUT: u64 = 1000;
+
+///////////////////////////////////////////////////////////////////////////////
+//// FUNCTIONS
+
+/// Main entry point for the interactive prompt. Reads instructions from stdin
+/// line by line, executing each one on the same simulator state, so that
+/// registers and memory carry over from one line to the next (until `reset`),
+/// and printing the registers that it changed.
+pub fn run_repl(config: &Config) {
+    let mut state = State::new(config);
+    println!("Project Daybreak interactive prompt.");
+    println!("Enter one instruction per line (e.g. `addi x1 x0 7`), `reset` to start over, or `exit` to quit.");
+    prompt();
+
+    // Lines are read one at a time without keeping stdin locked, as the
+    // program reads from it too, through the read system call
+    let stdin = io::stdin();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        match stdin.read_line(&mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => (),
+        }
+        let line = line.trim();
+        if EXIT_COMMANDS.contains(&line) {
+            break;
+        }
+
+        if line == RESET_COMMAND {
+            state.reset();
+            println!("Reset to the initial state.");
+        } else if !line.is_empty() {
+            let logged = state.log.total;
+            let result = execute_line(&mut state, line);
+            // There is no display for the log, so it goes to stderr instead
+            for (cycle, level, message) in state.log.since(logged) {
+                eprintln!("[{:>6} {:<5}] {}", cycle, level, message);
+            }
+            if let Some(pc) = state.breakpoint.take() {
+                println!("Breakpoint at {:08x}.", pc);
+            }
+            match result {
+                Ok(changes) => {
+                    for (reg, old, new) in changes {
+                        println!(
+                            "  {:>4} ({:>3}): {:08x} -> {:08x} ({})",
+                            format!("{:#}", reg),
+                            reg,
+                            old,
+                            new,
+                            new
+                        );
+                    }
+                }
+                Err(message) => println!("error: {}", message),
+            }
+        }
+
+        if state.register[Register::PC].data == -1 {
+            match state.exit_code {
+                Some(code) => println!("Program exited with code {}, finished.", code),
+                None => println!("Program returned to address -1, finished."),
+            }
+            break;
+        }
+        prompt();
+    }
+}
+
+/// Parses and encodes the given line of assembly, injecting it into memory at
+/// the current program counter before running the pipeline until it retires.
+///
+/// Returns the registers that changed value as `(register, old, new)`, or a
+/// message describing why the instruction could not be executed.
+pub fn execute_line(state: &mut State, line: &str) -> Result<Vec<(Register, i32, i32)>, String> {
+    let instruction = parse_instruction(line)?;
+    let pc = state.register[Register::PC].data;
+    if pc < 0 {
+        return Err(String::from("Program has finished, no more instructions can run."));
+    }
+
+    // Inject the instruction, and make sure that nothing stale is in flight
+    state.memory.write_i32(pc as usize, instruction.encode());
+    state.clear_pipeline(pc as usize);
+
+    let before = state.register.clone();
+    let executed = state.stats.retired();
+    let mut cycles = 0;
+    while state.stats.retired() == executed {
+        if step_cycle(state) {
+            break;
+        }
+        cycles += 1;
+        if cycles >= RETIRE_TIMEOUT {
+            return Err(format!("Instruction did not retire within {} cycles.", RETIRE_TIMEOUT));
+        }
+    }
+
+    // Discard anything fetched speculatively past the retired instruction
+    let new_pc = state.register[Register::PC].data;
+    if new_pc >= 0 {
+        state.clear_pipeline(new_pc as usize);
+    }
+
+    Ok(before
+        .file
+        .iter()
+        .zip(state.register.file.iter())
+        .enumerate()
+        .filter(|(_, (old, new))| old.data != new.data)
+        .map(|(n, (old, new))| (Register::from(n as i32), old.data, new.data))
+        .collect())
+}
+
+/// Prints the prompt, flushing stdout as there is no trailing newline.
+fn prompt() {
+    print!("> ");
+    #[allow(unused_must_use)]
+    {
+        io::stdout().flush();
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// TESTS
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::builder::StateBuilder;
+
+    #[test]
+    fn executes_typed_instruction() {
+        let mut state = StateBuilder::new().build();
+        let changes = execute_line(&mut state, "addi x1 x0 7").unwrap();
+        assert_eq!(state.register[Register::X1].data, 7);
+        assert!(changes.contains(&(Register::X1, -1, 7)));
+        assert_eq!(state.register[Register::PC].data, 4);
+    }
+
+    #[test]
+    fn rejects_invalid_instruction() {
+        let mut state = StateBuilder::new().build();
+        assert!(execute_line(&mut state, "addi x1 x0").is_err());
+        assert_eq!(state.stats.retired(), 0);
+    }
+}
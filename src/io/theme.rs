@@ -0,0 +1,155 @@
+use tui::style::{Color, Modifier, Style};
+
+///////////////////////////////////////////////////////////////////////////////
+//// ENUMS
+
+/// The colour schemes that the display can be drawn with.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ThemeMode {
+    /// The original colours of the display.
+    Default,
+    /// No colours at all, telling things apart by bold, faint, underlined and
+    /// inverted text instead, for terminals (or users) that struggle with the
+    /// default colours.
+    Plain,
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// STRUCTS
+
+/// The styles that the display draws things in, named by what they show
+/// rather than how they look, so the draw functions never pick colours
+/// themselves.
+#[derive(Clone, Debug)]
+pub struct Theme {
+    /// The titles of every block.
+    pub title: Style,
+    /// Anything ready or in use, such as a reservation whose operands are
+    /// available.
+    pub ready: Style,
+    /// Anything waiting or not in use, such as an empty reorder buffer entry.
+    pub not_ready: Style,
+    /// Reorder buffer entries that are executing in an execute unit.
+    pub in_flight: Style,
+    /// Reorder buffer entries that have finished executing, waiting to commit.
+    pub finished: Style,
+    /// Registers that changed in the last cycle.
+    pub changed: Style,
+    /// The program counter register, and the instructions being fetched from
+    /// the load counter.
+    pub pc: Style,
+    /// The instruction at the committed program counter.
+    pub current_pc: Style,
+    /// Instructions that are in the fetch queue.
+    pub queued: Style,
+    /// Memory in the stack guard region.
+    pub guard: Style,
+    /// Warning log messages.
+    pub warn: Style,
+    /// Info log messages.
+    pub info: Style,
+    /// The colours that the reorder buffer cycles through, by entry, to pair
+    /// the entries being waited on with the entries waiting on them. When
+    /// empty, the markers are drawn without colour.
+    pub dependencies: Vec<Color>,
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// IMPLEMENTATIONS
+
+impl Theme {
+    /// Creates the theme for the given colour scheme.
+    pub fn new(mode: ThemeMode) -> Theme {
+        match mode {
+            ThemeMode::Default => Theme {
+                title: Style::default().fg(Color::LightGreen).modifier(Modifier::Bold),
+                ready: Style::default().fg(Color::White),
+                not_ready: Style::default().fg(Color::DarkGray),
+                in_flight: Style::default().fg(Color::LightMagenta),
+                finished: Style::default().fg(Color::Green),
+                changed: Style::default().fg(Color::Black).bg(Color::LightYellow),
+                pc: Style::default().fg(Color::LightBlue),
+                current_pc: Style::default().fg(Color::Black).bg(Color::Green),
+                queued: Style::default().fg(Color::LightCyan),
+                guard: Style::default().fg(Color::Red),
+                warn: Style::default().fg(Color::LightRed),
+                info: Style::default().fg(Color::LightYellow),
+                dependencies: vec![
+                    Color::Red,
+                    Color::Yellow,
+                    Color::Blue,
+                    Color::Cyan,
+                    Color::LightRed,
+                    Color::LightBlue,
+                ],
+            },
+            ThemeMode::Plain => Theme {
+                title: Style::default().modifier(Modifier::Bold),
+                ready: Style::default(),
+                not_ready: Style::default().modifier(Modifier::Faint),
+                in_flight: Style::default().modifier(Modifier::Bold),
+                finished: Style::default().modifier(Modifier::Underline),
+                changed: Style::default().modifier(Modifier::Invert),
+                pc: Style::default().modifier(Modifier::Bold),
+                current_pc: Style::default().modifier(Modifier::Invert),
+                queued: Style::default().modifier(Modifier::Underline),
+                guard: Style::default().modifier(Modifier::Underline),
+                warn: Style::default().modifier(Modifier::Bold),
+                info: Style::default(),
+                dependencies: vec![],
+            },
+        }
+    }
+}
+
+impl Default for ThemeMode {
+    /// Defaults to the original colours.
+    fn default() -> ThemeMode {
+        ThemeMode::Default
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// TESTS
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::config::Config;
+
+    /// Returns every style in the theme.
+    fn styles(theme: &Theme) -> Vec<Style> {
+        vec![
+            theme.title,
+            theme.ready,
+            theme.not_ready,
+            theme.in_flight,
+            theme.finished,
+            theme.changed,
+            theme.pc,
+            theme.current_pc,
+            theme.queued,
+            theme.guard,
+            theme.warn,
+            theme.info,
+        ]
+    }
+
+    #[test]
+    fn no_color_sets_no_colours() {
+        let elf_file = "resources/programs/hello_world/a.out";
+        let config = Config::create_from(vec!["daybreak", "--no-color", elf_file]);
+        assert_eq!(config.theme, ThemeMode::Plain);
+        let theme = Theme::new(config.theme);
+        for style in styles(&theme) {
+            assert_eq!(style.fg, Color::Reset);
+            assert_eq!(style.bg, Color::Reset);
+        }
+        assert!(theme.dependencies.is_empty());
+
+        let config = Config::create_from(vec!["daybreak", elf_file]);
+        assert_eq!(config.theme, ThemeMode::Default);
+        let theme = Theme::new(config.theme);
+        assert!(styles(&theme).iter().all(|s| s.fg != Color::Reset));
+    }
+}
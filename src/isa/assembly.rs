@@ -0,0 +1,493 @@
+use std::collections::HashMap;
+
+use super::op_code::{Encodable, Operation};
+use super::operand::Register;
+use super::{Format, Instruction};
+
+///////////////////////////////////////////////////////////////////////////////
+//// ENUMS
+
+/// The sections that an assembly program is placed in.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Section {
+    /// The instructions, placed from the origin of the program.
+    Text,
+    /// The data, placed (word aligned) after the text section.
+    Data,
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// STRUCTS
+
+/// A program assembled from source, as the contents of memory from its
+/// origin.
+#[derive(Clone, Debug)]
+pub struct Program {
+    /// The address of the first byte of the program, where the text section
+    /// (and execution) starts.
+    pub origin: usize,
+    /// The bytes of the text section, followed by those of the data section.
+    pub bytes: Vec<u8>,
+    /// The number of bytes of the text section at the start of `bytes`,
+    /// including any padding up to the data section.
+    pub text_size: usize,
+    /// The address of every label in the program, by name.
+    pub labels: HashMap<String, usize>,
+}
+
+/// A single line of an assembly program, split into its parts.
+struct Line<'a> {
+    /// The labels defined at the start of the line.
+    labels: Vec<&'a str>,
+    /// The mnemonic or directive (with its leading `.`), if any.
+    mnemonic: Option<&'a str>,
+    /// The operands following the mnemonic or directive.
+    operands: Vec<&'a str>,
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// FUNCTIONS
+
+/// Assembles a whole program, placed in memory from the given origin. On top
+/// of the instructions understood by `parse_instruction`, each line may start
+/// with any number of labels (`name:`), and may end with a `#` comment.
+///
+/// The `.text` and `.data` directives switch between the text section,
+/// placed from the origin, and the data section, placed after it. The
+/// `.word` and `.byte` directives place their comma separated values in the
+/// current section. Branches and jumps may be given a label instead of their
+/// offset, as may the values of `.word`, and `la rd, label` loads the address
+/// of a label with an `auipc` and `addi`.
+///
+/// Labels are found in a first pass over the program, so may be used before
+/// they are defined. Returns a message describing the problem, and the line
+/// it is on, if the program could not be assembled.
+pub fn assemble_program(source: &str, origin: usize) -> Result<Program, String> {
+    let lines = source
+        .lines()
+        .enumerate()
+        .map(|(n, text)| split_line(text).map_err(|e| format!("Line {}: {}", n + 1, e)))
+        .collect::<Result<Vec<Line>, String>>()?;
+
+    // First pass: find the offset of every label within its section
+    let mut offsets = HashMap::new();
+    let mut section = Section::Text;
+    let mut sizes = [0, 0];
+    for (n, line) in lines.iter().enumerate() {
+        let at = |message: String| format!("Line {}: {}", n + 1, message);
+        for label in line.labels.iter() {
+            if offsets.insert(*label, (section, sizes[section as usize])).is_some() {
+                return Err(at(format!("Label '{}' is defined more than once.", label)));
+            }
+        }
+        match line.mnemonic {
+            Some(".text") => section = Section::Text,
+            Some(".data") => section = Section::Data,
+            Some(m) => sizes[section as usize] += line_size(m, &line.operands).map_err(at)?,
+            None => (),
+        }
+    }
+
+    // The data section follows the text section, word aligned
+    let data_base = origin + ((sizes[Section::Text as usize] + 3) & !3);
+    let address = |(section, offset): (Section, usize)| match section {
+        Section::Text => origin + offset,
+        Section::Data => data_base + offset,
+    };
+    let labels: HashMap<String, usize> = offsets
+        .iter()
+        .map(|(name, location)| (String::from(*name), address(*location)))
+        .collect();
+
+    // Second pass: place every instruction and value, resolving labels
+    let mut text = Vec::with_capacity(sizes[Section::Text as usize]);
+    let mut data = Vec::with_capacity(sizes[Section::Data as usize]);
+    let mut section = Section::Text;
+    for (n, line) in lines.iter().enumerate() {
+        let at = |message: String| format!("Line {}: {}", n + 1, message);
+        let bytes = match section {
+            Section::Text => &mut text,
+            Section::Data => &mut data,
+        };
+        let pc = address((section, bytes.len()));
+        match line.mnemonic {
+            Some(".text") => section = Section::Text,
+            Some(".data") => section = Section::Data,
+            Some(".word") => {
+                for token in line.operands.iter() {
+                    let value = parse_value(token, &labels).map_err(at)?;
+                    bytes.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+            Some(".byte") => {
+                for token in line.operands.iter() {
+                    match parse_immediate(token).map_err(at)? {
+                        v if -0x80 <= v && v <= 0xff => bytes.push(v as u8),
+                        _ => return Err(at(format!("Byte '{}' does not fit in 8 bits.", token))),
+                    }
+                }
+            }
+            Some(m) => {
+                for instruction in assemble_line(m, &line.operands, pc, &labels).map_err(at)? {
+                    bytes.extend_from_slice(&instruction.encode().to_le_bytes());
+                }
+            }
+            None => (),
+        }
+    }
+
+    let mut bytes = text;
+    bytes.resize(data_base - origin, 0);
+    bytes.append(&mut data);
+    Ok(Program {
+        origin,
+        bytes,
+        text_size: data_base - origin,
+        labels,
+    })
+}
+
+/// Parses a single line of assembly into an `Instruction`. The syntax is the
+/// same as that printed by the `Display` implementation of `Instruction`, i.e.
+/// the mnemonic followed by any of `rd`, `rs1`, `rs2` and the immediate that
+/// the instruction format uses, in that order. Operands may be separated by
+/// whitespace and/or commas.
+///
+/// Returns a message describing the problem if the line could not be parsed.
+pub fn parse_instruction(line: &str) -> Result<Instruction, String> {
+    let mut tokens = line
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|t| !t.is_empty());
+
+    let mnemonic = match tokens.next() {
+        Some(m) => m,
+        None => return Err(String::from("No instruction given.")),
+    };
+    let op = match mnemonic.parse::<Operation>() {
+        Ok(op) => op,
+        Err(_) => return Err(format!("Unrecognised operation '{}'.", mnemonic)),
+    };
+    let operands: Vec<&str> = tokens.collect();
+
+    // The environment calls and privileged instructions have their function
+    // encoded in the immediate, so do not require any operands.
+    let funct12 = match op {
+        Operation::ECALL => Some(0x0),
+        Operation::EBREAK => Some(0x1),
+        Operation::SRET => Some(0x102),
+        Operation::WFI => Some(0x105),
+        Operation::MRET => Some(0x302),
+        _ => None,
+    };
+    if let Some(funct12) = funct12 {
+        if !operands.is_empty() {
+            return Err(format!("'{}' does not take any operands.", op));
+        }
+        return Ok(Instruction {
+            op,
+            rd: Some(Register::X0),
+            rs1: Some(Register::X0),
+            rs2: None,
+            imm: Some(funct12),
+        });
+    }
+
+    #[rustfmt::skip]
+    let (rd, rs1, rs2, imm) = match Format::from(op) {
+        Format::R => (true,  true,  true,  false),
+        Format::I => (true,  true,  false, true),
+        Format::S => (false, true,  true,  true),
+        Format::B => (false, true,  true,  true),
+        Format::U => (true,  false, false, true),
+        Format::J => (true,  false, false, true),
+    };
+    let expected = [rd, rs1, rs2, imm].iter().filter(|b| **b).count();
+    if operands.len() != expected {
+        return Err(format!(
+            "'{}' expects {} operands, found {}.",
+            op,
+            expected,
+            operands.len()
+        ));
+    }
+
+    let mut operands = operands.into_iter();
+    let mut instruction = Instruction {
+        op,
+        rd: None,
+        rs1: None,
+        rs2: None,
+        imm: None,
+    };
+    if rd {
+        instruction.rd = Some(parse_register(operands.next().unwrap())?);
+    }
+    if rs1 {
+        instruction.rs1 = Some(parse_register(operands.next().unwrap())?);
+    }
+    if rs2 {
+        instruction.rs2 = Some(parse_register(operands.next().unwrap())?);
+    }
+    if imm {
+        let token = operands.next().unwrap();
+        let value = parse_immediate(token)?;
+        instruction.imm = Some(match Format::from(op) {
+            Format::U => parse_upper_immediate(token, value)?,
+            _ => value,
+        });
+    }
+    Ok(instruction)
+}
+
+/// Parses a register operand, given by either its numeric (`x0`..`x31`) or ABI
+/// name. The program counter cannot be used as an operand.
+fn parse_register(token: &str) -> Result<Register, String> {
+    match Register::from_name(token) {
+        Some(Register::PC) | None => Err(format!("Invalid register '{}'.", token)),
+        Some(reg) => Ok(reg),
+    }
+}
+
+/// Converts the parsed value of a U-type immediate operand, which is given as
+/// the 20 bits placed in the upper bits of the register (as printed), into the
+/// immediate of the instruction, with the lower 12 bits clear.
+fn parse_upper_immediate(token: &str, value: i32) -> Result<i32, String> {
+    if value < -0x8_0000 || value > 0xf_ffff {
+        return Err(format!("Upper immediate '{}' does not fit in 20 bits.", token));
+    }
+    Ok(value << 12)
+}
+
+/// Parses an immediate operand, given in either decimal or (`0x` prefixed)
+/// hexadecimal, with an optional leading minus sign.
+pub fn parse_immediate(token: &str) -> Result<i32, String> {
+    let (negative, digits) = if token.starts_with('-') {
+        (true, &token[1..])
+    } else {
+        (false, token)
+    };
+    let value = if digits.starts_with("0x") || digits.starts_with("0X") {
+        u32::from_str_radix(&digits[2..], 16).map(|v| v as i64)
+    } else {
+        digits.parse::<i64>()
+    };
+    match value {
+        Ok(v) if v <= i64::from(u32::max_value()) => {
+            Ok(if negative { (v as i32).wrapping_neg() } else { v as i32 })
+        }
+        _ => Err(format!("Invalid immediate '{}'.", token)),
+    }
+}
+
+/// Splits a line of an assembly program into its labels, mnemonic (or
+/// directive) and operands, ignoring any comment.
+fn split_line(text: &str) -> Result<Line<'_>, String> {
+    let mut rest = match text.find('#') {
+        Some(i) => &text[..i],
+        None => text,
+    }
+    .trim();
+
+    let mut labels = vec![];
+    while let Some(i) = rest.find(':') {
+        let label = rest[..i].trim();
+        if !is_label(label) {
+            return Err(format!("Invalid label '{}'.", label));
+        }
+        labels.push(label);
+        rest = rest[i + 1..].trim();
+    }
+
+    let mut tokens = rest
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|t| !t.is_empty());
+    Ok(Line {
+        labels,
+        mnemonic: tokens.next(),
+        operands: tokens.collect(),
+    })
+}
+
+/// Returns whether the given token is a valid label name, made of letters,
+/// digits, `_`, `.` and `$`, without a leading digit.
+fn is_label(token: &str) -> bool {
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$';
+    match token.chars().next() {
+        Some(c) if !c.is_ascii_digit() => token.chars().all(valid),
+        _ => false,
+    }
+}
+
+/// Returns the number of bytes that a line with the given mnemonic (or
+/// directive) and operands places in its section.
+fn line_size(mnemonic: &str, operands: &[&str]) -> Result<usize, String> {
+    match mnemonic {
+        ".word" => Ok(4 * operands.len()),
+        ".byte" => Ok(operands.len()),
+        "la" => Ok(8),
+        m if m.starts_with('.') => Err(format!("Unrecognised directive '{}'.", m)),
+        _ => Ok(4),
+    }
+}
+
+/// Assembles the instruction(s) of a line at the given address, replacing
+/// any label given as the offset of a branch or jump, or expanding an `la`
+/// into the `auipc` and `addi` that load the address of its label.
+fn assemble_line(
+    mnemonic: &str,
+    operands: &[&str],
+    pc: usize,
+    labels: &HashMap<String, usize>,
+) -> Result<Vec<Instruction>, String> {
+    if mnemonic == "la" {
+        if operands.len() != 2 {
+            return Err(format!("'la' expects 2 operands, found {}.", operands.len()));
+        }
+        let rd = parse_register(operands[0])?;
+        let offset = find_label(operands[1], labels)?.wrapping_sub(pc) as i32;
+        // The lower 12 bits are sign extended by the addi, so round the
+        // upper 20 bits to make up for it
+        let upper = offset.wrapping_add(0x800) >> 12;
+        let lower = offset.wrapping_sub(upper << 12);
+        return Ok(vec![
+            Instruction {
+                op: Operation::AUIPC,
+                rd: Some(rd),
+                rs1: None,
+                rs2: None,
+                imm: Some(upper << 12),
+            },
+            Instruction {
+                op: Operation::ADDI,
+                rd: Some(rd),
+                rs1: Some(rd),
+                rs2: None,
+                imm: Some(lower),
+            },
+        ]);
+    }
+
+    let offset;
+    let mut operands = operands.to_vec();
+    if let Ok(op) = mnemonic.parse::<Operation>() {
+        let range = match Format::from(op) {
+            Format::B => Some(0x1000),
+            Format::J => Some(0x10_0000),
+            _ => None,
+        };
+        match (range, operands.last()) {
+            (Some(range), Some(token)) if is_label(token) => {
+                let target = find_label(token, labels)?;
+                let value = target.wrapping_sub(pc) as i32;
+                if value < -range || value >= range {
+                    return Err(format!("Label '{}' is out of range of the {}.", token, op));
+                }
+                offset = value.to_string();
+                *operands.last_mut().unwrap() = &offset;
+            }
+            _ => (),
+        }
+    }
+    Ok(vec![parse_instruction(&format!(
+        "{} {}",
+        mnemonic,
+        operands.join(" ")
+    ))?])
+}
+
+/// Parses the value of a `.word`, given as either an immediate or a label,
+/// for the address of the label.
+fn parse_value(token: &str, labels: &HashMap<String, usize>) -> Result<i32, String> {
+    if is_label(token) {
+        find_label(token, labels).map(|addr| addr as i32)
+    } else {
+        parse_immediate(token)
+    }
+}
+
+/// Finds the address of a label, which must be defined somewhere in the
+/// program.
+fn find_label(token: &str, labels: &HashMap<String, usize>) -> Result<usize, String> {
+    match labels.get(token) {
+        Some(addr) => Ok(*addr),
+        None => Err(format!("Undefined label '{}'.", token)),
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// TESTS
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isa::decode_all;
+
+    /// Assembles a program from address `0x1000`, and disassembles each of its
+    /// words in the same syntax.
+    fn assemble(source: &str) -> (Program, Vec<String>) {
+        let program = assemble_program(source, 0x1000).unwrap();
+        let words = decode_all(&program.bytes)
+            .iter()
+            .map(|i| i.as_ref().map_or(String::from("?"), |i| i.to_string()))
+            .collect();
+        (program, words)
+    }
+
+    #[test]
+    fn resolves_forward_branch_to_label() {
+        let (program, words) = assemble(
+            "        beq x10 x0 done  # skip the next instruction
+                     addi x10 x0 1
+             done:   jalr x0 x1 0",
+        );
+        assert_eq!(program.labels["done"], 0x1008);
+        assert_eq!(
+            words,
+            vec!["beq a0 zero 8", "addi a0 zero 1", "jalr zero ra 0"]
+        );
+    }
+
+    #[test]
+    fn loads_address_of_label_with_la() {
+        // The data is far enough away that the addi must subtract from the
+        // rounded up auipc, with 0x1000 + 4096 - 1784 = 0x1908
+        let padding = vec!["0"; 0x900].join(", ");
+        let source = format!("la x10 value\n.data\n.byte {}\nvalue: .word 7", padding);
+        let (program, words) = assemble(&source);
+        assert_eq!(program.labels["value"], 0x1908);
+        assert_eq!(words[..2], ["auipc a0 4096", "addi a0 a0 -1784"]);
+    }
+
+    #[test]
+    fn places_address_of_label_in_word() {
+        let (program, _) = assemble(
+            "start: jalr x0 x1 0
+             .data
+             table: .word start, table, 5",
+        );
+        let word = |i: usize| {
+            let bytes = &program.bytes[4 + 4 * i..8 + 4 * i];
+            u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+        };
+        assert_eq!(program.labels["table"], 0x1004);
+        assert_eq!((word(0), word(1), word(2)), (0x1000, 0x1004, 5));
+    }
+
+    #[test]
+    fn reports_line_of_problem() {
+        let error = |source: &str| assemble_program(source, 0).unwrap_err();
+        assert_eq!(
+            error("nop:\n1st: jalr x0 x1 0"),
+            "Line 2: Invalid label '1st'."
+        );
+        assert_eq!(
+            error("beq x10 x0 nowhere"),
+            "Line 1: Undefined label 'nowhere'."
+        );
+        assert_eq!(
+            error("a:\na: .word 0"),
+            "Line 2: Label 'a' is defined more than once."
+        );
+        assert_eq!(error(".half 0"), "Line 1: Unrecognised directive '.half'.");
+    }
+}
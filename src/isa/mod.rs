@@ -1,11 +1,14 @@
 use std::fmt::{Display, Formatter, Result};
 
-use self::op_code::{BaseCode, Decodable, Operation};
-use self::operand::{extract_immediate, Register, RegisterOperand};
+use self::op_code::{BaseCode, Decodable, Encodable, Operation};
+use self::operand::{encode_immediate, extract_immediate, Register, RegisterOperand};
 
 ///////////////////////////////////////////////////////////////////////////////
 //// EXTERNAL MODULES
 
+/// Parsing of `rv32im` assembly text into instructions and whole programs.
+pub mod assembly;
+
 /// All things related to a `rv32im` opcodes.
 pub mod op_code;
 
@@ -63,7 +66,8 @@ impl From<op_code::BaseCode> for Format {
     #[rustfmt::skip]
     fn from(code: BaseCode) -> Format {
         match code {
-            op_code::BaseCode::OP      => Format::R,
+            op_code::BaseCode::OP      |
+            op_code::BaseCode::AMO     => Format::R,
             op_code::BaseCode::JALR    |
             op_code::BaseCode::LOAD    |
             op_code::BaseCode::OPIMM   |
@@ -134,4 +138,172 @@ impl Instruction {
             imm: extract_immediate(instruction),
         })
     }
+
+    /// Renders the instruction in canonical RISC-V assembly syntax, using the
+    /// ABI register names, e.g. `lw a0, 8(sp)` or `beq a0, zero, -12`.
+    #[rustfmt::skip]
+    pub fn disassemble(&self) -> String {
+        let rd = self.rd.unwrap_or(Register::X0);
+        let rs1 = self.rs1.unwrap_or(Register::X0);
+        let rs2 = self.rs2.unwrap_or(Register::X0);
+        let imm = self.imm.unwrap_or(0);
+        match self.op {
+            Operation::ECALL  |
+            Operation::EBREAK |
+            Operation::MRET   |
+            Operation::SRET   |
+            Operation::WFI    |
+            Operation::FENCEI => format!("{}", self.op),
+            Operation::FENCE => format!(
+                "{} {}, {}",
+                self.op,
+                fence_set(imm >> 4),
+                fence_set(imm)
+            ),
+            Operation::CSRRW |
+            Operation::CSRRS |
+            Operation::CSRRC => format!("{} {:#}, {:#x}, {:#}", self.op, rd, imm & 0xfff, rs1),
+            Operation::CSRRWI |
+            Operation::CSRRSI |
+            Operation::CSRRCI => format!("{} {:#}, {:#x}, {}", self.op, rd, imm & 0xfff, rs1 as i32),
+            Operation::SLLI |
+            Operation::SRLI |
+            Operation::SRAI => format!("{} {:#}, {:#}, {}", self.op, rd, rs1, imm & 0b11111),
+            Operation::LRW => format!("{} {:#}, ({:#})", self.op, rd, rs1),
+            Operation::SCW      |
+            Operation::AMOSWAPW |
+            Operation::AMOADDW  |
+            Operation::AMOXORW  |
+            Operation::AMOANDW  |
+            Operation::AMOORW   |
+            Operation::AMOMINW  |
+            Operation::AMOMAXW  |
+            Operation::AMOMINUW |
+            Operation::AMOMAXUW => format!("{} {:#}, {:#}, ({:#})", self.op, rd, rs2, rs1),
+            _ => match Format::from(self.op) {
+                Format::R => format!("{} {:#}, {:#}, {:#}", self.op, rd, rs1, rs2),
+                Format::I => match BaseCode::from(self.op) {
+                    BaseCode::LOAD |
+                    BaseCode::JALR => format!("{} {:#}, {}({:#})", self.op, rd, imm, rs1),
+                    _ => format!("{} {:#}, {:#}, {}", self.op, rd, rs1, imm),
+                },
+                Format::S => format!("{} {:#}, {}({:#})", self.op, rs2, imm, rs1),
+                Format::B => format!("{} {:#}, {:#}, {}", self.op, rs1, rs2, imm),
+                Format::U => format!("{} {:#}, {:#x}", self.op, rd, (imm as u32) >> 12),
+                Format::J => format!("{} {:#}, {}", self.op, rd, imm),
+            },
+        }
+    }
+}
+
+impl Encodable for Instruction {
+    /// Encodes the instruction into a RISC V binary instruction word from the
+    /// `rv32im` specification, i.e. the inverse of `Instruction::decode`.
+    fn encode(&self) -> i32 {
+        let mut word = self.op.encode();
+        if let Some(rd) = self.rd {
+            word |= (rd as i32 & 0b11111) << 7;
+        }
+        if let Some(rs1) = self.rs1 {
+            word |= (rs1 as i32 & 0b11111) << 15;
+        }
+        if let Some(rs2) = self.rs2 {
+            word |= (rs2 as i32 & 0b11111) << 20;
+        }
+        if let Some(imm) = self.imm {
+            word |= encode_immediate(Format::from(self.op), imm);
+        }
+        word
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// FUNCTIONS
+
+/// Decodes every word in a contiguous range of bytes, such as a section of
+/// memory, as a little endian instruction, from the start of the range.
+/// Words that fail to decode are `None`, as is any partial word at the end.
+pub fn decode_all(bytes: &[u8]) -> Vec<Option<Instruction>> {
+    bytes
+        .chunks(4)
+        .map(|chunk| match chunk {
+            [b0, b1, b2, b3] => Instruction::decode(i32::from_le_bytes([*b0, *b1, *b2, *b3])),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Renders the lower four bits of a `fence` instruction's predecessor or
+/// successor set as the letters of the accesses it orders, e.g. `iorw`.
+fn fence_set(bits: i32) -> String {
+    let set: String = ['i', 'o', 'r', 'w']
+        .iter()
+        .enumerate()
+        .filter(|(n, _)| bits & (0b1000 >> n) != 0)
+        .map(|(_, c)| *c)
+        .collect();
+    if set.is_empty() {
+        String::from("0")
+    } else {
+        set
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// TESTS
+
+#[cfg(test)]
+mod tests {
+    use super::assembly::parse_instruction;
+    use super::*;
+
+    #[test]
+    fn disassembles_one_of_each_format() {
+        #[rustfmt::skip]
+        let cases = [
+            ("add x10 x11 x12",    "add a0, a1, a2"),
+            ("addi x10 x2 -16",    "addi a0, sp, -16"),
+            ("lw x10 x2 8",        "lw a0, 8(sp)"),
+            ("jalr x0 x1 0",       "jalr zero, 0(ra)"),
+            ("srai x5 x6 31",      "srai t0, t1, 31"),
+            ("sw x2 x10 12",       "sw a0, 12(sp)"),
+            ("beq x10 x0 -12",     "beq a0, zero, -12"),
+            ("lui x10 0x12345",    "lui a0, 0x12345"),
+            ("auipc x3 0xfffff",   "auipc gp, 0xfffff"),
+            ("jal x1 2048",        "jal ra, 2048"),
+            ("amoadd.w x10 x6 x7", "amoadd.w a0, t2, (t1)"),
+            ("ecall",              "ecall"),
+        ];
+        for (line, expected) in cases.iter() {
+            // Disassembled as in the disasm mode, from the encoded word
+            let word = parse_instruction(line).unwrap().encode();
+            let instruction = Instruction::decode(word).unwrap();
+            assert_eq!(instruction.disassemble(), *expected);
+        }
+    }
+
+    #[test]
+    fn decodes_all_words_of_blob() {
+        #[rustfmt::skip]
+        let bytes = [
+            0x13, 0x05, 0x10, 0x00, // addi a0, zero, 1
+            0x00, 0x00, 0x00, 0x00, // illegal
+            0x67, 0x80, 0x00, 0x00, // jalr zero, 0(ra)
+            0x13, 0x05,             // partial word
+        ];
+        let decoded: Vec<Option<String>> = decode_all(&bytes)
+            .iter()
+            .map(|i| i.as_ref().map(|i| i.disassemble()))
+            .collect();
+        assert_eq!(
+            decoded,
+            vec![
+                Some(String::from("addi a0, zero, 1")),
+                None,
+                Some(String::from("jalr zero, 0(ra)")),
+                None,
+            ]
+        );
+        assert!(decode_all(&[]).is_empty());
+    }
 }
@@ -1,11 +1,12 @@
 use std::fmt;
+use std::str::FromStr;
 
 use super::Format;
 
 ///////////////////////////////////////////////////////////////////////////////
 //// ENUMS
 
-/// An enum of all the different base opcodes that are provided by `rv32im`.
+/// An enum of all the different base opcodes that are provided by `rv32ima`.
 ///
 ///  - These are always in bits `6-0` of the instruction, and should have
 ///    `11` in bits `0` and `1`.
@@ -24,14 +25,15 @@ pub enum BaseCode {
     JALR,
     JAL,
     SYSTEM,
+    AMO,
 }
 
-/// An enum of all the different operations that are provided by `rv32im`.
+/// An enum of all the different operations that are provided by `rv32ima`.
 ///
 /// These can be parse from a mixture of the `BaseCode` and/or the function
 /// code(s) within the instruction. Therefore, these are not necessarily
 /// derived from one contiguous bit-range within the instruction.
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Operation {
     LUI,
     AUIPC,
@@ -74,6 +76,9 @@ pub enum Operation {
     FENCEI,
     ECALL,
     EBREAK,
+    MRET,
+    SRET,
+    WFI,
     CSRRW,
     CSRRS,
     CSRRC,
@@ -88,6 +93,17 @@ pub enum Operation {
     DIVU,
     REM,
     REMU,
+    LRW,
+    SCW,
+    AMOSWAPW,
+    AMOADDW,
+    AMOXORW,
+    AMOANDW,
+    AMOORW,
+    AMOMINW,
+    AMOMAXW,
+    AMOMINUW,
+    AMOMAXUW,
 }
 
 ///////////////////////////////////////////////////////////////////////////////
@@ -103,6 +119,14 @@ pub trait Decodable {
         Self: Sized;
 }
 
+/// Trait for objects that can encode their internal representation back into
+/// the bits of an instruction word.
+pub trait Encodable {
+    /// Encodes the internal representation into a word, with all of the bits
+    /// that are not represented by this object left as 0.
+    fn encode(&self) -> i32;
+}
+
 ///////////////////////////////////////////////////////////////////////////////
 //// IMPLEMENTATIONS
 
@@ -123,6 +147,7 @@ impl fmt::Display for BaseCode {
             BaseCode::JALR    => f.pad("JALR"),
             BaseCode::JAL     => f.pad("JAL"),
             BaseCode::SYSTEM  => f.pad("SYSTEM"),
+            BaseCode::AMO     => f.pad("AMO"),
         }
     }
 }
@@ -173,6 +198,9 @@ impl From<Operation> for BaseCode {
             Operation::FENCEI => BaseCode::MISCMEM,
             Operation::ECALL  => BaseCode::SYSTEM,
             Operation::EBREAK => BaseCode::SYSTEM,
+            Operation::MRET   => BaseCode::SYSTEM,
+            Operation::SRET   => BaseCode::SYSTEM,
+            Operation::WFI    => BaseCode::SYSTEM,
             Operation::CSRRW  => BaseCode::SYSTEM,
             Operation::CSRRS  => BaseCode::SYSTEM,
             Operation::CSRRC  => BaseCode::SYSTEM,
@@ -187,6 +215,17 @@ impl From<Operation> for BaseCode {
             Operation::DIVU   => BaseCode::OP,
             Operation::REM    => BaseCode::OP,
             Operation::REMU   => BaseCode::OP,
+            Operation::LRW      => BaseCode::AMO,
+            Operation::SCW      => BaseCode::AMO,
+            Operation::AMOSWAPW => BaseCode::AMO,
+            Operation::AMOADDW  => BaseCode::AMO,
+            Operation::AMOXORW  => BaseCode::AMO,
+            Operation::AMOANDW  => BaseCode::AMO,
+            Operation::AMOORW   => BaseCode::AMO,
+            Operation::AMOMINW  => BaseCode::AMO,
+            Operation::AMOMAXW  => BaseCode::AMO,
+            Operation::AMOMINUW => BaseCode::AMO,
+            Operation::AMOMAXUW => BaseCode::AMO,
         }
     }
 }
@@ -206,11 +245,32 @@ impl Decodable for BaseCode {
             0x67 => Some(BaseCode::JALR),
             0x6F => Some(BaseCode::JAL),
             0x73 => Some(BaseCode::SYSTEM),
+            0x2f => Some(BaseCode::AMO),
             _    => None, // Unrecognised
         }
     }
 }
 
+impl Encodable for BaseCode {
+    #[rustfmt::skip]
+    fn encode(&self) -> i32 {
+        match self {
+            BaseCode::LOAD    => 0x03,
+            BaseCode::MISCMEM => 0x0f,
+            BaseCode::OPIMM   => 0x13,
+            BaseCode::AUIPC   => 0x17,
+            BaseCode::STORE   => 0x23,
+            BaseCode::OP      => 0x33,
+            BaseCode::LUI     => 0x37,
+            BaseCode::BRANCH  => 0x63,
+            BaseCode::JALR    => 0x67,
+            BaseCode::JAL     => 0x6F,
+            BaseCode::SYSTEM  => 0x73,
+            BaseCode::AMO     => 0x2f,
+        }
+    }
+}
+
 impl BaseCode {
     /// Checks if the instruction format has a destination register encoded
     /// within it, as per the `rv32im` specification.
@@ -314,6 +374,9 @@ impl fmt::Display for Operation {
             Operation::FENCEI => f.pad("fencei"),
             Operation::ECALL  => f.pad("ecall"),
             Operation::EBREAK => f.pad("ebreak"),
+            Operation::MRET   => f.pad("mret"),
+            Operation::SRET   => f.pad("sret"),
+            Operation::WFI    => f.pad("wfi"),
             Operation::CSRRW  => f.pad("csrrw"),
             Operation::CSRRS  => f.pad("csrrs"),
             Operation::CSRRC  => f.pad("csrrc"),
@@ -328,10 +391,154 @@ impl fmt::Display for Operation {
             Operation::DIVU   => f.pad("divu"),
             Operation::REM    => f.pad("rem"),
             Operation::REMU   => f.pad("remu"),
+            Operation::LRW      => f.pad("lr.w"),
+            Operation::SCW      => f.pad("sc.w"),
+            Operation::AMOSWAPW => f.pad("amoswap.w"),
+            Operation::AMOADDW  => f.pad("amoadd.w"),
+            Operation::AMOXORW  => f.pad("amoxor.w"),
+            Operation::AMOANDW  => f.pad("amoand.w"),
+            Operation::AMOORW   => f.pad("amoor.w"),
+            Operation::AMOMINW  => f.pad("amomin.w"),
+            Operation::AMOMAXW  => f.pad("amomax.w"),
+            Operation::AMOMINUW => f.pad("amominu.w"),
+            Operation::AMOMAXUW => f.pad("amomaxu.w"),
         }
     }
 }
 
+impl FromStr for Operation {
+    type Err = ();
+
+    /// Parses an operation from its (case insensitive) assembly mnemonic, as
+    /// printed by the `Display` implementation.
+    #[rustfmt::skip]
+    fn from_str(mnemonic: &str) -> Result<Operation, ()> {
+        match mnemonic.to_lowercase().as_str() {
+            "lui"     => Ok(Operation::LUI),
+            "auipc"   => Ok(Operation::AUIPC),
+            "jal"     => Ok(Operation::JAL),
+            "jalr"    => Ok(Operation::JALR),
+            "beq"     => Ok(Operation::BEQ),
+            "bne"     => Ok(Operation::BNE),
+            "blt"     => Ok(Operation::BLT),
+            "bge"     => Ok(Operation::BGE),
+            "bltu"    => Ok(Operation::BLTU),
+            "bgeu"    => Ok(Operation::BGEU),
+            "lb"      => Ok(Operation::LB),
+            "lh"      => Ok(Operation::LH),
+            "lw"      => Ok(Operation::LW),
+            "lbu"     => Ok(Operation::LBU),
+            "lhu"     => Ok(Operation::LHU),
+            "sb"      => Ok(Operation::SB),
+            "sh"      => Ok(Operation::SH),
+            "sw"      => Ok(Operation::SW),
+            "addi"    => Ok(Operation::ADDI),
+            "slti"    => Ok(Operation::SLTI),
+            "sltiu"   => Ok(Operation::SLTIU),
+            "xori"    => Ok(Operation::XORI),
+            "ori"     => Ok(Operation::ORI),
+            "andi"    => Ok(Operation::ANDI),
+            "slli"    => Ok(Operation::SLLI),
+            "srli"    => Ok(Operation::SRLI),
+            "srai"    => Ok(Operation::SRAI),
+            "add"     => Ok(Operation::ADD),
+            "sub"     => Ok(Operation::SUB),
+            "sll"     => Ok(Operation::SLL),
+            "slt"     => Ok(Operation::SLT),
+            "sltu"    => Ok(Operation::SLTU),
+            "xor"     => Ok(Operation::XOR),
+            "srl"     => Ok(Operation::SRL),
+            "sra"     => Ok(Operation::SRA),
+            "or"      => Ok(Operation::OR),
+            "and"     => Ok(Operation::AND),
+            "fence"   => Ok(Operation::FENCE),
+            "fencei"  => Ok(Operation::FENCEI),
+            "ecall"   => Ok(Operation::ECALL),
+            "ebreak"  => Ok(Operation::EBREAK),
+            "mret"    => Ok(Operation::MRET),
+            "sret"    => Ok(Operation::SRET),
+            "wfi"     => Ok(Operation::WFI),
+            "csrrw"   => Ok(Operation::CSRRW),
+            "csrrs"   => Ok(Operation::CSRRS),
+            "csrrc"   => Ok(Operation::CSRRC),
+            "csrrwi"  => Ok(Operation::CSRRWI),
+            "csrrsi"  => Ok(Operation::CSRRSI),
+            "csrrci"  => Ok(Operation::CSRRCI),
+            "mul"     => Ok(Operation::MUL),
+            "mulh"    => Ok(Operation::MULH),
+            "mulhsu"  => Ok(Operation::MULHSU),
+            "mulhu"   => Ok(Operation::MULHU),
+            "div"     => Ok(Operation::DIV),
+            "divu"    => Ok(Operation::DIVU),
+            "rem"     => Ok(Operation::REM),
+            "remu"    => Ok(Operation::REMU),
+            "lr.w"      => Ok(Operation::LRW),
+            "sc.w"      => Ok(Operation::SCW),
+            "amoswap.w" => Ok(Operation::AMOSWAPW),
+            "amoadd.w"  => Ok(Operation::AMOADDW),
+            "amoxor.w"  => Ok(Operation::AMOXORW),
+            "amoand.w"  => Ok(Operation::AMOANDW),
+            "amoor.w"   => Ok(Operation::AMOORW),
+            "amomin.w"  => Ok(Operation::AMOMINW),
+            "amomax.w"  => Ok(Operation::AMOMAXW),
+            "amominu.w" => Ok(Operation::AMOMINUW),
+            "amomaxu.w" => Ok(Operation::AMOMAXUW),
+            _           => Err(()),
+        }
+    }
+}
+
+impl Operation {
+    /// Checks if the operation is a conditional branch.
+    pub fn is_branch(self) -> bool {
+        BaseCode::from(self) == BaseCode::BRANCH
+    }
+
+    /// Checks if the operation is an unconditional jump, with or without a
+    /// register base.
+    pub fn is_jump(self) -> bool {
+        match BaseCode::from(self) {
+            BaseCode::JAL | BaseCode::JALR => true,
+            _ => false,
+        }
+    }
+
+    /// Checks if the operation is a branch or jump, which the pipeline
+    /// speculates past until it is committed.
+    pub fn is_control(self) -> bool {
+        self.is_branch() || self.is_jump()
+    }
+
+    /// Checks if the operation is a load, not including `lr.w`.
+    pub fn is_load(self) -> bool {
+        BaseCode::from(self) == BaseCode::LOAD
+    }
+
+    /// Checks if the operation is a store, not including `sc.w`.
+    pub fn is_store(self) -> bool {
+        BaseCode::from(self) == BaseCode::STORE
+    }
+
+    /// Checks if the operation is an atomic memory operation, including
+    /// `lr.w` and `sc.w`.
+    pub fn is_atomic(self) -> bool {
+        BaseCode::from(self) == BaseCode::AMO
+    }
+
+    /// Checks if the operation accesses main memory, so needs one of the
+    /// memory ports whilst it executes. Fences only order memory, so are not
+    /// included.
+    pub fn is_memory(self) -> bool {
+        self.is_load() || self.is_store() || self.is_atomic()
+    }
+
+    /// Checks if the operation is a system instruction, such as an
+    /// environment call, trap return or CSR access.
+    pub fn is_system(self) -> bool {
+        BaseCode::from(self) == BaseCode::SYSTEM
+    }
+}
+
 impl Decodable for Operation {
     fn from_instruction(instruction: i32) -> Option<Operation> {
         // To match Function Code, we first need the base code
@@ -435,11 +642,32 @@ impl Decodable for Operation {
                 _ => None, // Unrecognised funct 3
             },
             BaseCode::JAL => Some(Operation::JAL),
+            BaseCode::AMO => match funct3 {
+                // Ignore the aq/rl ordering bits, only match on funct5
+                0x2 => match funct7 >> 2 {
+                    0x00 => Some(Operation::AMOADDW),
+                    0x01 => Some(Operation::AMOSWAPW),
+                    0x02 => Some(Operation::LRW),
+                    0x03 => Some(Operation::SCW),
+                    0x04 => Some(Operation::AMOXORW),
+                    0x08 => Some(Operation::AMOORW),
+                    0x0c => Some(Operation::AMOANDW),
+                    0x10 => Some(Operation::AMOMINW),
+                    0x14 => Some(Operation::AMOMAXW),
+                    0x18 => Some(Operation::AMOMINUW),
+                    0x1c => Some(Operation::AMOMAXUW),
+                    _ => None, // Unrecognised funct5
+                },
+                _ => None, // Unrecognised funct3
+            },
             BaseCode::SYSTEM => match funct3 {
                 0x0 => match instruction >> 20 {
                     // Ambiguous Case (PRIV); Match on funct12
                     0x0 => Some(Operation::ECALL),
                     0x1 => Some(Operation::EBREAK),
+                    0x102 => Some(Operation::SRET),
+                    0x105 => Some(Operation::WFI),
+                    0x302 => Some(Operation::MRET),
                     _ => None, // Unrecognised funct12
                 },
                 0x1 => Some(Operation::CSRRW),
@@ -453,3 +681,165 @@ impl Decodable for Operation {
         }
     }
 }
+
+impl Encodable for Operation {
+    /// Encodes the operation into the base code and function code bits of an
+    /// instruction word. Note that the distinction between `ECALL`, `EBREAK`
+    /// and the privileged instructions is held within the immediate, and so
+    /// is not encoded here.
+    fn encode(&self) -> i32 {
+        let base_code = BaseCode::from(*self);
+        #[rustfmt::skip]
+        let (funct3, funct7) = match self {
+            Operation::LUI    => (0x0, 0x00),
+            Operation::AUIPC  => (0x0, 0x00),
+            Operation::JAL    => (0x0, 0x00),
+            Operation::JALR   => (0x0, 0x00),
+            Operation::BEQ    => (0x0, 0x00),
+            Operation::BNE    => (0x1, 0x00),
+            Operation::BLT    => (0x4, 0x00),
+            Operation::BGE    => (0x5, 0x00),
+            Operation::BLTU   => (0x6, 0x00),
+            Operation::BGEU   => (0x7, 0x00),
+            Operation::LB     => (0x0, 0x00),
+            Operation::LH     => (0x1, 0x00),
+            Operation::LW     => (0x2, 0x00),
+            Operation::LBU    => (0x4, 0x00),
+            Operation::LHU    => (0x5, 0x00),
+            Operation::SB     => (0x0, 0x00),
+            Operation::SH     => (0x1, 0x00),
+            Operation::SW     => (0x2, 0x00),
+            Operation::ADDI   => (0x0, 0x00),
+            Operation::SLTI   => (0x2, 0x00),
+            Operation::SLTIU  => (0x3, 0x00),
+            Operation::XORI   => (0x4, 0x00),
+            Operation::ORI    => (0x6, 0x00),
+            Operation::ANDI   => (0x7, 0x00),
+            Operation::SLLI   => (0x1, 0x00),
+            Operation::SRLI   => (0x5, 0x00),
+            Operation::SRAI   => (0x5, 0x20),
+            Operation::ADD    => (0x0, 0x00),
+            Operation::SUB    => (0x0, 0x20),
+            Operation::SLL    => (0x1, 0x00),
+            Operation::SLT    => (0x2, 0x00),
+            Operation::SLTU   => (0x3, 0x00),
+            Operation::XOR    => (0x4, 0x00),
+            Operation::SRL    => (0x5, 0x00),
+            Operation::SRA    => (0x5, 0x20),
+            Operation::OR     => (0x6, 0x00),
+            Operation::AND    => (0x7, 0x00),
+            Operation::FENCE  => (0x0, 0x00),
+            Operation::FENCEI => (0x1, 0x00),
+            Operation::ECALL  => (0x0, 0x00),
+            Operation::EBREAK => (0x0, 0x00),
+            Operation::MRET   => (0x0, 0x00),
+            Operation::SRET   => (0x0, 0x00),
+            Operation::WFI    => (0x0, 0x00),
+            Operation::CSRRW  => (0x1, 0x00),
+            Operation::CSRRS  => (0x2, 0x00),
+            Operation::CSRRC  => (0x3, 0x00),
+            Operation::CSRRWI => (0x5, 0x00),
+            Operation::CSRRSI => (0x6, 0x00),
+            Operation::CSRRCI => (0x7, 0x00),
+            Operation::MUL    => (0x0, 0x01),
+            Operation::MULH   => (0x1, 0x01),
+            Operation::MULHSU => (0x2, 0x01),
+            Operation::MULHU  => (0x3, 0x01),
+            Operation::DIV    => (0x4, 0x01),
+            Operation::DIVU   => (0x5, 0x01),
+            Operation::REM    => (0x6, 0x01),
+            Operation::REMU   => (0x7, 0x01),
+            Operation::LRW      => (0x2, 0x08),
+            Operation::SCW      => (0x2, 0x0c),
+            Operation::AMOSWAPW => (0x2, 0x04),
+            Operation::AMOADDW  => (0x2, 0x00),
+            Operation::AMOXORW  => (0x2, 0x10),
+            Operation::AMOANDW  => (0x2, 0x30),
+            Operation::AMOORW   => (0x2, 0x20),
+            Operation::AMOMINW  => (0x2, 0x40),
+            Operation::AMOMAXW  => (0x2, 0x50),
+            Operation::AMOMINUW => (0x2, 0x60),
+            Operation::AMOMAXUW => (0x2, 0x70),
+        };
+        let mut word = base_code.encode();
+        if base_code.has_funct_code() {
+            word |= funct3 << 12;
+        }
+        if base_code.may_have_funct7() {
+            word |= funct7 << 25;
+        }
+        word
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// TESTS
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isa::Format;
+    use crate::simulator::execute::UnitType;
+
+    /// Every operation, in the order they are declared.
+    #[rustfmt::skip]
+    const OPERATIONS: [Operation; 69] = [
+        Operation::LUI, Operation::AUIPC, Operation::JAL, Operation::JALR, Operation::BEQ,
+        Operation::BNE, Operation::BLT, Operation::BGE, Operation::BLTU, Operation::BGEU,
+        Operation::LB, Operation::LH, Operation::LW, Operation::LBU, Operation::LHU, Operation::SB,
+        Operation::SH, Operation::SW, Operation::ADDI, Operation::SLTI, Operation::SLTIU,
+        Operation::XORI, Operation::ORI, Operation::ANDI, Operation::SLLI, Operation::SRLI,
+        Operation::SRAI, Operation::ADD, Operation::SUB, Operation::SLL, Operation::SLT,
+        Operation::SLTU, Operation::XOR, Operation::SRL, Operation::SRA, Operation::OR,
+        Operation::AND, Operation::FENCE, Operation::FENCEI, Operation::ECALL, Operation::EBREAK,
+        Operation::MRET, Operation::SRET, Operation::WFI, Operation::CSRRW, Operation::CSRRS,
+        Operation::CSRRC, Operation::CSRRWI, Operation::CSRRSI, Operation::CSRRCI, Operation::MUL,
+        Operation::MULH, Operation::MULHSU, Operation::MULHU, Operation::DIV, Operation::DIVU,
+        Operation::REM, Operation::REMU, Operation::LRW, Operation::SCW, Operation::AMOSWAPW,
+        Operation::AMOADDW, Operation::AMOXORW, Operation::AMOANDW, Operation::AMOORW,
+        Operation::AMOMINW, Operation::AMOMAXW, Operation::AMOMINUW, Operation::AMOMAXUW,
+    ];
+
+    #[test]
+    fn classifiers_agree_with_unit_and_format() {
+        for &op in OPERATIONS.iter() {
+            let unit = UnitType::from(op);
+            let format = Format::from(op);
+            let classes = [op.is_branch(), op.is_jump(), op.is_memory(), op.is_system()];
+            assert!(classes.iter().filter(|c| **c).count() <= 1, "{:?}", op);
+
+            // Exactly the branches and jumps go to the branch logic units
+            assert_eq!(op.is_control(), unit == UnitType::BLU, "{:?}", op);
+            assert_eq!(op.is_branch(), format == Format::B, "{:?}", op);
+            if op.is_jump() {
+                assert!(format == Format::J || format == Format::I, "{:?}", op);
+            }
+
+            // Memory accesses, fences and system instructions go to the
+            // memory and control units
+            let fence = op == Operation::FENCE || op == Operation::FENCEI;
+            assert_eq!(
+                op.is_memory() || op.is_system() || fence,
+                unit == UnitType::MCU,
+                "{:?}",
+                op
+            );
+            assert_eq!(op.is_store(), format == Format::S, "{:?}", op);
+            if op.is_load() || op.is_system() {
+                assert_eq!(format, Format::I, "{:?}", op);
+            }
+            if op.is_atomic() {
+                assert_eq!(format, Format::R, "{:?}", op);
+            }
+            assert_eq!(
+                op.is_memory(),
+                op.is_load() || op.is_store() || op.is_atomic(),
+                "{:?}",
+                op
+            );
+        }
+        assert!(Operation::LRW.is_atomic() && !Operation::LRW.is_load());
+        assert!(Operation::SCW.is_atomic() && !Operation::SCW.is_store());
+        assert!(!Operation::FENCE.is_memory());
+    }
+}
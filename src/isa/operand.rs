@@ -155,6 +155,21 @@ impl From<i32> for Register {
 }
 
 impl Register {
+    /// Parses a register from either its numeric (`x0`..`x31`, `pc`) or ABI
+    /// (`zero`, `ra`, `sp`, ...) name, i.e. the inverse of both forms of
+    /// `Display`. The frame pointer `fp` is also accepted as an alias for
+    /// `s0`/`x8`. Names are case insensitive.
+    /// Returns None on a failure.
+    pub fn from_name(name: &str) -> Option<Register> {
+        let name = name.to_lowercase();
+        if name == "fp" {
+            return Some(Register::X8);
+        }
+        (0..=32)
+            .map(Register::from)
+            .find(|r| format!("{}", r) == name || format!("{:#}", r) == name)
+    }
+
     /// Decodes a given register operand out of a full instruction word, into
     /// an internal representation.
     /// Returns None on a failure.
@@ -222,6 +237,30 @@ pub fn extract_immediate(instruction: i32) -> Option<i32> {
     }
 }
 
+/// Encodes the given immediate into the bits of an instruction word of the
+/// given format, i.e. the inverse of `extract_immediate`. All other bits are
+/// left as 0.
+pub fn encode_immediate(format: Format, imm: i32) -> i32 {
+    match format {
+        Format::R => 0,
+        Format::I => (imm & 0xfff) << 20,
+        Format::S => ((imm & 0xfe0) << 20) | ((imm & 0x1f) << 7),
+        Format::B => {
+            ((imm & 0x1000) << 19)
+                | ((imm & 0x7e0) << 20)
+                | ((imm & 0x1e) << 7)
+                | ((imm & 0x800) >> 4)
+        }
+        Format::U => imm & !0xfff,
+        Format::J => {
+            ((imm & 0x10_0000) << 11)
+                | ((imm & 0x7fe) << 20)
+                | ((imm & 0x800) << 9)
+                | (imm & 0xf_f000)
+        }
+    }
+}
+
 /// Machinery to extract bits of the immediate from an instruction, and place
 /// them in a word containing a partially complete immediate.
 ///
@@ -279,3 +318,46 @@ fn sign_extend_from_msb(msb: u8, word: i32) -> i32 {
         word
     }
 }
+
+///////////////////////////////////////////////////////////////////////////////
+//// TESTS
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The ABI name of every register, in order from `x0`.
+    #[rustfmt::skip]
+    const ABI_NAMES: [&str; 32] = [
+        "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2",
+        "s0",   "s1", "a0", "a1", "a2", "a3", "a4", "a5",
+        "a6",   "a7", "s2", "s3", "s4", "s5", "s6", "s7",
+        "s8",   "s9", "s10", "s11", "t3", "t4", "t5", "t6",
+    ];
+
+    #[test]
+    fn parses_both_names_of_every_register() {
+        for (n, abi) in ABI_NAMES.iter().enumerate() {
+            let reg = Register::from(n as i32);
+            assert_eq!(Register::from_name(&format!("x{}", n)), Some(reg));
+            assert_eq!(Register::from_name(abi), Some(reg));
+            assert_eq!(Register::from_name(&abi.to_uppercase()), Some(reg));
+            assert_eq!(format!("{:#}", reg), *abi);
+        }
+        assert_eq!(Register::from_name("pc"), Some(Register::PC));
+    }
+
+    #[test]
+    fn parses_frame_pointer_as_s0() {
+        assert_eq!(Register::from_name("fp"), Some(Register::X8));
+        assert_eq!(Register::from_name("s0"), Some(Register::X8));
+        assert_eq!(Register::from_name("x8"), Some(Register::X8));
+    }
+
+    #[test]
+    fn rejects_unknown_names() {
+        for name in ["", "x32", "x-1", "a8", "t7", "s12", "r1", " x1"].iter() {
+            assert_eq!(Register::from_name(name), None, "{:?}", name);
+        }
+    }
+}
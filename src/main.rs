@@ -7,7 +7,7 @@
 //! ![Project Daybreak Simulator Diagram](https://github.com/AnthonyWharton/AdvancedComputerArchitecture/raw/master/resources/diagram.png)
 
 use crate::io::IoThread;
-use crate::util::config::Config;
+use crate::util::config::{Command, Config};
 
 ///////////////////////////////////////////////////////////////////////////////
 //// EXTERNAL MODULES
@@ -32,7 +32,50 @@ mod simulator;
 fn main() {
     util::panic::set_panic_hook();
     let config = Config::create_from_args();
-    let io = IoThread::new();
-    simulator::run_simulator(io, &config);
-    println!("Goodbye!\r");
+    if config.elf_info {
+        util::loader::print_elf_info(&config);
+        return;
+    }
+    if config.dry_run {
+        simulator::dry_run(&config);
+        return;
+    }
+    let exit_code = match config.command {
+        Command::Disasm => {
+            io::headless::run_disasm(&config);
+            None
+        }
+        Command::Trace => io::headless::run_trace(&config),
+        Command::Asm => {
+            io::headless::run_asm(&config);
+            None
+        }
+        Command::Bench => {
+            io::headless::run_bench(&config);
+            None
+        }
+        Command::Run => {
+            let exit_code = if config.repl {
+                io::repl::run_repl(&config);
+                None
+            } else if config.interactive_step.is_some() {
+                io::headless::headless_interactive(&config)
+            } else {
+                let io = IoThread::new(
+                    config.history,
+                    config.mem_dump.clone(),
+                    config.follow_pc,
+                    config.start_paused,
+                    config.theme,
+                );
+                simulator::run_simulator(io, &config)
+            };
+            println!("Goodbye!\r");
+            exit_code
+        }
+    };
+    // Pass on the exit code of the simulated program, for use in scripts
+    if let Some(code) = exit_code {
+        std::process::exit(code);
+    }
 }
@@ -1,11 +1,20 @@
-use crate::isa::Instruction;
-use crate::isa::op_code::Operation;
+use std::collections::HashMap;
+
+use either::{Either, Left};
+
+use crate::isa::{Format, Instruction};
+use crate::isa::op_code::{BaseCode, Operation};
 use crate::isa::operand::Register;
 use crate::util::config::Config;
 
-use super::memory::Access;
+use super::commit::{amo_result, SYSCALL_READ, SYSCALL_WRITE};
+use super::execute::{alu_i_result, alu_r_result, UnitType};
+use super::fetch::LatchFetch;
+use super::load::{access_size, MemoryDisambiguation};
+use super::memory::{effective_address, in_uart, Access, Memory};
 use super::register::RegisterFile;
 use super::reorder::ReorderEntry;
+use super::state::Stats;
 
 ///////////////////////////////////////////////////////////////////////////////
 //// CONST/STATIC
@@ -14,6 +23,11 @@ use super::reorder::ReorderEntry;
 /// *MUST* be a power of two.
 const TWO_LEVEL: u8 = 1 << 3;
 
+/// The alignment, in bytes, that the targets of control transfer instructions
+/// must have. Without the compressed instruction (C) extension, which is not
+/// supported, this is a whole instruction.
+pub const INSTRUCTION_ALIGNMENT: i32 = 4;
+
 ///////////////////////////////////////////////////////////////////////////////
 //// ENUMS
 
@@ -28,6 +42,10 @@ pub enum BranchPredictorMode {
     TwoBit,
     /// Two Level adaptive 3 bit predictor enabled.
     TwoLevel,
+    /// Perfect prediction enabled, running the program ahead of the pipeline
+    /// to resolve branches at fetch time. Only for finding the upper bound on
+    /// performance, as real hardware cannot do this.
+    Oracle,
 }
 
 /// The branch prediction FSM state.
@@ -64,6 +82,9 @@ pub struct BranchPredictor {
     pub return_stack_d: Option<Vec<usize>>,
     /// The clean return address stack.
     pub return_stack_c: Option<Vec<usize>>,
+    /// The maximum number of return addresses either return stack holds,
+    /// where 0 is unbounded. When full, the oldest address is dropped.
+    pub ras_depth: usize,
     /// The global saturating counter finite state machine for branch
     /// prediction choices.
     pub saturating_counter: BranchState,
@@ -71,6 +92,57 @@ pub struct BranchPredictor {
     pub two_level_counter: Vec<BranchState>,
     /// The branch history for the two level prediction.
     pub two_level_history: u8,
+    /// The program as run ahead of the pipeline, if needed by the oracle or
+    /// by perfect memory disambiguation.
+    pub oracle: Option<OracleModel>,
+}
+
+/// The branch predictor's outcome for a single fetched instruction on the
+/// predicted path.
+#[derive(Copy, Clone, Debug)]
+pub struct Prediction {
+    /// The return stack operation and branch history to go with the
+    /// instruction.
+    pub bp_data: (ReturnStackOp, u8),
+    /// The address in memory that the instruction accesses, if it is a memory
+    /// operation whose address was known by running the program ahead.
+    pub mem_addr: Option<usize>,
+}
+
+/// A functional model of the program that the oracle runs ahead of the
+/// pipeline, executing each instruction in program order as it is fetched, so
+/// that the outcome of every branch (and the address of every memory access)
+/// is known before it is executed. It is synchronised with the committed
+/// state whenever the pipeline is cleared.
+#[derive(Clone)]
+pub struct OracleModel {
+    /// The value of each register, or `None` where it cannot be known ahead
+    /// of the pipeline, such as the result of a CSR instruction.
+    pub registers: Vec<Option<i32>>,
+    /// The bytes written by the stores that the model has run, which may not
+    /// yet have been committed to memory.
+    pub stores: HashMap<usize, u8>,
+    /// Whether memory can be read ahead of the pipeline, which it cannot once
+    /// a system call may have read program input into it.
+    pub memory_known: bool,
+    /// Whether the model must be synchronised with the committed state before
+    /// it next runs, as the pipeline has been cleared.
+    pub stale: bool,
+    /// The address of the memory-mapped UART, if any, which is not memory.
+    pub uart_addr: Option<usize>,
+}
+
+/// A branch predictor that runs alongside the real one without driving the
+/// _fetch_ stage, observing the same committed branches so as to measure how
+/// accurate it would have been in another mode.
+#[derive(Clone)]
+pub struct ShadowPredictor {
+    /// The shadowing branch predictor, of which only the counters are used.
+    pub predictor: BranchPredictor,
+    /// The number of branches this predictor would have predicted correctly.
+    pub success: u64,
+    /// The number of branches this predictor would have mispredicted.
+    pub failure: u64,
 }
 
 ///////////////////////////////////////////////////////////////////////////////
@@ -93,9 +165,17 @@ impl BranchPredictor {
             } else {
                 None
             },
+            ras_depth: config.ras_depth,
             saturating_counter: BranchState::default(),
             two_level_counter: vec![BranchState::default(); TWO_LEVEL as usize],
             two_level_history: 0b0000,
+            oracle: if config.branch_prediction == BranchPredictorMode::Oracle
+                || config.mem_disambig == MemoryDisambiguation::Perfect
+            {
+                Some(OracleModel::new(config.uart_addr))
+            } else {
+                None
+            },
         }
     }
 
@@ -108,51 +188,140 @@ impl BranchPredictor {
 
     /// The feedback from the _fetch_ stage as to last instructions that were
     /// loaded from memory, used to make the next prediction. Returns the
-    /// return address stack operations for the instructions that were fetched.
+    /// predictions for the instructions that were fetched, up to and including
+    /// the first predicted branch. Any instructions past this are not on the
+    /// predicted path, and should be discarded.
+    ///
+    /// If the program is run ahead of the pipeline, the fetched instructions
+    /// are run on its model, reading memory through the given `memory`. Only
+    /// for the values that the model cannot know does it fall back to the
+    /// register file, where the instructions already waiting in the fetch
+    /// queue tell it which register values are yet to be written.
     pub fn predict(
         &mut self,
         n_way: usize,
         next_instrs: &Vec<Access<i32>>,
         rf: &RegisterFile,
-    ) -> Vec<(ReturnStackOp, u8)>{
-        if self.mode != BranchPredictorMode::Off {
-            let mut bp_data = vec![];
-            for raw in next_instrs.iter() {
-                let instr = match Instruction::decode(raw.word) {
-                    Some(instr) => instr,
-                    None => {
-                        break
-                    }
-                };
+        queued: &LatchFetch,
+        memory: &Memory,
+        stats: &mut Stats,
+    ) -> Vec<Prediction> {
+        let predict = |bp_data, mem_addr| Prediction { bp_data, mem_addr };
+        if self.mode == BranchPredictorMode::Off && self.oracle.is_none() {
+            self.lc += 4 * n_way;
+            return vec![predict((ReturnStackOp::None, 0), None); n_way];
+        }
 
-                // If return stack optimisation is used and provides a
-                // prediction, use it.
-                let (rs_op, rs_pred) = self.process_return_address(instr, self.lc);
-               bp_data.push((rs_op, self.two_level_history));
-                if let Some(pc) = rs_pred {
-                    self.lc = pc;
-                    break;
+        // Registers that older instructions are yet to write to
+        let mut pending: Vec<Register> = match self.oracle.as_mut() {
+            Some(model) => {
+                if model.stale {
+                    model.sync(rf);
                 }
+                queued
+                    .queue
+                    .iter()
+                    .filter_map(|e| Instruction::decode(e.access.word))
+                    .filter_map(|i| i.rd)
+                    .collect()
+            }
+            None => vec![],
+        };
 
-                // Otherwise, stick with usual branch prediction method
-                let (brk, pc) = self.process_saturating_counter(instr, rf);
-                self.lc = pc;
-                if brk {
-                    break
+        let mut predictions = vec![];
+        for raw in next_instrs.iter() {
+            let instr = match Instruction::decode(raw.word) {
+                Some(instr) => instr,
+                None => {
+                    // Leave it for the decode stage to deal with
+                    predictions.push(predict((ReturnStackOp::None, self.two_level_history), None));
+                    self.lc += 4;
+                    continue
+                }
+            };
+
+            // Run the instruction ahead of the pipeline, if needed
+            let (rs1, rs2, mem_addr) = match self.oracle.as_mut() {
+                Some(model) => {
+                    let rs1 = model.operand(instr.rs1, rf, &pending);
+                    let rs2 = model.operand(instr.rs2, rf, &pending);
+                    let mem_addr = model.execute(instr, self.lc, rs1, rs2, memory);
+                    (rs1, rs2, mem_addr)
                 }
+                None => (None, None, None),
+            };
+            if let Some(rd) = instr.rd {
+                pending.push(rd);
+            }
+
+            if self.mode == BranchPredictorMode::Off {
+                predictions.push(predict((ReturnStackOp::None, 0), mem_addr));
+                self.lc += 4;
+                continue
+            }
+
+            // If the oracle can resolve the branch, trust it. Otherwise
+            // it falls back to the usual methods below.
+            if self.mode == BranchPredictorMode::Oracle {
+                match self.process_oracle(instr, rs1, rs2) {
+                    Some((brk, pc)) => {
+                        predictions.push(predict((ReturnStackOp::None, self.two_level_history), mem_addr));
+                        self.lc = pc;
+                        if brk {
+                            break
+                        }
+                        continue
+                    }
+                    None => stats.oracle_fallbacks += 1,
+                }
+            }
+
+            // If return stack optimisation is used and provides a
+            // prediction, use it.
+            let (rs_op, rs_pred) = self.process_return_address(instr, self.lc);
+            predictions.push(predict((rs_op, self.two_level_history), mem_addr));
+            if let Some(pc) = rs_pred {
+                self.lc = pc;
+                break;
+            }
+
+            // Otherwise, stick with usual branch prediction method
+            let (brk, pc) = self.process_saturating_counter(instr, rf);
+            self.lc = pc;
+            if brk {
+                break
             }
-            bp_data.resize(n_way, (ReturnStackOp::None, 0));
-            bp_data
-        } else {
-            self.lc += 4 * n_way;
-            vec![(ReturnStackOp::None, 0); 4]
         }
+        predictions
     }
 
     /// Feedback on how the branch actually went from the _commit_ stage, where
     /// `mismatch` is set when the branch prediction failed.
-    pub fn commit_feedback(&mut self, rob_entry: &ReorderEntry, mismatch: bool) {
-        if rob_entry.pc + 4 == rob_entry.act_pc as usize {
+    pub fn commit_feedback(&mut self, rob_entry: &ReorderEntry, mismatch: bool, stats: &mut Stats) {
+        let taken = rob_entry.pc + 4 != rob_entry.act_pc as usize;
+        self.update_counters(taken, rob_entry.bp_data.1);
+
+        // Sort out return stack
+        self.apply_stack_operation(rob_entry.bp_data.0, stats);
+        if mismatch {
+            self.return_stack_d = self.return_stack_c.clone();
+        }
+    }
+
+    /// Feedback that the branch predictor should reset to the load counter in
+    /// the given `corrected_pc` in the next cycle. This could be from a
+    /// pipeline stall, or a pipeline flush from a mispredicted branch.
+    pub fn force_update(&mut self, corrected_pc: usize) {
+        self.lc = corrected_pc;
+        if let Some(model) = self.oracle.as_mut() {
+            model.stale = true;
+        }
+    }
+
+    /// Moves the branch prediction state machines on, given whether or not a
+    /// branch was taken and the two level history it was predicted with.
+    fn update_counters(&mut self, taken: bool, history: u8) {
+        if !taken {
             // Sort out saturating counter
             self.saturating_counter = BranchState::not_taken(
                 self.saturating_counter,
@@ -160,8 +329,8 @@ impl BranchPredictor {
             );
 
             // Sort out two level prediction
-            self.two_level_counter[rob_entry.bp_data.1 as usize] = BranchState::not_taken(
-                self.two_level_counter[rob_entry.bp_data.1 as usize],
+            self.two_level_counter[history as usize] = BranchState::not_taken(
+                self.two_level_counter[history as usize],
                 false
             );
             self.two_level_history = (self.two_level_history << 1) & (TWO_LEVEL - 1);
@@ -173,42 +342,12 @@ impl BranchPredictor {
             );
 
             // Sort out two level prediction
-            self.two_level_counter[rob_entry.bp_data.1 as usize] = BranchState::taken(
-                self.two_level_counter[rob_entry.bp_data.1 as usize],
+            self.two_level_counter[history as usize] = BranchState::taken(
+                self.two_level_counter[history as usize],
                 false
             );
             self.two_level_history = ((self.two_level_history << 1) & (TWO_LEVEL - 1)) | 0b1;
         }
-
-        // Sort out return stack
-        self.apply_stack_operation(rob_entry.bp_data.0);
-        if mismatch {
-            self.return_stack_d = self.return_stack_c.clone();
-        }
-    }
-
-    /// Feedback that the branch predictor should reset to the load counter in
-    /// the given `corrected_pc` in the next cycle. This could be from a
-    /// pipeline stall, or a pipeline flush from a mispredicted branch.
-    pub fn force_update(&mut self, corrected_pc: usize) {
-        self.lc = corrected_pc;
-    }
-
-    /// Whether or not the _decode_ state should halt allocating future
-    /// instructions given that it has just decoded the given type of
-    /// operation.
-    pub fn should_halt_decode(&self, operation: Operation) -> bool {
-        match operation {
-            Operation::JAL  |
-            Operation::JALR |
-            Operation::BEQ  |
-            Operation::BNE  |
-            Operation::BLT  |
-            Operation::BGE  |
-            Operation::BLTU |
-            Operation::BGEU => return self.mode != BranchPredictorMode::Off,
-            _ => return false,
-        }
     }
 
     /// Process an instruction for the return address stack optimisation.
@@ -219,12 +358,13 @@ impl BranchPredictor {
         instr: Instruction,
         pc: usize,
     ) -> (ReturnStackOp, Option<usize>) {
+        let depth = self.ras_depth;
         if let Some(stack) = &mut self.return_stack_d {
             match instr.op {
                 Operation::JAL => {
                     if let Some(rd) = instr.rd {
                         if rd == Register::X1 || rd == Register::X5 {
-                            stack.push(pc + 4);
+                            push_return(stack, depth, pc + 4);
                             return (ReturnStackOp::Pushed(pc + 4), None)
                         }
                     }
@@ -240,14 +380,14 @@ impl BranchPredictor {
                     } else if !rd && rs1 {
                         (ReturnStackOp::Popped, stack.pop())
                     } else if rd && !rs1 {
-                        stack.push(pc + 4);
+                        push_return(stack, depth, pc + 4);
                         (ReturnStackOp::Pushed(pc + 4), None)
                     } else if rd && rs1 && !eq {
                         let ret = stack.pop();
-                        stack.push(pc + 4);
+                        push_return(stack, depth, pc + 4);
                         (ReturnStackOp::PushPop(pc + 4), ret)
                     } else {
-                        stack.push(pc + 4);
+                        push_return(stack, depth, pc + 4);
                         (ReturnStackOp::Pushed(pc + 4), None)
                     }
                 }
@@ -258,6 +398,44 @@ impl BranchPredictor {
         }
     }
 
+    /// Resolves a control flow instruction for the oracle, from the values of
+    /// its source registers, if known. Returns whether fetching should break
+    /// after the instruction and the next program counter, or `None` if a
+    /// register value it depends on is not known. Other instructions always
+    /// fall through.
+    fn process_oracle(
+        &self,
+        instr: Instruction,
+        rs1: Option<i32>,
+        rs2: Option<i32>,
+    ) -> Option<(bool, usize)> {
+        let target = ((self.lc as i32).wrapping_add(instr.imm.unwrap_or(0))) as usize;
+        let taken = match instr.op {
+            Operation::JAL => return Some((true, target)),
+            Operation::JALR => {
+                let new_lc = jalr_target(rs1?, instr.imm.unwrap());
+                // Don't jump to zero/minus 1 (end of execution), or to a
+                // misaligned target, which traps once committed
+                if 0 < new_lc && new_lc % INSTRUCTION_ALIGNMENT == 0 {
+                    return Some((true, new_lc as usize))
+                }
+                return Some((true, self.lc + 4))
+            }
+            Operation::BEQ  => rs1? == rs2?,
+            Operation::BNE  => rs1? != rs2?,
+            Operation::BLT  => rs1? < rs2?,
+            Operation::BGE  => rs1? >= rs2?,
+            Operation::BLTU => (rs1? as u32) < (rs2? as u32),
+            Operation::BGEU => (rs1? as u32) >= (rs2? as u32),
+            _ => return Some((false, self.lc + 4)),
+        };
+        if taken {
+            Some((true, target))
+        } else {
+            Some((false, self.lc + 4))
+        }
+    }
+
     fn process_saturating_counter(
         &mut self,
         instr: Instruction,
@@ -267,23 +445,20 @@ impl BranchPredictor {
             Operation::JALR => {
                 let are = &rf[instr.rs1.unwrap()];
                 if are.rename.is_none() {
-                    let new_lc = (are.data + instr.imm.unwrap()) & !0b1;
-                    // Don't jump to zero/minus 1 (end of execution)
-                    if 0 < new_lc {
+                    let new_lc = jalr_target(are.data, instr.imm.unwrap());
+                    // Don't jump to zero/minus 1 (end of execution), or to a
+                    // misaligned target, which traps once committed
+                    if 0 < new_lc && new_lc % INSTRUCTION_ALIGNMENT == 0 {
                         return (true, new_lc as usize)
                     }
                 }
                 (true, self.lc + 4)
             }
-            Operation::JAL  |
-            Operation::BEQ  |
-            Operation::BNE  |
-            Operation::BLT  |
-            Operation::BGE  |
-            Operation::BLTU |
-            Operation::BGEU => {
-                if self.saturating_counter.should_take() {
-                    (true, ((self.lc as i32) + instr.imm.unwrap()) as usize)
+            op if op.is_branch() || op == Operation::JAL => {
+                // The target wraps around the address space, as it does
+                // when the branch is executed
+                if self.predict_taken() {
+                    (true, ((self.lc as i32).wrapping_add(instr.imm.unwrap())) as usize)
                 } else {
                     (false, self.lc + 4)
                 }
@@ -292,19 +467,258 @@ impl BranchPredictor {
         }
     }
 
+    /// Returns whether the counters predict that the next branch is taken.
+    /// Two level prediction picks the counter for the recent branch history,
+    /// whereas the other modes share the one global counter.
+    fn predict_taken(&self) -> bool {
+        match self.mode {
+            BranchPredictorMode::TwoLevel => {
+                self.two_level_counter[self.two_level_history as usize].should_take()
+            }
+            _ => self.saturating_counter.should_take(),
+        }
+    }
+
     /// Applies a `ReturnStackOp` to the return stack in the branch predictor,
-    /// this will apply to the clean return stack.
-    fn apply_stack_operation(&mut self, op: ReturnStackOp) {
+    /// this will apply to the clean return stack. Any overflows or underflows
+    /// of the stack are counted in the given stats.
+    fn apply_stack_operation(&mut self, op: ReturnStackOp, stats: &mut Stats) {
+        let depth = self.ras_depth;
         if let Some(stack) = &mut self.return_stack_c {
-            match op {
-                ReturnStackOp::None => (),
-                ReturnStackOp::Popped => { stack.pop(); },
-                ReturnStackOp::Pushed(pc) => stack.push(pc),
-                ReturnStackOp::PushPop(pc) => {
-                    stack.pop();
-                    stack.push(pc)
+            let (pop, push) = match op {
+                ReturnStackOp::None => (false, None),
+                ReturnStackOp::Popped => (true, None),
+                ReturnStackOp::Pushed(pc) => (false, Some(pc)),
+                ReturnStackOp::PushPop(pc) => (true, Some(pc)),
+            };
+            if pop && stack.pop().is_none() {
+                stats.ras_underflows += 1;
+            }
+            if push.map_or(false, |pc| push_return(stack, depth, pc)) {
+                stats.ras_overflows += 1;
+            }
+        }
+    }
+}
+
+impl ShadowPredictor {
+    /// Creates a shadow predictor for every branch prediction mode.
+    pub fn new_all(config: &Config) -> Vec<ShadowPredictor> {
+        [
+            BranchPredictorMode::Off,
+            BranchPredictorMode::OneBit,
+            BranchPredictorMode::TwoBit,
+            BranchPredictorMode::TwoLevel,
+        ]
+        .iter()
+        .map(|&mode| {
+            let mut predictor = BranchPredictor::new(config);
+            predictor.mode = mode;
+            predictor.return_stack_d = None;
+            predictor.return_stack_c = None;
+            predictor.oracle = None;
+            ShadowPredictor {
+                predictor,
+                success: 0,
+                failure: 0,
+            }
+        })
+        .collect()
+    }
+
+    /// Feedback on how a branch actually went from the _commit_ stage. Branches
+    /// and jumps whose direction the counters predict are scored, whereas
+    /// `JALR` only moves the counters on, as it is predicted from registers.
+    pub fn observe(&mut self, rob_entry: &ReorderEntry) {
+        let taken = rob_entry.pc + 4 != rob_entry.act_pc as usize;
+        if rob_entry.op != Operation::JALR {
+            let predicted = self.predictor.mode != BranchPredictorMode::Off
+                && self.predictor.predict_taken();
+            if predicted == taken {
+                self.success += 1;
+            } else {
+                self.failure += 1;
+            }
+        }
+        let history = self.predictor.two_level_history;
+        self.predictor.update_counters(taken, history);
+    }
+}
+
+impl OracleModel {
+    /// Creates a new model of the program, which is synchronised with the
+    /// committed state before it first runs.
+    pub fn new(uart_addr: Option<usize>) -> OracleModel {
+        OracleModel {
+            registers: vec![],
+            stores: HashMap::new(),
+            memory_known: true,
+            stale: true,
+            uart_addr,
+        }
+    }
+
+    /// Returns the value of the given source register, if known. Registers
+    /// that the model does not know fall back to the register file, if no
+    /// older instruction is yet to write to them.
+    fn operand(&self, reg: Option<Register>, rf: &RegisterFile, pending: &[Register]) -> Option<i32> {
+        match reg {
+            Some(Register::X0) => Some(0),
+            Some(r) => self.registers[r as usize].or_else(|| {
+                if rf[r].rename.is_none() && !pending.contains(&r) {
+                    Some(rf[r].data)
+                } else {
+                    None
+                }
+            }),
+            None => None,
+        }
+    }
+
+    /// Synchronises the model with the committed state of the program, held
+    /// in the given register file and in memory.
+    fn sync(&mut self, rf: &RegisterFile) {
+        self.registers = rf.file.iter().map(|r| Some(r.data)).collect();
+        self.stores.clear();
+        self.memory_known = true;
+        self.stale = false;
+    }
+
+    /// Executes the given instruction at the given program counter, from the
+    /// values of its source registers, if known. Where a result cannot be
+    /// known, the register (or memory) it is written to is no longer known
+    /// either. Returns the address in memory that the instruction accesses,
+    /// if it is a memory operation and the address is known.
+    fn execute(
+        &mut self,
+        instr: Instruction,
+        pc: usize,
+        rs1: Option<i32>,
+        rs2: Option<i32>,
+        memory: &Memory,
+    ) -> Option<usize> {
+        // The address of an atomic memory operation is `rs1` itself
+        let addr = match instr.op {
+            op if op.is_atomic() => rs1.map(|base| base as u32 as usize),
+            op if op.is_memory() => {
+                rs1.and_then(|base| effective_address(base, instr.imm.unwrap_or(0)))
+            }
+            _ => None,
+        };
+        let result = self.result(instr, pc, rs1, rs2, addr, memory);
+        match instr.rd {
+            Some(Register::X0) | None => (),
+            Some(rd) => self.registers[rd as usize] = result,
+        }
+        addr
+    }
+
+    /// Returns the value that the given instruction writes to its destination
+    /// register, if it has one and it can be known, carrying out any write to
+    /// memory that it makes at the given address. Accesses to the UART do not
+    /// read or write memory, so cannot be known.
+    fn result(
+        &mut self,
+        instr: Instruction,
+        pc: usize,
+        rs1: Option<i32>,
+        rs2: Option<i32>,
+        addr: Option<usize>,
+        memory: &Memory,
+    ) -> Option<i32> {
+        let op = instr.op;
+        let imm = instr.imm.unwrap_or(0);
+        let size = access_size(op);
+        let uart_addr = self.uart_addr;
+        let addr = addr.filter(|&addr| !in_uart(uart_addr, addr, size));
+        match op {
+            Operation::LUI => Some(imm),
+            Operation::AUIPC => Some((pc as i32).wrapping_add(imm)),
+            Operation::JAL | Operation::JALR => Some((pc as i32).wrapping_add(4)),
+            op if op.is_load() => {
+                if !self.memory_known {
+                    return None;
+                }
+                let word = self.read(memory, addr?, size);
+                #[rustfmt::skip]
+                let value = match op {
+                    Operation::LB => word as u8 as i8 as i32,
+                    Operation::LH => word as u16 as i16 as i32,
+                    _             => word as i32,
+                };
+                Some(value)
+            }
+            op if op.is_store() => {
+                match (addr, rs2) {
+                    (Some(addr), Some(value)) => self.write(addr, size, value),
+                    _ => self.memory_known = false,
+                }
+                None
+            }
+            op if op.is_atomic() => {
+                let addr = match addr {
+                    Some(addr) if self.memory_known => addr,
+                    _ => {
+                        self.memory_known = false;
+                        return None;
+                    }
+                };
+                let old = self.read(memory, addr, 4) as i32;
+                match (op, rs2) {
+                    (Operation::LRW, _) => Some(old),
+                    (_, Some(rs2)) => {
+                        let (rd_val, new) = amo_result(op, old, rs2);
+                        if let Some(word) = new {
+                            self.write(addr, 4, word);
+                        }
+                        Some(rd_val)
+                    }
+                    _ => {
+                        self.memory_known = false;
+                        None
+                    }
+                }
+            }
+            Operation::ECALL => {
+                // Reads depend on the program input, and writes return their
+                // length, while other system calls leave registers as they are
+                let a7 = self.registers[Register::X17 as usize];
+                if a7 == Some(SYSCALL_WRITE) {
+                    self.registers[Register::X10 as usize] = self.registers[Register::X12 as usize];
+                } else if a7.is_none() || a7 == Some(SYSCALL_READ) {
+                    self.registers[Register::X10 as usize] = None;
+                    self.memory_known = false;
                 }
+                None
             }
+            op if UnitType::from(op) == UnitType::ALU => match Format::from(op) {
+                Format::R => Some(alu_r_result(op, rs1?, rs2?)),
+                Format::I => Some(alu_i_result(op, rs1?, imm)),
+                _ => None,
+            },
+            // The results of CSR instructions are not modelled
+            _ => None,
+        }
+    }
+
+    /// Reads the little endian value of the given size from the model's view
+    /// of memory, where the stores it has run overwrite what has been
+    /// committed.
+    fn read(&self, memory: &Memory, addr: usize, size: usize) -> u32 {
+        (0..size).rev().fold(0, |word, i| {
+            let byte = match self.stores.get(&(addr + i)) {
+                Some(&byte) => byte,
+                None => memory.read_u8(addr + i).word,
+            };
+            word << 8 | u32::from(byte)
+        })
+    }
+
+    /// Writes the little endian value of the given size to the model's view
+    /// of memory.
+    fn write(&mut self, addr: usize, size: usize, value: i32) {
+        for i in 0..size {
+            self.stores.insert(addr + i, (value >> (8 * i)) as u8);
         }
     }
 }
@@ -357,3 +771,297 @@ impl BranchState {
         }
     }
 }
+
+///////////////////////////////////////////////////////////////////////////////
+//// FUNCTIONS
+
+/// Computes the target of a jump and link register instruction, from the
+/// value of its base register and its immediate offset. The lowest bit is
+/// cleared, as the specification requires, but the target may still be
+/// misaligned to an instruction.
+pub fn jalr_target(base: i32, offset: i32) -> i32 {
+    base.wrapping_add(offset) & !0b1
+}
+
+/// Computes the target of a branch or jump at `pc`, for display, given its
+/// `rs1` operand and immediate. Returns `None` for any other instruction, or
+/// a jump and link register whose base register is not yet known.
+pub fn control_target(
+    op: Operation,
+    pc: usize,
+    rs1: Either<i32, usize>,
+    imm: Option<i32>,
+) -> Option<i32> {
+    let imm = imm?;
+    match (BaseCode::from(op), rs1) {
+        (BaseCode::BRANCH, _) | (BaseCode::JAL, _) => Some((pc as i32).wrapping_add(imm)),
+        (BaseCode::JALR, Left(base)) => Some(jalr_target(base, imm)),
+        _ => None,
+    }
+}
+
+/// Pushes a return address onto a return stack holding at most `depth`
+/// addresses (or unbounded, if 0), dropping the oldest address to make room
+/// if it is full. Returns whether the stack overflowed.
+fn push_return(stack: &mut Vec<usize>, depth: usize, pc: usize) -> bool {
+    let overflow = depth != 0 && stack.len() >= depth;
+    if overflow {
+        stack.remove(0);
+    }
+    stack.push(pc);
+    overflow
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// TESTS
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::builder::{run, StateBuilder};
+    use crate::simulator::state::State;
+
+    /// A loop around a branch that alternates between taken and not taken.
+    /// The padding lets each branch commit before the next is fetched, so
+    /// that the real predictor sees the same counters as the shadows do, and
+    /// the registers each depends on before it is fetched.
+    const ALTERNATING: &str = "
+            addi x5 x0 24
+        loop:
+            andi x6 x5 1
+            addi x5 x5 -1
+            addi x0 x0 0
+            addi x0 x0 0
+            addi x0 x0 0
+            addi x0 x0 0
+            addi x0 x0 0
+            addi x0 x0 0
+            addi x0 x0 0
+            addi x0 x0 0
+        alternate:
+            beq x6 x0 skip
+            addi x7 x7 1
+        skip:
+            addi x0 x0 0
+            addi x0 x0 0
+            addi x0 x0 0
+            addi x0 x0 0
+            addi x0 x0 0
+            addi x0 x0 0
+            addi x0 x0 0
+            addi x0 x0 0
+        back:
+            bne x5 x0 loop
+            jalr x0 x1 0
+    ";
+
+    /// Runs the alternating loop with the given mode, comparing predictors.
+    fn run_alternating(mode: BranchPredictorMode) -> State {
+        let mut config = Config::default();
+        config.branch_prediction = mode;
+        config.compare_predictors = true;
+        config.rob_size = 4;
+        let mut state = StateBuilder::new()
+            .config(config)
+            .program(ALTERNATING)
+            .build();
+        run(&mut state, 10_000);
+        assert_eq!(state.register[Register::X7].data, 12);
+        state
+    }
+
+    /// Returns the shadow predictor for the given mode.
+    fn shadow(state: &State, mode: BranchPredictorMode) -> &ShadowPredictor {
+        state
+            .shadow_predictors
+            .iter()
+            .find(|s| s.predictor.mode == mode)
+            .unwrap()
+    }
+
+    #[test]
+    fn shadow_matches_real_predictor() {
+        let modes = [
+            BranchPredictorMode::OneBit,
+            BranchPredictorMode::TwoBit,
+            BranchPredictorMode::TwoLevel,
+        ];
+        for &mode in modes.iter() {
+            let state = run_alternating(mode);
+            let branches: Vec<usize> = state
+                .symbols
+                .iter()
+                .filter(|(_, name)| *name == "alternate" || *name == "back")
+                .map(|(addr, _)| *addr)
+                .collect();
+            let (failure, total) = branches
+                .iter()
+                .map(|pc| state.stats.branch_counts[pc])
+                .fold((0, 0), |(f, t), (bf, bt)| (f + bf, t + bt));
+            let shadow = shadow(&state, mode);
+            assert_eq!(total, 48, "{:?}", mode);
+            assert_eq!(
+                (shadow.failure, shadow.success + shadow.failure),
+                (failure, total),
+                "{:?}",
+                mode
+            );
+        }
+    }
+
+    #[test]
+    fn two_level_learns_alternating_branch() {
+        let state = run_alternating(BranchPredictorMode::TwoBit);
+        let two_bit = shadow(&state, BranchPredictorMode::TwoBit);
+        let two_level = shadow(&state, BranchPredictorMode::TwoLevel);
+        assert!(two_level.failure < two_bit.failure);
+    }
+
+    #[test]
+    fn oracle_never_mispredicts() {
+        let state = run_alternating(BranchPredictorMode::Oracle);
+        assert_eq!(state.stats.bp_failure, 0);
+        assert_eq!(state.stats.oracle_fallbacks, 0);
+        assert!(state.stats.bp_success >= 48);
+    }
+
+    /// Runs a program that fills an array with pseudo-random bits, and then
+    /// counts the set bits with a branch on each, with the given mode.
+    fn run_random_bits(mode: BranchPredictorMode) -> State {
+        let program = format!(
+            "
+                la x5 bits
+                addi x6 x0 64
+                addi x7 x0 1
+                addi x28 x0 75
+            fill:
+                mul x7 x7 x28
+                addi x7 x7 74
+                srli x29 x7 7
+                andi x29 x29 1
+                sw x5 x29 0
+                addi x5 x5 4
+                addi x6 x6 -1
+                bne x6 x0 fill
+                la x5 bits
+                addi x6 x0 64
+                addi x10 x0 0
+            count:
+                lw x29 x5 0
+                beq x29 x0 clear
+                addi x10 x10 1
+            clear:
+                addi x5 x5 4
+                addi x6 x6 -1
+                bne x6 x0 count
+                jalr x0 x1 0
+            .data
+            bits: .word {}
+            ",
+            vec!["0"; 64].join(", ")
+        );
+        let mut config = Config::default();
+        config.branch_prediction = mode;
+        let mut state = StateBuilder::new().config(config).program(&program).build();
+        run(&mut state, 100_000);
+        state
+    }
+
+    #[test]
+    fn oracle_resolves_branches_on_values_in_flight() {
+        let mut seed = 1i32;
+        let mut ones = 0;
+        for _ in 0..64 {
+            seed = seed.wrapping_mul(75).wrapping_add(74);
+            ones += (seed as u32 >> 7) & 1;
+        }
+
+        // The bits were only just stored, and are loaded right before being
+        // branched on, so are still in flight when the branches are fetched
+        let oracle = run_random_bits(BranchPredictorMode::Oracle);
+        let two_bit = run_random_bits(BranchPredictorMode::TwoBit);
+        assert_eq!(oracle.register[Register::X10].data, ones as i32);
+        assert_eq!(two_bit.register[Register::X10].data, ones as i32);
+        assert_eq!(oracle.stats.bp_failure, 0);
+        assert_eq!(oracle.stats.oracle_fallbacks, 0);
+        assert!(two_bit.stats.bp_failure > 10, "{}", two_bit.stats.bp_failure);
+        assert!(oracle.stats.cycles < two_bit.stats.cycles);
+    }
+
+    /// Runs the given program with a return address stack of the given depth.
+    fn run_with_ras(depth: usize, program: &str) -> State {
+        let mut config = Config::default();
+        config.return_address_stack = true;
+        config.ras_depth = depth;
+        let mut state = StateBuilder::new()
+            .config(config)
+            .reg(Register::X3, -1)
+            .program(program)
+            .build();
+        run(&mut state, 10_000);
+        state
+    }
+
+    #[test]
+    fn deep_recursion_overflows_return_stack() {
+        // Five calls deep, so three return addresses are dropped, and the
+        // returns to them find the stack empty
+        let program = "
+                addi x10 x0 4
+                jal x1 recurse
+                jalr x0 x3 0
+            recurse:
+                beq x10 x0 done
+                addi x2 x2 -4
+                sw x2 x1 0
+                addi x10 x10 -1
+                jal x1 recurse
+                lw x1 x2 0
+                addi x2 x2 4
+            done:
+                jalr x0 x1 0
+        ";
+        let state = run_with_ras(2, program);
+        assert_eq!(state.stats.ras_overflows, 3);
+        assert_eq!(state.stats.ras_underflows, 3);
+
+        let state = run_with_ras(0, program);
+        assert_eq!(state.stats.ras_overflows, 0);
+        assert_eq!(state.stats.ras_underflows, 0);
+    }
+
+    #[test]
+    fn return_with_empty_stack_underflows() {
+        let state = run_with_ras(8, "jalr x0 x1 0");
+        assert_eq!(state.stats.ras_underflows, 1);
+        assert_eq!(state.stats.ras_overflows, 0);
+    }
+
+    #[test]
+    fn predicted_branch_targets_wrap_around_as_executed() {
+        // The same targets as the branch logic unit computes
+        let mut predictor = BranchPredictor::new(&Config::default());
+        predictor.saturating_counter = BranchState::StronglyTaken;
+        #[rustfmt::skip]
+        let cases = [
+            (0x7fff_fffc, 8,  0x8000_0004u32 as i32),
+            (0xffff_fff8, 16, 8),
+            (4,           -8, -4),
+        ];
+        for &(pc, imm, target) in cases.iter() {
+            predictor.lc = pc;
+            let instr = Instruction {
+                op: Operation::BEQ,
+                rd: None,
+                rs1: Some(Register::X0),
+                rs2: Some(Register::X0),
+                imm: Some(imm),
+            };
+            let rf = RegisterFile::default();
+            assert_eq!(
+                predictor.process_saturating_counter(instr, &rf),
+                (true, target as usize)
+            );
+        }
+    }
+}
@@ -0,0 +1,245 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::isa::Instruction;
+use crate::isa::assembly::{assemble_program, parse_instruction};
+use crate::isa::op_code::Encodable;
+use crate::isa::operand::Register;
+use crate::util::config::Config;
+
+use super::state::State;
+use super::step_cycle;
+
+///////////////////////////////////////////////////////////////////////////////
+//// STRUCTS
+
+/// Builds a [`State`](../state/struct.State.html) that runs a given sequence
+/// of instructions, with preset registers and memory, without an elf file, so
+/// that the simulator can be driven programmatically. For example:
+///
+/// ```ignore
+/// let state = StateBuilder::new()
+///     .reg(Register::X1, 5)
+///     .asm("addi x2 x1 3")
+///     .build();
+/// ```
+///
+/// The `ra` register starts as -1, as when running an elf file, so ending the
+/// instructions with `jalr x0 x1 0` finishes the simulation (unless `ra` is
+/// overwritten).
+#[derive(Clone)]
+pub struct StateBuilder {
+    /// The config that the state is created from.
+    config: Config,
+    /// The address that the instructions are placed from, and where execution
+    /// starts.
+    origin: usize,
+    /// The instructions to place in memory, in order.
+    instructions: Vec<Instruction>,
+    /// The words to write to memory, as `(address, word)`.
+    memory: Vec<(usize, i32)>,
+    /// The registers to preset, as `(register, value)`.
+    registers: Vec<(Register, i32)>,
+    /// The names of the labels of any assembled program, by address.
+    symbols: BTreeMap<usize, String>,
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// IMPLEMENTATIONS
+
+impl StateBuilder {
+    /// Creates a new builder with the default config, that places
+    /// instructions from address 0.
+    pub fn new() -> StateBuilder {
+        StateBuilder {
+            config: Config::default(),
+            origin: 0,
+            instructions: vec![],
+            memory: vec![],
+            registers: vec![],
+            symbols: BTreeMap::new(),
+        }
+    }
+
+    /// Sets the config that the state is created from. Any elf file it names
+    /// is ignored.
+    pub fn config(mut self, config: Config) -> StateBuilder {
+        self.config = config;
+        self
+    }
+
+    /// Sets the address that the instructions are placed from, and where
+    /// execution starts.
+    pub fn origin(mut self, origin: usize) -> StateBuilder {
+        self.origin = origin;
+        self
+    }
+
+    /// Adds an instruction after any already added.
+    pub fn instr(mut self, instruction: Instruction) -> StateBuilder {
+        self.instructions.push(instruction);
+        self
+    }
+
+    /// Adds an instruction given as a line of assembly, in the syntax used by
+    /// the interactive prompt, after any already added.
+    ///
+    /// Panics if the line could not be parsed.
+    pub fn asm(self, line: &str) -> StateBuilder {
+        match parse_instruction(line) {
+            Ok(i) => self.instr(i),
+            Err(message) => panic!("Invalid instruction '{}': {}", line, message),
+        }
+    }
+
+    /// Assembles a whole program (see
+    /// [`assemble_program`](../../isa/assembly/fn.assemble_program.html)),
+    /// placed in memory from the origin, naming its labels in disassembled
+    /// instructions. Any instructions added are placed over it.
+    ///
+    /// Panics if the program could not be assembled.
+    pub fn program(mut self, source: &str) -> StateBuilder {
+        let program = match assemble_program(source, self.origin) {
+            Ok(p) => p,
+            Err(message) => panic!("Invalid program: {}", message),
+        };
+        for (n, chunk) in program.bytes.chunks(4).enumerate() {
+            let mut word = [0; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.memory.push((program.origin + (4 * n), i32::from_le_bytes(word)));
+        }
+        for (name, addr) in program.labels {
+            self.symbols.insert(addr, name);
+        }
+        self
+    }
+
+    /// Presets a word in memory at the given address.
+    pub fn word(mut self, addr: usize, word: i32) -> StateBuilder {
+        self.memory.push((addr, word));
+        self
+    }
+
+    /// Presets the value of a register.
+    pub fn reg(mut self, register: Register, value: i32) -> StateBuilder {
+        self.registers.push((register, value));
+        self
+    }
+
+    /// Builds the state, ready to execute from the first instruction. The
+    /// built state resets back to how it was built.
+    pub fn build(self) -> State {
+        // Start with empty memory, as the interactive prompt does
+        let mut config = self.config;
+        config.repl = true;
+        let mut state = State::new(&config);
+        // The tests give their input in a file, if any, never from stdin
+        state.host_input = false;
+
+        for (addr, word) in self.memory {
+            state.memory.write_i32(addr, word);
+        }
+        for (n, instruction) in self.instructions.iter().enumerate() {
+            state.memory.write_i32(self.origin + (4 * n), instruction.encode());
+        }
+        for (register, value) in self.registers {
+            state.register[register].data = value;
+        }
+        if !self.symbols.is_empty() {
+            state.symbols = Arc::new(self.symbols);
+        }
+        state.register[Register::PC].data = self.origin as i32;
+        state.clear_pipeline(self.origin);
+
+        // Replace the pristine state from before anything was preset
+        state.pristine = None;
+        state.pristine = Some(Arc::new(state.clone()));
+        state
+    }
+}
+
+impl Default for StateBuilder {
+    fn default() -> StateBuilder {
+        StateBuilder::new()
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// FUNCTIONS
+
+/// Runs the given state until the simulated program finishes, returning the
+/// number of cycles that it took.
+///
+/// Panics if the program has not finished within the given number of cycles.
+pub fn run(state: &mut State, limit: u64) -> u64 {
+    let start = state.stats.cycles;
+    while !step_cycle(state) {
+        if state.stats.cycles - start >= limit {
+            panic!("Program did not finish within {} cycles.", limit);
+        }
+    }
+    state.stats.cycles - start
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// TESTS
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isa::op_code::Operation;
+
+    #[test]
+    fn runs_injected_instructions() {
+        let addi = Instruction {
+            op: Operation::ADDI,
+            rd: Some(Register::X2),
+            rs1: Some(Register::X1),
+            rs2: None,
+            imm: Some(3),
+        };
+        let mut state = StateBuilder::new()
+            .reg(Register::X1, 5)
+            .reg(Register::X3, -1)
+            .instr(addi)
+            .asm("jalr x0 x3 0")
+            .build();
+        run(&mut state, 100);
+        assert_eq!(state.register[Register::X2].data, 8);
+    }
+
+    #[test]
+    fn presets_memory_from_origin() {
+        let mut config = Config::default();
+        config.n_way = 1;
+        let mut state = StateBuilder::new()
+            .config(config)
+            .origin(0x1000)
+            .word(0x2000, 42)
+            .reg(Register::X5, 0x2000)
+            .asm("lw x10 x5 0")
+            .asm("jalr x0 x1 0")
+            .build();
+        assert_eq!(state.register[Register::PC].data, 0x1000);
+        run(&mut state, 100);
+        assert_eq!(state.register[Register::X10].data, 42);
+    }
+
+    #[test]
+    fn names_labels_of_program() {
+        let mut state = StateBuilder::new()
+            .origin(0x1000)
+            .program(
+                "
+                        addi a0, zero, 3
+                loop:   addi a0, a0, -1
+                        bne  a0, zero, loop
+                        jalr zero, ra, 0
+                ",
+            )
+            .build();
+        assert_eq!(state.symbols.get(&0x1004).map(String::as_str), Some("loop"));
+        run(&mut state, 200);
+        assert_eq!(state.register[Register::X10].data, 0);
+    }
+}
@@ -1,10 +1,32 @@
+use std::cmp::min;
+use std::io::{self, Read};
+
 use either::{Left, Right};
 
 use crate::isa::Format;
 use crate::isa::op_code::Operation;
 use crate::isa::operand::Register;
+use crate::util::log::LogLevel;
 
-use super::state::State;
+use super::branch::INSTRUCTION_ALIGNMENT;
+use super::execute::UnalignedAccess;
+use super::load::access_size;
+use super::memory::{effective_address, in_uart};
+use super::reorder::ReorderEntry;
+use super::state::{AccessKind, State};
+use super::trap::{take_trap, Trap};
+
+///////////////////////////////////////////////////////////////////////////////
+//// CONST/STATIC
+
+/// The system call number (in `a7`) for reading from the program input.
+pub const SYSCALL_READ: i32 = 63;
+
+/// The system call number (in `a7`) for writing to the program output.
+pub const SYSCALL_WRITE: i32 = 64;
+
+/// The system call number (in `a7`) for exiting the program.
+const SYSCALL_EXIT: i32 = 93;
 
 ///////////////////////////////////////////////////////////////////////////////
 //// FUNCTIONS
@@ -16,8 +38,14 @@ use super::state::State;
 pub fn commit_stage(state_p: &State, state: &mut State) -> bool {
     let entries = state_p
         .reorder_buffer
-        .pop_finished_entries(&mut state.reorder_buffer, state_p.issue_limit);
+        .pop_finished_entries(&mut state.reorder_buffer, state_p.commit_width);
     for entry in entries {
+        // The result moves to the architectural register file, freeing its
+        // physical register
+        if let Some(reg) = state_p.reorder_buffer[entry].phys_rd {
+            state.phys_regs.release(reg);
+        }
+
         let flushed = match Format::from(state_p.reorder_buffer[entry].op) {
             Format::R => cm_r_type(state_p, state, entry),
             Format::I => cm_i_type(state_p, state, entry),
@@ -29,43 +57,61 @@ pub fn commit_stage(state_p: &State, state: &mut State) -> bool {
 
         // Housekeeping
         state.stats.executed += 1;
+        *state
+            .stats
+            .op_counts
+            .entry(state_p.reorder_buffer[entry].op)
+            .or_insert(0) += 1;
+        state.reorder_buffer[entry].cycle_committed = Some(state_p.stats.cycles);
+        record_load_use(state_p, state, entry);
+        let diverged = check_replay(state, entry);
+        check_max_insns(state);
 
-        // Early exit if finished execution or pipeline flush
-        if flushed || state.register[Register::PC].data == -1 {
+        // Early exit if finished execution, pipeline flush or divergence
+        if flushed || diverged || state.register[Register::PC].data == -1 {
             break;
         }
 
-        // Remove reference counts and bypass results from loads/stores.
-        match state.reorder_buffer[entry].op {
-            Operation::LB  |
-            Operation::LH  |
-            Operation::LW  |
-            Operation::LBU |
-            Operation::LHU |
-            Operation::SB  |
-            Operation::SH  |
-            Operation::SW  => {
-                // Bypass, let everyone that is waiting for this
-                // register know it's value. (Lower down values).
-                if let Some(rd) = state.reorder_buffer[entry].act_rd {
+        // Remove reference counts and bypass results from memory operations.
+        if state.reorder_buffer[entry].op.is_memory() {
+            // Bypass, let everyone that is waiting for this
+            // register know it's value. (Lower down values). Loads
+            // were already bypassed when they finished executing.
+            let load = state.reorder_buffer[entry].op.is_load();
+            match state.reorder_buffer[entry].act_rd {
+                Some(rd) if !load => {
                     state.resv_station.execute_bypass(entry, rd);
                     state.reorder_buffer.execute_bypass(entry, rd);
                 }
-                // Finish with the dependencies that this was using.
-                // (Higher up values).
-                if let Right(name) = state.reorder_buffer[entry].rs1 {
-                    state.reorder_buffer[name].ref_count -= 1;
-                    state.reorder_buffer[entry].rs1 = Left(0);
-                }
-                if let Right(name) = state.reorder_buffer[entry].rs2 {
-                    state.reorder_buffer[name].ref_count -= 1;
-                    state.reorder_buffer[entry].rs2 = Left(0);
-                }
+                _ => (),
+            }
+            // Finish with the dependencies that this was using.
+            // (Higher up values).
+            if let Right(name) = state.reorder_buffer[entry].rs1 {
+                state.reorder_buffer[name].ref_count -= 1;
+                state.reorder_buffer[entry].rs1 = Left(0);
+            }
+            if let Right(name) = state.reorder_buffer[entry].rs2 {
+                state.reorder_buffer[name].ref_count -= 1;
+                state.reorder_buffer[entry].rs2 = Left(0);
             }
-            _ => ()
         }
     }
-    state.register[Register::PC].data == -1
+
+    check_deadlock(state_p, state);
+
+    // A replayed trace must also finish with the program, unless it was
+    // stopped early
+    let finished = state.register[Register::PC].data == -1;
+    let limited = state.deadlocked || (state.max_insns != 0 && state.stats.retired() >= state.max_insns);
+    let unfinished = match &state.replay {
+        Some(replay) if finished && !limited && !replay.diverged => replay.check_finished().err(),
+        _ => None,
+    };
+    if let Some(messages) = unfinished {
+        replay_diverged(state, messages);
+    }
+    finished
 }
 
 /// Commits an R type instruction from a reorder buffer entry to the given
@@ -74,82 +120,330 @@ fn cm_r_type(state_p: &State, state: &mut State, entry: usize) -> bool {
     let rob = &state_p.reorder_buffer;
     let rob_entry = &rob[entry];
     // Branch prediction failure check
-    let next_pc = if (entry + 1) % rob.capacity != rob.back {
-        rob[(entry + 1) % rob.capacity].pc as i32
+    let next_pc = next_pc(state_p, entry);
+
+    // Atomic memory operations have not touched memory yet, so do so now
+    let (rd_val, addr) = if rob_entry.op.is_atomic() {
+        let (rd_val, addr) = match cm_amo(state, rob_entry) {
+            Some(result) => result,
+            None => return true,
+        };
+        state.reorder_buffer[entry].act_rd = Some(rd_val);
+        if addr.map_or(false, |a| check_stack_guard(state, rob_entry, a, 4)) {
+            return true;
+        }
+        (rd_val, addr)
     } else {
-        -1
+        (rob_entry.act_rd.unwrap(), None)
     };
-    if rob_entry.act_pc == next_pc {
-        // Write back to register file
-        state.register.writeback(rob_entry.reg_rd.unwrap(), entry, rob_entry.act_rd.unwrap());
-        state.register[Register::PC].data = rob_entry.act_pc;
-        false
-    } else {
-        panic!(
-            format!("Did not expect R type instruction to have mismatching PC! - {:?}", rob_entry)
-        )
+
+    // Write back to register file
+    state.register.writeback(rob_entry.reg_rd.unwrap(), entry, rd_val);
+    state.register[Register::PC].data = rob_entry.act_pc;
+    if check_fall_through(state, rob_entry, next_pc) {
+        return true;
+    }
+
+    // Memory ordering violation and stale fetch checks, as with stores
+    match addr.and_then(|a| state.load_queue.find_violation(a, 4, entry, rob.capacity)) {
+        Some(_) => {
+            state.replay_load(rob_entry.act_pc as usize);
+            true
+        }
+        None => addr.map_or(false, |a| check_stale_fetch(state, rob_entry, entry, a, 4)),
     }
 }
 
-/// Commits an I type instruction from a reorder buffer entry to the given
-/// state. Returns whether a full pipeline flush occured.
-fn cm_i_type(state_p: &State, state: &mut State, entry: usize) -> bool {
-    let rob = &state_p.reorder_buffer;
-    let rob_entry = &rob[entry];
-    let rs1_s = match rob_entry.rs1 {
+/// Handles a breakpoint (`ebreak`) at the given program counter, recording it
+/// for whatever is running the simulator to stop at, unless breakpoints are
+/// being ignored. Returns whether the pipeline needs to be restarted.
+fn cm_ebreak(state: &mut State, pc: usize) -> bool {
+    if state.ignore_ebreak {
+        return false;
+    }
+    state.breakpoint = Some(pc);
+    state.log(LogLevel::Info, format!("Breakpoint at {:08x}.", pc));
+    true
+}
+
+/// Performs the system call requested by an `ecall`, given by the number in
+/// `a7`. Reading and writing follow the Linux convention, with the buffer in
+/// `a1` and its length in `a2`, returning the number of bytes transferred in
+/// `a0`. Reads return 0 at the end of the program input. Exiting finishes the
+/// program with the exit code in `a0`. Any other number prints the single
+/// character in `a1`, as used by the example programs. Returns whether
+/// registers or memory were changed, such that any in flight instructions need
+/// to be restarted.
+fn cm_ecall(state: &mut State) -> bool {
+    let buf = state.register[Register::X11].data as u32 as usize;
+    let len = state.register[Register::X12].data as u32 as usize;
+    match state.register[Register::X17].data {
+        SYSCALL_READ => {
+            // Input past the end of the address space is left unread
+            let space = state.mem_size.saturating_sub(buf);
+            if state.input_pos == state.input.len() && state.host_input {
+                read_host_input(state, min(len, space));
+            }
+            let count = min(min(len, space), state.input.len() - state.input_pos);
+            for i in 0..count {
+                let byte = state.input[state.input_pos + i];
+                state.memory.write_u8(buf + i, byte);
+            }
+            state.input_pos += count;
+            state.register[Register::X10].data = count as i32;
+            true
+        }
+        SYSCALL_WRITE => {
+            for i in 0..len {
+                let c = state.memory.read_u8(buf + i).word as char;
+                print_char(state, c);
+            }
+            state.register[Register::X10].data = len as i32;
+            true
+        }
+        SYSCALL_EXIT => {
+            state.exit_code = Some(state.register[Register::X10].data);
+            true
+        }
+        _ => {
+            let c = (state.register[Register::X11].data as u8) as char;
+            print_char(state, c);
+            false
+        }
+    }
+}
+
+/// Appends up to `len` bytes from the host's stdin to the program input,
+/// waiting for at least one unless at the end of stdin.
+fn read_host_input(state: &mut State, len: usize) {
+    let mut buf = vec![0; len];
+    match io::stdin().read(&mut buf) {
+        Ok(n) => state.input.extend_from_slice(&buf[..n]),
+        Err(e) => error!(format!("Failed to read program input from stdin:\n{}", e)),
+    }
+}
+
+/// Prints a character to the program output, starting a new line on a line
+/// feed and ignoring any other unprintable characters.
+fn print_char(state: &mut State, c: char) {
+    match c {
+        '\n' => state.out.push(String::new()),
+        a if a.is_ascii_graphic() || a.is_ascii_whitespace() => {
+            let last = state.out.len() - 1;
+            state.out[last].push(a)
+        }
+        _ => ()
+    }
+}
+
+/// Performs the read-modify-write of an atomic memory operation, returning
+/// the value for the destination register and the address written to, if any.
+/// As there is only a single hart, reservations from `lr.w` can never be
+/// broken, so `sc.w` will always succeed.
+///
+/// Returns `None` if the address was out of range or not word aligned,
+/// trapping.
+fn cm_amo(state: &mut State, rob_entry: &ReorderEntry) -> Option<(i32, Option<usize>)> {
+    let addr = access_address(state, rob_entry, 0)?;
+    let rs2 = match rob_entry.rs2 {
         Left(val) => val,
         Right(name) => state
             .reorder_buffer[name]
             .act_rd
-            .unwrap_or(0) // Some instructions may not require this - namely
-                          // those that are not loads, so fail quietly
+            .expect("Commit AMO expected rs2!"),
+    };
+    let old = state.memory.read_i32(addr).word;
+    if rob_entry.op != Operation::SCW {
+        state.profile_access(addr, AccessKind::Load);
+    }
+
+    let (rd_val, new) = amo_result(rob_entry.op, old, rs2);
+    match new {
+        Some(word) => {
+            state.memory.write_i32(addr, word);
+            state.profile_access(addr, AccessKind::Store);
+            Some((rd_val, Some(addr)))
+        }
+        None => Some((rd_val, None)),
+    }
+}
+
+/// Returns the value for the destination register of an atomic memory
+/// operation, and the word it writes back to memory, if any, given the `old`
+/// word in memory and the value of `rs2`.
+#[rustfmt::skip]
+pub fn amo_result(op: Operation, old: i32, rs2: i32) -> (i32, Option<i32>) {
+    match op {
+        Operation::LRW      => (old, None),
+        Operation::SCW      => (0, Some(rs2)),
+        Operation::AMOSWAPW => (old, Some(rs2)),
+        Operation::AMOADDW  => (old, Some(old.wrapping_add(rs2))),
+        Operation::AMOXORW  => (old, Some(old ^ rs2)),
+        Operation::AMOANDW  => (old, Some(old & rs2)),
+        Operation::AMOORW   => (old, Some(old | rs2)),
+        Operation::AMOMINW  => (old, Some(old.min(rs2))),
+        Operation::AMOMAXW  => (old, Some(old.max(rs2))),
+        Operation::AMOMINUW => (old, Some((old as u32).min(rs2 as u32) as i32)),
+        Operation::AMOMAXUW => (old, Some((old as u32).max(rs2 as u32) as i32)),
+        _ => panic!("Unknown atomic memory operation failed to commit."),
+    }
+}
+
+/// Performs the read-modify-write of a CSR instruction, returning the old
+/// value of the CSR for the destination register. The immediate forms hold
+/// their 5 bit immediate in place of `rs1`.
+///
+/// Returns `None` if the CSR does not exist, trapping with an illegal
+/// instruction.
+fn cm_csr(state: &mut State, rob_entry: &ReorderEntry) -> Option<i32> {
+    let csr = (rob_entry.imm.expect("Commit CSR missing imm!") & 0xfff) as u32;
+    let src = match rob_entry.rs1 {
+        Left(val) => val,
+        Right(name) => state
+            .reorder_buffer[name]
+            .act_rd
+            .expect("Commit CSR expected rs1!"),
+    } as u32;
+    let old = match state.csr.read(csr) {
+        Some(old) => old,
+        None => {
+            state.stats.illegal_instructions += 1;
+            let word = state.memory.read_i32(rob_entry.pc).word;
+            let message = format!(
+                "Illegal instruction {:08x} at {:08x}, CSR {:#x} does not exist.",
+                word, rob_entry.pc, csr
+            );
+            take_trap(state, Trap::IllegalInstruction, rob_entry.pc, word as u32, message);
+            return None;
+        }
     };
-    let imm_s = rob_entry.imm.unwrap_or(0);
 
+    #[rustfmt::skip]
+    let new = match rob_entry.op {
+        Operation::CSRRW | Operation::CSRRWI => src,
+        Operation::CSRRS | Operation::CSRRSI => old | src,
+        Operation::CSRRC | Operation::CSRRCI => old & !src,
+        _ => panic!("Unknown CSR instruction failed to commit."),
+    };
+    state.csr.write(csr, new);
+    Some(old as i32)
+}
+
+/// Commits an I type instruction from a reorder buffer entry to the given
+/// state. Returns whether a full pipeline flush occured.
+fn cm_i_type(state_p: &State, state: &mut State, entry: usize) -> bool {
+    let rob = &state_p.reorder_buffer;
+    let rob_entry = &rob[entry];
+    if rob_entry.op == Operation::JALR && trap_misaligned(state, rob_entry) {
+        return true;
+    }
+
+    // Loads have already read memory speculatively, and are now safe
+    state.load_queue.remove(entry);
+    if rob_entry.op.is_load() {
+        let imm = rob_entry.imm.expect("Commit load missing imm!");
+        match access_address(state, rob_entry, imm) {
+            Some(addr) => state.profile_access(addr, AccessKind::Load),
+            None => return true,
+        }
+    }
+
+    // With a trap handler installed, environment calls and breakpoints are
+    // left to it, rather than performed by the simulator
+    if state.csr.has_handler() {
+        let trap = match rob_entry.op {
+            Operation::ECALL => Some(Trap::EnvironmentCall),
+            Operation::EBREAK if !state.ignore_ebreak => Some(Trap::Breakpoint),
+            _ => None,
+        };
+        if let Some(trap) = trap {
+            let message = format!("Trap on {} at {:08x}.", trap, rob_entry.pc);
+            take_trap(state, trap, rob_entry.pc, 0, message);
+            return true;
+        }
+    }
+
+    let mut resync = false;
     #[rustfmt::skip]
     let rd_val = match rob_entry.op {
-        Operation::LB  => state.memory[(rs1_s + imm_s) as usize] as i8 as i32,
-        Operation::LH  => state.memory.read_i16((rs1_s + imm_s) as usize).word as i32,
-        Operation::LW  => state.memory.read_i32((rs1_s + imm_s) as usize).word,
-        Operation::LBU => state.memory[(rs1_s + imm_s) as usize] as i32,
-        Operation::LHU => state.memory.read_u16((rs1_s + imm_s) as usize).word as i32,
         Operation::ECALL => {
-            match (state.register[Register::X11].data as u8) as char {
-                '\n' => {
-                    state.out.push(String::new())
-                }
-                a if a.is_ascii_graphic() || a.is_ascii_whitespace() => {
-                    let last = state.out.len() - 1;
-                    state.out[last].push(a)
-                }
-                _ => ()
-            }
+            resync = cm_ecall(state);
+            0
+        }
+        Operation::EBREAK => {
+            resync = cm_ebreak(state, rob_entry.pc);
+            0
+        }
+        // Return from the trap handler to the instruction in mepc. There is
+        // only machine mode, so sret does the same.
+        Operation::MRET |
+        Operation::SRET => {
+            state.reorder_buffer[entry].act_pc = state.csr.mepc as i32;
+            resync = true;
             0
         }
+        // There are no interrupts to wait for, so this wakes at once
+        Operation::WFI => 0,
+        // Memory accesses already commit in program order, and are seen by
+        // every later instruction, so there is nothing to order
+        Operation::FENCE => 0,
+        // Anything fetched after an instruction fetch fence is fetched again,
+        // in case it was fetched before being overwritten
+        Operation::FENCEI => {
+            resync = true;
+            0
+        }
+        Operation::CSRRW  |
+        Operation::CSRRS  |
+        Operation::CSRRC  |
+        Operation::CSRRWI |
+        Operation::CSRRSI |
+        Operation::CSRRCI => {
+            resync = true;
+            match cm_csr(state, rob_entry) {
+                Some(val) => val,
+                None => return true,
+            }
+        }
         _ => rob_entry.act_rd.unwrap()
     };
 
-    // Write back to register file (and ROB in case it was a load)
+    // Write back to register file (and ROB in case it was an ecall/ebreak)
     state.reorder_buffer[entry].act_rd = Some(rd_val);
     state.register.writeback(rob_entry.reg_rd.unwrap(), entry, rd_val);
-    state.register[Register::PC].data = rob_entry.act_pc;
+    let act_pc = state.reorder_buffer[entry].act_pc;
+    state.register[Register::PC].data = act_pc;
+
+    // The program has finished once it exits, or returns to address -1. As
+    // the example programs return nothing, returning is taken as success.
+    if act_pc == -1 {
+        state.exit_code = Some(0);
+    } else if state.exit_code.is_some() {
+        state.register[Register::PC].data = -1;
+        return true;
+    }
 
+    // System calls may have changed registers and memory behind the back of
+    // any instructions in flight, so they must be restarted. This also stops
+    // anything after a breakpoint committing before it is handled, passes the
+    // result of a CSR instruction to anything waiting on it, and refetches
+    // everything after an instruction fetch fence.
+    if resync {
+        state.resync_pipeline(act_pc as usize);
+        return true;
+    }
 
     // Branch prediction update and failure check
-    let next_pc = if (entry + 1) % rob.capacity != rob.back {
-        rob[(entry + 1) % rob.capacity].pc as i32
-    } else {
-        -1
-    };
+    let next_pc = next_pc(state_p, entry);
     if rob_entry.act_pc != next_pc && rob_entry.act_pc != -1 {
         if rob_entry.op == Operation::JALR {
-            state.branch_predictor.commit_feedback(rob_entry, true);
+            branch_feedback(state, rob_entry, true);
         }
         state.flush_pipeline(rob_entry.act_pc as usize);
         true
     } else {
         if rob_entry.op == Operation::JALR {
-            state.branch_predictor.commit_feedback(rob_entry, false);
+            branch_feedback(state, rob_entry, false);
             state.stats.bp_success += 1;
         }
         false
@@ -161,13 +455,6 @@ fn cm_i_type(state_p: &State, state: &mut State, entry: usize) -> bool {
 fn cm_s_type(state_p: &State, state: &mut State, entry: usize) -> bool {
     let rob = &state_p.reorder_buffer;
     let rob_entry = &rob[entry];
-    let rs1 = match rob_entry.rs1 {
-        Left(val) => val,
-        Right(name) => state
-            .reorder_buffer[name]
-            .act_rd
-            .expect("Commit S-type expected rs1!"),
-    };
     let rs2 = match rob_entry.rs2 {
         Left(val) => val,
         Right(name) => state
@@ -176,34 +463,52 @@ fn cm_s_type(state_p: &State, state: &mut State, entry: usize) -> bool {
             .expect("Commit S-type expected rs2!"),
     };
     let imm = rob_entry.imm.expect("Commit S type missing imm!");
+    let addr = match access_address(state, rob_entry, imm) {
+        Some(addr) => addr,
+        None => return true,
+    };
+    state.profile_access(addr, AccessKind::Store);
 
-    // Write back value to memory
-    match rob_entry.op {
-        Operation::SB => state.memory[(rs1 + imm) as usize] = rs2 as u8,
-        Operation::SH => {
-            state.memory.write_i16((rs1 + imm) as usize, rs2 as i16);
-            ()
-        }
-        Operation::SW => {
-            state.memory.write_i32((rs1 + imm) as usize, rs2);
-            ()
-        }
+    // Write back value to memory, or print it if it is for the UART
+    let size = match rob_entry.op {
+        Operation::SB => 1,
+        Operation::SH => 2,
+        Operation::SW => 4,
         _ => panic!("Unknown S-type instruction failed to commit."),
     };
+    let value = (rs2 as u32) & (u32::max_value() >> (32 - 8 * size));
+    state.reorder_buffer[entry].mem_write = Some((addr, value));
+    if in_uart(state.uart_addr, addr, size) {
+        // Only the low byte written to the data register is transmitted
+        if Some(addr) == state.uart_addr {
+            print_char(state, rs2 as u8 as char);
+        }
+    } else {
+        match size {
+            1 => state.memory.write_i8(addr, rs2 as i8),
+            2 => state.memory.write_i16(addr, rs2 as i16),
+            _ => state.memory.write_i32(addr, rs2),
+        };
+        if check_stack_guard(state, rob_entry, addr, size) || check_tohost(state, addr, size) {
+            return true;
+        }
+    }
 
     // Branch prediction failure check
-    let next_pc = if (entry + 1) % rob.capacity != rob.back {
-        rob[(entry + 1) % rob.capacity].pc as i32
-    } else {
-        -1
-    };
-    if rob_entry.act_pc == next_pc {
-        state.register[Register::PC].data = rob_entry.act_pc;
-        false
-    } else {
-        panic!(
-            format!("Did not expect S-type instruction to have mismatching PC! - {:?}", rob_entry)
-        )
+    let next_pc = next_pc(state_p, entry);
+    state.register[Register::PC].data = rob_entry.act_pc;
+    if check_fall_through(state, rob_entry, next_pc) {
+        return true;
+    }
+
+    // Memory ordering violation check, replaying if any younger load has
+    // already read the memory that was just written
+    match state.load_queue.find_violation(addr, size, entry, rob.capacity) {
+        Some(_) => {
+            state.replay_load(rob_entry.act_pc as usize);
+            true
+        }
+        None => check_stale_fetch(state, rob_entry, entry, addr, size),
     }
 }
 
@@ -212,19 +517,18 @@ fn cm_s_type(state_p: &State, state: &mut State, entry: usize) -> bool {
 fn cm_b_type(state_p: &State, state: &mut State, entry: usize) -> bool {
     let rob = &state_p.reorder_buffer;
     let rob_entry = &rob[entry];
+    if trap_misaligned(state, rob_entry) {
+        return true;
+    }
 
     // Branch prediction update and failure check
-    let next_pc = if (entry + 1) % rob.capacity != rob.back {
-        rob[(entry + 1) % rob.capacity].pc as i32
-    } else {
-        -1
-    };
+    let next_pc = next_pc(state_p, entry);
     if rob_entry.act_pc != next_pc && rob_entry.act_pc != -1 {
-        state.branch_predictor.commit_feedback(rob_entry, true);
+        branch_feedback(state, rob_entry, true);
         state.flush_pipeline(rob_entry.act_pc as usize);
         true
     } else {
-        state.branch_predictor.commit_feedback(rob_entry, false);
+        branch_feedback(state, rob_entry, false);
         state.register[Register::PC].data = rob_entry.act_pc;
         state.stats.bp_success += 1;
         false
@@ -241,18 +545,12 @@ fn cm_u_type(state_p: &State, state: &mut State, entry: usize) -> bool {
     state.register[Register::PC].data = rob_entry.act_pc;
 
     // Branch prediction failure
-    let next_pc = if (entry + 1) % rob.capacity != rob.back {
-        rob[(entry + 1) % rob.capacity].pc as i32
+    let next_pc = next_pc(state_p, entry);
+    if check_fall_through(state, rob_entry, next_pc) {
+        true
     } else {
-        -1
-    };
-    if rob_entry.act_pc == next_pc {
         state.stats.bp_success += 1;
         false
-    } else {
-        panic!(
-            format!("Did not expect U type instruction to have mismatching PC! - {:?}", rob_entry)
-        )
     }
 }
 
@@ -261,24 +559,770 @@ fn cm_u_type(state_p: &State, state: &mut State, entry: usize) -> bool {
 fn cm_j_type(state_p: &State, state: &mut State, entry: usize) -> bool {
     let rob = &state_p.reorder_buffer;
     let rob_entry = &rob[entry];
+    if trap_misaligned(state, rob_entry) {
+        return true;
+    }
 
     // Write back to register file
     state.register.writeback(rob_entry.reg_rd.unwrap(), entry, rob_entry.act_rd.unwrap());
     state.register[Register::PC].data = rob_entry.act_pc;
 
     // Branch prediction update and failure check
-    let next_pc = if (entry + 1) % rob.capacity != rob.back {
-        rob[(entry + 1) % rob.capacity].pc as i32
-    } else {
-        -1
-    };
+    let next_pc = next_pc(state_p, entry);
     if rob_entry.act_pc != next_pc && rob_entry.act_pc != -1 {
-        state.branch_predictor.commit_feedback(rob_entry, true);
+        branch_feedback(state, rob_entry, true);
         state.flush_pipeline(rob_entry.act_pc as usize);
         true
     } else {
-        state.branch_predictor.commit_feedback(rob_entry, false);
+        branch_feedback(state, rob_entry, false);
         state.stats.bp_success += 1;
         false
     }
 }
+
+/// Gives feedback on how a branch actually went to the branch predictor, as
+/// well as to any shadow predictors, where `mismatch` is set when the branch
+/// prediction failed. The branch is also counted against its program counter.
+fn branch_feedback(state: &mut State, rob_entry: &ReorderEntry, mismatch: bool) {
+    let counts = state.stats.branch_counts.entry(rob_entry.pc).or_insert((0, 0));
+    counts.0 += mismatch as u64;
+    counts.1 += 1;
+    state.branch_predictor.commit_feedback(rob_entry, mismatch, &mut state.stats);
+    for shadow in state.shadow_predictors.iter_mut() {
+        shadow.observe(rob_entry);
+    }
+}
+
+/// Checks a committed reorder buffer entry against the trace being replayed
+/// (if any), stopping the simulation if it diverged. Returns whether it
+/// diverged.
+fn check_replay(state: &mut State, entry: usize) -> bool {
+    let mut replay = match state.replay.take() {
+        Some(replay) => replay,
+        None => return false,
+    };
+    let result = replay.check(state, entry);
+    state.replay = Some(replay);
+    match result {
+        Ok(()) => false,
+        Err(messages) => {
+            replay_diverged(state, messages);
+            true
+        }
+    }
+}
+
+/// Reports how the simulation diverged from the trace being replayed on the
+/// console output, and stops the simulation.
+fn replay_diverged(state: &mut State, messages: Vec<String>) {
+    state.log(LogLevel::Warn, messages[0].clone());
+    for message in messages {
+        state.report(message);
+    }
+    if let Some(replay) = state.replay.as_mut() {
+        replay.diverged = true;
+    }
+    state.exit_code = None;
+    state.register[Register::PC].data = -1;
+}
+
+/// Restarts the pipeline from the instruction following a store, if any
+/// younger instruction was fetched from the `size` bytes at `addr` that it
+/// just wrote to, as they would be stale copies of self-modifying code.
+/// Returns whether the pipeline was restarted.
+fn check_stale_fetch(
+    state: &mut State,
+    rob_entry: &ReorderEntry,
+    entry: usize,
+    addr: usize,
+    size: usize,
+) -> bool {
+    if !state.fetched_after(entry, addr, size) {
+        return false;
+    }
+    state.log(
+        LogLevel::Info,
+        format!(
+            "Store to fetched instruction at {:08x}, restarting from {:08x}.",
+            addr, rob_entry.act_pc
+        ),
+    );
+    state.resync_pipeline(rob_entry.act_pc as usize);
+    true
+}
+
+/// Stops the simulation if a store of `size` bytes to `addr` wrote into the
+/// guard region below the stack limit, reporting the overflow on the console
+/// output. Returns whether the simulation was stopped.
+fn check_stack_guard(state: &mut State, rob_entry: &ReorderEntry, addr: usize, size: usize) -> bool {
+    if !state.in_stack_guard(addr, size) {
+        return false;
+    }
+    let message = format!(
+        "Stack overflow! Store to {:08x} by instruction at {:08x}.",
+        addr, rob_entry.pc
+    );
+    state.log(LogLevel::Warn, message.clone());
+    state.report(message);
+    state.register[Register::PC].data = -1;
+    true
+}
+
+/// Finishes a RISC-V test once a store writes a non-zero word to its `tohost`
+/// symbol, reporting whether it passed. The word is 1 for a pass, or
+/// otherwise holds the number of the failing test above its lowest bit.
+fn check_tohost(state: &mut State, addr: usize, size: usize) -> bool {
+    let tohost = match state.tohost {
+        Some(t) if addr <= t && t < addr + size => t,
+        _ => return false,
+    };
+    let value = state.memory.read_u32(tohost).word;
+    let message = match value {
+        0 => return false,
+        1 => String::from("PASS"),
+        v => format!("FAIL: test {}", v >> 1),
+    };
+    state.exit_code = Some((value >> 1) as i32);
+    state.log(LogLevel::Info, format!("RISC-V test finished, {}.", message));
+    state.report(message);
+    state.register[Register::PC].data = -1;
+    true
+}
+
+/// Computes the address accessed by a memory operation, from its `rs1` base
+/// and the given offset. If the address is out of range, the access runs past
+/// the end of the address space (other than to the UART), or it writes to a
+/// write-protected address, this traps with an access fault, returning
+/// `None`. Likewise, atomic memory operations (and loads and stores, if
+/// unaligned accesses trap) that are not aligned to their size trap with an
+/// address misaligned exception.
+fn access_address(state: &mut State, rob_entry: &ReorderEntry, offset: i32) -> Option<usize> {
+    let base = match rob_entry.rs1 {
+        Left(val) => val,
+        Right(name) => state
+            .reorder_buffer[name]
+            .act_rd
+            .expect("Commit memory access expected rs1!"),
+    };
+    let size = access_size(rob_entry.op);
+    let writes = !rob_entry.op.is_load() && rob_entry.op != Operation::LRW;
+    let message = match effective_address(base, offset) {
+        Some(addr) if addr + size > state.mem_size && !in_uart(state.uart_addr, addr, size) => {
+            format!(
+                "Access fault! Address {:08x} runs past the {} byte memory for instruction at {:08x}.",
+                addr, state.mem_size, rob_entry.pc
+            )
+        }
+        Some(addr) if writes && state.in_protected(addr, size) => format!(
+            "Access fault! Address {:08x} is write-protected for instruction at {:08x}.",
+            addr, rob_entry.pc
+        ),
+        Some(addr) => {
+            let strict = rob_entry.op.is_atomic() || state.unaligned == UnalignedAccess::Trap;
+            if strict && addr % size != 0 {
+                trap_unaligned(state, rob_entry, addr, size);
+                return None;
+            }
+            return Some(addr);
+        }
+        None => format!(
+            "Access fault! Address {:08x} {:+} out of range for instruction at {:08x}.",
+            base, offset, rob_entry.pc
+        ),
+    };
+    state.stats.access_faults += 1;
+    let trap = match rob_entry.op {
+        Operation::LRW => Trap::LoadAccessFault,
+        op if op.is_load() => Trap::LoadAccessFault,
+        _ => Trap::StoreAccessFault,
+    };
+    let tval = base.wrapping_add(offset) as u32;
+    take_trap(state, trap, rob_entry.pc, tval, message);
+    None
+}
+
+/// Traps with an address misaligned exception, for a memory operation that
+/// accesses an address not aligned to its size.
+fn trap_unaligned(state: &mut State, rob_entry: &ReorderEntry, addr: usize, size: usize) {
+    let trap = match rob_entry.op {
+        Operation::LRW => Trap::LoadMisaligned,
+        op if op.is_load() => Trap::LoadMisaligned,
+        _ => Trap::StoreMisaligned,
+    };
+    let message = format!(
+        "Address misaligned! Address {:08x} of instruction at {:08x} is not aligned to {} bytes.",
+        addr, rob_entry.pc, size
+    );
+    take_trap(state, trap, rob_entry.pc, addr as u32, message);
+}
+
+/// Checks that the resulting program counter of a control transfer
+/// instruction is aligned to an instruction, and if not traps with an
+/// instruction address misaligned exception. Returns whether the instruction
+/// trapped.
+fn trap_misaligned(state: &mut State, rob_entry: &ReorderEntry) -> bool {
+    if rob_entry.act_pc == -1 || rob_entry.act_pc % INSTRUCTION_ALIGNMENT == 0 {
+        return false;
+    }
+    state.stats.misaligned_targets += 1;
+    let message = format!(
+        "Instruction address misaligned! Target {:08x} of instruction at {:08x}.",
+        rob_entry.act_pc, rob_entry.pc
+    );
+    take_trap(state, Trap::InstructionMisaligned, rob_entry.pc, rob_entry.act_pc as u32, message);
+    true
+}
+
+/// Records how long a committed reorder buffer entry that was waiting on the
+/// result of a load took to issue after it, and whether it was the very next
+/// instruction.
+fn record_load_use(state_p: &State, state: &mut State, entry: usize) {
+    let rob = &state_p.reorder_buffer;
+    if let (Some(load), Some(cycles)) = (rob[entry].load_dep, rob[entry].load_use) {
+        state.stats.load_uses += 1;
+        state.stats.load_use_cycles += cycles;
+        if (load + 1) % rob.capacity == entry {
+            state.stats.load_uses_adjacent += 1;
+        }
+    }
+}
+
+/// Stops the simulation once the limit of retired instructions (if any) has
+/// been reached, unless the program has already finished.
+fn check_max_insns(state: &mut State) {
+    if state.max_insns == 0
+        || state.stats.retired() < state.max_insns
+        || state.register[Register::PC].data == -1
+    {
+        return;
+    }
+    let message = format!("Stopped after retiring {} instructions.", state.stats.retired());
+    state.log(LogLevel::Info, message.clone());
+    state.report(message);
+    state.register[Register::PC].data = -1;
+}
+
+/// Halts the simulation once no instruction has retired for the number of
+/// cycles that the pipeline is taken to be deadlocked after (if any), unless
+/// the program has already finished.
+fn check_deadlock(state_p: &State, state: &mut State) {
+    if state.stats.retired() != state_p.stats.retired() {
+        state.cycle_retired = state_p.stats.cycles;
+        return;
+    }
+    if state.deadlock_cycles == 0
+        || state_p.stats.cycles - state.cycle_retired < state.deadlock_cycles
+        || state.register[Register::PC].data == -1
+    {
+        return;
+    }
+    let message = format!(
+        "Deadlocked! Nothing has retired for {} cycles.",
+        state_p.stats.cycles - state.cycle_retired
+    );
+    state.log(LogLevel::Warn, message.clone());
+    state.report(message);
+    state.register[Register::PC].data = -1;
+    state.deadlocked = true;
+}
+
+/// Returns the program counter of the instruction that was fetched after the
+/// given reorder buffer entry. For the youngest entry in the reorder buffer,
+/// this is the next instruction waiting in the fetch queue or, failing that,
+/// the next instruction the branch predictor will fetch.
+fn next_pc(state_p: &State, entry: usize) -> i32 {
+    let rob = &state_p.reorder_buffer;
+    if (entry + 1) % rob.capacity != rob.back {
+        rob[(entry + 1) % rob.capacity].pc as i32
+    } else {
+        match state_p.latch_fetch.queue.front() {
+            Some(e) => e.pc as i32,
+            None => state_p.branch_predictor.get_prediction() as i32,
+        }
+    }
+}
+
+/// Checks that an instruction which does not alter control flow was followed
+/// by the instruction at its resulting program counter, and flushes the
+/// pipeline if not. Returns whether a full pipeline flush occured.
+fn check_fall_through(state: &mut State, rob_entry: &ReorderEntry, next_pc: i32) -> bool {
+    if rob_entry.act_pc == next_pc {
+        false
+    } else {
+        state.flush_pipeline(rob_entry.act_pc as usize);
+        true
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// TESTS
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::builder::{run, StateBuilder};
+    use crate::simulator::memory::INIT_MEMORY_SIZE;
+    use crate::simulator::step_cycle;
+    use crate::util::config::Config;
+
+    #[test]
+    fn commits_youngest_entry_across_wrap_around() {
+        // With two entries, the reorder buffer wraps around every other
+        // instruction, and each is often the youngest when committed
+        let mut config = Config::default();
+        config.rob_size = 2;
+        let mut builder = StateBuilder::new().config(config).reg(Register::X5, 1);
+        for _ in 0..7 {
+            builder = builder.asm("add x10 x10 x5").asm("sw x0 x10 256");
+        }
+        let mut state = builder.asm("lui x11 0x12345").asm("jalr x0 x1 0").build();
+        run(&mut state, 500);
+        assert_eq!(state.register[Register::X10].data, 7);
+        assert_eq!(state.register[Register::X11].data, 0x1234_5000);
+        assert_eq!(state.memory.read_i32(256).word, 7);
+        // Nothing was flushed as if mispredicted
+        assert_eq!(state.stats.bp_failure, 0);
+    }
+
+    #[test]
+    fn adds_atomically_returning_old_value() {
+        let mut state = StateBuilder::new()
+            .origin(0x1000)
+            .word(0x2000, 40)
+            .reg(Register::X6, 0x2000)
+            .reg(Register::X7, 2)
+            .asm("amoadd.w x10 x6 x7")
+            .asm("lw x11 x6 0")
+            .asm("jalr x0 x1 0")
+            .build();
+        run(&mut state, 100);
+        assert_eq!(state.register[Register::X10].data, 40);
+        assert_eq!(state.register[Register::X11].data, 42);
+        assert_eq!(state.memory.read_i32(0x2000).word, 42);
+    }
+
+    /// Runs twelve independent instructions on a wide machine with the given
+    /// commit width, returning the most instructions ever in flight in the
+    /// reorder buffer, and the most committed in one cycle.
+    fn run_wide(commit_width: usize) -> (usize, u64) {
+        let mut config = Config::default();
+        config.n_way = 4;
+        config.alu_units = 4;
+        config.issue_limit = 0;
+        config.commit_width = commit_width;
+        let mut builder = StateBuilder::new().config(config);
+        for n in 0..12 {
+            builder = builder.asm(&format!("addi x{} x0 {}", n + 5, n));
+        }
+        let mut state = builder.asm("jalr x0 x1 0").build();
+        let (mut backlog, mut widest) = (0, 0);
+        loop {
+            let retired = state.stats.retired();
+            let finished = step_cycle(&mut state);
+            backlog = backlog.max(state.reorder_buffer.in_flight());
+            widest = widest.max(state.stats.retired() - retired);
+            if finished {
+                break;
+            }
+        }
+        assert_eq!(state.register[Register::X16].data, 11);
+        (backlog, widest)
+    }
+
+    #[test]
+    fn narrow_commit_builds_backlog() {
+        let (wide_backlog, wide) = run_wide(0);
+        let (narrow_backlog, narrow) = run_wide(1);
+        assert!(wide > 1);
+        assert_eq!(narrow, 1);
+        assert!(narrow_backlog > wide_backlog);
+    }
+
+    /// Runs a single load of the given width from the given offset from
+    /// `0x100`, which holds the half-words `0x7fff` and `0x8000`, followed by
+    /// the bytes `0x7f` and `0x80`, returning the loaded value.
+    fn load(op: &str, offset: i32) -> i32 {
+        let mut state = StateBuilder::new()
+            .word(0x100, 0x8000_7fff_u32 as i32)
+            .word(0x104, 0x807f)
+            .reg(Register::X5, 0x100)
+            .asm(&format!("{} x10 x5 {}", op, offset))
+            .asm("jalr x0 x1 0")
+            .build();
+        run(&mut state, 100);
+        state.register[Register::X10].data
+    }
+
+    #[test]
+    fn sign_extends_signed_loads() {
+        assert_eq!(load("lb", 4), 0x7f);
+        assert_eq!(load("lb", 5), -0x80);
+        assert_eq!(load("lh", 0), 0x7fff);
+        assert_eq!(load("lh", 2), -0x8000);
+    }
+
+    #[test]
+    fn zero_extends_unsigned_loads() {
+        assert_eq!(load("lbu", 4), 0x7f);
+        assert_eq!(load("lbu", 5), 0x80);
+        assert_eq!(load("lhu", 0), 0x7fff);
+        assert_eq!(load("lhu", 2), 0x8000);
+    }
+
+    /// Stores to the given address with a stack limit at 0x8000, and then
+    /// sets `a0`, returning the state once finished.
+    fn store_near_stack_limit(addr: i32) -> State {
+        let mut config = Config::default();
+        config.stack_limit = Some(0x8000);
+        let mut state = StateBuilder::new()
+            .config(config)
+            .origin(0x1000)
+            .reg(Register::X5, addr)
+            .asm("sw x5 x0 0")
+            .asm("addi x10 x0 1")
+            .asm("jalr x0 x1 0")
+            .build();
+        run(&mut state, 100);
+        state
+    }
+
+    #[test]
+    fn store_below_stack_limit_trips_guard() {
+        let state = store_near_stack_limit(0x7ffc);
+        assert!(state.out.iter().any(|l| l.starts_with("Stack overflow!")));
+        assert_eq!(state.register[Register::X10].data, 0);
+    }
+
+    #[test]
+    fn store_above_stack_limit_is_allowed() {
+        let state = store_near_stack_limit(0x8000);
+        assert!(!state.out.iter().any(|l| l.starts_with("Stack overflow!")));
+        assert_eq!(state.register[Register::X10].data, 1);
+    }
+
+    /// Runs a program that reads up to 16 bytes of input into memory and
+    /// writes however many it read back out, with the given input file.
+    fn echo_input(name: &str, input: &[u8]) -> State {
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, input).unwrap();
+        let mut config = Config::default();
+        config.stdin = Some(path.to_string_lossy().into_owned());
+        let mut state = StateBuilder::new()
+            .config(config)
+            .asm("addi x17 x0 63")
+            .asm("addi x10 x0 0")
+            .asm("addi x11 x0 512")
+            .asm("addi x12 x0 16")
+            .asm("ecall")
+            .asm("addi x12 x10 0")
+            .asm("addi x17 x0 64")
+            .asm("addi x10 x0 1")
+            .asm("ecall")
+            .asm("addi x10 x12 0")
+            .asm("jalr x0 x1 0")
+            .build();
+        run(&mut state, 1000);
+        std::fs::remove_file(&path).unwrap();
+        state
+    }
+
+    #[test]
+    fn echoes_input_file() {
+        let state = echo_input("daybreak_echo_input", b"hello\n");
+        assert_eq!(state.register[Register::X10].data, 6);
+        assert_eq!(state.out, vec!["hello", ""]);
+    }
+
+    #[test]
+    fn reads_nothing_at_end_of_input() {
+        let state = echo_input("daybreak_empty_input", b"");
+        assert_eq!(state.register[Register::X10].data, 0);
+        assert_eq!(state.out, vec![""]);
+    }
+
+    /// Runs a single memory access with a base register of `0x7fffffff`,
+    /// whose address overflows.
+    fn access_past_max_address(line: &str) -> State {
+        let mut state = StateBuilder::new()
+            .reg(Register::X5, i32::MAX)
+            .reg(Register::X10, 3)
+            .asm(line)
+            .asm("addi x11 x0 1")
+            .asm("jalr x0 x1 0")
+            .build();
+        run(&mut state, 100);
+        assert_eq!(state.stats.access_faults, 1);
+        assert!(state
+            .out
+            .iter()
+            .any(|l| l.starts_with("Access fault! Address 7fffffff +4 out of range")));
+        assert_eq!(state.register[Register::X11].data, 0);
+        state
+    }
+
+    #[test]
+    fn load_past_max_address_traps() {
+        let state = access_past_max_address("lw x10 x5 4");
+        assert_eq!(state.register[Register::X10].data, 3);
+    }
+
+    #[test]
+    fn store_past_max_address_traps() {
+        access_past_max_address("sw x5 x10 4");
+    }
+
+    #[test]
+    fn store_to_uart_prints_byte() {
+        let mut config = Config::default();
+        config.uart_addr = Some(0x1000_0000);
+        let mut state = StateBuilder::new()
+            .config(config)
+            .asm("lui x5 0x10000")
+            .asm("addi x6 x0 72")
+            .asm("sb x5 x6 0")
+            .asm("addi x6 x0 0x169")
+            .asm("sw x5 x6 0")
+            .asm("lw x7 x5 4")
+            .asm("jalr x0 x1 0")
+            .build();
+        run(&mut state, 100);
+        assert_eq!(state.out, vec!["Hi"]);
+        assert_eq!(state.register[Register::X7].data, 1);
+        assert!(state.memory.len() < 0x1000_0000);
+    }
+
+    #[test]
+    fn counts_retired_operations() {
+        let mut state = StateBuilder::new()
+            .reg(Register::X6, 3)
+            .program(
+                "
+                    addi x5 x0 10
+                loop:
+                    add x10 x10 x6
+                    addi x5 x5 -1
+                    bne x5 x0 loop
+                    jalr x0 x1 0
+                ",
+            )
+            .build();
+        run(&mut state, 1000);
+        assert_eq!(state.register[Register::X10].data, 30);
+        let counts = &state.stats.op_counts;
+        assert_eq!(counts[&Operation::ADD], 10);
+        assert_eq!(counts[&Operation::ADDI], 11);
+        assert_eq!(counts[&Operation::BNE], 10);
+        assert_eq!(counts[&Operation::JALR], 1);
+        assert_eq!(counts.values().sum::<u64>(), state.stats.retired());
+    }
+
+    #[test]
+    fn reports_misaligned_branch_target() {
+        let mut state = StateBuilder::new()
+            .asm("addi x5 x0 1")
+            .asm("beq x0 x0 6")
+            .asm("addi x6 x0 1")
+            .asm("jalr x0 x1 0")
+            .build();
+        run(&mut state, 100);
+        assert_eq!(state.stats.misaligned_targets, 1);
+        assert_eq!(state.register[Register::X5].data, 1);
+        assert_eq!(state.register[Register::X6].data, 0);
+        let message = "Instruction address misaligned! Target 0000000a of instruction at 00000004.";
+        assert!(state.out.iter().any(|l| l == message));
+    }
+
+    #[test]
+    fn stops_after_retiring_max_insns() {
+        // Several instructions commit each cycle, but none past the limit
+        let mut config = Config::default();
+        config.n_way = 4;
+        config.commit_width = 4;
+        config.max_insns = 7;
+        let mut state = StateBuilder::new()
+            .config(config)
+            .asm("addi x10 x10 1")
+            .asm("jal x0 -4")
+            .build();
+        run(&mut state, 1000);
+        assert_eq!(state.stats.executed, 7);
+        assert_eq!(state.register[Register::X10].data, 4);
+        assert!(state
+            .out
+            .iter()
+            .any(|l| l == "Stopped after retiring 7 instructions."));
+    }
+
+    /// Runs a jump and link register from 0x4 to the given target, over an
+    /// instruction setting x6, to one setting x7.
+    fn run_jalr(target: i32) -> State {
+        let mut state = StateBuilder::new()
+            .reg(Register::X5, target)
+            .asm("addi x0 x0 0")
+            .asm("jalr x0 x5 0")
+            .asm("addi x6 x0 1")
+            .asm("addi x7 x0 1")
+            .asm("jalr x0 x1 0")
+            .build();
+        run(&mut state, 100);
+        state
+    }
+
+    #[test]
+    fn reports_misaligned_jalr_target() {
+        // The lowest bit is cleared, leaving an aligned target
+        let state = run_jalr(0xd);
+        assert_eq!(state.stats.misaligned_targets, 0);
+        assert_eq!(state.register[Register::X6].data, 0);
+        assert_eq!(state.register[Register::X7].data, 1);
+
+        // But the next lowest is not
+        let state = run_jalr(0xe);
+        assert_eq!(state.stats.misaligned_targets, 1);
+        assert_eq!(state.register[Register::X6].data, 0);
+        assert_eq!(state.register[Register::X7].data, 0);
+        let message = "Instruction address misaligned! Target 0000000e of instruction at 00000004.";
+        assert!(state.out.iter().any(|l| l == message));
+    }
+
+    #[test]
+    fn counts_mispredictions_per_branch() {
+        let mut state = StateBuilder::new()
+            .program(
+                "
+                    addi x5 x0 8
+                loop:
+                    andi x6 x5 1
+                    beq x6 x0 skip
+                    addi x10 x10 1
+                skip:
+                    addi x5 x5 -1
+                    bne x5 x0 loop
+                    jalr x0 x1 0
+                ",
+            )
+            .build();
+        run(&mut state, 1000);
+        assert_eq!(state.register[Register::X10].data, 4);
+        // The alternating branch mispredicts every time it is not taken
+        let counts = &state.stats.branch_counts;
+        assert_eq!(counts[&8], (4, 8));
+        assert_eq!(counts[&20], (1, 8));
+        assert_eq!(counts[&24], (0, 1));
+        assert_eq!(counts.len(), 3);
+    }
+
+    #[test]
+    fn executes_instruction_stored_ahead_of_fetch() {
+        // Overwrites `addi x10 x0 1` with `addi x10 x0 42` (0x02a00513) after
+        // it has been fetched, then jumps to it
+        let mut state = StateBuilder::new()
+            .reg(Register::X5, 0x02a0_0513)
+            .asm("sw x0 x5 12")
+            .asm("jal x0 8")
+            .asm("addi x0 x0 0")
+            .asm("addi x10 x0 1")
+            .asm("jalr x0 x1 0")
+            .build();
+        run(&mut state, 100);
+        assert_eq!(state.register[Register::X10].data, 42);
+        assert_eq!(state.memory.read_i32(12).word, 0x02a0_0513);
+    }
+
+    #[test]
+    fn retires_fences() {
+        let mut state = StateBuilder::new()
+            .asm("addi x10 x0 1")
+            .asm("sw x2 x10 -4")
+            .asm("fence x0 x0 255")
+            .asm("lw x11 x2 -4")
+            .asm("fencei x0 x0 0")
+            .asm("addi x10 x11 1")
+            .asm("jalr x0 x1 0")
+            .build();
+        run(&mut state, 1000);
+        assert_eq!(state.register[Register::X10].data, 2);
+        assert_eq!(state.stats.op_counts[&Operation::FENCE], 1);
+        assert_eq!(state.stats.op_counts[&Operation::FENCEI], 1);
+    }
+
+    #[test]
+    fn measures_load_use_distance() {
+        let mut state = StateBuilder::new()
+            .reg(Register::X10, 5)
+            .asm("sw x2 x10 -4")
+            .asm("lw x11 x2 -4")
+            .asm("addi x12 x11 1")
+            .asm("lw x13 x2 -4")
+            .asm("addi x0 x0 0")
+            .asm("add x14 x13 x12")
+            .asm("jalr x0 x1 0")
+            .build();
+        run(&mut state, 1000);
+        assert_eq!(state.register[Register::X14].data, 11);
+        // Only the first use immediately follows its load, but both wait for
+        // the load to finish executing before they can issue
+        assert_eq!(state.stats.load_uses, 2);
+        assert_eq!(state.stats.load_uses_adjacent, 1);
+        assert_eq!(state.stats.load_use_cycles, 8);
+    }
+
+    #[test]
+    fn store_past_memory_size_traps_to_handler() {
+        // The address is in range, but far beyond the end of the address
+        // space, so memory must not grow to reach it
+        let mut state = StateBuilder::new()
+            .program(
+                "
+                    addi x5 x0 28
+                    csrrw x0 x5 0x305
+                    lui x5 0x7ffff
+                    addi x10 x0 3
+                    sw x5 x10 0x7f0
+                    addi x11 x0 1
+                    jalr x0 x1 0
+                handler:
+                    csrrs x6 x0 0x342
+                    csrrs x7 x0 0x341
+                    addi x7 x7 4
+                    csrrw x0 x7 0x341
+                    mret
+                ",
+            )
+            .build();
+        run(&mut state, 1000);
+        assert_eq!(state.stats.access_faults, 1);
+        assert_eq!(state.stats.traps_taken, 1);
+        assert_eq!(state.register[Register::X6].data, 7);
+        assert_eq!(state.csr.mtval, 0x7fff_f7f0);
+        assert_eq!(state.register[Register::X11].data, 1);
+        assert_eq!(state.memory.len(), INIT_MEMORY_SIZE);
+    }
+
+    #[test]
+    fn store_into_protected_code_traps() {
+        // Code may still be read, and written to outside of the protection
+        let mut state = StateBuilder::new()
+            .asm("lw x6 x0 0")
+            .asm("addi x5 x0 0x100")
+            .asm("sw x5 x5 0")
+            .asm("sw x0 x5 4")
+            .asm("addi x11 x0 1")
+            .asm("jalr x0 x1 0")
+            .build();
+        state.protected = vec![(0, 24)];
+        let code = state.memory.read_i32(4).word;
+        run(&mut state, 100);
+        assert_eq!(
+            state.register[Register::X6].data,
+            state.memory.read_i32(0).word
+        );
+        assert_eq!(state.memory.read_i32(0x100).word, 0x100);
+        assert_eq!(state.memory.read_i32(4).word, code);
+        assert_eq!(state.register[Register::X11].data, 0);
+        assert_eq!(state.stats.access_faults, 1);
+        assert!(state.out.iter().any(|l| l.starts_with(
+            "Access fault! Address 00000004 is write-protected for instruction at 0000000c."
+        )));
+    }
+}
@@ -0,0 +1,75 @@
+///////////////////////////////////////////////////////////////////////////////
+//// CONST/STATIC
+
+/// The CSR number of `mtvec`, the base address of the trap handler.
+pub const CSR_MTVEC: u32 = 0x305;
+
+/// The CSR number of `mscratch`, a scratch register for the trap handler.
+pub const CSR_MSCRATCH: u32 = 0x340;
+
+/// The CSR number of `mepc`, the program counter of the trapped instruction.
+pub const CSR_MEPC: u32 = 0x341;
+
+/// The CSR number of `mcause`, the cause of the last trap.
+pub const CSR_MCAUSE: u32 = 0x342;
+
+/// The CSR number of `mtval`, the faulting address or instruction of the last
+/// trap.
+pub const CSR_MTVAL: u32 = 0x343;
+
+///////////////////////////////////////////////////////////////////////////////
+//// STRUCTS
+
+/// The machine mode control and status registers that are used for handling
+/// traps. Any other CSR is treated as not existing, so accessing it is an
+/// illegal instruction.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct CsrFile {
+    /// The base address of the trap handler, or 0 if none is installed. Only
+    /// the direct mode is supported, so the mode bits always read as 0.
+    pub mtvec: u32,
+    /// A scratch register, free for the trap handler to use.
+    pub mscratch: u32,
+    /// The program counter of the instruction that last trapped.
+    pub mepc: u32,
+    /// The cause of the last trap, see `Trap::cause`.
+    pub mcause: u32,
+    /// The faulting address or instruction of the last trap, or 0.
+    pub mtval: u32,
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// IMPLEMENTATIONS
+
+impl CsrFile {
+    /// Reads the CSR with the given number, returning `None` if it does not
+    /// exist.
+    pub fn read(&self, csr: u32) -> Option<u32> {
+        match csr {
+            CSR_MTVEC => Some(self.mtvec),
+            CSR_MSCRATCH => Some(self.mscratch),
+            CSR_MEPC => Some(self.mepc),
+            CSR_MCAUSE => Some(self.mcause),
+            CSR_MTVAL => Some(self.mtval),
+            _ => None,
+        }
+    }
+
+    /// Writes to the CSR with the given number, ignoring any bits that are
+    /// fixed to 0. The CSR must exist.
+    pub fn write(&mut self, csr: u32, value: u32) {
+        match csr {
+            CSR_MTVEC => self.mtvec = value & !0b11,
+            CSR_MSCRATCH => self.mscratch = value,
+            CSR_MEPC => self.mepc = value & !0b11,
+            CSR_MCAUSE => self.mcause = value,
+            CSR_MTVAL => self.mtval = value,
+            _ => panic!("Write to unknown CSR {:#x}.", csr),
+        }
+    }
+
+    /// Returns whether a trap handler has been installed in `mtvec`.
+    pub fn has_handler(&self) -> bool {
+        self.mtvec != 0
+    }
+}
@@ -3,60 +3,172 @@ use std::cmp::min;
 use either::{Either, Left, Right};
 
 use crate::isa::Instruction;
+use crate::isa::op_code::Operation;
 use crate::isa::operand::Register;
+use crate::util::log::LogLevel;
 
-use super::branch::ReturnStackOp;
+use super::fetch::FetchEntry;
 use super::reorder::ReorderEntry;
 use super::reservation::Reservation;
 use super::state::State;
+use super::trap::{take_trap, Trap};
+
+///////////////////////////////////////////////////////////////////////////////
+//// CONST/STATIC
+
+/// The base codes (bits `6-0`) of the instructions in the floating point
+/// extensions, which the simulator does not support.
+#[rustfmt::skip]
+const FLOAT_BASE_CODES: [i32; 7] = [
+    0x07, // LOAD-FP
+    0x27, // STORE-FP
+    0x43, // MADD
+    0x47, // MSUB
+    0x4b, // NMSUB
+    0x4f, // NMADD
+    0x53, // OP-FP
+];
+
+///////////////////////////////////////////////////////////////////////////////
+//// ENUMS
+
+/// The reasons that the _decode & rename_ stage may fail to reserve the
+/// resources for an instruction, causing a structural stall.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StallReason {
+    /// The reservation station has no free capacity.
+    ResvStationFull,
+    /// The reorder buffer has no free capacity.
+    ReorderBufferFull,
+    /// Every physical register is in use.
+    PhysRegFileFull,
+    /// The limit of branches and jumps in flight has been reached.
+    SpeculationLimit,
+}
 
 ///////////////////////////////////////////////////////////////////////////////
 //// FUNCTIONS
 
 /// The _Decode & Rename_ stage of the pipeline. This will decode
-/// instruction(s) from the previous
-/// [`LatchFetch`](../fetch/struct.LatchFetch.html) in the pipeline; perform
+/// instruction(s) from the front of the previous
+/// [`LatchFetch`](../fetch/struct.LatchFetch.html) instruction queue; perform
 /// register renaming; allocate a slot in the
 /// [`ReorderBuffer`](../reorder/struct.ReorderBuffer.html) and then place into
 /// the next stage in the pipeline, the
 /// [`ResvStation`](../reservation/struct.ResvStation.html).
 ///
-/// If sanitisation is not possible, this will stall the pipeline.
+/// Instructions only leave the queue once `decode_latency` cycles have passed
+/// since they were fetched, modelling a pipelined decoder.
+///
+/// If sanitisation is not possible, this will stall the pipeline, leaving the
+/// instruction in the queue to be retried in the next cycle.
 pub fn decode_and_rename_stage(state_p: &State, state: &mut State) {
-    state.decode_halt = false;
-    let limit = min(
-        state_p.latch_fetch.data.len(),
-        if state_p.decode_halt { 0 } else { state_p.n_way },
-    );
+    // Injected bubbles hold everything in the fetch queue for the cycle,
+    // which only delays it
+    if let Some(injector) = state.bubble_injector.as_mut() {
+        if injector.roll() {
+            state.stats.bubbles_injected += 1;
+            state.stall("bubble injected");
+            return;
+        }
+    }
+
+    // Only instructions fetched before this cycle are available to decode
+    let limit = min(state_p.latch_fetch.queue.len(), state_p.decode_width);
     for i in 0..limit {
-        let word = state_p.latch_fetch.data[i].word;
-        let bp_data = if i < state_p.latch_fetch.bp_data.len() {
-            state_p.latch_fetch.bp_data[i]
-        } else {
-            (ReturnStackOp::None, 0)
-        };
-        let pc = state_p.latch_fetch.pc + (4 * i);
-        let instr = match Instruction::decode(word) {
+        let entry = state.latch_fetch.queue[0];
+        if entry.cycle_fetched + state.decode_latency as u64 > state_p.stats.cycles {
+            if i == 0 {
+                state.stats.decode_wait_cycles += 1;
+            }
+            break;
+        }
+        let instr = match Instruction::decode(entry.access.word) {
             Some(i) => i,
+            // Only trap once every older instruction has committed, as until
+            // then this may turn out to be on a mispredicted path
+            None if state.reorder_buffer.in_flight() == 0 => {
+                if trap_illegal_instruction(state, entry) {
+                    continue;
+                }
+                break;
+            },
             None => {
-                state.stall(pc);
+                state.stall("illegal instruction may be speculative");
                 break;
             },
         };
 
-        let resv_result = sanitise_and_reserve(instr, bp_data, pc, state);
-
-        if resv_result.is_err() {
-            state.stall(pc);
-            break;
-        } else {
-            if state.branch_predictor.should_halt_decode(instr.op) {
+        match sanitise_and_reserve(instr, entry, state) {
+            Ok(()) => {
+                state.latch_fetch.queue.pop_front();
+            }
+            Err(reason) => {
+                match reason {
+                    StallReason::ResvStationFull => {
+                        state.stats.stalls_rsv += 1;
+                        state.stall("reservation station full");
+                    }
+                    StallReason::ReorderBufferFull => {
+                        state.stats.stalls_rob += 1;
+                        state.stall("reorder buffer full");
+                    }
+                    StallReason::PhysRegFileFull => {
+                        state.stats.stalls_prf += 1;
+                        state.stall("physical register file full");
+                    }
+                    StallReason::SpeculationLimit => {
+                        state.stats.stalls_spec += 1;
+                        state.stall("speculation depth reached");
+                    }
+                }
                 break;
             }
         }
     }
 }
 
+/// Handles an illegal instruction at the front of the fetch queue, trapping
+/// to the trap handler if one is installed. Floating point instructions are
+/// called out, as they are most likely from a program compiled for the wrong
+/// target. Without a trap handler, this halts the simulation, unless the
+/// simulator is set to continue past traps, where it is reported in the
+/// program output and skipped.
+///
+/// An all-zero word is always illegal, and is most likely uninitialised memory
+/// that the program has jumped into. This is never skipped, as skipping over
+/// it would only run on through the rest of the zeroes.
+///
+/// Returns whether or not decoding can carry on past the instruction.
+fn trap_illegal_instruction(state: &mut State, entry: FetchEntry) -> bool {
+    state.stats.illegal_instructions += 1;
+    let message = if FLOAT_BASE_CODES.contains(&(entry.access.word & 0x7f)) {
+        format!(
+            "Floating point instruction {:08x} at {:08x} is not supported, compile with -march=rv32im -mabi=ilp32.",
+            entry.access.word, entry.pc
+        )
+    } else if entry.access.word == 0 {
+        format!(
+            "Illegal instruction {:08x} at {:08x}, executing uninitialised memory.",
+            entry.access.word, entry.pc
+        )
+    } else {
+        format!(
+            "Illegal instruction {:08x} at {:08x}.",
+            entry.access.word, entry.pc
+        )
+    };
+    if state.csr.has_handler() || !state.trap_continue || entry.access.word == 0 {
+        take_trap(state, Trap::IllegalInstruction, entry.pc, entry.access.word as u32, message);
+        return false;
+    }
+    state.log(LogLevel::Warn, message.clone());
+    state.report(message);
+    state.latch_fetch.queue.pop_front();
+    state.register[Register::PC].data = (entry.pc as i32).wrapping_add(4);
+    true
+}
+
 /// Handles all the reservations for a decoded instruction.
 ///
 ///   1) Renames the writeback register.
@@ -66,41 +178,79 @@ pub fn decode_and_rename_stage(state_p: &State, state: &mut State) {
 /// Should always undo any resource allocations should a resource not be
 /// available when being run in a single thread.
 ///
-/// Returns whether or not all reservations were made succesffully.
+/// Returns whether or not all reservations were made succesffully, and if not
+/// the reason for the resulting stall.
 fn sanitise_and_reserve(
     instruction: Instruction,
-    bp_data: (ReturnStackOp, u8),
-    pc: usize,
+    entry: FetchEntry,
     state: &mut State,
-) -> Result<(), ()> {
-    // Check RS and ROB both have free capacity for a reservation
-    if !state.resv_station.free_capacity() || !state.reorder_buffer.free_capacity() {
-        return Err(());
+) -> Result<(), StallReason> {
+    let pc = entry.pc;
+    // Check RS and ROB both have free capacity for a reservation, and that
+    // there is a physical register for the result (if any)
+    if !state.resv_station.free_capacity() {
+        return Err(StallReason::ResvStationFull);
+    }
+    if !state.reorder_buffer.free_capacity() {
+        return Err(StallReason::ReorderBufferFull);
+    }
+    let writes_rd = instruction.rd.map_or(false, |rd| rd != Register::X0);
+    if writes_rd && state.phys_regs.free.is_empty() {
+        return Err(StallReason::PhysRegFileFull);
+    }
+    if state.spec_depth != 0
+        && instruction.op.is_control()
+        && branches_in_flight(state) >= state.spec_depth
+    {
+        return Err(StallReason::SpeculationLimit);
     }
 
-    // Get renamed registers for instruction (if required)
-    let rs1 = match instruction.rs1 {
-        Some(rs1) => get_read(state, rs1),
-        None => Left(0),
+    // Get renamed registers for instruction (if required), where the
+    // immediate CSR instructions hold a 5 bit immediate in place of rs1
+    #[rustfmt::skip]
+    let rs1 = match (instruction.op, instruction.rs1) {
+        (Operation::CSRRWI, Some(zimm)) |
+        (Operation::CSRRSI, Some(zimm)) |
+        (Operation::CSRRCI, Some(zimm)) => Left(zimm as i32),
+        (_, Some(rs1)) => get_read(state, rs1),
+        (_, None) => Left(0),
     };
     let rs2 = match instruction.rs2 {
         Some(rs2) => get_read(state, rs2),
         None => Left(0),
     };
 
-    // Reserve a reorder buffer entry
+    // Note any load that is yet to forward the result this depends on
+    let load_dep = [rs1, rs2]
+        .iter()
+        .filter_map(|rs| rs.right())
+        .find(|n| state.reorder_buffer[*n].op.is_load());
+
+    // Reserve a physical register and a reorder buffer entry
+    let phys_rd = if writes_rd { state.phys_regs.allocate() } else { None };
     let reorder_entry = ReorderEntry {
         finished: false,
         ref_count: 0,
-        bp_data,
+        bp_data: entry.bp_data,
         op: instruction.op,
         pc,
         act_pc: 0,
         act_rd: None,
         reg_rd: instruction.rd,
+        phys_rd,
         rs1,
         rs2,
         imm: instruction.imm,
+        mem_addr: entry.mem_addr,
+        cycle_fetched: entry.cycle_fetched,
+        cycle_reserved: state.stats.cycles,
+        cycle_issued: None,
+        execute_unit: None,
+        cycle_finished: None,
+        cycle_committed: None,
+        load_dep,
+        load_use: None,
+        mem_write: None,
     };
     let rob_entry = match state.reorder_buffer.reserve_entry(reorder_entry) {
         Some(entry) => entry,
@@ -121,13 +271,32 @@ fn sanitise_and_reserve(
         rs1,
         rs2,
         imm: instruction.imm,
+        depth: 0,
     };
+
+    // Lengthen the dependency chains of the instructions this depends on
+    if let Right(n) = rs1 {
+        state.resv_station.deepen(n, 1);
+    }
+    if let Right(n) = rs2 {
+        state.resv_station.deepen(n, 1);
+    }
+
     match state.resv_station.reserve(reservation) {
         Ok(()) => Ok(()),
         Err(()) => panic!("RS was free at start of reservation stage but not at the end!"),
     }
 }
 
+/// Returns the number of branches and jumps in the reorder buffer that are yet
+/// to be committed, and so may still turn out to be mispredicted.
+fn branches_in_flight(state: &State) -> usize {
+    let rob = &state.reorder_buffer;
+    (0..rob.in_flight())
+        .filter(|i| rob[rob.front_fin + i].op.is_control())
+        .count()
+}
+
 /// Either returns the valid value of the given register, or the reorder buffer
 /// entry that will hold the required result when ready.
 fn get_read(state: &mut State, register: Register) -> Either<i32, usize> {
@@ -135,11 +304,220 @@ fn get_read(state: &mut State, register: Register) -> Either<i32, usize> {
         Left(state.register[register].data)
     } else {
         let rename = state.register[register].rename.unwrap();
-        if state.reorder_buffer[rename].finished {
-            Left(state.reorder_buffer[rename].act_rd.unwrap_or(0))
-        } else {
-            state.reorder_buffer[rename].ref_count += 1;
-            Right(rename)
+        // Finished memory operations may only have a result once committed
+        match state.reorder_buffer[rename].act_rd {
+            Some(val) if state.reorder_buffer[rename].finished => Left(val),
+            _ => {
+                state.reorder_buffer[rename].ref_count += 1;
+                Right(rename)
+            }
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// TESTS
+
+#[cfg(test)]
+mod tests {
+    use super::branches_in_flight;
+    use crate::isa::operand::Register;
+    use crate::simulator::builder::{run, StateBuilder};
+    use crate::simulator::state::State;
+    use crate::simulator::step_cycle;
+    use crate::util::config::Config;
+
+    /// Runs a chain of dependent divides, which hold up everything after them.
+    fn run_divides(config: Config) -> State {
+        let mut builder = StateBuilder::new()
+            .config(config)
+            .reg(Register::X10, 1 << 30);
+        for _ in 0..8 {
+            builder = builder.asm("div x10 x10 x5");
+        }
+        let mut state = builder.reg(Register::X5, 2).asm("jalr x0 x1 0").build();
+        run(&mut state, 1000);
+        assert_eq!(state.register[Register::X10].data, 1 << 22);
+        state
+    }
+
+    #[test]
+    fn counts_full_reservation_station() {
+        let mut config = Config::default();
+        config.rsv_size = 2;
+        config.rob_size = 32;
+        let state = run_divides(config);
+        assert!(state.stats.stalls_rsv > 0);
+        assert_eq!(state.stats.stalls_rob, 0);
+    }
+
+    #[test]
+    fn counts_full_reorder_buffer() {
+        let mut config = Config::default();
+        config.rsv_size = 32;
+        config.rob_size = 2;
+        let state = run_divides(config);
+        assert!(state.stats.stalls_rob > 0);
+        assert_eq!(state.stats.stalls_rsv, 0);
+    }
+
+    #[test]
+    fn counts_full_physical_register_file() {
+        let mut config = Config::default();
+        config.rsv_size = 32;
+        config.rob_size = 32;
+        config.prf_size = 2;
+        let state = run_divides(config);
+        assert!(state.stats.stalls_prf > 0);
+        assert_eq!(state.stats.stalls_rob, 0);
+        assert_eq!(state.stats.stalls_rsv, 0);
+    }
+
+    /// Runs a program with an illegal instruction in the middle of it.
+    fn run_illegal(config: Config) -> State {
+        let mut state = StateBuilder::new()
+            .config(config)
+            .program("addi x5 x0 1\n.word 0xffffffff\naddi x6 x0 2\njalr x0 x1 0\n")
+            .build();
+        run(&mut state, 1000);
+        assert_eq!(state.stats.illegal_instructions, 1);
+        assert!(state
+            .out
+            .iter()
+            .any(|l| l.contains("Illegal instruction ffffffff at 00000004.")));
+        assert_eq!(state.register[Register::X5].data, 1);
+        state
+    }
+
+    #[test]
+    fn halts_at_illegal_instruction() {
+        let state = run_illegal(Config::default());
+        assert_eq!(state.register[Register::X6].data, 0);
+    }
+
+    #[test]
+    fn skips_illegal_instruction_when_continuing() {
+        let mut config = Config::default();
+        config.trap_continue = true;
+        let state = run_illegal(config);
+        assert_eq!(state.register[Register::X6].data, 2);
+    }
+
+    #[test]
+    fn reports_floating_point_instruction() {
+        // fadd.s f1, f2, f3
+        let mut state = StateBuilder::new()
+            .program("addi x5 x0 1\n.word 0x003100d3\njalr x0 x1 0\n")
+            .build();
+        run(&mut state, 1000);
+        assert_eq!(state.stats.illegal_instructions, 1);
+        let message = "Floating point instruction 003100d3 at 00000004 is not supported";
+        assert!(state.out.iter().any(|l| l.starts_with(message)));
+    }
+
+    /// Returns the cycle that the first instruction committed in, with the
+    /// given decode latency.
+    fn first_commit(decode_latency: usize) -> u64 {
+        let mut config = Config::default();
+        config.decode_latency = decode_latency;
+        let mut state = StateBuilder::new()
+            .config(config)
+            .asm("addi x10 x0 1")
+            .asm("jalr x0 x1 0")
+            .build();
+        run(&mut state, 100);
+        assert_eq!(state.register[Register::X10].data, 1);
+        state.reorder_buffer[0].cycle_committed.unwrap()
+    }
+
+    #[test]
+    fn decode_latency_delays_first_commit() {
+        assert_eq!(first_commit(2), first_commit(1) + 1);
+        assert_eq!(first_commit(4), first_commit(1) + 3);
+    }
+
+    #[test]
+    fn halts_in_zeroed_memory_even_when_continuing() {
+        let mut config = Config::default();
+        config.trap_continue = true;
+        let mut state = StateBuilder::new()
+            .config(config)
+            .asm("addi x5 x0 1")
+            .asm("jal x0 0x100")
+            .build();
+        run(&mut state, 1000);
+        assert_eq!(state.stats.illegal_instructions, 1);
+        let message = "Illegal instruction 00000000 at 00000104, executing uninitialised memory.";
+        assert!(state.out.iter().any(|l| l == message));
+        assert_eq!(state.register[Register::X5].data, 1);
+    }
+
+    /// Runs three branches waiting on a slow division, then a jump, with the
+    /// given limit on the branches in flight. Returns the most that were ever
+    /// in flight, and the state once finished.
+    fn most_branches_in_flight(spec_depth: usize) -> (usize, State) {
+        let mut config = Config::default();
+        config.n_way = 4;
+        config.spec_depth = spec_depth;
+        let mut state = StateBuilder::new()
+            .config(config)
+            .reg(Register::X6, 7)
+            .reg(Register::X7, 2)
+            .asm("div x5 x6 x7")
+            .asm("beq x5 x0 4")
+            .asm("beq x5 x0 4")
+            .asm("beq x5 x0 4")
+            .asm("addi x10 x0 1")
+            .asm("jalr x0 x1 0")
+            .build();
+        let mut most = 0;
+        while !step_cycle(&mut state) {
+            most = most.max(branches_in_flight(&state));
+            assert!(state.stats.cycles < 1000, "Did not finish.");
+        }
+        assert_eq!(state.register[Register::X5].data, 3);
+        assert_eq!(state.register[Register::X10].data, 1);
+        (most, state)
+    }
+
+    #[test]
+    fn speculates_past_one_branch_at_a_time() {
+        // The branches and the jump are all decoded behind the division
+        let (most, state) = most_branches_in_flight(0);
+        assert_eq!(most, 4);
+        assert_eq!(state.stats.stalls_spec, 0);
+
+        let (most, state) = most_branches_in_flight(1);
+        assert_eq!(most, 1);
+        assert!(state.stats.stalls_spec > 0);
+    }
+
+    #[test]
+    fn injected_bubbles_only_delay_decode() {
+        let base = run_divides(Config::default());
+        let mut config = Config::default();
+        config.inject_bubbles = Some((42, 0.0));
+        let never = run_divides(config.clone());
+        assert_eq!(never.stats.bubbles_injected, 0);
+        assert_eq!(never.stats.cycles, base.stats.cycles);
+
+        config.inject_bubbles = Some((42, 0.5));
+        let some = run_divides(config.clone());
+        assert!(some.stats.bubbles_injected > 0);
+        assert!(some.stats.cycles > base.stats.cycles);
+
+        // Decode stalls every cycle, so nothing ever reaches the reorder buffer
+        config.inject_bubbles = Some((42, 1.0));
+        let mut state = StateBuilder::new()
+            .config(config)
+            .asm("addi x10 x0 1")
+            .asm("jalr x0 x1 0")
+            .build();
+        for _ in 0..50 {
+            assert!(!step_cycle(&mut state));
         }
+        assert_eq!(state.stats.bubbles_injected, 50);
+        assert_eq!(state.reorder_buffer.count, 0);
+        assert_eq!(state.stats.retired(), 0);
     }
 }
@@ -1,11 +1,14 @@
 use std::collections::VecDeque;
-use std::fmt::{Display, Formatter, Result};
+use std::fmt::{Debug, Display, Formatter, Result};
 
 use either::{Left, Right};
 
 use crate::isa::op_code::Operation;
 use crate::isa::Format;
 
+use super::branch::jalr_target;
+use super::load::{access_size, LoadEntry, LoadQueue};
+use super::memory::{effective_address, in_uart, Memory, UART_READY, UART_STATUS_OFFSET};
 use super::reorder::ReorderBuffer;
 use super::reservation::{ResvStation, Reservation};
 use super::state::State;
@@ -24,33 +27,68 @@ pub enum UnitType {
     /// the program counter, causing the program to jump or branch to other
     /// instructions.
     BLU,
-    /// **Memory & Control Unit**, Responsible for load and store operations
-    /// that happen with main memory in order, as well as control operations
-    /// and system calls which also need to occur in order at the writeback
+    /// **Memory & Control Unit**, Responsible for load, store and atomic
+    /// operations with main memory, as well as control operations and system
+    /// calls. Loads read memory speculatively, whereas stores, atomics, control
+    /// operations and system calls need to occur in order at the writeback
     /// stage.
     MCU,
 }
 
+/// The policy used for loads and stores to addresses that are not aligned to
+/// their size, as hardware varies in how it handles these.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum UnalignedAccess {
+    /// Misaligned accesses raise an address misaligned exception.
+    Trap,
+    /// Misaligned accesses complete, but take extra cycles in the memory
+    /// control unit, as if fixed up in microcode.
+    Slow,
+    /// Misaligned accesses complete as quickly as aligned ones.
+    Fast,
+}
+
 ///////////////////////////////////////////////////////////////////////////////
 //// STRUCTS
 
-/// An Execute Unit is responsible for executing an instruction inside the
-/// simulated processor - it's the brains of the operation! In this
-/// implmentmentation, all Execute Unit's are made out of one of these objects,
-/// but instantiated differently depending on the type of execute unit they
-/// are, and will then accordingly behave differently.
+/// The pipeline of an execute unit, holding the executions that are in
+/// progress. This works the same way for every kind of execute unit, which
+/// differ in the operations that they execute.
 #[derive(Clone, Debug)]
-pub struct ExecuteUnit {
-    /// The type of execute unit this instantiated struct is.
-    pub unit_type: UnitType,
+pub struct Pipeline {
     /// The depth of the pipeline size for this execute unit. A value of 1 is
     /// a non-pipelined unit.
     pub pipeline_size: usize,
+    /// The number of steps left in an execution at which its result is
+    /// forwarded to the instructions waiting on it. A value of 0 forwards the
+    /// result as it is written back, and a value of 1 a cycle earlier.
+    pub forward_at_step: u8,
     /// The pipeline of executing instructions, and how many cycles left in the
     /// execution of the instruction.
     pub executing: VecDeque<(ExecuteResult, ExecutionLen)>,
 }
 
+/// The **Arithmetic Logic Unit**, executing the operations of
+/// [`UnitType::ALU`](enum.UnitType.html#variant.ALU).
+#[derive(Clone, Debug)]
+pub struct Alu {
+    pipeline: Pipeline,
+}
+
+/// The **Branch Logic Unit**, executing the operations of
+/// [`UnitType::BLU`](enum.UnitType.html#variant.BLU).
+#[derive(Clone, Debug)]
+pub struct Blu {
+    pipeline: Pipeline,
+}
+
+/// The **Memory & Control Unit**, executing the operations of
+/// [`UnitType::MCU`](enum.UnitType.html#variant.MCU).
+#[derive(Clone, Debug)]
+pub struct Mcu {
+    pipeline: Pipeline,
+}
+
 /// The resulting bus that holds the results from the execute unit upon
 /// completion. The execute unit will write directly to the reorder buffer.
 #[derive(Copy, Clone, Debug)]
@@ -72,6 +110,91 @@ pub struct ExecutionLen {
     pub steps: u8,
 }
 
+///////////////////////////////////////////////////////////////////////////////
+//// TRAITS
+
+/// An Execute Unit is responsible for executing an instruction inside the
+/// simulated processor - it's the brains of the operation! Every kind of
+/// execute unit moves executions through its [`Pipeline`](struct.Pipeline.html)
+/// the same way, and only decides what the operations it handles do. Other
+/// kinds of unit can be added to a [`State`](../state/struct.State.html) by
+/// implementing this trait, without touching the built in units.
+pub trait ExecuteUnit: Debug + Send + Sync {
+    /// Returns the name of this execute unit, which is how it is shown. The
+    /// built in units are named after their
+    /// [`UnitType`](enum.UnitType.html), but other units may use any name.
+    /// Units with the same name are numbered together, in the order that
+    /// they are in the state.
+    fn name(&self) -> &str;
+
+    /// Returns the pipeline of executions in progress.
+    fn pipeline(&self) -> &Pipeline;
+
+    /// Returns the pipeline of executions in progress, mutably.
+    fn pipeline_mut(&mut self) -> &mut Pipeline;
+
+    /// Returns a boxed copy of this execute unit, as the state holding it is
+    /// cloned every cycle.
+    fn clone_box(&self) -> Box<dyn ExecuteUnit>;
+
+    /// Executes a reservation for one of the operations that this unit
+    /// handles, returning its result. Any loads that read from memory are
+    /// recorded in the given load queue.
+    fn execute(
+        &mut self,
+        state_p: &State,
+        reservation: &Reservation,
+        load_queue: &mut LoadQueue,
+    ) -> ExecuteResult;
+
+    /// Returns whether this execute unit is capable of executing the given
+    /// operation, which decides what is issued and routed to it.
+    fn handles(&self, op: Operation) -> bool;
+
+    /// Returns how long this execute unit takes to execute the given
+    /// operation, and whether it blocks the pipeline whilst doing so.
+    fn execution_len(&self, op: Operation) -> ExecutionLen {
+        ExecutionLen::from(op)
+    }
+
+    /// Indicates whether or not this Execute Unit is free to take on another
+    /// instruction of the given operation.
+    fn is_free(&self, op: Operation) -> bool {
+        self.pipeline().is_free(self.execution_len(op))
+    }
+
+    /// Handles the logic for the execution of an
+    /// [`Operation`](../../isa/op_code/enum.Operation.html) that this
+    /// execution unit is responsible for. If the execute unit is pipelined,
+    /// this will add the execution to the pipeline. Any loads that read from
+    /// memory are recorded in the given load queue.
+    fn handle_issue(
+        &mut self,
+        state_p: &State,
+        reservation: &Reservation,
+        load_queue: &mut LoadQueue,
+    ) {
+        if !self.handles(reservation.op) {
+            panic!(
+                "Execute Unit ({}) was given Operation ({:?}) that it is incapable of processing",
+                self.name(),
+                reservation.op
+            )
+        }
+
+        let result = self.execute(state_p, reservation, load_queue);
+        let mut len = self.execution_len(reservation.op);
+
+        // Misaligned loads and stores hold the unit for longer when they are
+        // fixed up
+        let rob = &state_p.reorder_buffer;
+        if state_p.unaligned == UnalignedAccess::Slow && is_misaligned(reservation, rob) {
+            len.steps = len.steps.saturating_add(state_p.unaligned_penalty);
+        }
+        self.pipeline_mut().executing.push_back((result, len));
+    }
+}
+
 ///////////////////////////////////////////////////////////////////////////////
 //// IMPLEMENTATIONS
 
@@ -120,6 +243,9 @@ impl From<Operation> for ExecutionLen {
             Operation::FENCEI => ExecutionLen { blocking: false, steps: 1 },
             Operation::ECALL  => ExecutionLen { blocking: false, steps: 1 },
             Operation::EBREAK => ExecutionLen { blocking: false, steps: 1 },
+            Operation::MRET   => ExecutionLen { blocking: false, steps: 1 },
+            Operation::SRET   => ExecutionLen { blocking: false, steps: 1 },
+            Operation::WFI    => ExecutionLen { blocking: false, steps: 1 },
             Operation::CSRRW  => ExecutionLen { blocking: false, steps: 1 },
             Operation::CSRRS  => ExecutionLen { blocking: false, steps: 1 },
             Operation::CSRRC  => ExecutionLen { blocking: false, steps: 1 },
@@ -134,6 +260,17 @@ impl From<Operation> for ExecutionLen {
             Operation::DIVU   => ExecutionLen { blocking:  true, steps: 7 },
             Operation::REM    => ExecutionLen { blocking:  true, steps: 7 },
             Operation::REMU   => ExecutionLen { blocking:  true, steps: 7 },
+            Operation::LRW      => ExecutionLen { blocking:  true, steps: 3 },
+            Operation::SCW      => ExecutionLen { blocking:  true, steps: 3 },
+            Operation::AMOSWAPW => ExecutionLen { blocking:  true, steps: 3 },
+            Operation::AMOADDW  => ExecutionLen { blocking:  true, steps: 3 },
+            Operation::AMOXORW  => ExecutionLen { blocking:  true, steps: 3 },
+            Operation::AMOANDW  => ExecutionLen { blocking:  true, steps: 3 },
+            Operation::AMOORW   => ExecutionLen { blocking:  true, steps: 3 },
+            Operation::AMOMINW  => ExecutionLen { blocking:  true, steps: 3 },
+            Operation::AMOMAXW  => ExecutionLen { blocking:  true, steps: 3 },
+            Operation::AMOMINUW => ExecutionLen { blocking:  true, steps: 3 },
+            Operation::AMOMAXUW => ExecutionLen { blocking:  true, steps: 3 },
         }
     }
 }
@@ -143,7 +280,7 @@ impl From<Operation> for UnitType {
     fn from(op: Operation) -> UnitType {
         match op {
             Operation::LUI    => UnitType::ALU,
-            Operation::AUIPC  => UnitType::BLU,
+            Operation::AUIPC  => UnitType::ALU,
             Operation::JAL    => UnitType::BLU,
             Operation::JALR   => UnitType::BLU,
             Operation::BEQ    => UnitType::BLU,
@@ -183,6 +320,9 @@ impl From<Operation> for UnitType {
             Operation::FENCEI => UnitType::MCU,
             Operation::ECALL  => UnitType::MCU,
             Operation::EBREAK => UnitType::MCU,
+            Operation::MRET   => UnitType::MCU,
+            Operation::SRET   => UnitType::MCU,
+            Operation::WFI    => UnitType::MCU,
             Operation::CSRRW  => UnitType::MCU,
             Operation::CSRRS  => UnitType::MCU,
             Operation::CSRRC  => UnitType::MCU,
@@ -197,27 +337,62 @@ impl From<Operation> for UnitType {
             Operation::DIVU   => UnitType::ALU,
             Operation::REM    => UnitType::ALU,
             Operation::REMU   => UnitType::ALU,
+            Operation::LRW      => UnitType::MCU,
+            Operation::SCW      => UnitType::MCU,
+            Operation::AMOSWAPW => UnitType::MCU,
+            Operation::AMOADDW  => UnitType::MCU,
+            Operation::AMOXORW  => UnitType::MCU,
+            Operation::AMOANDW  => UnitType::MCU,
+            Operation::AMOORW   => UnitType::MCU,
+            Operation::AMOMINW  => UnitType::MCU,
+            Operation::AMOMAXW  => UnitType::MCU,
+            Operation::AMOMINUW => UnitType::MCU,
+            Operation::AMOMAXUW => UnitType::MCU,
         }
     }
 }
 
-impl ExecuteUnit {
-    /// Constructs a new execute unit with given properties.
-    pub fn new(unit_type: UnitType, pipeline_size: usize) -> ExecuteUnit {
-        ExecuteUnit {
-            unit_type,
-            pipeline_size,
-            executing: VecDeque::new(),
+impl Default for UnalignedAccess {
+    /// Defaults to ignoring alignment.
+    fn default() -> UnalignedAccess {
+        UnalignedAccess::Fast
+    }
+}
+
+impl Display for UnitType {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        if f.alternate() {
+            match self {
+                UnitType::ALU => f.pad("A"),
+                UnitType::BLU => f.pad("B"),
+                UnitType::MCU => f.pad("M"),
+            }
+        } else {
+            f.pad(&format!("{:?}", self))
         }
     }
+}
 
-    /// Returns what type of execution unit this is.
-    pub fn get_type(&self) -> UnitType {
-        self.unit_type
+impl Clone for Box<dyn ExecuteUnit> {
+    fn clone(&self) -> Box<dyn ExecuteUnit> {
+        self.clone_box()
     }
+}
 
-    /// Indicates whether or not this Execute Unit is free to take on another
-    /// specified instruction.
+////////////////////////////////////////////////////////////////////// Pipeline
+
+impl Pipeline {
+    /// Constructs a new, empty pipeline with the given properties.
+    pub fn new(pipeline_size: usize, forward_at_step: u8) -> Pipeline {
+        Pipeline {
+            pipeline_size,
+            forward_at_step,
+            executing: VecDeque::new(),
+        }
+    }
+
+    /// Indicates whether or not this pipeline is free to take on another
+    /// execution of the given length.
     pub fn is_free(&self, el: ExecutionLen) -> bool {
         if el.blocking {
             return self.executing.is_empty();
@@ -237,44 +412,25 @@ impl ExecuteUnit {
         }
     }
 
-    /// Handles the logic for the execution of an
-    /// [`Operation`](../../isa/op_code/enum.Operation.html) that this
-    /// execution unit is responsible for. If the execute unit is pipelined,
-    /// this will add the execution to the pipeline.
-    pub fn handle_issue(
-        &mut self,
-        state_p: &State,
-        reservation: &Reservation,
-    ) {
-        if self.unit_type != UnitType::from(reservation.op) {
-            panic!(format!(
-                "Execute Unit ({:?}) was given Operation ({:?}) that it is incapable of processing",
-                self.unit_type, reservation.op
-            ))
-        }
-
-        match Format::from(reservation.op) {
-            Format::R => self.ex_r_type(reservation, &state_p.reorder_buffer),
-            Format::I => self.ex_i_type(reservation, &state_p.reorder_buffer),
-            Format::S => self.ex_s_type(reservation),
-            Format::B => self.ex_b_type(reservation, &state_p.reorder_buffer),
-            Format::U => self.ex_u_type(reservation),
-            Format::J => self.ex_j_type(reservation),
-        }
-    }
-
-    /// Triggers an exection step, only modifying the given new execution unit.
-    /// Will advance the exectuion pipeline, writing any completed executions
-    /// to the reorder buffer, also setting the finished bit.
-    pub fn advance_pipeline(
+    /// Triggers an exection step, only modifying the given new pipeline. Will
+    /// advance the exectuion pipeline, writing any completed executions to
+    /// the reorder buffer, also setting the finished bit and the current
+    /// `cycle` that it finished in. Results may also be forwarded before they
+    /// complete, depending on `forward_at_step`.
+    ///
+    /// A completed execution is only written back if `bus_free`, otherwise it
+    /// stays at the front of the pipeline until a later cycle.
+    pub fn advance(
         &self,
-        new_eu: &mut ExecuteUnit,
+        new: &mut Pipeline,
         rob: &mut ReorderBuffer,
-        rs: &mut ResvStation
+        rs: &mut ResvStation,
+        cycle: u64,
+        bus_free: bool,
     ) {
         // Ensure we do not minus 1 from an execution added to the new state in
         // the issue stage (which may have touched the execute unit already)
-        let iter = new_eu.executing.iter_mut().take(self.executing.len());
+        let iter = new.executing.iter_mut().take(self.executing.len());
 
         // Progress all executions in pipeline
         for (_, len) in iter {
@@ -284,22 +440,26 @@ impl ExecuteUnit {
         }
 
         // If instruction has finished, write back to reorder buffer
-        if let Some((_, el)) = new_eu.executing.front() {
-            if el.steps == 0 {
-                let result: ExecuteResult = new_eu.executing.pop_front().unwrap().0;
+        if let Some((_, el)) = new.executing.front() {
+            if el.steps == 0 && bus_free {
+                let result: ExecuteResult = new.executing.pop_front().unwrap().0;
                 rob[result.rob_entry].act_pc = result.pc;
                 rob[result.rob_entry].act_rd = result.rd;
                 rob[result.rob_entry].finished = true;
+                rob[result.rob_entry].cycle_finished = Some(cycle);
 
                 match rob[result.rob_entry].op {
-                    Operation::LB  |
-                    Operation::LH  |
-                    Operation::LW  |
-                    Operation::LBU |
-                    Operation::LHU |
-                    Operation::SB  |
-                    Operation::SH  |
-                    Operation::SW  => (),
+                    op if op.is_load() => {
+                        // Loads have already read memory speculatively, so
+                        // can bypass their result now, as any that read stale
+                        // memory are replayed along with their dependents.
+                        // Their dependencies are still needed at commit.
+                        if let Some(rd) = result.rd {
+                            rs.execute_bypass(result.rob_entry, rd);
+                            rob.execute_bypass(result.rob_entry, rd);
+                        }
+                    }
+                    op if op.is_memory() => (),
                     _ => {
                         // Bypass, let everyone that is waiting for this
                         // register know it's value. (Lower down values).
@@ -321,16 +481,50 @@ impl ExecuteUnit {
                 }
             }
         }
+
+        // Forward the results that are ready early to anything waiting on
+        // them, ahead of them being written back
+        if new.forward_at_step > 0 {
+            for (result, len) in new.executing.iter() {
+                match result.rd {
+                    Some(rd) if len.steps == new.forward_at_step => {
+                        rs.execute_bypass(result.rob_entry, rd);
+                        rob.execute_bypass(result.rob_entry, rd);
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    /// Returns the reorder buffer entry of the execution that completes in
+    /// this cycle (if any), which needs a result bus to be written back.
+    pub fn completing(&self) -> Option<usize> {
+        match self.executing.front() {
+            Some((result, len)) if len.steps <= 1 => Some(result.rob_entry),
+            _ => None,
+        }
     }
 
-    /// Flushes the execute unit, this would happen when the pipeline is
+    /// Flushes the pipeline, this would happen when the pipeline is
     /// invalidated and needs to be restarted from scratch.
     pub fn flush(&mut self) {
         self.executing.clear()
     }
+}
+
+/////////////////////////////////////////////////////////////////////////// Alu
+
+impl Alu {
+    /// Constructs a new arithmetic logic unit with given properties.
+    pub fn new(pipeline_size: usize, forward_at_step: u8) -> Alu {
+        Alu {
+            pipeline: Pipeline::new(pipeline_size, forward_at_step),
+        }
+    }
 
-    /// Executes an R type instruction, putting the results in self.
-    fn ex_r_type(&mut self, r: &Reservation, rob: &ReorderBuffer) {
+    /// Executes an R type instruction, returning its result.
+    fn ex_r_type(r: &Reservation, rob: &ReorderBuffer) -> ExecuteResult {
         let rs1_s = match r.rs1 {
             Left(val) => val,
             Right(name) => rob[name]
@@ -343,142 +537,118 @@ impl ExecuteUnit {
                 .act_rd
                 .expect("Execute unit ({:?}) R-type expected rs2!"),
         };
-        let rs1_u = rs1_s as u32;
-        let rs2_u = rs2_s as u32;
-        #[rustfmt::skip]
-        let rd_val = match r.op {
-            Operation::ADD    => rs1_s.overflowing_add(rs2_s).0,
-            Operation::SUB    => rs1_s.overflowing_sub(rs2_s).0,
-            Operation::SLL    => rs1_s << (rs2_s & 0b11111),
-            Operation::SLT    => (rs1_s < rs2_s) as i32,
-            Operation::SLTU   => (rs1_u < rs2_u) as i32,
-            Operation::XOR    => rs1_s ^ rs2_s,
-            Operation::SRL    => (rs1_u >> (rs2_u & 0b11111)) as i32,
-            Operation::SRA    => rs1_s >> (rs2_s & 0b11111),
-            Operation::OR     => rs1_s | rs2_s,
-            Operation::AND    => rs1_s & rs2_s,
-            Operation::MUL    => rs1_s.overflowing_mul(rs2_s).0,
-            Operation::MULH   => ((i64::from(rs1_s) * i64::from(rs2_s)) >> 32) as i32,
-            Operation::MULHU  => ((u64::from(rs1_u) * u64::from(rs2_u)) >> 32) as i32,
-            Operation::MULHSU => ((i64::from(rs1_s) * i64::from(rs2_u)) >> 32) as i32,
-            Operation::DIV    => match rs2_s {
-                                     0  => -1i32,
-                                     _  => match rs1_s.overflowing_div(rs2_s) {
-                                         (_, true) => i32::min_value(),
-                                         (v, _)    => v,
-                                     },
-                                 },
-            Operation::DIVU   => match rs2_s {
-                                     0  => i32::max_value(),
-                                     _  => (rs1_u / rs2_u) as i32,
-                                 },
-            Operation::REM    => match rs2_s {
-                                     0 => rs1_s,
-                                     _ => match rs1_s.overflowing_div(rs2_s) {
-                                         (_, true) => 0,
-                                         (v, _)    => v,
-                                     }
-                                 },
-            Operation::REMU   => match rs2_s {
-                                     0 => rs1_s,
-                                     _ => (rs1_u % rs2_u) as i32,
-                                 },
-            _ => panic!("Unknown R-type instruction failed to execute.")
-        };
+        let rd_val = alu_r_result(r.op, rs1_s, rs2_s);
 
-        self.executing.push_back((
-            ExecuteResult {
-                rob_entry: r.rob_entry,
-                pc: r.pc as i32 + 4,
-                rd: Some(rd_val),
-            },
-            ExecutionLen::from(r.op),
-        ))
+        ExecuteResult {
+            rob_entry: r.rob_entry,
+            pc: (r.pc as i32).wrapping_add(4),
+            rd: Some(rd_val),
+        }
     }
 
-    /// Executes an I type instruction, modifying the borrowed state.
-    fn ex_i_type(&mut self, r: &Reservation, rob: &ReorderBuffer) {
+    /// Executes an I type instruction, returning its result.
+    fn ex_i_type(r: &Reservation, rob: &ReorderBuffer) -> ExecuteResult {
         let rs1_s = match r.rs1 {
             Left(val) => val,
             Right(name) => rob[name]
                 .act_rd
                 .expect("Execute unit ({:?}) I-type expected rs1!"),
         };
-        let rs1_u = rs1_s as u32;
         let imm_s = r.imm.expect("Execute unit I-type missing imm!");
-        let imm_u = imm_s as u32;
+        let rd_val = alu_i_result(r.op, rs1_s, imm_s);
 
-        #[rustfmt::skip]
+        ExecuteResult {
+            rob_entry: r.rob_entry,
+            pc: (r.pc as i32).wrapping_add(4),
+            rd: Some(rd_val),
+        }
+    }
+
+    /// Executes an U type instruction, returning its result.
+    fn ex_u_type(r: &Reservation) -> ExecuteResult {
+        let pc = r.pc as i32;
+        // The immediate is already in the upper 20 bits, with the rest clear
+        let imm = r.imm.expect("Execute unit U-type missing imm!");
+
+        // AUIPC only reads the program counter of the instruction itself, and
+        // never changes control flow, so does not need a branch logic unit.
         let rd_val = match r.op {
-            Operation::JALR   => Some(r.pc as i32 + 4),
-            Operation::LB     => None, //
-            Operation::LH     => None, //
-            Operation::LW     => None, // All done in commit stage
-            Operation::LBU    => None, //
-            Operation::LHU    => None, //
-            Operation::ADDI   => Some( rs1_s +  imm_s),
-            Operation::SLTI   => Some((rs1_s <  imm_s) as i32),
-            Operation::SLTIU  => Some((rs1_u <  imm_u) as i32),
-            Operation::XORI   => Some( rs1_s ^  imm_s),
-            Operation::ORI    => Some( rs1_s |  imm_s),
-            Operation::ANDI   => Some( rs1_s &  imm_s),
-            Operation::SLLI   => Some( rs1_s << imm_s),
-            Operation::SRLI   => Some((rs1_u >> imm_u) as i32),
-            Operation::SRAI   => Some( rs1_s >> (imm_s & 0b11111)),
-            Operation::FENCE  => unimplemented!(),
-            Operation::FENCEI => unimplemented!(),
-            Operation::ECALL  => None, // Done in commit stage
-            Operation::EBREAK => unimplemented!(),
-            Operation::CSRRW  => unimplemented!(),
-            Operation::CSRRS  => unimplemented!(),
-            Operation::CSRRC  => unimplemented!(),
-            Operation::CSRRWI => unimplemented!(),
-            Operation::CSRRSI => unimplemented!(),
-            Operation::CSRRCI => unimplemented!(),
-            _ => panic!("Unknown I-type instruction failed to execute.")
+            Operation::LUI => imm,
+            Operation::AUIPC => pc.wrapping_add(imm),
+            _ => panic!("Unknown U-type instruction failed to execute."),
         };
 
-        let pc_val = if r.op == Operation::JALR {
-            if rs1_s != -1 {
-                (rs1_s + imm_s) & !0b1
-            } else {
-                -1
-            }
-        } else {
-            r.pc as i32 + 4
-        };
+        ExecuteResult {
+            rob_entry: r.rob_entry,
+            pc: pc.wrapping_add(4),
+            rd: Some(rd_val),
+        }
+    }
+}
 
-        self.executing.push_back((
-            ExecuteResult {
-                rob_entry: r.rob_entry,
-                pc: pc_val,
-                rd: rd_val,
-            },
-            ExecutionLen::from(r.op),
-        ))
+impl ExecuteUnit for Alu {
+    fn name(&self) -> &str {
+        "ALU"
     }
 
-    /// Executes an S type instruction, modifying the borrowed state.
-    fn ex_s_type(&mut self, r: &Reservation) {
-        match r.op {
-            Operation::SB => (), //
-            Operation::SH => (), // All done in commit stage
-            Operation::SW => (), //
-            _ => panic!("Unknown S-type instruction failed to execute."),
+    fn handles(&self, op: Operation) -> bool {
+        UnitType::from(op) == UnitType::ALU
+    }
+
+    fn pipeline(&self) -> &Pipeline {
+        &self.pipeline
+    }
+
+    fn pipeline_mut(&mut self) -> &mut Pipeline {
+        &mut self.pipeline
+    }
+
+    fn clone_box(&self) -> Box<dyn ExecuteUnit> {
+        Box::new(self.clone())
+    }
+
+    fn execute(&mut self, state_p: &State, r: &Reservation, _: &mut LoadQueue) -> ExecuteResult {
+        match Format::from(r.op) {
+            Format::R => Alu::ex_r_type(r, &state_p.reorder_buffer),
+            Format::I => Alu::ex_i_type(r, &state_p.reorder_buffer),
+            Format::U => Alu::ex_u_type(r),
+            _ => panic!("Unknown ALU instruction failed to execute."),
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////// Blu
+
+impl Blu {
+    /// Constructs a new branch logic unit with given properties.
+    pub fn new(pipeline_size: usize, forward_at_step: u8) -> Blu {
+        Blu {
+            pipeline: Pipeline::new(pipeline_size, forward_at_step),
+        }
+    }
+
+    /// Executes an I type instruction (a `jalr`), returning its result.
+    fn ex_i_type(r: &Reservation, rob: &ReorderBuffer) -> ExecuteResult {
+        let rs1_s = match r.rs1 {
+            Left(val) => val,
+            Right(name) => rob[name]
+                .act_rd
+                .expect("Execute unit ({:?}) I-type expected rs1!"),
         };
+        let imm = r.imm.expect("Execute unit I-type missing imm!");
 
-        self.executing.push_back((
-            ExecuteResult {
+        match r.op {
+            Operation::JALR => ExecuteResult {
                 rob_entry: r.rob_entry,
-                pc: r.pc as i32 + 4,
-                rd: None,
+                pc: if rs1_s != -1 { jalr_target(rs1_s, imm) } else { -1 },
+                rd: Some((r.pc as i32).wrapping_add(4)),
             },
-            ExecutionLen::from(r.op),
-        ))
+            _ => panic!("Unknown I-type instruction failed to execute."),
+        }
     }
 
-    /// Executes an B type instruction, modifying the borrowed state.
-    fn ex_b_type(&mut self, r: &Reservation, rob: &ReorderBuffer) {
+    /// Executes an B type instruction, returning its result.
+    fn ex_b_type(r: &Reservation, rob: &ReorderBuffer) -> ExecuteResult {
         let rs1_s = match r.rs1 {
             Left(val) => val,
             Right(name) => rob[name]
@@ -496,7 +666,7 @@ impl ExecuteUnit {
         let imm = r.imm.expect("Execute unit B-type missing imm!");
 
         #[rustfmt::skip]
-        let pc_val = r.pc as i32 + match r.op {
+        let pc_val = (r.pc as i32).wrapping_add(match r.op {
             Operation::BEQ  => if rs1_s == rs2_s { imm } else { 4 },
             Operation::BNE  => if rs1_s != rs2_s { imm } else { 4 },
             Operation::BLT  => if rs1_s <  rs2_s { imm } else { 4 },
@@ -504,70 +674,195 @@ impl ExecuteUnit {
             Operation::BLTU => if rs1_u <  rs2_u { imm } else { 4 },
             Operation::BGEU => if rs1_u >= rs2_u { imm } else { 4 },
             _ => panic!("Unknown B-type instruction failed to execute.")
-        };
+        });
 
-        self.executing.push_back((
-            ExecuteResult {
-                rob_entry: r.rob_entry,
-                pc: pc_val,
-                rd: None,
-            },
-            ExecutionLen::from(r.op),
-        ))
+        ExecuteResult {
+            rob_entry: r.rob_entry,
+            pc: pc_val,
+            rd: None,
+        }
     }
 
-    /// Executes an U type instruction, modifying the borrowed state.
-    fn ex_u_type(&mut self, r: &Reservation) {
-        let pc = r.pc as i32;
-        let imm = r.imm.expect("Execute unit U-type missing imm!");
+    /// Executes an J type instruction, returning its result.
+    fn ex_j_type(r: &Reservation) -> ExecuteResult {
+        let imm = r.imm.expect("Execute unit J-type missing imm!");
+
+        match r.op {
+            Operation::JAL => {
+                let old_pc = r.pc as i32;
+                ExecuteResult {
+                    rob_entry: r.rob_entry,
+                    pc: old_pc.wrapping_add(imm),
+                    rd: Some(old_pc.wrapping_add(4)),
+                }
+            }
+            _ => panic!("Unknown J-type instruction failed to execute."),
+        }
+    }
+}
+
+impl ExecuteUnit for Blu {
+    fn name(&self) -> &str {
+        "BLU"
+    }
+
+    fn handles(&self, op: Operation) -> bool {
+        UnitType::from(op) == UnitType::BLU
+    }
+
+    fn pipeline(&self) -> &Pipeline {
+        &self.pipeline
+    }
+
+    fn pipeline_mut(&mut self) -> &mut Pipeline {
+        &mut self.pipeline
+    }
+
+    fn clone_box(&self) -> Box<dyn ExecuteUnit> {
+        Box::new(self.clone())
+    }
 
+    fn execute(&mut self, state_p: &State, r: &Reservation, _: &mut LoadQueue) -> ExecuteResult {
+        match Format::from(r.op) {
+            Format::I => Blu::ex_i_type(r, &state_p.reorder_buffer),
+            Format::B => Blu::ex_b_type(r, &state_p.reorder_buffer),
+            Format::J => Blu::ex_j_type(r),
+            _ => panic!("Unknown BLU instruction failed to execute."),
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////// Mcu
+
+impl Mcu {
+    /// Constructs a new memory and control unit with given properties.
+    pub fn new(pipeline_size: usize, forward_at_step: u8) -> Mcu {
+        Mcu {
+            pipeline: Pipeline::new(pipeline_size, forward_at_step),
+        }
+    }
+
+    /// Executes an I type instruction, returning its result. Loads
+    /// speculatively read from the given memory, and are added to the load
+    /// queue, unless they read from the memory-mapped UART.
+    fn ex_i_type(
+        r: &Reservation,
+        rob: &ReorderBuffer,
+        memory: &Memory,
+        uart_addr: Option<usize>,
+        load_queue: &mut LoadQueue,
+    ) -> ExecuteResult {
+        let rs1_s = match r.rs1 {
+            Left(val) => val,
+            Right(name) => rob[name]
+                .act_rd
+                .expect("Execute unit ({:?}) I-type expected rs1!"),
+        };
+        let imm_s = r.imm.expect("Execute unit I-type missing imm!");
+        // Out of range loads read nothing, as they trap once committed
+        let (addr, faulted) = match effective_address(rs1_s, imm_s) {
+            Some(addr) => (addr, false),
+            None => (0, true),
+        };
+
+        #[rustfmt::skip]
         let rd_val = match r.op {
-            Operation::LUI => imm,
-            Operation::AUIPC => pc + imm,
-            _ => panic!("Unknown U-type instruction failed to execute."),
+            Operation::LB     => Some(memory.read_i8(addr).word as i32),
+            Operation::LH     => Some(memory.read_i16(addr).word as i32),
+            Operation::LW     => Some(memory.read_i32(addr).word),
+            Operation::LBU    => Some(memory.read_u8(addr).word as i32),
+            Operation::LHU    => Some(memory.read_u16(addr).word as i32),
+            Operation::FENCE  => None, // Done in commit stage
+            Operation::FENCEI => None, // Done in commit stage
+            op if op.is_system() => None, // Done in commit stage
+            _ => panic!("Unknown I-type instruction failed to execute.")
         };
 
-        self.executing.push_back((
-            ExecuteResult {
+        #[rustfmt::skip]
+        let load_size = match r.op {
+            Operation::LB  | Operation::LBU => 1,
+            Operation::LH  | Operation::LHU => 2,
+            Operation::LW                   => 4,
+            _                               => 0,
+        };
+
+        // Loads from the UART read its registers rather than memory, so are
+        // never affected by stores to memory either
+        let uart = load_size != 0 && !faulted && in_uart(uart_addr, addr, load_size);
+        let rd_val = match uart_addr {
+            Some(base) if uart && addr == base + UART_STATUS_OFFSET => Some(UART_READY),
+            Some(_) if uart => Some(0),
+            _ => rd_val,
+        };
+        if load_size != 0 && !faulted && !uart {
+            load_queue.push(LoadEntry {
                 rob_entry: r.rob_entry,
-                pc: pc + 4,
-                rd: Some(rd_val),
-            },
-            ExecutionLen::from(r.op),
-        ))
-    }
+                address: addr,
+                size: load_size,
+            });
+        }
 
-    /// Executes an J type instruction, modifying the borrowed state.
-    fn ex_j_type(&mut self, r: &Reservation) {
-        let imm = r.imm.expect("Execute unit J-type missing imm!");
+        ExecuteResult {
+            rob_entry: r.rob_entry,
+            pc: (r.pc as i32).wrapping_add(4),
+            rd: rd_val,
+        }
+    }
 
+    /// Executes an instruction that is carried out in order by the commit
+    /// stage (a store or atomic memory operation), so has no result yet.
+    fn ex_in_order(r: &Reservation) -> ExecuteResult {
         match r.op {
-            Operation::JAL => {
-                let old_pc = r.pc as i32;
-                self.executing.push_back((
-                    ExecuteResult {
-                        rob_entry: r.rob_entry,
-                        pc: old_pc + imm,
-                        rd: Some(old_pc + 4),
-                    },
-                    ExecutionLen::from(r.op),
-                ))
-            }
-            _ => panic!("Unknown J-type instruction failed to execute."),
+            op if op.is_store() => (), // All done in commit stage
+            op if op.is_atomic() => (), // Reads, modifies and writes in commit stage
+            _ => panic!("Unknown MCU instruction failed to execute."),
+        };
+
+        ExecuteResult {
+            rob_entry: r.rob_entry,
+            pc: (r.pc as i32).wrapping_add(4),
+            rd: None,
         }
     }
 }
 
-impl Display for UnitType {
-    fn fmt(&self, f: &mut Formatter) -> Result {
-        if f.alternate() {
-            match self {
-                UnitType::ALU => f.pad("A"),
-                UnitType::BLU => f.pad("B"),
-                UnitType::MCU => f.pad("M"),
-            }
-        } else {
-            f.pad(&format!("{:?}", self))
+impl ExecuteUnit for Mcu {
+    fn name(&self) -> &str {
+        "MCU"
+    }
+
+    fn handles(&self, op: Operation) -> bool {
+        UnitType::from(op) == UnitType::MCU
+    }
+
+    fn pipeline(&self) -> &Pipeline {
+        &self.pipeline
+    }
+
+    fn pipeline_mut(&mut self) -> &mut Pipeline {
+        &mut self.pipeline
+    }
+
+    fn clone_box(&self) -> Box<dyn ExecuteUnit> {
+        Box::new(self.clone())
+    }
+
+    fn execute(
+        &mut self,
+        state_p: &State,
+        r: &Reservation,
+        load_queue: &mut LoadQueue,
+    ) -> ExecuteResult {
+        match Format::from(r.op) {
+            Format::I => Mcu::ex_i_type(
+                r,
+                &state_p.reorder_buffer,
+                &state_p.memory,
+                state_p.uart_addr,
+                load_queue,
+            ),
+            Format::R | Format::S => Mcu::ex_in_order(r),
+            _ => panic!("Unknown MCU instruction failed to execute."),
         }
     }
 }
@@ -575,15 +870,619 @@ impl Display for UnitType {
 ///////////////////////////////////////////////////////////////////////////////
 //// FUNCTIONS
 
+/// Constructs a new built in execute unit of the given type, with the given
+/// pipeline properties.
+pub fn new_unit(
+    unit_type: UnitType,
+    pipeline_size: usize,
+    forward_at_step: u8,
+) -> Box<dyn ExecuteUnit> {
+    match unit_type {
+        UnitType::ALU => Box::new(Alu::new(pipeline_size, forward_at_step)),
+        UnitType::BLU => Box::new(Blu::new(pipeline_size, forward_at_step)),
+        UnitType::MCU => Box::new(Mcu::new(pipeline_size, forward_at_step)),
+    }
+}
+
+/// Returns the result of an R type instruction executed by an ALU, on the
+/// given values of its source registers.
+#[rustfmt::skip]
+pub fn alu_r_result(op: Operation, rs1_s: i32, rs2_s: i32) -> i32 {
+    let rs1_u = rs1_s as u32;
+    let rs2_u = rs2_s as u32;
+    // The upper multiplications are widened to 64 bits, where the product
+    // always fits: signed x signed is at most 2^62 (INT_MIN x INT_MIN),
+    // unsigned x unsigned is below 2^64, and signed x unsigned lies
+    // within (-2^63, 2^63). The unsigned operand of MULHSU must therefore
+    // be zero extended, not sign extended, before being widened.
+    // Division does not trap: dividing by zero gives a quotient with all
+    // bits set and leaves the dividend as the remainder, and the signed
+    // overflow of INT_MIN / -1 gives INT_MIN with a remainder of 0.
+    match op {
+        Operation::ADD    => rs1_s.overflowing_add(rs2_s).0,
+        Operation::SUB    => rs1_s.overflowing_sub(rs2_s).0,
+        Operation::SLL    => rs1_s << (rs2_s & 0b11111),
+        Operation::SLT    => (rs1_s < rs2_s) as i32,
+        Operation::SLTU   => (rs1_u < rs2_u) as i32,
+        Operation::XOR    => rs1_s ^ rs2_s,
+        Operation::SRL    => (rs1_u >> (rs2_u & 0b11111)) as i32,
+        Operation::SRA    => rs1_s >> (rs2_s & 0b11111),
+        Operation::OR     => rs1_s | rs2_s,
+        Operation::AND    => rs1_s & rs2_s,
+        Operation::MUL    => rs1_s.overflowing_mul(rs2_s).0,
+        Operation::MULH   => ((i64::from(rs1_s) * i64::from(rs2_s)) >> 32) as i32,
+        Operation::MULHU  => ((u64::from(rs1_u) * u64::from(rs2_u)) >> 32) as i32,
+        Operation::MULHSU => ((i64::from(rs1_s) * i64::from(rs2_u)) >> 32) as i32,
+        Operation::DIV    => match rs2_s {
+                                 0  => -1i32,
+                                 _  => match rs1_s.overflowing_div(rs2_s) {
+                                     (_, true) => i32::min_value(),
+                                     (v, _)    => v,
+                                 },
+                             },
+        Operation::DIVU   => match rs2_s {
+                                 0  => u32::max_value() as i32,
+                                 _  => (rs1_u / rs2_u) as i32,
+                             },
+        Operation::REM    => match rs2_s {
+                                 0 => rs1_s,
+                                 _ => match rs1_s.overflowing_rem(rs2_s) {
+                                     (_, true) => 0,
+                                     (v, _)    => v,
+                                 }
+                             },
+        Operation::REMU   => match rs2_s {
+                                 0 => rs1_s,
+                                 _ => (rs1_u % rs2_u) as i32,
+                             },
+        _ => panic!("Unknown R-type instruction failed to execute.")
+    }
+}
+
+/// Returns the result of an I type instruction executed by an ALU, on the
+/// given value of its source register and its immediate.
+#[rustfmt::skip]
+pub fn alu_i_result(op: Operation, rs1_s: i32, imm_s: i32) -> i32 {
+    let rs1_u = rs1_s as u32;
+    let imm_u = imm_s as u32;
+    match op {
+        Operation::ADDI   =>  rs1_s.wrapping_add(imm_s),
+        Operation::SLTI   => (rs1_s <  imm_s) as i32,
+        Operation::SLTIU  => (rs1_u <  imm_u) as i32,
+        Operation::XORI   =>  rs1_s ^  imm_s,
+        Operation::ORI    =>  rs1_s |  imm_s,
+        Operation::ANDI   =>  rs1_s &  imm_s,
+        Operation::SLLI   =>  rs1_s << (imm_s & 0b11111),
+        Operation::SRLI   => (rs1_u >> (imm_u & 0b11111)) as i32,
+        Operation::SRAI   =>  rs1_s >> (imm_s & 0b11111),
+        _ => panic!("Unknown I-type instruction failed to execute.")
+    }
+}
+
 /// Runs the _Execute & Writeback_ stage on every
-/// [`ExecuteUnit`](../execute/struct.ExecuteUnit.html) in the given previous
+/// [`ExecuteUnit`](../execute/trait.ExecuteUnit.html) in the given previous
 /// [`State`](../state/struct.State.html), `state_p`, while putting the new
 /// results in the current [`State`](../state/struct.State.html), `state`.
+///
+/// If the number of result buses is limited, only that many of the executions
+/// completing this cycle are written back, oldest first.
 pub fn execute_and_writeback_stage(state_p: &State, state: &mut State) {
+    let bus_free = arbitrate_result_buses(state_p, state);
     let iter_p = state_p.execute_units.iter();
     let iter = state.execute_units.iter_mut();
     // Loop over both past and current execute units at the same time
-    for (eu_p, mut eu) in iter_p.zip(iter) {
-        eu_p.advance_pipeline(&mut eu, &mut state.reorder_buffer, &mut state.resv_station)
+    for ((eu_p, eu), bus_free) in iter_p.zip(iter).zip(bus_free) {
+        eu_p.pipeline().advance(
+            eu.pipeline_mut(),
+            &mut state.reorder_buffer,
+            &mut state.resv_station,
+            state_p.stats.cycles,
+            bus_free,
+        )
+    }
+}
+
+/// Decides which execute units may write back a result this cycle, given the
+/// number of result buses, by the age of the instructions that they are
+/// completing. Returns whether each execute unit has a bus, in order.
+fn arbitrate_result_buses(state_p: &State, state: &mut State) -> Vec<bool> {
+    let units = state_p.execute_units.len();
+    if state.result_buses == 0 {
+        return vec![true; units];
+    }
+    let rob = &state.reorder_buffer;
+    let mut completing: Vec<(usize, usize)> = state_p
+        .execute_units
+        .iter()
+        .enumerate()
+        .filter_map(|(n, eu)| eu.pipeline().completing().map(|entry| (n, entry)))
+        .collect();
+    completing.sort_by_key(|&(_, entry)| (entry + rob.capacity - rob.front) % rob.capacity);
+
+    let mut bus_free = vec![false; units];
+    for (n, _) in completing.iter().take(state.result_buses) {
+        bus_free[*n] = true;
+    }
+    state.stats.bus_stalls += completing.len().saturating_sub(state.result_buses) as u64;
+    bus_free
+}
+
+/// Returns whether the given reservation is a load or store to an address
+/// that is not aligned to its size. Out of range addresses are left to trap
+/// once committed, so are not counted as misaligned.
+fn is_misaligned(r: &Reservation, rob: &ReorderBuffer) -> bool {
+    if !r.op.is_load() && !r.op.is_store() {
+        return false;
+    }
+    let base = match r.rs1 {
+        Left(val) => val,
+        Right(name) => rob[name]
+            .act_rd
+            .expect("Execute unit memory access expected rs1!"),
+    };
+    let offset = r.imm.expect("Execute unit memory access missing imm!");
+    match effective_address(base, offset) {
+        Some(addr) => addr % access_size(r.op) != 0,
+        None => false,
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// TESTS
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isa::operand::Register;
+    use crate::simulator::builder::{run, StateBuilder};
+    use crate::simulator::{step_cycle, write_deadlock};
+    use crate::util::config::Config;
+
+    /// A custom execute unit that only executes `addi`, which is also what
+    /// `nop` is, in a single step.
+    #[derive(Clone, Debug)]
+    struct AddiUnit {
+        pipeline: Pipeline,
+    }
+
+    impl ExecuteUnit for AddiUnit {
+        fn name(&self) -> &str {
+            "ADDI"
+        }
+
+        fn pipeline(&self) -> &Pipeline {
+            &self.pipeline
+        }
+
+        fn pipeline_mut(&mut self) -> &mut Pipeline {
+            &mut self.pipeline
+        }
+
+        fn clone_box(&self) -> Box<dyn ExecuteUnit> {
+            Box::new(self.clone())
+        }
+
+        fn handles(&self, op: Operation) -> bool {
+            op == Operation::ADDI
+        }
+
+        fn execution_len(&self, _: Operation) -> ExecutionLen {
+            ExecutionLen {
+                blocking: false,
+                steps: 1,
+            }
+        }
+
+        fn execute(
+            &mut self,
+            state_p: &State,
+            r: &Reservation,
+            _: &mut LoadQueue,
+        ) -> ExecuteResult {
+            let rs1 = match r.rs1 {
+                Left(val) => val,
+                Right(name) => state_p.reorder_buffer[name].act_rd.unwrap(),
+            };
+            ExecuteResult {
+                rob_entry: r.rob_entry,
+                pc: r.pc as i32 + 4,
+                rd: Some(rs1 + r.imm.unwrap()),
+            }
+        }
+    }
+
+    #[test]
+    fn executes_with_custom_unit() {
+        // Without any ALUs, only the custom unit can execute the additions
+        let mut config = Config::default();
+        config.alu_units = 0;
+        let mut state = StateBuilder::new()
+            .config(config)
+            .asm("addi x0 x0 0")
+            .asm("addi x5 x0 20")
+            .asm("addi x0 x0 0")
+            .asm("addi x5 x5 22")
+            .asm("jalr x0 x1 0")
+            .build();
+        let custom = state.execute_units.len();
+        state.execute_units.push(Box::new(AddiUnit {
+            pipeline: Pipeline::new(1, 0),
+        }));
+        let mut issued = vec![];
+        while !step_cycle(&mut state) {
+            assert!(state.stats.cycles < 100, "Did not finish.");
+            let unit = &state.execute_units[custom];
+            issued.extend(unit.pipeline().executing.iter().map(|(r, _)| r.rob_entry));
+        }
+        issued.dedup();
+        assert_eq!(state.register[Register::X5].data, 42);
+        assert_eq!(state.stats.op_counts[&Operation::ADDI], 4);
+        assert_eq!(issued.len(), 4);
+        assert!(!state.execute_units[0].handles(Operation::ADDI));
+
+        // The unit is shown by its own name, numbered apart from the others
+        let mut out = vec![];
+        write_deadlock(&state, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("  ADDI 0: idle"), "{}", out);
+        assert!(out.contains("  BLU 0: idle"), "{}", out);
+    }
+
+    /// Interesting operands for the arithmetic, around the edges of both the
+    /// signed and unsigned ranges.
+    const EDGES: [i32; 10] = [0, 1, 2, -1, -2, 7, -7, i32::MAX, i32::MIN, i32::MIN + 1];
+
+    /// Runs a single R type instruction on the given operands, returning its
+    /// result.
+    fn r_type(mnemonic: &str, rs1: i32, rs2: i32) -> i32 {
+        let mut state = StateBuilder::new()
+            .reg(Register::X5, rs1)
+            .reg(Register::X6, rs2)
+            .asm(&format!("{} x10 x5 x6", mnemonic))
+            .asm("jalr x0 x1 0")
+            .build();
+        run(&mut state, 100);
+        state.register[Register::X10].data
+    }
+
+    /// Runs a single I type instruction on the given operand and immediate,
+    /// returning its result.
+    fn i_type(mnemonic: &str, rs1: i32, imm: i32) -> i32 {
+        let mut state = StateBuilder::new()
+            .reg(Register::X5, rs1)
+            .asm(&format!("{} x10 x5 {}", mnemonic, imm))
+            .asm("jalr x0 x1 0")
+            .build();
+        run(&mut state, 100);
+        state.register[Register::X10].data
+    }
+
+    #[test]
+    fn multiplies_upper_halves() {
+        #[rustfmt::skip]
+        let cases = [
+            ("mulh",   i32::MIN,     -1,             0),
+            ("mulhu",  i32::MIN,     -1,             i32::MAX),
+            ("mulhsu", i32::MIN,     -1,             i32::MIN),
+            ("mulh",   -1,           -1,             0),
+            ("mulhu",  -1,           -1,             -2),
+            ("mulhsu", -1,           -1,             -1),
+            ("mulh",   i32::MIN,     i32::MIN,       0x4000_0000),
+            ("mulhu",  i32::MIN,     i32::MIN,       0x4000_0000),
+            ("mulhsu", i32::MIN,     i32::MIN,       -0x4000_0000),
+            ("mulhsu", 2,            i32::MIN,       1),
+            ("mulh",   i32::MAX,     i32::MAX,       0x3fff_ffff),
+        ];
+        for (op, rs1, rs2, rd) in cases.iter() {
+            assert_eq!(r_type(op, *rs1, *rs2), *rd, "{} {} {}", op, rs1, rs2);
+        }
+    }
+
+    #[test]
+    fn multiplies_upper_halves_like_wide_multiplication() {
+        for &rs1 in EDGES.iter() {
+            for &rs2 in EDGES.iter() {
+                let (s1, s2) = (i128::from(rs1), i128::from(rs2));
+                let (u1, u2) = (i128::from(rs1 as u32), i128::from(rs2 as u32));
+                assert_eq!(r_type("mulh", rs1, rs2), ((s1 * s2) >> 32) as i32);
+                assert_eq!(r_type("mulhu", rs1, rs2), ((u1 * u2) >> 32) as i32);
+                assert_eq!(r_type("mulhsu", rs1, rs2), ((s1 * u2) >> 32) as i32);
+                assert_eq!(r_type("mul", rs1, rs2), (s1 * s2) as i32);
+            }
+        }
+    }
+
+    #[test]
+    fn adds_upper_immediate_to_own_address() {
+        let mut state = StateBuilder::new()
+            .origin(0x1000)
+            .asm("addi x0 x0 0")
+            .asm("auipc x10 0x12345")
+            .asm("jalr x0 x1 0")
+            .build();
+        run(&mut state, 100);
+        assert_eq!(state.register[Register::X10].data, 0x1234_6004);
+        assert_eq!(state.stats.op_counts[&Operation::AUIPC], 1);
+        assert_eq!(UnitType::from(Operation::AUIPC), UnitType::ALU);
+    }
+
+    #[test]
+    fn shifts_by_immediate_boundary_amounts() {
+        #[rustfmt::skip]
+        let cases = [
+            ("slli", 1,           31, i32::MIN),
+            ("slli", -1,          31, i32::MIN),
+            ("slli", 0x1234_5678, 0,  0x1234_5678),
+            ("slli", 3,           1,  6),
+            ("srli", -1,          31, 1),
+            ("srli", i32::MIN,    31, 1),
+            ("srli", -1,          0,  -1),
+            ("srli", -1,          1,  i32::MAX),
+            ("srai", i32::MIN,    31, -1),
+            ("srai", i32::MAX,    31, 0),
+            ("srai", -8,          0,  -8),
+            ("srai", -8,          1,  -4),
+        ];
+        for (op, rs1, imm, rd) in cases.iter() {
+            assert_eq!(i_type(op, *rs1, *imm), *rd, "{} {} {}", op, rs1, imm);
+        }
+    }
+
+    #[test]
+    fn bypasses_load_results_before_commit() {
+        // Chases a pointer, so the second load waits on the first
+        let mut state = StateBuilder::new()
+            .word(0x100, 0x104)
+            .word(0x104, 7)
+            .asm("lw x10 x0 0x100")
+            .asm("lw x11 x10 0")
+            .asm("jalr x0 x1 0")
+            .build();
+        while !state.reorder_buffer[0].finished {
+            step_cycle(&mut state);
+        }
+
+        // The waiting load has the address as soon as the first finishes
+        assert_eq!(state.reorder_buffer[0].cycle_committed, None);
+        let waiting = state
+            .resv_station
+            .contents
+            .iter()
+            .find(|r| r.pc == 4)
+            .unwrap();
+        assert_eq!(waiting.rs1, Left(0x104));
+        run(&mut state, 100);
+        assert_eq!(state.register[Register::X11].data, 7);
+    }
+
+    /// Returns the cycle that an instruction waiting on a load issued in.
+    fn load_use_issue_cycle(early_forward: bool) -> u64 {
+        let mut config = Config::default();
+        config.early_forward = early_forward;
+        let mut state = StateBuilder::new()
+            .config(config)
+            .word(0x100, 41)
+            .asm("lw x10 x0 0x100")
+            .asm("addi x11 x10 1")
+            .asm("jalr x0 x1 0")
+            .build();
+        for _ in 0..100 {
+            if let Some(cycle) = state.reorder_buffer[1].cycle_issued {
+                run(&mut state, 100);
+                assert_eq!(state.register[Register::X11].data, 42);
+                return cycle;
+            }
+            step_cycle(&mut state);
+        }
+        panic!("Dependent instruction never issued");
+    }
+
+    #[test]
+    fn early_forward_issues_dependent_a_cycle_earlier() {
+        assert_eq!(load_use_issue_cycle(true) + 1, load_use_issue_cycle(false));
+    }
+
+    #[test]
+    fn add_immediate_wraps_around() {
+        assert_eq!(i_type("addi", i32::MAX, 1), i32::MIN);
+        assert_eq!(i_type("addi", i32::MIN, -1), i32::MAX);
+        assert_eq!(i_type("addi", -1, 1), 0);
+    }
+
+    #[test]
+    fn loads_upper_immediate_unshifted() {
+        let mut state = StateBuilder::new()
+            .asm("lui x10 0xABCDE")
+            .asm("jalr x0 x1 0")
+            .build();
+        run(&mut state, 100);
+        assert_eq!(state.register[Register::X10].data as u32, 0xABCD_E000);
+    }
+
+    #[test]
+    fn adds_upper_immediate_wrapping_around() {
+        let mut state = StateBuilder::new()
+            .origin(0x1000)
+            .asm("auipc x10 0x80000")
+            .asm("auipc x11 0xfffff")
+            .asm("jalr x0 x1 0")
+            .build();
+        run(&mut state, 100);
+        assert_eq!(state.register[Register::X10].data as u32, 0x8000_1000);
+        assert_eq!(state.register[Register::X11].data, 0x4);
+    }
+
+    /// Runs three additions, on as many ALUs, that all wait on the same load
+    /// so complete in the same cycle, with the given number of result buses.
+    /// Returns the cycles that each addition finished in, and the number of
+    /// cycles that a completion waited for a bus.
+    fn finish_together(result_buses: usize) -> (Vec<u64>, u64) {
+        let mut config = Config::default();
+        config.n_way = 4;
+        config.alu_units = 3;
+        config.issue_limit = 0;
+        config.result_buses = result_buses;
+        let mut state = StateBuilder::new()
+            .config(config)
+            .word(0x100, 40)
+            .asm("lw x5 x0 0x100")
+            .asm("addi x10 x5 1")
+            .asm("addi x11 x5 2")
+            .asm("addi x12 x5 3")
+            .asm("jalr x0 x1 0")
+            .build();
+        run(&mut state, 100);
+        assert_eq!(state.register[Register::X10].data, 41);
+        assert_eq!(state.register[Register::X11].data, 42);
+        assert_eq!(state.register[Register::X12].data, 43);
+        let cycles = (1..4)
+            .map(|n| state.reorder_buffer[n].cycle_finished.unwrap())
+            .collect();
+        (cycles, state.stats.bus_stalls)
+    }
+
+    #[test]
+    fn one_result_bus_serializes_completions() {
+        let (cycles, stalls) = finish_together(0);
+        assert_eq!(cycles, vec![cycles[0]; 3]);
+        assert_eq!(stalls, 0);
+
+        // The oldest goes first, and the others wait a cycle each in turn, so
+        // two wait in the first cycle and one in the second
+        let (cycles, stalls) = finish_together(1);
+        assert_eq!(cycles, vec![cycles[0], cycles[0] + 1, cycles[0] + 2]);
+        assert_eq!(stalls, 3);
+    }
+
+    /// Executes a single branch at the given program counter, on the given
+    /// operands, returning the program counter that it resulted in.
+    fn branch(op: Operation, pc: usize, rs1: i32, rs2: i32, imm: i32) -> i32 {
+        let reservation = Reservation {
+            rob_entry: 0,
+            op,
+            pc,
+            reg_rd: None,
+            rs1: Left(rs1),
+            rs2: Left(rs2),
+            imm: Some(imm),
+            depth: 0,
+        };
+        Blu::ex_b_type(&reservation, &ReorderBuffer::new(1)).pc
+    }
+
+    #[test]
+    fn branch_targets_wrap_around() {
+        #[rustfmt::skip]
+        let cases = [
+            (Operation::BEQ,  0x7fff_fffc, 0,  0, 8,    0x8000_0004u32 as i32),
+            (Operation::BNE,  0x7fff_fffc, 0,  0, 8,    0x8000_0000u32 as i32),
+            (Operation::BLT,  0xffff_fff8, -1, 0, 16,   8),
+            (Operation::BGEU, 0xffff_fffc, -1, 0, -4,   -8),
+            (Operation::BLTU, 4,           0,  1, -8,   -4),
+            (Operation::BGE,  0x8000_0000, 0,  0, -4,   i32::MAX - 3),
+        ];
+        for (op, pc, rs1, rs2, imm, target) in cases.iter() {
+            assert_eq!(
+                branch(*op, *pc, *rs1, *rs2, *imm),
+                *target,
+                "{} at {:08x}",
+                op,
+                pc
+            );
+        }
+    }
+
+    #[test]
+    fn divides_by_zero_and_overflows_without_trapping() {
+        #[rustfmt::skip]
+        let cases = [
+            // Division by zero gives all bits set, with the dividend left over
+            ("div",    7,            0,              -1),
+            ("divu",   7,            0,              -1),
+            ("rem",    7,            0,              7),
+            ("remu",   7,            0,              7),
+            ("div",    i32::MIN,     0,              -1),
+            ("divu",   i32::MIN,     0,              -1),
+            ("rem",    i32::MIN,     0,              i32::MIN),
+            ("remu",   i32::MIN,     0,              i32::MIN),
+            ("div",    0,            0,              -1),
+            ("rem",    0,            0,              0),
+            // Only signed division overflows, and the unsigned equivalent
+            // divides by the largest divisor
+            ("div",    i32::MIN,     -1,             i32::MIN),
+            ("rem",    i32::MIN,     -1,             0),
+            ("divu",   i32::MIN,     -1,             0),
+            ("remu",   i32::MIN,     -1,             i32::MIN),
+            ("div",    i32::MIN + 1, -1,             i32::MAX),
+            ("rem",    i32::MIN + 1, -1,             0),
+            // Signed division rounds towards zero, with the remainder taking
+            // the sign of the dividend
+            ("div",    -7,           2,              -3),
+            ("rem",    -7,           2,              -1),
+            ("div",    7,            -2,             -3),
+            ("rem",    7,            -2,             1),
+            ("divu",   -7,           2,              0x7fff_fffc),
+            ("remu",   -7,           2,              1),
+        ];
+        for (op, rs1, rs2, rd) in cases.iter() {
+            assert_eq!(r_type(op, *rs1, *rs2), *rd, "{} {} {}", op, rs1, rs2);
+        }
+        for &rs1 in EDGES.iter() {
+            assert_eq!(r_type("div", rs1, 0), -1, "div {} 0", rs1);
+            assert_eq!(r_type("divu", rs1, 0), -1, "divu {} 0", rs1);
+            assert_eq!(r_type("rem", rs1, 0), rs1, "rem {} 0", rs1);
+            assert_eq!(r_type("remu", rs1, 0), rs1, "remu {} 0", rs1);
+        }
+    }
+
+    /// Runs a load of the word at the given offset from `0x100`, with the
+    /// given policy for unaligned accesses and a penalty of 8 cycles.
+    fn load_word(unaligned: UnalignedAccess, offset: i32) -> State {
+        let mut config = Config::default();
+        config.unaligned = unaligned;
+        config.unaligned_penalty = 8;
+        let mut state = StateBuilder::new()
+            .config(config)
+            .word(0x100, 0x4433_2211)
+            .word(0x104, -0x7788_99ab)
+            .reg(Register::X5, 0x100)
+            .asm(&format!("lw x10 x5 {}", offset))
+            .asm("addi x11 x0 1")
+            .asm("jalr x0 x1 0")
+            .build();
+        run(&mut state, 100);
+        state
+    }
+
+    #[test]
+    fn fast_unaligned_load_ignores_alignment() {
+        let aligned = load_word(UnalignedAccess::Fast, 0);
+        let state = load_word(UnalignedAccess::Fast, 1);
+        assert_eq!(state.register[Register::X10].data, 0x5544_3322);
+        assert_eq!(state.register[Register::X11].data, 1);
+        assert_eq!(state.stats.cycles, aligned.stats.cycles);
+    }
+
+    #[test]
+    fn slow_unaligned_load_takes_penalty() {
+        let aligned = load_word(UnalignedAccess::Slow, 0);
+        let fast = load_word(UnalignedAccess::Fast, 0);
+        let state = load_word(UnalignedAccess::Slow, 1);
+        assert_eq!(state.register[Register::X10].data, 0x5544_3322);
+        assert_eq!(state.register[Register::X11].data, 1);
+        assert_eq!(aligned.stats.cycles, fast.stats.cycles);
+        assert_eq!(state.stats.cycles, aligned.stats.cycles + 8);
+    }
+
+    #[test]
+    fn trapping_unaligned_load_raises_exception() {
+        let aligned = load_word(UnalignedAccess::Trap, 0);
+        assert_eq!(aligned.register[Register::X10].data, 0x4433_2211);
+        let state = load_word(UnalignedAccess::Trap, 1);
+        assert_eq!(state.register[Register::X10].data, 0);
+        assert_eq!(state.register[Register::X11].data, 0);
+        assert!(state.out.iter().any(|l| l.starts_with(
+            "Address misaligned! Address 00000101 of instruction at 00000000 is not aligned to 4"
+        )));
     }
 }
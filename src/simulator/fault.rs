@@ -0,0 +1,175 @@
+use crate::isa::operand::Register;
+use crate::util::log::LogLevel;
+
+use super::state::State;
+
+///////////////////////////////////////////////////////////////////////////////
+//// STRUCTS
+
+/// A deterministic source of transient faults, used to study how the simulated
+/// processor behaves when its state is silently corrupted. The same seed and
+/// rate will always inject the same faults at the same cycles. This is also
+/// used to inject pipeline bubbles, which only affect timing.
+#[derive(Clone, Debug)]
+pub struct FaultInjector {
+    /// The internal state of the pseudo random number generator.
+    rng: u64,
+    /// The probability of a fault being injected in any given cycle, between
+    /// 0 and 1.
+    pub rate: f64,
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// IMPLEMENTATIONS
+
+impl FaultInjector {
+    /// Creates a new fault injector from the given seed, injecting faults
+    /// with the given probability every cycle.
+    pub fn new(seed: u64, rate: f64) -> FaultInjector {
+        FaultInjector {
+            // Xorshift gets stuck on a zero state, so scramble the seed first
+            rng: (seed ^ 0x9e37_79b9_7f4a_7c15) | 1,
+            rate,
+        }
+    }
+
+    /// Returns the next pseudo random number, using a xorshift64* generator.
+    fn next(&mut self) -> u64 {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        self.rng.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Decides whether or not a fault should be injected this cycle.
+    pub fn roll(&mut self) -> bool {
+        // A rate of zero must never consume randomness or inject anything
+        if self.rate <= 0.0 {
+            return false;
+        }
+        let sample = (self.next() >> 11) as f64 / (1u64 << 53) as f64;
+        sample < self.rate
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// FUNCTIONS
+
+/// The fault injection hook, run once at the end of every cycle after the
+/// _commit_ stage. If the state has a fault injector and it decides that a
+/// fault should occur, a single bit is flipped in either a register value,
+/// the result of a finished reorder buffer entry, or a byte of memory.
+pub fn inject_fault(state: &mut State) {
+    let (target, index, bit) = match state.fault_injector.as_mut() {
+        Some(f) => {
+            if !f.roll() {
+                return;
+            }
+            (f.next() % 3, f.next() as usize, f.next() % 32)
+        }
+        None => return,
+    };
+
+    let injected = match target {
+        // Register file, skipping x0 which is hardwired to zero
+        0 => {
+            let reg = Register::from(1 + (index % 31) as i32);
+            state.register[reg].data ^= 1 << bit;
+            true
+        }
+        // Reorder buffer, only if there is a finished result in flight
+        1 => {
+            let rob = &mut state.reorder_buffer;
+            if rob.count == 0 {
+                false
+            } else {
+                let entry = (rob.front + index % rob.count) % rob.capacity;
+                let finished = rob[entry].finished;
+                match rob[entry].act_rd.as_mut() {
+                    Some(rd) if finished => {
+                        *rd ^= 1 << bit;
+                        true
+                    }
+                    _ => false,
+                }
+            }
+        }
+        // Main memory
+        _ => {
+            let addr = index % state.memory.len();
+            state.memory[addr] ^= 1 << (bit % 8);
+            true
+        }
+    };
+
+    if injected {
+        state.stats.faults_injected += 1;
+        let location = match target {
+            0 => "register",
+            1 => "reorder buffer",
+            _ => "memory",
+        };
+        state.log(LogLevel::Info, format!("Injected a fault into {}.", location));
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// TESTS
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::builder::{run, StateBuilder};
+    use crate::util::config::Config;
+
+    /// Runs a loop that sums and stores the numbers 1 to 10, injecting faults
+    /// as given.
+    fn run_loop(inject_faults: Option<(u64, f64)>) -> State {
+        let mut config = Config::default();
+        config.inject_faults = inject_faults;
+        let mut state = StateBuilder::new()
+            .config(config)
+            .origin(0x1000)
+            .program(
+                "
+                        addi t0, zero, 10
+                        addi a0, zero, 0
+                        lui  t1, 2
+                loop:   add  a0, a0, t0
+                        sw   t1, a0, 0
+                        addi t1, t1, 4
+                        addi t0, t0, -1
+                        bne  t0, zero, loop
+                        jalr zero, ra, 0
+                ",
+            )
+            .build();
+        run(&mut state, 1000);
+        state
+    }
+
+    #[test]
+    fn zero_rate_never_perturbs_state() {
+        let clean = run_loop(None);
+        let state = run_loop(Some((42, 0.0)));
+        assert_eq!(state.stats.faults_injected, 0);
+        assert_eq!(state.stats.cycles, clean.stats.cycles);
+        for n in 0..33 {
+            let reg = Register::from(n);
+            assert_eq!(state.register[reg].data, clean.register[reg].data);
+        }
+        assert_eq!(&state.memory[0x2000..0x2028], &clean.memory[0x2000..0x2028]);
+        assert_eq!(clean.memory.read_i32(0x2024).word, 55);
+    }
+
+    #[test]
+    fn same_seed_injects_same_faults() {
+        let mut a = FaultInjector::new(7, 0.5);
+        let mut b = FaultInjector::new(7, 0.5);
+        let rolls: Vec<bool> = (0..64).map(|_| a.roll()).collect();
+        assert_eq!(rolls, (0..64).map(|_| b.roll()).collect::<Vec<_>>());
+        assert!(rolls.contains(&true) && rolls.contains(&false));
+        let mut never = FaultInjector::new(7, 0.0);
+        assert!((0..64).all(|_| !never.roll()));
+    }
+}
@@ -1,38 +1,270 @@
+use std::cmp::min;
+use std::collections::VecDeque;
+
 use super::branch::ReturnStackOp;
 use super::memory::Access;
-use super::state::State;
+use super::state::{AccessKind, State};
 
 ///////////////////////////////////////////////////////////////////////////////
 //// STRUCTS
 
-/// The contents of the latch that the fetch stage feeds into.
+/// The instruction queue that the fetch stage feeds into. This is a bounded
+/// FIFO, allowing the _fetch_ stage to run ahead of the _decode_ stage, and
+/// keeping hold of any fetched instructions while _decode_ is stalled.
 #[derive(Clone, Debug, Default)]
 pub struct LatchFetch {
-    /// The `n` pieces of data fetched (determined by the
-    /// [State's](../state/struct.State.html) `n_way` settings).
-    pub data: Vec<Access<i32>>,
-    /// The `n` pieces of associate branch prediction data for the fetched
-    /// memory address. This include the return stack operations as well as
-    /// the two level prediction state.
-    pub bp_data: Vec<(ReturnStackOp, u8)>,
-    /// The program counter value for the _first_ instruction fetched,
-    /// indicating the choice the branch predictor made.
+    /// The amount of fetched instructions the queue can hold.
+    pub capacity: usize,
+    /// The fetched instructions, in program order, waiting to be decoded.
+    pub queue: VecDeque<FetchEntry>,
+}
+
+/// A single fetched instruction within the instruction queue.
+#[derive(Copy, Clone, Debug)]
+pub struct FetchEntry {
+    /// The data fetched from memory.
+    pub access: Access<i32>,
+    /// The associated branch prediction data for the fetched memory address.
+    /// This includes the return stack operation as well as the two level
+    /// prediction state.
+    pub bp_data: (ReturnStackOp, u8),
+    /// The address in memory that this instruction accesses, if it is a
+    /// memory operation whose address was known when it was fetched.
+    pub mem_addr: Option<usize>,
+    /// The program counter value for this instruction, indicating the choice
+    /// the branch predictor made.
     pub pc: usize,
+    /// The cycle in which this instruction was fetched.
+    pub cycle_fetched: u64,
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// IMPLEMENTATIONS
+
+impl LatchFetch {
+    /// Creates a new empty instruction queue with given capacity.
+    pub fn new(capacity: usize) -> LatchFetch {
+        LatchFetch {
+            capacity,
+            queue: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Returns the number of instructions that can be added to the queue
+    /// before it is full.
+    pub fn free_capacity(&self) -> usize {
+        self.capacity.saturating_sub(self.queue.len())
+    }
+
+    /// Discards every instruction in the queue.
+    pub fn flush(&mut self) {
+        self.queue.clear();
+    }
 }
 
 ///////////////////////////////////////////////////////////////////////////////
 //// FUNCTIONS
 
 /// The _Fetch_ stage of the pipeline. This will fetch the next instruction(s)
-/// from [`Memory`](../memory/struct.Memory.html), and put them into the
-/// [`LatchFetch`](../fetch/struct.LatchFetch.html) ready for the next pipeline
-/// stage.
+/// from [`Memory`](../memory/struct.Memory.html), and put them onto the back
+/// of the [`LatchFetch`](../fetch/struct.LatchFetch.html) instruction queue,
+/// ready for the next pipeline stage.
+///
+/// Only as many instructions as there is room for in the queue are fetched,
+/// up to the fetch width, and none past the end of the fetch block that the
+/// first lies in. Any instructions following a predicted branch are
+/// not enqueued, as the branch predictor will fetch from the branch target in
+/// the next cycle. Nothing is fetched whilst a flush penalty is being served.
 pub fn fetch_stage(state_p: &State, state: &mut State) {
+    if state_p.flush_stall_remaining > 0 {
+        state.flush_stall_remaining -= 1;
+        state.stats.flush_penalty_cycles += 1;
+        return;
+    }
+
+    let mut count = min(state_p.fetch_width, state_p.latch_fetch.free_capacity());
+    if count == 0 {
+        return;
+    }
+
     let lc = state_p.branch_predictor.get_prediction();
+    if state_p.fetch_block != 0 {
+        // Always fetch at least the first instruction, even if misaligned
+        let block = state_p.fetch_block;
+        let in_block = (block - lc % block + 3) / 4;
+        if in_block < count {
+            count = in_block;
+            state.stats.fetch_truncations += 1;
+        }
+    }
     let mut data = vec![];
-    for offset in 0..state_p.n_way {
+    for offset in 0..count {
         data.push(state_p.memory.read_i32(lc + (4 * offset)))
     }
-    let bp_data = state.branch_predictor.predict(state_p.n_way, &data, &state_p.register);
-    state.latch_fetch = LatchFetch { data, bp_data, pc: lc };
+    let predictions = state.branch_predictor.predict(
+        count,
+        &data,
+        &state_p.register,
+        &state_p.latch_fetch,
+        &state_p.memory,
+        &mut state.stats,
+    );
+    if predictions.len() < count {
+        state.stats.fetch_redirects += 1;
+    }
+
+    for (offset, (access, prediction)) in data.into_iter().zip(predictions).enumerate() {
+        state.profile_access(lc + (4 * offset), AccessKind::Fetch);
+        state.latch_fetch.queue.push_back(FetchEntry {
+            access,
+            bp_data: prediction.bp_data,
+            mem_addr: prediction.mem_addr,
+            pc: lc + (4 * offset),
+            cycle_fetched: state_p.stats.cycles,
+        });
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// TESTS
+
+#[cfg(test)]
+mod tests {
+    use crate::isa::operand::Register;
+    use crate::simulator::builder::{run, StateBuilder};
+    use crate::simulator::state::State;
+    use crate::simulator::step_cycle;
+    use crate::util::config::Config;
+
+    #[test]
+    fn fetches_ahead_while_decode_stalls() {
+        // The slow divisions fill the tiny reorder buffer, stalling decode
+        let mut config = Config::default();
+        config.rob_size = 2;
+        config.fetch_queue = 8;
+        let mut builder = StateBuilder::new()
+            .config(config)
+            .reg(Register::X5, 100)
+            .reg(Register::X6, 3)
+            .asm("div x10 x5 x6")
+            .asm("div x11 x10 x6");
+        for _ in 0..6 {
+            builder = builder.asm("addi x12 x12 1");
+        }
+        let mut state = builder.asm("jalr x0 x1 0").build();
+
+        let mut ran_ahead = false;
+        loop {
+            let queued = state.latch_fetch.queue.len();
+            let stalls = state.stats.stalls_rob;
+            let finished = step_cycle(&mut state);
+            if state.stats.stalls_rob > stalls && state.latch_fetch.queue.len() > queued {
+                ran_ahead = true;
+            }
+            assert!(state.latch_fetch.queue.len() <= 8);
+            if finished {
+                break;
+            }
+            assert!(state.stats.cycles < 1000, "Did not finish.");
+        }
+        assert!(ran_ahead);
+        assert_eq!(state.register[Register::X11].data, 11);
+        assert_eq!(state.register[Register::X12].data, 6);
+        assert_eq!(state.stats.retired(), 9);
+    }
+
+    /// Runs a program with a single mispredicted branch, which is predicted
+    /// taken (weakly) but falls through, with the given flush penalty.
+    fn run_mispredict(flush_penalty: usize) -> State {
+        let mut config = Config::default();
+        config.flush_penalty = flush_penalty;
+        let mut state = StateBuilder::new()
+            .config(config)
+            .asm("bne x0 x0 8")
+            .asm("addi x10 x0 1")
+            .asm("jalr x0 x1 0")
+            .build();
+        run(&mut state, 1000);
+        assert_eq!(state.register[Register::X10].data, 1);
+        assert_eq!(state.stats.bp_failure, 1);
+        state
+    }
+
+    #[test]
+    fn flush_penalty_idles_fetch() {
+        let base = run_mispredict(0);
+        let slow = run_mispredict(3);
+        assert_eq!(slow.stats.cycles, base.stats.cycles + 3);
+        assert_eq!(base.stats.flush_penalty_cycles, 0);
+        assert_eq!(slow.stats.flush_penalty_cycles, 3);
+    }
+
+    /// Runs the first few cycles of a long run of instructions, with the
+    /// given fetch and decode widths, returning the length of the fetch queue
+    /// after each.
+    fn fetch_queue_lengths(fetch_width: usize, decode_width: usize) -> Vec<usize> {
+        let mut config = Config::default();
+        config.fetch_width = fetch_width;
+        config.decode_width = decode_width;
+        config.fetch_queue = 8;
+        config.rob_size = 64;
+        config.rsv_size = 64;
+        let mut builder = StateBuilder::new().config(config);
+        for _ in 0..64 {
+            builder = builder.asm("addi x10 x10 1");
+        }
+        let mut state = builder.asm("jalr x0 x1 0").build();
+        (0..8)
+            .map(|_| {
+                step_cycle(&mut state);
+                state.latch_fetch.queue.len()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fetch_wider_than_decode_fills_queue() {
+        // Two of every four fetched are left over each cycle, until there is
+        // no longer room for a whole fetch group
+        assert_eq!(fetch_queue_lengths(4, 2), vec![4, 6, 6, 6, 6, 6, 6, 6]);
+        assert_eq!(fetch_queue_lengths(4, 4), vec![4; 8]);
+        assert_eq!(fetch_queue_lengths(2, 2), vec![2; 8]);
+    }
+
+    /// Builds a state with a long run of instructions starting two words
+    /// before the end of a 16 byte block, with the given fetch block size.
+    fn near_block_end(fetch_block: usize) -> State {
+        let mut config = Config::default();
+        config.fetch_width = 4;
+        config.fetch_queue = 8;
+        config.fetch_block = fetch_block;
+        let mut builder = StateBuilder::new().config(config).origin(0x8);
+        for _ in 0..8 {
+            builder = builder.asm("addi x10 x10 1");
+        }
+        builder.asm("jalr x0 x1 0").build()
+    }
+
+    #[test]
+    fn fetch_stops_at_end_of_block() {
+        let mut state = near_block_end(16);
+        step_cycle(&mut state);
+        let pcs: Vec<usize> = state.latch_fetch.queue.iter().map(|e| e.pc).collect();
+        assert_eq!(pcs, vec![0x8, 0xc]);
+        assert_eq!(state.stats.fetch_truncations, 1);
+
+        // The next fetch starts on the block boundary, so is not cut short
+        step_cycle(&mut state);
+        let pcs: Vec<usize> = state.latch_fetch.queue.iter().map(|e| e.pc).collect();
+        assert_eq!(pcs, vec![0xc, 0x10, 0x14, 0x18, 0x1c]);
+        assert_eq!(state.stats.fetch_truncations, 1);
+
+        let mut unlimited = near_block_end(0);
+        step_cycle(&mut unlimited);
+        assert_eq!(unlimited.latch_fetch.queue.len(), 4);
+        assert_eq!(unlimited.stats.fetch_truncations, 0);
+
+        run(&mut state, 1000);
+        assert_eq!(state.register[Register::X10].data, 8);
+    }
 }
@@ -1,3 +1,6 @@
+use crate::util::log::LogLevel;
+
+use super::reorder::ReorderBuffer;
 use super::state::State;
 
 ///////////////////////////////////////////////////////////////////////////////
@@ -6,24 +9,236 @@ use super::state::State;
 /// This is responsible for the _Issue_ stage of the pipeline, taking
 /// pending instructions from the
 /// [`ResvStation`](../reservation/struct.ResvStation.html) to free
-/// [`ExecuteUnit`s](../execute/struct.ExecuteUnit.html).
+/// [`ExecuteUnit`s](../execute/trait.ExecuteUnit.html).
+///
+/// At most `issue_limit` instructions are issued, from within the first
+/// `issue_limit` entries of the reservation station. If the number of memory
+/// ports is limited, memory accesses hold a port until they are written back.
+///
+/// Execute units take their turn in the order they were created (every ALU,
+/// then every BLU, then every MCU), each taking at most one reservation, so
+/// when several units of a type could issue the same reservation the first
+/// of them always does. Operations that are routed to a unit are only ever
+/// issued to that unit, even if others of its type are free.
 pub fn issue_stage(state_p: &State, state: &mut State) {
-    let mut effective_limit = state.issue_limit; 
-    for eu in state.execute_units.iter_mut() {
+    let mut effective_limit = state.issue_limit;
+    let mut ports_free = match state.mem_ports {
+        0 => usize::max_value(),
+        n => {
+            let rob = &state.reorder_buffer;
+            let in_use = state
+                .execute_units
+                .iter()
+                .flat_map(|eu| eu.pipeline().executing.iter())
+                .filter(|(result, _)| rob[result.rob_entry].op.is_memory())
+                .count();
+            n.saturating_sub(in_use)
+        }
+    };
+
+    for (n, eu) in state.execute_units.iter_mut().enumerate() {
+        if effective_limit == 0 {
+            break;
+        }
         let (next, new_limit) = state_p
             .resv_station
             .consume_next(
                 &mut state.resv_station,
-                &eu,
+                eu.as_ref(),
+                n,
+                &state.routes,
                 &state.reorder_buffer,
                 effective_limit,
+                ports_free > 0,
+                state.mem_disambig,
             );
         effective_limit = new_limit;
         if let Some(r) = next {
-            eu.handle_issue(state_p, &r);
-            if effective_limit == 0 {
+            if r.op.is_memory() {
+                ports_free -= 1;
+            }
+            eu.handle_issue(state_p, &r, &mut state.load_queue);
+            state.reorder_buffer[r.rob_entry].cycle_issued = Some(state_p.stats.cycles);
+            state.reorder_buffer[r.rob_entry].execute_unit = Some(n);
+            state.reorder_buffer[r.rob_entry].load_use =
+                load_use_distance(&state.reorder_buffer, r.rob_entry, state_p.stats.cycles);
+        }
+    }
+
+    // Memory accesses left waiting with every port in use are contended
+    if ports_free == 0 && state.resv_station.memory_access_ready(&state.reorder_buffer) {
+        state.stats.mem_port_stalls += 1;
+        state.log(
+            LogLevel::Debug,
+            String::from("Issue stalled: memory ports in use."),
+        );
+    }
+}
+
+/// Returns the number of cycles since the load that the given reorder buffer
+/// entry was waiting on (if any) was issued, for the entry issued in the
+/// given cycle.
+fn load_use_distance(rob: &ReorderBuffer, entry: usize, cycle: u64) -> Option<u64> {
+    let load = &rob[rob[entry].load_dep?];
+    // Once committed, the load's entry may have since been reused by a
+    // younger instruction
+    if load.cycle_reserved > rob[entry].cycle_reserved {
+        return None;
+    }
+    load.cycle_issued.map(|issued| cycle - issued)
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// TESTS
+
+#[cfg(test)]
+mod tests {
+    use crate::isa::op_code::Operation;
+    use crate::isa::operand::Register;
+    use crate::simulator::builder::{run, StateBuilder};
+    use crate::simulator::step_cycle;
+    use crate::util::config::Config;
+
+    /// Runs an instruction for each of the three execute units, which are
+    /// independent so can all issue at once, with the given issue limit.
+    /// Returns the most instructions that issued in one cycle.
+    fn most_issued_at_once(issue_limit: usize) -> usize {
+        let mut config = Config::default();
+        config.n_way = 4;
+        config.issue_limit = issue_limit;
+        let mut state = StateBuilder::new()
+            .config(config)
+            .word(0x100, 42)
+            .asm("lw x11 x0 0x100")
+            .asm("addi x10 x0 1")
+            .asm("beq x0 x0 4")
+            .asm("jalr x0 x1 0")
+            .build();
+        assert_eq!(state.execute_units.len(), 3);
+
+        let mut most = 0;
+        loop {
+            let finished = step_cycle(&mut state);
+            let cycle = state.stats.cycles - 1;
+            let rob = &state.reorder_buffer;
+            let issued = (0..rob.capacity)
+                .filter(|n| rob[*n].cycle_issued == Some(cycle))
+                .count();
+            most = most.max(issued);
+            if finished {
                 break;
             }
+            assert!(state.stats.cycles < 100, "Did not finish.");
+        }
+        assert_eq!(state.register[Register::X11].data, 42);
+        most
+    }
+
+    #[test]
+    fn issue_limit_of_zero_is_number_of_units() {
+        assert_eq!(most_issued_at_once(0), 3);
+        assert_eq!(most_issued_at_once(1), 1);
+    }
+
+    /// Runs three independent loads on as many memory units, with the given
+    /// number of memory ports. Returns the cycles that each load issued in,
+    /// and the number of cycles that a memory access stalled.
+    fn issue_loads(mem_ports: usize) -> (Vec<u64>, u64) {
+        let mut config = Config::default();
+        config.n_way = 4;
+        config.mcu_units = 3;
+        config.mem_ports = mem_ports;
+        let mut state = StateBuilder::new()
+            .config(config)
+            .word(0x100, 1)
+            .word(0x104, 2)
+            .word(0x108, 3)
+            .asm("lw x11 x0 0x100")
+            .asm("lw x12 x0 0x104")
+            .asm("lw x13 x0 0x108")
+            .asm("jalr x0 x1 0")
+            .build();
+        run(&mut state, 100);
+        assert_eq!(state.register[Register::X11].data, 1);
+        assert_eq!(state.register[Register::X12].data, 2);
+        assert_eq!(state.register[Register::X13].data, 3);
+        let cycles = (0..3)
+            .map(|n| state.reorder_buffer[n].cycle_issued.unwrap())
+            .collect();
+        (cycles, state.stats.mem_port_stalls)
+    }
+
+    #[test]
+    fn one_memory_port_serializes_loads() {
+        // The loads are decoded a cycle apart, and issue as soon as they are
+        let (cycles, stalls) = issue_loads(0);
+        assert_eq!(cycles, vec![cycles[0], cycles[0] + 1, cycles[0] + 2]);
+        assert_eq!(stalls, 0);
+
+        // Each load holds the port until it is written back, three steps on
+        let (cycles, stalls) = issue_loads(1);
+        assert!(cycles[1] >= cycles[0] + 3, "{:?}", cycles);
+        assert!(cycles[2] >= cycles[1] + 3, "{:?}", cycles);
+        assert!(stalls > 0);
+    }
+
+    #[test]
+    fn two_alus_issue_distinct_reservations_together() {
+        // Both additions wait on the load, so are ready in the same cycle
+        let mut config = Config::default();
+        config.n_way = 4;
+        config.alu_units = 2;
+        config.issue_limit = 0;
+        let mut state = StateBuilder::new()
+            .config(config)
+            .word(0x100, 40)
+            .asm("lw x5 x0 0x100")
+            .asm("addi x10 x5 1")
+            .asm("addi x11 x5 2")
+            .asm("jalr x0 x1 0")
+            .build();
+        run(&mut state, 100);
+        assert_eq!(state.register[Register::X10].data, 41);
+        assert_eq!(state.register[Register::X11].data, 42);
+
+        let rob = &state.reorder_buffer;
+        assert_eq!(rob[1].cycle_issued, rob[2].cycle_issued);
+        assert_eq!(rob[1].execute_unit, Some(0));
+        assert_eq!(rob[2].execute_unit, Some(1));
+        assert_eq!(state.stats.retired(), 4);
+    }
+
+    #[test]
+    fn routed_operation_only_issues_to_its_unit() {
+        // The multiplies are independent, so would otherwise spread out over
+        // whichever ALUs are free
+        let mut config = Config::default();
+        config.n_way = 4;
+        config.alu_units = 3;
+        config.issue_limit = 0;
+        config.routes.insert(Operation::MUL, 2);
+        let mut builder = StateBuilder::new().config(config).reg(Register::X5, 3);
+        for n in 10..16 {
+            builder = builder
+                .asm(&format!("mul x{} x5 x5", n))
+                .asm("addi x6 x6 1");
+        }
+        let mut state = builder.asm("jalr x0 x1 0").build();
+
+        let mut units_used = vec![];
+        while !step_cycle(&mut state) {
+            for (n, eu) in state.execute_units.iter().enumerate() {
+                for (result, _) in eu.pipeline().executing.iter() {
+                    if state.reorder_buffer[result.rob_entry].op == Operation::MUL {
+                        units_used.push(n);
+                    }
+                }
+            }
+            assert!(state.stats.cycles < 1000, "Did not finish.");
         }
+        assert_eq!(state.register[Register::X15].data, 9);
+        assert_eq!(state.register[Register::X6].data, 6);
+        assert!(!units_used.is_empty());
+        assert!(units_used.iter().all(|n| *n == 2), "{:?}", units_used);
     }
 }
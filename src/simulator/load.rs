@@ -0,0 +1,374 @@
+use either::{Left, Right};
+
+use crate::isa::op_code::{BaseCode, Operation};
+
+use super::memory::effective_address;
+use super::reorder::ReorderBuffer;
+use super::reservation::Reservation;
+
+///////////////////////////////////////////////////////////////////////////////
+//// ENUMS
+
+/// The policy used to decide when a load may read from memory, given the
+/// older stores that are yet to be committed.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MemoryDisambiguation {
+    /// Loads wait until every older store has been committed.
+    Conservative,
+    /// Loads only wait for older stores that truly write to the memory they
+    /// read, with the address of every memory access known up front by
+    /// running the program ahead of the pipeline.
+    Perfect,
+    /// As perfect disambiguation, but without knowing addresses up front.
+    /// Stores whose address is yet to be calculated are assumed not to write
+    /// to the memory that the load reads, with any that do being caught at
+    /// commit, as with speculative loads.
+    Optimistic,
+    /// Loads read memory as soon as their operands are ready, and are
+    /// replayed if an older store later writes to the memory they read.
+    Speculative,
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// STRUCTS
+
+/// The load queue keeps track of all of the loads that have speculatively
+/// read from memory in the _execute_ stage, but are yet to be committed. This
+/// is used to detect when an older store writes to memory that a younger load
+/// has already (incorrectly) read.
+#[derive(Clone, Debug, Default)]
+pub struct LoadQueue {
+    /// The speculatively executed loads, in the order that they were issued.
+    pub entries: Vec<LoadEntry>,
+}
+
+/// A single load that has speculatively read from memory.
+#[derive(Copy, Clone, Debug)]
+pub struct LoadEntry {
+    /// The entry in the reorder buffer that corresponds to this load.
+    pub rob_entry: usize,
+    /// The address in memory that was read.
+    pub address: usize,
+    /// The number of bytes that were read.
+    pub size: usize,
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// IMPLEMENTATIONS
+
+impl LoadQueue {
+    /// Records a load that has speculatively read from memory.
+    pub fn push(&mut self, entry: LoadEntry) {
+        self.entries.push(entry);
+    }
+
+    /// Removes the load associated with the given reorder buffer entry, this
+    /// would happen when the load is committed.
+    pub fn remove(&mut self, rob_entry: usize) {
+        self.entries.retain(|e| e.rob_entry != rob_entry);
+    }
+
+    /// Checks whether a store of `size` bytes to `address`, committed from the
+    /// reorder buffer entry `store_entry`, overlaps any load that has already
+    /// read from memory. Returns the oldest such load in program order, as
+    /// this is where execution must be replayed from.
+    pub fn find_violation(
+        &self,
+        address: usize,
+        size: usize,
+        store_entry: usize,
+        rob_capacity: usize,
+    ) -> Option<LoadEntry> {
+        self.entries
+            .iter()
+            .filter(|e| {
+                e.address < address.saturating_add(size)
+                    && address < e.address.saturating_add(e.size)
+            })
+            .min_by_key(|e| (e.rob_entry + rob_capacity - store_entry) % rob_capacity)
+            .cloned()
+    }
+
+    /// Flushes the load queue, this would happen when the pipeline is
+    /// invalidated and needs to be restarted from scratch.
+    pub fn flush(&mut self) {
+        self.entries.clear()
+    }
+}
+
+impl MemoryDisambiguation {
+    /// Returns whether the given reservation, with its operands ready, may be
+    /// issued under this policy. Only loads are ever held back, by the older
+    /// stores and atomic operations in the reorder buffer that are yet to be
+    /// committed.
+    pub fn may_issue(self, r: &Reservation, rob: &ReorderBuffer) -> bool {
+        if !r.op.is_load() || self == MemoryDisambiguation::Speculative {
+            return true;
+        }
+        let size = access_size(r.op);
+        let base = match r.rs1 {
+            Left(val) => Some(val),
+            Right(n) => rob[n].act_rd,
+        };
+        let addr = rob[r.rob_entry]
+            .mem_addr
+            .or_else(|| base.and_then(|b| effective_address(b, r.imm.unwrap_or(0))));
+
+        let mut i = rob.front_fin;
+        while i != r.rob_entry {
+            let entry = &rob[i];
+            i = (i + 1) % rob.capacity;
+            match BaseCode::from(entry.op) {
+                BaseCode::STORE | BaseCode::AMO => (),
+                _ => continue,
+            }
+            if self == MemoryDisambiguation::Conservative {
+                return false;
+            }
+            let base = match entry.rs1 {
+                Left(val) => Some(val),
+                Right(n) => rob[n].act_rd,
+            };
+            let offset = match BaseCode::from(entry.op) {
+                BaseCode::STORE => entry.imm.unwrap_or(0),
+                _ => 0,
+            };
+            let overlaps = |store: usize, load: usize| {
+                store < load.saturating_add(size)
+                    && load < store.saturating_add(access_size(entry.op))
+            };
+            let store = match self {
+                MemoryDisambiguation::Perfect => entry.mem_addr,
+                _ => None,
+            };
+            match (store.or_else(|| base.and_then(|b| effective_address(b, offset))), addr) {
+                (Some(store), Some(load)) if overlaps(store, load) => return false,
+                // Without knowing where the store writes, perfect
+                // disambiguation cannot rule it out
+                (None, _) | (_, None) if self == MemoryDisambiguation::Perfect => return false,
+                _ => (),
+            }
+        }
+        true
+    }
+}
+
+impl Default for MemoryDisambiguation {
+    /// Defaults to reading memory speculatively.
+    fn default() -> MemoryDisambiguation {
+        MemoryDisambiguation::Speculative
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// FUNCTIONS
+
+/// Returns the number of bytes of memory that the given load, store or atomic
+/// operation accesses.
+#[rustfmt::skip]
+pub fn access_size(op: Operation) -> usize {
+    match op {
+        Operation::LB | Operation::LBU | Operation::SB => 1,
+        Operation::LH | Operation::LHU | Operation::SH => 2,
+        _                                              => 4,
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// TESTS
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isa::operand::Register;
+    use crate::simulator::builder::{run, StateBuilder};
+    use crate::util::config::Config;
+
+    /// Stores 99 to the address in `t3`, which is only known once a slow
+    /// divide finishes, before loading from `t1 + offset`, with the given
+    /// policy. The load may issue ahead of the store.
+    fn store_then_load(policy: MemoryDisambiguation, offset: i32) -> (i32, u64) {
+        let mut config = Config::default();
+        config.mem_disambig = policy;
+        config.issue_limit = 3;
+        let mut state = StateBuilder::new()
+            .config(config)
+            .origin(0x1000)
+            .word(0x2000, 1)
+            .word(0x2004, 2)
+            .reg(Register::X6, 0x2000)
+            .reg(Register::X7, 1)
+            .reg(Register::X29, 99)
+            .asm("div x28 x6 x7")
+            .asm("sw x28 x29 0")
+            .asm(&format!("lw x10 x6 {}", offset))
+            .asm("jalr x0 x1 0")
+            .build();
+        run(&mut state, 500);
+        (
+            state.register[Register::X10].data,
+            state.stats.load_violations,
+        )
+    }
+
+    #[test]
+    fn replays_load_after_aliasing_store() {
+        let (a0, violations) = store_then_load(MemoryDisambiguation::Speculative, 0);
+        assert_eq!(a0, 99);
+        assert_eq!(violations, 1);
+    }
+
+    #[test]
+    fn keeps_load_after_independent_store() {
+        let (a0, violations) = store_then_load(MemoryDisambiguation::Speculative, 4);
+        assert_eq!(a0, 2);
+        assert_eq!(violations, 0);
+    }
+
+    #[test]
+    fn optimistic_replays_load_past_unknown_store_address() {
+        // The address of the store is not known when the load is ready, so
+        // it is assumed not to alias, unlike with perfect disambiguation
+        let (a0, violations) = store_then_load(MemoryDisambiguation::Optimistic, 0);
+        assert_eq!(a0, 99);
+        assert_eq!(violations, 1);
+
+        let (a0, violations) = store_then_load(MemoryDisambiguation::Conservative, 0);
+        assert_eq!(a0, 99);
+        assert_eq!(violations, 0);
+    }
+
+    #[test]
+    fn finds_oldest_overlapping_load() {
+        let mut queue = LoadQueue::default();
+        queue.push(LoadEntry {
+            rob_entry: 6,
+            address: 0x100,
+            size: 4,
+        });
+        queue.push(LoadEntry {
+            rob_entry: 1,
+            address: 0x102,
+            size: 2,
+        });
+        queue.push(LoadEntry {
+            rob_entry: 3,
+            address: 0x104,
+            size: 4,
+        });
+        // The store is in entry 5 of 8, so the load in entry 6 is older than
+        // the one in entry 1, which has wrapped around
+        assert_eq!(queue.find_violation(0x103, 1, 5, 8).unwrap().rob_entry, 6);
+        assert!(queue.find_violation(0x108, 4, 5, 8).is_none());
+        queue.remove(6);
+        assert_eq!(queue.find_violation(0x103, 1, 5, 8).unwrap().rob_entry, 1);
+    }
+
+    /// Runs a loop that copies eight words from one array to another, through
+    /// a slow divide by one, with the given policy. Returns the number of
+    /// cycles taken.
+    fn copy_loop(policy: MemoryDisambiguation) -> u64 {
+        let mut config = Config::default();
+        config.mem_disambig = policy;
+        config.n_way = 4;
+        config.alu_units = 2;
+        config.issue_limit = 16;
+        config.mcu_units = 2;
+        let mut builder = StateBuilder::new().config(config).reg(Register::X8, 1);
+        for n in 0..8 {
+            builder = builder.word(0x400 + 4 * n, n as i32 + 1);
+        }
+        let mut state = builder
+            .program(
+                "
+                    addi x5 x0 0x400
+                    addi x6 x0 0x600
+                    addi x7 x0 8
+                loop:
+                    lw x10 x5 0
+                    div x11 x10 x8
+                    sw x6 x11 0
+                    addi x5 x5 4
+                    addi x6 x6 4
+                    addi x7 x7 -1
+                    bne x7 x0 loop
+                    jalr x0 x1 0
+                ",
+            )
+            .build();
+        run(&mut state, 1000);
+        for n in 0..8 {
+            assert_eq!(state.memory.read_i32(0x600 + 4 * n).word, n as i32 + 1);
+        }
+        assert_eq!(state.stats.load_violations, 0);
+        state.stats.cycles
+    }
+
+    #[test]
+    fn independent_loads_pass_stores_unless_conservative() {
+        let conservative = copy_loop(MemoryDisambiguation::Conservative);
+        let perfect = copy_loop(MemoryDisambiguation::Perfect);
+        let optimistic = copy_loop(MemoryDisambiguation::Optimistic);
+        let speculative = copy_loop(MemoryDisambiguation::Speculative);
+        assert!(perfect < conservative);
+        assert_eq!(perfect, speculative);
+        assert_eq!(optimistic, speculative);
+    }
+
+    /// Runs a loop that adds one to each of eight words, storing it to an
+    /// address calculated through a slow divide by one, then reads back the
+    /// word just written, with the given policy. Returns the number of cycles
+    /// taken and loads replayed.
+    fn increment_loop(policy: MemoryDisambiguation) -> (u64, u64) {
+        let mut config = Config::default();
+        config.mem_disambig = policy;
+        config.n_way = 4;
+        config.alu_units = 2;
+        config.issue_limit = 16;
+        config.mcu_units = 2;
+        let mut builder = StateBuilder::new().config(config).reg(Register::X8, 1);
+        for n in 0..8 {
+            builder = builder.word(0x400 + 4 * n, n as i32);
+        }
+        let mut state = builder
+            .program(
+                "
+                    addi x5 x0 0x400
+                    addi x7 x0 8
+                    addi x12 x0 0
+                loop:
+                    lw x10 x5 0
+                    addi x10 x10 1
+                    div x9 x5 x8
+                    sw x9 x10 0
+                    lw x13 x5 0
+                    add x12 x12 x13
+                    addi x5 x5 4
+                    addi x7 x7 -1
+                    bne x7 x0 loop
+                    jalr x0 x1 0
+                ",
+            )
+            .build();
+        run(&mut state, 2000);
+        assert_eq!(state.register[Register::X12].data, 36);
+        (state.stats.cycles, state.stats.load_violations)
+    }
+
+    #[test]
+    fn only_aliasing_loads_wait_when_perfect() {
+        let (conservative, _) = increment_loop(MemoryDisambiguation::Conservative);
+        let (perfect, perfect_violations) = increment_loop(MemoryDisambiguation::Perfect);
+        let (optimistic, optimistic_violations) = increment_loop(MemoryDisambiguation::Optimistic);
+        let (speculative, speculative_violations) =
+            increment_loop(MemoryDisambiguation::Speculative);
+        // Only the load reading back each store waits for it, so no loads are
+        // replayed, unlike when the store address is not known up front
+        assert_eq!(perfect_violations, 0);
+        assert!(optimistic_violations > 0);
+        assert!(speculative_violations > 0);
+        assert!(perfect < conservative);
+        assert!(perfect < optimistic);
+        assert!(perfect < speculative);
+    }
+}
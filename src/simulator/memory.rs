@@ -1,14 +1,45 @@
 use std::fmt::{Display, Formatter, LowerHex, Result};
+use std::fs;
+use std::io;
 use std::ops::{Deref, DerefMut};
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-use elf::Section;
+use elf::types::ProgramHeader;
+
 
 ///////////////////////////////////////////////////////////////////////////////
 //// CONST/STATIC
 
 pub const INIT_MEMORY_SIZE: usize = 1_000_000; // 1 Megabyte
 
+/// The default size of the address space that programs may access, beyond
+/// which loads and stores trap. Memory is only allocated as it is written.
+pub const DEFAULT_MEM_SIZE: usize = 0x1000_0000; // 256 Megabytes
+
+/// The alignment of the initial stack pointer, as the RISC-V calling
+/// convention requires of the stack pointer on entry to a function.
+pub const DEFAULT_STACK_ALIGN: usize = 16;
+
+/// The number of bytes directly below the stack limit (if any) that are
+/// reserved as a guard region for detecting stack overflows.
+pub const STACK_GUARD_SIZE: usize = 256;
+
+/// The number of bytes of address space taken by the memory-mapped UART (if
+/// any). The first word is the data register, the second the status register.
+pub const UART_SIZE: usize = 8;
+
+/// The offset of the memory-mapped UART's status register from its base
+/// address.
+pub const UART_STATUS_OFFSET: usize = 4;
+
+/// The value read from the memory-mapped UART's status register, indicating
+/// that it is always ready to transmit.
+pub const UART_READY: i32 = 1;
+
+/// The number of bytes compared at once when finding the differences between
+/// two memories, before looking for the bytes that differ within them.
+const DIFF_CHUNK_SIZE: usize = 64;
+
 ///////////////////////////////////////////////////////////////////////////////
 //// STRUCTS
 
@@ -21,10 +52,28 @@ pub struct Access<W: Default> {
     pub word: W,
 }
 
+/// The bytes of a memory that differ from a newer copy of it, from which the
+/// newer copy can be restored back to the older, see `Memory::diff_from`.
+#[derive(Clone, Debug)]
+pub struct MemoryDiff {
+    /// The addresses that differed, and the bytes the older memory held there.
+    changed: Vec<(usize, u8)>,
+    /// The length of the older memory.
+    len: usize,
+    /// The high water mark of the older memory.
+    high_water: usize,
+}
+
 /// Smart Pointer on a vector of bytes to store the memory for the simulator.
 /// See the implemented methods for extra functionality.
 #[derive(Clone)]
-pub struct Memory(Vec<u8>);
+pub struct Memory {
+    /// The byte-data of the memory.
+    data: Vec<u8>,
+    /// One past the highest address that has been written to, either by the
+    /// loaded elf file or by the simulated program.
+    high_water: usize,
+}
 
 ///////////////////////////////////////////////////////////////////////////////
 //// IMPLEMENTATIONS
@@ -46,7 +95,7 @@ impl<W: Default + Display + LowerHex> Display for Access<W> {
 impl Deref for Memory {
     type Target = Vec<u8>;
     fn deref(&self) -> &Vec<u8> {
-        &self.0
+        &self.data
     }
 }
 
@@ -54,7 +103,7 @@ impl Deref for Memory {
 /// the `Memory` struct.
 impl DerefMut for Memory {
     fn deref_mut(&mut self) -> &mut Vec<u8> {
-        &mut self.0
+        &mut self.data
     }
 }
 
@@ -63,34 +112,51 @@ impl Memory {
     /// Creates a new `Memory` struct of given capacity with a 0-initialised
     /// byte-data.
     pub fn create_empty(capacity: usize) -> Memory {
-        Memory(vec![0u8; capacity])
+        Memory {
+            data: vec![0u8; capacity],
+            high_water: 0,
+        }
     }
 
     /// Reads a signed 32 bit word from `Memory` at a given index, returning
     /// the word and whether or not a misaligned access was used.
     ///
-    /// Requires self to be mutable as this function will 0-extend memory if
-    /// attempting to access memory that has not been initialised before.
+    /// Memory that has not been initialised before reads as 0, including the
+    /// bytes of a word that straddles the end of initialised memory. Unlike
+    /// writes, reads never extend memory.
     pub fn read_i32(&self, index: usize) -> Access<i32> {
         Access {
             aligned: index % 4 == 0,
             word: if self.is_capable(index, 4) {
-                (&self.0[index..]).read_i32::<LittleEndian>().unwrap()
+                (&self.data[index..]).read_i32::<LittleEndian>().unwrap()
             } else {
-                0
+                self.read_partial(index, 4) as i32
             },
         }
     }
 
+    /// Reads an unsigned 32 bit word from `Memory` at a given index,
+    /// returning the word and whether or not a misaligned access was used.
+    ///
+    /// Memory that has not been initialised before reads as 0.
+    pub fn read_u32(&self, index: usize) -> Access<u32> {
+        let r = self.read_i32(index);
+        Access {
+            aligned: r.aligned,
+            word: r.word as u32,
+        }
+    }
+
     /// Writes a signed 32 bit word to `Memory` at a given index, returning
     /// whether or not a misaligned access was used.
     ///
     /// Requires self to be mutable as this function will 0-extend memory if
     /// attempting to access memory that has not been initialised before.
     pub fn write_i32(&mut self, index: usize, word: i32) -> bool {
-        self.zero_extend(index);
+        self.zero_extend(index + 4);
+        self.mark_written(index + 4);
 
-        let mut wtr = &mut self.0[index..];
+        let mut wtr = &mut self.data[index..];
         wtr.write_i32::<LittleEndian>(word).unwrap();
         index % 4 == 0
     }
@@ -99,15 +165,15 @@ impl Memory {
     /// returning the half-word and whether or not a misaligned access was
     /// used.
     ///
-    /// Requires self to be mutable as this function will 0-extend memory if
-    /// attempting to access memory that has not been initialised before.
+    /// Memory that has not been initialised before reads as 0, including the
+    /// byte of a half-word that straddles the end of initialised memory.
     pub fn read_i16(&self, index: usize) -> Access<i16> {
         Access {
             aligned: index % 2 == 0,
             word: if self.is_capable(index, 2) {
-                (&self.0[index..]).read_i16::<LittleEndian>().unwrap()
+                (&self.data[index..]).read_i16::<LittleEndian>().unwrap()
             } else {
-                0
+                self.read_partial(index, 2) as i16
             },
         }
     }
@@ -116,9 +182,8 @@ impl Memory {
     /// returning the half-word and whether or not a misaligned access was
     /// used.
     ///
-    /// Requires self to be mutable as this function will 0-extend memory if
-    /// attempting to access memory that has not been initialised before.
-    pub fn read_u16(&mut self, index: usize) -> Access<u16> {
+    /// Memory that has not been initialised before reads as 0.
+    pub fn read_u16(&self, index: usize) -> Access<u16> {
         let r = self.read_i16(index);
         Access {
             aligned: r.aligned,
@@ -132,49 +197,321 @@ impl Memory {
     /// Requires self to be mutable as this function will 0-extend memory if
     /// attempting to access memory that has not been initialised before.
     pub fn write_i16(&mut self, index: usize, word: i16) -> bool {
-        self.zero_extend(index + 1);
+        self.zero_extend(index + 2);
+        self.mark_written(index + 2);
 
-        let mut wtr = &mut self.0[index..];
+        let mut wtr = &mut self.data[index..];
         wtr.write_i16::<LittleEndian>(word).unwrap();
         index % 2 == 0
     }
 
-    /// Loads the data from the given section into memory if required. If not
-    /// required, performs no operation.
-    pub fn load_elf_section(&mut self, section: &Section) {
-        // Check if we actually want to load this section
-        if section.shdr.name == ".shstrtab" || section.shdr.size == 0 {
-            return;
+    /// Writes an unsigned 32 bit word to `Memory` at a given index,
+    /// returning whether or not a misaligned access was used.
+    ///
+    /// Requires self to be mutable as this function will 0-extend memory if
+    /// attempting to access memory that has not been initialised before.
+    pub fn write_u32(&mut self, index: usize, word: u32) -> bool {
+        self.write_i32(index, word as i32)
+    }
+
+    /// Reads a signed byte from `Memory` at a given index. Byte accesses are
+    /// always aligned.
+    ///
+    /// Memory that has not been initialised before reads as 0.
+    pub fn read_i8(&self, index: usize) -> Access<i8> {
+        let r = self.read_u8(index);
+        Access {
+            aligned: r.aligned,
+            word: r.word as i8,
+        }
+    }
+
+    /// Reads an unsigned byte from `Memory` at a given index. Byte accesses
+    /// are always aligned.
+    ///
+    /// Memory that has not been initialised before reads as 0.
+    pub fn read_u8(&self, index: usize) -> Access<u8> {
+        Access {
+            aligned: true,
+            word: if self.is_capable(index, 1) {
+                self.data[index]
+            } else {
+                0
+            },
+        }
+    }
+
+    /// Writes a signed byte to `Memory` at a given index, returning whether or
+    /// not a misaligned access was used, which is never the case for bytes.
+    ///
+    /// Requires self to be mutable as this function will 0-extend memory if
+    /// attempting to access memory that has not been initialised before.
+    pub fn write_i8(&mut self, index: usize, byte: i8) -> bool {
+        self.write_u8(index, byte as u8)
+    }
+
+    /// Writes an unsigned byte to `Memory` at a given index, returning whether
+    /// or not a misaligned access was used, which is never the case for
+    /// bytes.
+    ///
+    /// Requires self to be mutable as this function will 0-extend memory if
+    /// attempting to access memory that has not been initialised before.
+    pub fn write_u8(&mut self, index: usize, byte: u8) -> bool {
+        self.zero_extend(index + 1);
+        self.mark_written(index + 1);
+        self.data[index] = byte;
+        true
+    }
+
+    /// Returns one past the highest address that has been written to, which
+    /// is the length of memory that holds anything of interest.
+    pub fn high_water(&self) -> usize {
+        self.high_water
+    }
+
+    /// Writes the contents of `Memory` to the file at the given path, up to
+    /// the highest address that has been written to.
+    pub fn dump(&self, path: &str) -> io::Result<()> {
+        fs::write(path, &self.data[..self.high_water])
+    }
+
+    /// Returns the bytes of this memory that differ from the given newer copy
+    /// of it, so that the newer copy can later be restored back to this one
+    /// with `undo`, without keeping a copy of all of it.
+    pub fn diff_from(&self, newer: &Memory) -> MemoryDiff {
+        let mut changed = vec![];
+        let chunks = self.data.chunks(DIFF_CHUNK_SIZE).zip(newer.data.chunks(DIFF_CHUNK_SIZE));
+        for (n, (old, new)) in chunks.enumerate().filter(|(_, (old, new))| old != new) {
+            let start = n * DIFF_CHUNK_SIZE;
+            for (i, (o, _)) in old.iter().zip(new.iter()).enumerate().filter(|(_, (o, n))| o != n) {
+                changed.push((start + i, *o));
+            }
+        }
+        // Memory only shrinks if reset, in which case anything past the end
+        // of the newer copy is restored from zero
+        for (i, byte) in self.data.iter().enumerate().skip(newer.len()).filter(|(_, b)| **b != 0) {
+            changed.push((i, *byte));
         }
+        MemoryDiff {
+            changed,
+            len: self.data.len(),
+            high_water: self.high_water,
+        }
+    }
 
-        // Extend the size of memory to contain new data
-        self.zero_extend((section.shdr.addr + section.shdr.size) as usize);
+    /// Restores this memory back to the older copy that the given difference
+    /// was taken from. This must be the memory the difference was taken
+    /// against, or one restored back to it.
+    pub fn undo(&mut self, diff: &MemoryDiff) {
+        self.data.resize(diff.len, 0);
+        for (addr, byte) in diff.changed.iter() {
+            self.data[*addr] = *byte;
+        }
+        self.high_water = diff.high_water;
+    }
+
+    /// Loads a loadable segment, described by the given program header, into
+    /// memory at its virtual address. The `data` is the segment's contents in
+    /// the elf file, and any remaining size of the segment in memory (such as
+    /// the `.bss` section) is zero filled.
+    pub fn load_elf_segment(&mut self, header: &ProgramHeader, data: &[u8]) {
+        if header.memsz == 0 {
+            return;
+        }
 
-        // Load in the section
+        // Extend the size of memory to contain the whole segment
         // `usize as u64` cast is safe as simulator is for 32 bit architectures
-        let s_addr: usize = section.shdr.addr as usize;
-        let e_addr: usize = s_addr + section.data.len();
-        self.splice(s_addr..e_addr, section.data.iter().cloned());
+        let s_addr: usize = header.vaddr as usize;
+        let f_addr: usize = s_addr + data.len();
+        let e_addr: usize = s_addr + header.memsz as usize;
+        self.zero_extend(e_addr);
+        self.mark_written(e_addr);
+
+        // Load in the segment, zero filling past the end of the file data
+        self.data[s_addr..f_addr].copy_from_slice(data);
+        for byte in self.data[f_addr..e_addr].iter_mut() {
+            *byte = 0;
+        }
     }
 
-    /// Zero extends memory to the index given, if it is not currently
-    /// generated within the simulated `Memory` data structure.
-    fn zero_extend(&mut self, index: usize) {
+    /// Zero extends memory so that every address below the `end` given is
+    /// generated within the simulated `Memory` data structure, if it is not
+    /// already.
+    fn zero_extend(&mut self, end: usize) {
         // Check if memory data structure is large enough, if not extend
-        let (diff, sufficient) = (index).overflowing_sub(self.len());
-        if !sufficient {
-            self.0.append(&mut vec![0; diff]);
+        if end > self.len() {
+            self.data.resize(end, 0);
         }
     }
 
+    /// Raises the high water mark of written memory to the given address, if
+    /// it is not already above it.
+    fn mark_written(&mut self, end: usize) {
+        self.high_water = self.high_water.max(end);
+    }
+
     /// Whether or not the memory is capable of reading or writing a value of
     /// `size` bytes at `index` - i.e. if the memory has been allocated on the
-    /// host machine of the simulator.
+    /// host machine of the simulator. An access ending exactly at the end of
+    /// memory (`index + size == len()`) is capable.
     fn is_capable(&self, index: usize, size: usize) -> bool {
-        if size == 0 {
-            true
-        } else {
-            (index + size - 1).overflowing_sub(self.len()).1
+        match index.checked_add(size) {
+            Some(end) => end <= self.len(),
+            None => false,
         }
     }
+
+    /// Reads a little endian value of `size` bytes at `index` one byte at a
+    /// time, for accesses that are not entirely within allocated memory. Any
+    /// bytes past the end of memory read as 0.
+    fn read_partial(&self, index: usize, size: usize) -> u32 {
+        (0..size).rev().fold(0, |word, i| {
+            let byte = match index.checked_add(i) {
+                Some(addr) if addr < self.len() => self.data[addr],
+                _ => 0,
+            };
+            (word << 8) | u32::from(byte)
+        })
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// FUNCTIONS
+
+/// Computes the address accessed by a load or store, from the value of its
+/// base register and its immediate offset.
+///
+/// Addresses are treated as signed, so only the lower half of the address
+/// space (`0x00000000` to `0x7fffffff`) can be accessed. Returns `None` if the
+/// addition overflows or the address is negative, in which case the access
+/// faults and the instruction traps when it is committed.
+pub fn effective_address(base: i32, offset: i32) -> Option<usize> {
+    match base.checked_add(offset) {
+        Some(addr) if addr >= 0 => Some(addr as usize),
+        _ => None,
+    }
+}
+
+/// Whether or not an access of `size` bytes to `addr` touches the memory-mapped
+/// UART with the given base address. Always false if there is no UART.
+pub fn in_uart(uart_addr: Option<usize>, addr: usize, size: usize) -> bool {
+    match uart_addr {
+        Some(base) => base < addr.saturating_add(size) && addr < base.saturating_add(UART_SIZE),
+        None => false,
+    }
+}
+
+/// Returns the initial stack (and frame) pointer, which is the last word of
+/// memory, aligned down to the given number of bytes (a power of two).
+pub fn initial_stack_pointer(align: usize) -> i32 {
+    ((INIT_MEMORY_SIZE - 4) & !(align - 1)) as i32
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// TESTS
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isa::operand::Register;
+    use crate::simulator::builder::{run, StateBuilder};
+
+    #[test]
+    fn reads_bytes_and_half_words_signed_and_unsigned() {
+        let mut memory = Memory::create_empty(0x10);
+        memory.write_i32(0x0, 0x8000_7fff_u32 as i32);
+        memory.write_i32(0x4, 0x807f);
+        assert_eq!(memory.read_i8(0x4).word, 0x7f);
+        assert_eq!(memory.read_i8(0x5).word, -0x80);
+        assert_eq!(memory.read_u8(0x4).word, 0x7f);
+        assert_eq!(memory.read_u8(0x5).word, 0x80);
+        assert_eq!(memory.read_i16(0x0).word, 0x7fff);
+        assert_eq!(memory.read_i16(0x2).word, -0x8000);
+        assert_eq!(memory.read_u16(0x0).word, 0x7fff);
+        assert_eq!(memory.read_u16(0x2).word, 0x8000);
+
+        // Past the end of initialised memory reads as 0
+        memory.write_u8(0xf, 0x80);
+        assert_eq!(memory.read_u16(0xf).word, 0x80);
+        assert_eq!(memory.read_i16(0xf).word, 0x80);
+        assert_eq!(memory.read_i8(0x10).word, 0);
+    }
+
+    #[test]
+    fn dumps_stored_word_at_its_address() {
+        let mut state = StateBuilder::new()
+            .reg(Register::X5, 0x1234_5678)
+            .asm("sw x0 x5 0x240")
+            .asm("jalr x0 x1 0")
+            .build();
+        run(&mut state, 100);
+
+        let path = std::env::temp_dir().join("daybreak_mem_dump");
+        state.memory.dump(path.to_str().unwrap()).unwrap();
+        let dump = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(dump.len(), 0x244);
+        assert_eq!(dump[0x240..], [0x78, 0x56, 0x34, 0x12]);
+    }
+
+    #[test]
+    fn writes_grow_memory_to_the_end_of_the_access() {
+        let mut memory = Memory::create_empty(8);
+        assert!(memory.write_u32(8, 0x8765_4321));
+        assert_eq!(memory.len(), 12);
+        assert!(!memory.write_i16(13, -2));
+        assert_eq!(memory.len(), 15);
+        assert!(memory.write_i8(15, -3));
+        assert_eq!(memory.len(), 16);
+        assert!(memory.write_u8(16, 0xfe));
+        assert_eq!(memory.len(), 17);
+        assert_eq!(memory.high_water(), 17);
+
+        assert_eq!(memory.read_u32(8).word, 0x8765_4321);
+        assert_eq!(memory.read_i32(8).word, 0x8765_4321u32 as i32);
+        assert_eq!(memory.read_i16(13).word, -2);
+        assert_eq!(memory.read_u16(13).word, 0xfffe);
+        assert_eq!(memory.read_i8(15).word, -3);
+        assert_eq!(memory.read_u8(15).word, 0xfd);
+        assert_eq!(memory.read_u8(16).word, 0xfe);
+        assert_eq!(memory.read_i8(16).word, -2);
+    }
+
+    #[test]
+    fn reads_past_the_end_as_zero_without_growing() {
+        let mut memory = Memory::create_empty(8);
+        memory.write_u32(4, 0xffff_ffff);
+        // Only the bytes of a read that are past the end read as zero
+        assert_eq!(memory.read_u32(5).word, 0x00ff_ffff);
+        assert!(!memory.read_u32(5).aligned);
+        assert_eq!(memory.read_i16(7).word, 0xff);
+        assert_eq!(memory.read_u8(7).word, 0xff);
+        assert_eq!(memory.read_u8(8).word, 0);
+        assert_eq!(memory.read_i8(usize::max_value()).word, 0);
+        assert_eq!(memory.read_u32(usize::max_value() - 1).word, 0);
+        assert_eq!(memory.len(), 8);
+    }
+
+    #[test]
+    fn accesses_ending_exactly_at_the_end_are_capable() {
+        let mut memory = Memory::create_empty(8);
+        assert!(memory.is_capable(4, 4));
+        assert!(memory.is_capable(6, 2));
+        assert!(memory.is_capable(7, 1));
+        assert!(memory.is_capable(8, 0));
+        assert!(!memory.is_capable(5, 4));
+        assert!(!memory.is_capable(8, 1));
+        assert!(!memory.is_capable(usize::max_value(), 2));
+
+        // Writing right up to the end does not grow memory, and reads back
+        // in full
+        assert!(memory.write_u32(4, 0x8765_4321));
+        assert!(memory.write_i16(2, -2));
+        assert_eq!(memory.len(), 8);
+        assert_eq!(memory.read_u32(4).word, 0x8765_4321);
+        assert_eq!(memory.read_i16(6).word, 0x8765u16 as i16);
+        assert_eq!(memory.read_u8(7).word, 0x87);
+        assert_eq!(memory.read_u16(2).word, 0xfffe);
+        assert_eq!(memory.len(), 8);
+    }
 }
@@ -1,14 +1,20 @@
-use std::sync::mpsc::TryRecvError;
-use std::thread;
-use std::time::Duration;
+use std::cmp::Reverse;
+use std::io::{self, Write};
+use std::sync::mpsc::{RecvTimeoutError, TryRecvError};
+use std::time::{Duration, Instant};
 
 use crate::io::{IoEvent, IoThread, SimulatorEvent};
+use crate::isa::{decode_all, Instruction};
+use crate::util::chrome_trace::ChromeTrace;
 use crate::util::config::Config;
+use crate::util::loader::executable_segments;
+use crate::util::metrics::MetricsCsv;
 
 use self::commit::commit_stage;
 use self::decode::decode_and_rename_stage;
 use self::issue::issue_stage;
-use self::execute::execute_and_writeback_stage;
+use self::execute::{execute_and_writeback_stage, UnalignedAccess, UnitType};
+use self::fault::inject_fault;
 use self::fetch::fetch_stage;
 use self::state::State;
 
@@ -43,6 +49,28 @@ pub mod commit;
 /// _fetch_ stage of which instruction to fetch next for speculative execution.
 pub mod branch;
 
+/// The control and status registers, which are used for handling traps.
+pub mod csr;
+
+/// A builder for simulator states that run given instructions, with preset
+/// registers and memory, without needing an elf file. Only used by the tests.
+#[cfg(test)]
+pub mod builder;
+
+/// Logic and data structures for injecting transient faults into the state of
+/// the simulated processor, used for resilience testing.
+pub mod fault;
+
+/// Logic and data structures for the load queue, which keeps track of loads
+/// that have read memory speculatively, so that memory ordering violations
+/// with older stores can be detected.
+pub mod load;
+
+/// Logic and data structures for replaying a trace of committed instructions,
+/// checking that the simulator commits the same instructions with the same
+/// results.
+pub mod replay;
+
 /// Logic and data structures for the simulated main memory module, which is
 /// where program instructions and data are stored.
 pub mod memory;
@@ -65,11 +93,37 @@ pub mod reservation;
 /// almost all of the submodules within this module.
 pub mod state;
 
+/// The exceptions that instructions may raise, and the logic for taking them,
+/// either vectoring to the trap handler or halting the simulation.
+pub mod trap;
+
 ///////////////////////////////////////////////////////////////////////////////
 //// CONST/STATIC
 
-/// Whether or not the simulator is initially paused upon being opened.
-pub const INITIALLY_PAUSED: bool = true;
+/// The number of cycles after which a simulation without the display is
+/// assumed to never finish, and is stopped.
+pub const HEADLESS_CYCLE_LIMIT: u64 = 10_000_000;
+
+/// The width of the bar drawn for the most accessed page of memory, when
+/// profiling memory.
+const PROFILE_BAR_WIDTH: u64 = 40;
+
+/// The types of execute unit, in the order that a dry run reports them.
+const UNIT_TYPES: [UnitType; 3] = [UnitType::ALU, UnitType::BLU, UnitType::MCU];
+
+///////////////////////////////////////////////////////////////////////////////
+//// ENUMS
+
+/// The reason that a simulation without the display stopped.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StopReason {
+    /// The program finished.
+    Finished,
+    /// The program stopped at the breakpoint at the given address.
+    Breakpoint(usize),
+    /// The program did not finish within `HEADLESS_CYCLE_LIMIT` cycles.
+    CycleLimit,
+}
 
 ///////////////////////////////////////////////////////////////////////////////
 //// FUNCTIONS
@@ -78,39 +132,494 @@ pub const INITIALLY_PAUSED: bool = true;
 ///
 /// Requires an IoThread for sending events to be output to the display, as
 /// well as for receiving any calls to close the simulation.
-pub fn run_simulator(io: IoThread, config: &Config) {
+///
+/// Returns the exit code of the program, if it exited.
+pub fn run_simulator(io: IoThread, config: &Config) -> Option<i32> {
     let mut state = State::new(&config);
-    let mut paused = INITIALLY_PAUSED;
+    let mut paused = config.start_paused;
+    let mut metrics = config.metrics_csv.as_ref().map(|path| MetricsCsv::create(path));
+    let mut chrome_trace = config.chrome_trace.as_ref().map(|path| ChromeTrace::create(path, &state));
+    let period = match config.speed {
+        0 => None,
+        hz => Some(Duration::from_nanos(1_000_000_000 / hz)),
+    };
 
     // Send the initial state to the UI to be displayed
     io.tx.send(IoEvent::UpdateState(state.clone())).unwrap();
 
     while handle_io_and_continue(&mut paused, &io) {
-        // Maintain immutable past state
-        let state_p = state.clone();
-
-        fetch_stage(&state_p, &mut state);
-        decode_and_rename_stage(&state_p, &mut state);
-        issue_stage(&state_p, &mut state);
-        execute_and_writeback_stage(&state_p, &mut state);
-        let finished = commit_stage(&state_p, &mut state);
-
-        // End of cycle, start housekeeping
-        state.stats.cycles += 1;
+        let finished = step_cycle(&mut state);
+        if let Some(m) = metrics.as_mut() {
+            m.record(&state);
+        }
+        if let Some(t) = chrome_trace.as_mut() {
+            t.record(&state);
+        }
 
-        // Update IO thread and sleep for a moment
+        // Update IO thread and wait for the next cycle, pausing at breakpoints
         io.tx.send(IoEvent::UpdateState(state.clone())).unwrap();
+        if let Some(pc) = state.breakpoint.take() {
+            paused = true;
+            io.tx.send(IoEvent::Breakpoint(pc)).unwrap();
+        }
         if finished {
             io.tx.send(IoEvent::Finish).unwrap();
             break;
         }
-        thread::sleep(Duration::from_millis(25));
+        if let Some(period) = period {
+            if !wait_for_next_cycle(period, &mut paused, &io) {
+                break;
+            }
+        }
+    }
+
+    if let Some(mut m) = metrics {
+        m.flush();
+    }
+    if let Some(mut t) = chrome_trace {
+        t.finish();
     }
 
     #[allow(unused_must_use)]
     {
         io.handle.join();
     }
+
+    // Only report or dump memory once the display has been torn down
+    for shadow in state.shadow_predictors.iter() {
+        let total = shadow.success + shadow.failure;
+        println!(
+            "{:?} predictor: {}/{} branches correct ({:.3})\r",
+            shadow.predictor.mode,
+            shadow.success,
+            total,
+            shadow.success as f32 / total as f32
+        );
+    }
+    if config.op_histogram {
+        print_op_histogram(&state);
+    }
+    if config.hot_branches > 0 {
+        print_hot_branches(&state, config.hot_branches);
+    }
+    if config.profile_memory > 0 {
+        print_memory_profile(&state, config.profile_memory);
+    }
+    if state.deadlocked {
+        print_deadlock(&state);
+    }
+    if let Some(path) = &config.mem_dump {
+        if let Err(e) = state.memory.dump(path) {
+            error!(format!("Failed to dump memory to file:\n{}", e));
+        }
+    }
+    if state.replay.as_ref().map_or(false, |r| r.diverged) {
+        error!("The simulation diverged from the replayed trace.");
+    }
+    if let Some(code) = state.exit_code {
+        println!("Program exited with code {}.\r", code);
+    }
+    state.exit_code
+}
+
+/// Entry point for running the simulation without the display. The given
+/// closure observes the state after every cycle, until the program finishes,
+/// stops at a breakpoint or the cycle limit is reached.
+///
+/// Returns the final state, and the reason that the simulation stopped.
+pub fn run_headless<F: FnMut(&State)>(config: &Config, observe: F) -> (State, StopReason) {
+    run_headless_from(config, State::new(&config), observe)
+}
+
+/// Runs the simulation without the display from the given state, as for
+/// `run_headless`.
+fn run_headless_from<F: FnMut(&State)>(
+    config: &Config,
+    mut state: State,
+    mut observe: F,
+) -> (State, StopReason) {
+    let mut metrics = config.metrics_csv.as_ref().map(|path| MetricsCsv::create(path));
+    let mut chrome_trace = config.chrome_trace.as_ref().map(|path| ChromeTrace::create(path, &state));
+
+    let mut stop = StopReason::CycleLimit;
+    while state.stats.cycles < HEADLESS_CYCLE_LIMIT {
+        let logged = state.log.total;
+        let finished = step_cycle(&mut state);
+        if let Some(m) = metrics.as_mut() {
+            m.record(&state);
+        }
+        if let Some(t) = chrome_trace.as_mut() {
+            t.record(&state);
+        }
+        // There is no display for the log, so it goes to stderr instead
+        for (cycle, level, message) in state.log.since(logged) {
+            eprintln!("[{:>6} {:<5}] {}", cycle, level, message);
+        }
+        observe(&state);
+        if finished {
+            stop = StopReason::Finished;
+            break;
+        }
+        if let Some(pc) = state.breakpoint {
+            stop = StopReason::Breakpoint(pc);
+            break;
+        }
+    }
+
+    if let Some(mut m) = metrics {
+        m.flush();
+    }
+    if let Some(mut t) = chrome_trace {
+        t.finish();
+    }
+    if state.deadlocked {
+        print_deadlock(&state);
+    }
+    if let Some(path) = &config.mem_dump {
+        if let Err(e) = state.memory.dump(path) {
+            error!(format!("Failed to dump memory to file:\n{}", e));
+        }
+    }
+    (state, stop)
+}
+
+/// Entry point for a dry run, which reports the machine that the config
+/// resolves to, and checks it for problems, without simulating. Quits with an
+/// error if the machine would be unable to run the program.
+pub fn dry_run(config: &Config) {
+    let state = State::new(config);
+    let units: Vec<usize> = UNIT_TYPES
+        .iter()
+        .map(|t| unit_count(&state, &t.to_string()))
+        .collect();
+
+    // Count the instructions in the program of each type, and those of them
+    // that no execute unit can execute
+    let mut needed = [0; 3];
+    let mut unhandled = [0; 3];
+    for (start, end) in executable_segments(config) {
+        for i in decode_all(&state.memory[start..end]).into_iter().flatten() {
+            let unit = UnitType::from(i.op);
+            let n = UNIT_TYPES.iter().position(|t| *t == unit).unwrap();
+            needed[n] += 1;
+            if !state.execute_units.iter().any(|eu| eu.handles(i.op)) {
+                unhandled[n] += 1;
+            }
+        }
+    }
+
+    println!("Machine configuration:");
+    if config.fetch_width == 0 {
+        println!("  fetch width:         {} (n-way)", state.fetch_width);
+    } else {
+        println!("  fetch width:         {}", state.fetch_width);
+    }
+    if config.decode_width == 0 {
+        println!("  decode width:        {} (n-way)", state.decode_width);
+    } else {
+        println!("  decode width:        {}", state.decode_width);
+    }
+    println!("  fetch queue:         {}", state.latch_fetch.capacity);
+    match state.fetch_block {
+        0 => println!("  fetch block:         unlimited"),
+        n => println!("  fetch block:         {} bytes", n),
+    }
+    println!("  decode latency:      {}", state.decode_latency);
+    if config.issue_limit == 0 {
+        println!("  issue limit:         {} (number of execute units)", state.issue_limit);
+    } else {
+        println!("  issue limit:         {}", state.issue_limit);
+    }
+    if config.commit_width == 0 {
+        println!("  commit width:        {} (issue limit)", state.commit_width);
+    } else {
+        println!("  commit width:        {}", state.commit_width);
+    }
+    println!("  execute units:       {} ALU, {} BLU, {} MCU", units[0], units[1], units[2]);
+    let mut others: Vec<&str> = vec![];
+    for eu in state.execute_units.iter() {
+        if !UNIT_TYPES.iter().any(|t| t.to_string() == eu.name()) && !others.contains(&eu.name()) {
+            others.push(eu.name());
+        }
+    }
+    for name in others {
+        println!("  other units:         {} {}", unit_count(&state, name), name);
+    }
+    match state.mem_ports {
+        0 => println!("  memory ports:        unlimited"),
+        n => println!("  memory ports:        {}", n),
+    }
+    match state.result_buses {
+        0 => println!("  result buses:        unlimited"),
+        n => println!("  result buses:        {}", n),
+    }
+    match state.spec_depth {
+        0 => println!("  speculation depth:   unlimited"),
+        n => println!("  speculation depth:   {}", n),
+    }
+    let mut routes: Vec<_> = state.routes.iter().collect();
+    routes.sort_by_key(|(op, n)| (**n, op.to_string()));
+    for (op, n) in routes {
+        let name = state.execute_units[*n].name();
+        let index = state.execute_units[..*n]
+            .iter()
+            .filter(|e| e.name() == name)
+            .count();
+        println!("  route:               {} to {} {}", op, name, index);
+    }
+    println!("  memory ordering:     {:?}", config.mem_disambig);
+    match config.unaligned {
+        UnalignedAccess::Slow => println!(
+            "  unaligned accesses:  Slow (+{} cycles)",
+            config.unaligned_penalty
+        ),
+        policy => println!("  unaligned accesses:  {:?}", policy),
+    }
+    println!("  reservation station: {} ({:?} issue)", config.rsv_size, config.issue_policy);
+    println!("  reorder buffer:      {}", config.rob_size);
+    if config.prf_size == 0 {
+        println!("  physical registers:  {} (reorder buffer)", state.phys_regs.capacity);
+    } else {
+        println!("  physical registers:  {}", state.phys_regs.capacity);
+    }
+    println!(
+        "  branch prediction:   {:?}{}",
+        config.branch_prediction,
+        if config.return_address_stack {
+            format!(", with return stack (depth {})", config.ras_depth)
+        } else {
+            String::new()
+        }
+    );
+    println!("  flush penalty:       {}", state.flush_penalty);
+    if state.warmup != 0 {
+        println!("  warmup:              {} cycles", state.warmup);
+    }
+    println!("  memory size:         {} bytes", state.mem_size);
+    for (start, end) in state.protected.iter() {
+        println!("  write-protected:     {:#010x}..{:#010x}", start, end);
+    }
+    if let Some(addr) = state.uart_addr {
+        println!("  uart:                {:#010x}", addr);
+    }
+    println!("Program instructions:  {} ALU, {} BLU, {} MCU", needed[0], needed[1], needed[2]);
+
+    let (warnings, errors) = check_machine(config, &state, &unhandled);
+    for warning in warnings.iter() {
+        println!("warning: {}", warning);
+    }
+    for error in errors.iter() {
+        println!("error: {}", error);
+    }
+    if !errors.is_empty() {
+        error!(format!("The configuration has {} error(s).", errors.len()));
+    }
+    println!("Configuration OK.");
+}
+
+/// Checks the machine that the config resolves to, in the given state, for
+/// problems, given the number of program instructions of each type that no
+/// execute unit can execute, in the order of `UNIT_TYPES`. Returns the
+/// warnings, and the errors that leave the machine unable to run the program.
+fn check_machine(
+    config: &Config,
+    state: &State,
+    unhandled: &[usize],
+) -> (Vec<String>, Vec<String>) {
+    let mut warnings = vec![];
+    let mut errors = vec![];
+    if state.fetch_width == 0 {
+        errors.push(String::from("The fetch width is 0, so nothing can be fetched."));
+    }
+    if state.decode_width == 0 {
+        errors.push(String::from("The decode width is 0, so nothing can be decoded."));
+    }
+    if config.rsv_size == 0 {
+        errors.push(String::from("The reservation station has no entries."));
+    }
+    if config.rob_size == 0 {
+        errors.push(String::from("The reorder buffer has no entries."));
+    }
+    if config.rob_size < config.rsv_size {
+        warnings.push(format!(
+            "The reorder buffer is smaller than the reservation station, so {} reservation station entries can never be used.",
+            config.rsv_size - config.rob_size
+        ));
+    }
+    if state.issue_limit > state.execute_units.len() {
+        warnings.push(format!(
+            "The issue limit ({}) is more than the number of execute units ({}), so can never be reached.",
+            state.issue_limit,
+            state.execute_units.len()
+        ));
+    }
+    for (n, unit_type) in UNIT_TYPES.iter().enumerate() {
+        if unhandled[n] > 0 {
+            errors.push(format!(
+                "The program has {} {:?} instructions, but there are no {:?}s to execute them.",
+                unhandled[n], unit_type, unit_type
+            ));
+        }
+    }
+    (warnings, errors)
+}
+
+/// Returns the number of execute units in the given state with the given name.
+fn unit_count(state: &State, name: &str) -> usize {
+    state.execute_units.iter().filter(|e| e.name() == name).count()
+}
+
+/// Prints how many of each operation have been committed, and what proportion
+/// of all committed instructions that is, most frequent first.
+fn print_op_histogram(state: &State) {
+    let mut counts: Vec<_> = state.stats.op_counts.iter().collect();
+    counts.sort_by_key(|(op, count)| (Reverse(**count), format!("{}", op)));
+    println!("Committed instructions by operation:\r");
+    for (op, count) in counts {
+        println!(
+            "  {:<10} {:>10} ({:.2}%)\r",
+            format!("{}", op),
+            count,
+            100.0 * *count as f32 / state.stats.executed as f32
+        );
+    }
+}
+
+/// Prints the `n` branches and jumps with the most mispredictions, along with
+/// their disassembled instruction and misprediction rate, most first.
+fn print_hot_branches(state: &State, n: usize) {
+    let mut counts: Vec<_> = state
+        .stats
+        .branch_counts
+        .iter()
+        .filter(|(_, (mispredicted, _))| *mispredicted > 0)
+        .collect();
+    counts.sort_by_key(|(pc, (mispredicted, _))| (Reverse(*mispredicted), **pc));
+    println!("Most mispredicted branches:\r");
+    for (pc, (mispredicted, total)) in counts.into_iter().take(n) {
+        let asm = match Instruction::decode(state.memory.read_i32(*pc).word) {
+            Some(i) => i.disassemble(),
+            None => String::from("<illegal>"),
+        };
+        println!(
+            "  {:08x}  {:<24} {:>8}/{:<8} ({:.3})\r",
+            pc,
+            asm,
+            mispredicted,
+            total,
+            *mispredicted as f32 / *total as f32
+        );
+    }
+}
+
+/// Prints the number of fetches, loads and stores made to each page of memory
+/// that was accessed, in address order, with a bar showing the total accesses
+/// relative to the most accessed page.
+fn print_memory_profile(state: &State, page_size: usize) {
+    let mut pages: Vec<_> = state.stats.page_counts.iter().collect();
+    pages.sort_by_key(|(page, _)| **page);
+    let most = pages.iter().map(|(_, c)| c.iter().sum::<u64>()).max().unwrap_or(0);
+    println!("Memory accesses by {} byte page:\r", page_size);
+    println!("  {:<17} {:>10} {:>10} {:>10}\r", "page", "fetches", "loads", "stores");
+    for (page, counts) in pages {
+        let total: u64 = counts.iter().sum();
+        let start = page * page_size;
+        println!(
+            "  {:08x}-{:08x} {:>10} {:>10} {:>10} {}\r",
+            start,
+            start.saturating_add(page_size - 1),
+            counts[0],
+            counts[1],
+            counts[2],
+            "#".repeat(((PROFILE_BAR_WIDTH * total + most - 1) / most) as usize)
+        );
+    }
+}
+
+/// Prints the reorder buffer, reservation station and execute units to stderr,
+/// to show what the pipeline was stuck waiting on when it deadlocked.
+pub fn print_deadlock(state: &State) {
+    write_deadlock(state, &mut io::stderr()).expect("Failed to print deadlock.");
+}
+
+/// Writes the reorder buffer, reservation station and execute units to the
+/// given output, as printed when the pipeline deadlocks.
+fn write_deadlock<W: Write>(state: &State, out: &mut W) -> io::Result<()> {
+    let rob = &state.reorder_buffer;
+    writeln!(out, "Reorder buffer ({} in use):\r", rob.count)?;
+    for i in 0..rob.count {
+        let n = (rob.front + i) % rob.capacity;
+        writeln!(out, "  {:>3}: {}\r", n, rob[n])?;
+    }
+    writeln!(out, "Reservation station ({} in use):\r", state.resv_station.contents.len())?;
+    for r in state.resv_station.contents.iter() {
+        writeln!(out, "  {}\r", r)?;
+    }
+    writeln!(out, "Execute units:\r")?;
+    for (n, eu) in state.execute_units.iter().enumerate() {
+        let index = state.execute_units[..n]
+            .iter()
+            .filter(|e| e.name() == eu.name())
+            .count();
+        let executing: Vec<String> = eu
+            .pipeline()
+            .executing
+            .iter()
+            .map(|(r, len)| format!("r{} ({} steps left)", r.rob_entry, len.steps))
+            .collect();
+        if executing.is_empty() {
+            writeln!(out, "  {} {}: idle\r", eu.name(), index)?;
+        } else {
+            writeln!(out, "  {} {}: {}\r", eu.name(), index, executing.join(", "))?;
+        }
+    }
+    Ok(())
+}
+
+/// Runs the simulated processor for a single clock cycle, advancing every
+/// stage of the pipeline once. Returns whether or not the simulated program
+/// has finished execution.
+pub fn step_cycle(state: &mut State) -> bool {
+    // Maintain immutable past state
+    let state_p = state.clone();
+
+    fetch_stage(&state_p, state);
+    decode_and_rename_stage(&state_p, state);
+    issue_stage(&state_p, state);
+    execute_and_writeback_stage(&state_p, state);
+    let finished = commit_stage(&state_p, state);
+    inject_fault(state);
+
+    // End of cycle, start housekeeping
+    state.stats.cycles += 1;
+    if !finished && state.stats.cycles == state.warmup {
+        state.stats.end_warmup();
+    }
+    finished
+}
+
+/// Waits for the given period between cycles, whilst still handling messages
+/// from the input/output thread as they arrive, so that pausing is immediate
+/// however slowly the simulation is running. Stops waiting early if paused.
+/// Returns false when the user closed the simulator.
+fn wait_for_next_cycle(period: Duration, paused: &mut bool, io: &IoThread) -> bool {
+    let deadline = Instant::now() + period;
+    while !*paused {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        match io.rx.recv_timeout(deadline - now) {
+            Ok(e) => {
+                if !handle_message(e, paused) {
+                    return false;
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => error!("IO Thread missing, assumed dead."),
+            Err(RecvTimeoutError::Timeout) => break,
+        }
+    }
+    true
 }
 
 /// Handles any messages from the input/output thread. Will block if paused, &
@@ -144,3 +653,227 @@ fn handle_message(event: SimulatorEvent, paused: &mut bool) -> bool {
         SimulatorEvent::Cycle => true,
     }
 }
+
+///////////////////////////////////////////////////////////////////////////////
+//// TESTS
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isa::operand::Register;
+    use crate::simulator::builder::StateBuilder;
+    use crate::util::config::Command;
+
+    #[test]
+    fn reports_missing_execute_unit() {
+        let mut config = Config::default();
+        config.blu_units = 0;
+        let state = StateBuilder::new().config(config.clone()).build();
+        let (_, errors) = check_machine(&config, &state, &[0, 3, 0]);
+        assert_eq!(
+            errors,
+            vec!["The program has 3 BLU instructions, but there are no BLUs to execute them."]
+        );
+        let (_, errors) = check_machine(&config, &state, &[0, 0, 0]);
+        assert!(errors.is_empty());
+    }
+
+    /// Runs the program assembled from the given source in `tests/fixtures`
+    /// to completion on both the default and a wide machine, checking the
+    /// final state of each with the given function.
+    fn run_fixture<F: Fn(&State)>(name: &str, check: F) {
+        let elf_file = format!("tests/fixtures/{}.elf", name);
+        let wide = [
+            "--alu=4", "--mcu=4", "--rsv=32", "--rob=64", "-n=4", "-i=6", "-r",
+        ];
+        for options in [&[][..], &wide[..]].iter() {
+            let mut args = vec!["daybreak", "trace", "--symbols"];
+            args.extend(options.iter());
+            args.push(&elf_file);
+            let (state, stop) = run_headless(&Config::create_from(args), |_| ());
+            assert_eq!(stop, StopReason::Finished, "{} {:?}", name, options);
+            assert_eq!(state.exit_code, Some(0), "{} {:?}", name, options);
+            check(&state);
+        }
+    }
+
+    /// Returns the address of the label with the given name in the program.
+    fn label(state: &State, name: &str) -> usize {
+        *state
+            .symbols
+            .iter()
+            .find(|(_, n)| n.as_str() == name)
+            .unwrap()
+            .0
+    }
+
+    #[test]
+    fn runs_arithmetic_fixture() {
+        run_fixture("arithmetic", |state| {
+            let regs = [
+                (Register::X7, 93),
+                (Register::X28, 107),
+                (Register::X29, -700),
+                (Register::X30, -14),
+                (Register::X31, 2),
+                (Register::X11, 1600),
+                (Register::X12, -4),
+                (Register::X13, 1),
+                (Register::X14, -99),
+            ];
+            for (register, value) in regs.iter() {
+                assert_eq!(state.register[*register].data, *value, "{:?}", register);
+            }
+            let results = label(state, "results");
+            for (i, value) in [93, 107, -700, -14, 2].iter().enumerate() {
+                assert_eq!(state.memory.read_i32(results + 4 * i).word, *value);
+            }
+        });
+    }
+
+    #[test]
+    fn runs_branch_loop_fixture() {
+        run_fixture("branch_loop", |state| {
+            assert_eq!(state.register[Register::X5].data, 21);
+            assert_eq!(state.register[Register::X10].data, 210);
+            assert_eq!(state.register[Register::X11].data, 100);
+            let sums = label(state, "sums");
+            assert_eq!(state.memory.read_i32(sums).word, 210);
+            assert_eq!(state.memory.read_i32(sums + 4).word, 100);
+        });
+    }
+
+    #[test]
+    fn runs_memory_copy_fixture() {
+        run_fixture("memory_copy", |state| {
+            let (source, dest) = (label(state, "source"), label(state, "dest"));
+            for i in 0..8 {
+                assert_eq!(
+                    state.memory.read_i32(dest + 4 * i).word,
+                    state.memory.read_i32(source + 4 * i).word
+                );
+            }
+            assert_eq!(state.memory.read_i32(dest + 16).word, 0x1234_5678);
+            assert_eq!(state.register[Register::X11].data, 0x1234_5688);
+            let text_copy = label(state, "text_copy");
+            assert_eq!(state.memory.read_u32(text_copy).word, 0x0021_6968);
+        });
+    }
+
+    #[test]
+    fn wider_machines_sweep_to_higher_ipc() {
+        let mut config = Config::default();
+        config.command = Command::Bench;
+        config.elf_file = String::from("resources/programs/fib_non_recursive/a.out");
+        let ipcs: Vec<f64> = ["1", "2", "4"]
+            .iter()
+            .map(|n_way| {
+                let mut run = config.clone();
+                run.set_param("n-way", n_way).unwrap();
+                let (state, stop) = run_headless(&run, |_| ());
+                assert_eq!(stop, StopReason::Finished);
+                assert_eq!(state.exit_code, Some(0));
+                state.stats.retired() as f64 / state.stats.cycles as f64
+            })
+            .collect();
+        assert!(ipcs[1] > ipcs[0], "{:?}", ipcs);
+        assert!(ipcs[2] >= ipcs[1] * 0.95, "{:?}", ipcs);
+    }
+
+    #[test]
+    fn stops_at_breakpoint() {
+        let state = StateBuilder::new()
+            .asm("addi x5 x0 1")
+            .asm("ebreak")
+            .asm("addi x6 x0 1")
+            .asm("jalr x0 x1 0")
+            .build();
+        let (state, stop) = run_headless_from(&Config::default(), state, |_| ());
+        assert_eq!(stop, StopReason::Breakpoint(4));
+        assert_eq!(state.register[Register::X5].data, 1);
+        assert_eq!(state.register[Register::X6].data, 0);
+    }
+
+    #[test]
+    fn runs_headless_without_pacing() {
+        let mut config = Config::default();
+        config.command = Command::Bench;
+        config.elf_file = String::from("resources/programs/fib_recursive/a.out");
+        let start = Instant::now();
+        let (state, stop) = run_headless(&config, |_| ());
+        assert_eq!(stop, StopReason::Finished);
+        // Far less than the 25ms per cycle that the display runs at
+        let limit = Duration::from_millis(25) * state.stats.cycles as u32 / 10;
+        assert!(start.elapsed() < limit, "{:?}", start.elapsed());
+    }
+
+    #[test]
+    fn stops_with_exit_code() {
+        let state = StateBuilder::new()
+            .asm("addi x10 x0 7")
+            .asm("addi x17 x0 93")
+            .asm("ecall")
+            .asm("addi x10 x0 1")
+            .asm("jalr x0 x1 0")
+            .build();
+        let (state, stop) = run_headless_from(&Config::default(), state, |_| ());
+        assert_eq!(stop, StopReason::Finished);
+        assert_eq!(state.exit_code, Some(7));
+        assert_eq!(state.register[Register::X10].data, 7);
+    }
+
+    #[test]
+    fn warmup_leaves_early_instructions_out_of_statistics() {
+        let mut config = Config::default();
+        config.command = Command::Bench;
+        config.elf_file = String::from("resources/programs/fib_non_recursive/a.out");
+        let (cold, _) = run_headless(&config, |_| ());
+
+        // Whatever retired in the first 50 cycles is left out, and every
+        // later instruction is counted just the same
+        let mut early = State::new(&config);
+        for _ in 0..50 {
+            step_cycle(&mut early);
+        }
+        config.warmup = 50;
+        let (warm, stop) = run_headless(&config, |_| ());
+        assert_eq!(stop, StopReason::Finished);
+        assert!(early.stats.executed > 0);
+        assert_eq!(warm.stats.warmup_executed, early.stats.executed);
+        assert_eq!(
+            warm.stats.executed,
+            cold.stats.executed - early.stats.executed
+        );
+        assert_eq!(warm.stats.retired(), cold.stats.retired());
+        assert_eq!(warm.stats.measured_cycles(), cold.stats.cycles - 50);
+    }
+
+    #[test]
+    fn dumps_pipeline_when_deadlocked() {
+        // Without a memory unit, the load can never execute
+        let mut config = Config::default();
+        config.mcu_units = 0;
+        config.deadlock_cycles = 20;
+        let mut state = StateBuilder::new()
+            .config(config)
+            .asm("addi x5 x0 1")
+            .asm("lw x10 x0 0")
+            .asm("jalr x0 x1 0")
+            .build();
+        while !step_cycle(&mut state) {
+            assert!(state.stats.cycles < 1000, "Did not finish.");
+        }
+        assert!(state.deadlocked);
+        assert_eq!(state.stats.retired(), 1);
+        assert_eq!(state.register[Register::X5].data, 1);
+
+        let mut out = Vec::new();
+        write_deadlock(&state, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("Reorder buffer (2 in use):"));
+        assert!(out.contains("    1: × N000  0     lw 00000004"));
+        assert!(out.contains("Reservation station (2 in use):"));
+        assert!(out.contains("ALU 0: idle"));
+        assert!(out.contains("BLU 0: idle"));
+    }
+}
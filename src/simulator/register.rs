@@ -1,3 +1,4 @@
+use std::collections::VecDeque;
 use std::default::Default;
 use std::ops::{Index, IndexMut};
 
@@ -29,6 +30,20 @@ pub struct ArchRegEntry {
     pub rename: Option<usize>,
 }
 
+/// The free list of the physical register file, which holds the result of
+/// each instruction that writes to a register from when it is renamed until
+/// it is committed. Results are forwarded through the reorder buffer, so only
+/// which physical registers are free is kept, limiting how many of these
+/// instructions can be in flight independently of the reorder buffer size.
+#[derive(Clone, Default)]
+pub struct PhysRegFile {
+    /// The number of physical registers.
+    pub capacity: usize,
+    /// The physical registers that are not holding a result, in the order
+    /// that they will be allocated.
+    pub free: VecDeque<usize>,
+}
+
 ///////////////////////////////////////////////////////////////////////////////
 //// IMPLEMENTATIONS
 
@@ -88,6 +103,37 @@ impl RegisterFile {
     }
 }
 
+impl PhysRegFile {
+    /// Creates a new physical register file, with every register free.
+    pub fn new(capacity: usize) -> PhysRegFile {
+        PhysRegFile {
+            capacity,
+            free: (0..capacity).collect(),
+        }
+    }
+
+    /// Allocates a free physical register, if there are any.
+    pub fn allocate(&mut self) -> Option<usize> {
+        self.free.pop_front()
+    }
+
+    /// Frees a physical register, once its result has been committed.
+    pub fn release(&mut self, reg: usize) {
+        self.free.push_back(reg);
+    }
+
+    /// Returns the number of physical registers holding a result.
+    pub fn in_use(&self) -> usize {
+        self.capacity - self.free.len()
+    }
+
+    /// Frees every physical register, this would happen when the pipeline is
+    /// invalidated and needs to be restarted from scratch.
+    pub fn flush(&mut self) {
+        self.free = (0..self.capacity).collect();
+    }
+}
+
 impl Default for ArchRegEntry {
     /// Returns the default register entry, that is with a valid value of 0
     /// set.
@@ -7,7 +7,7 @@ use either::{Either, Left, Right};
 use crate::isa::op_code::Operation;
 use crate::isa::operand::Register;
 
-use super::branch::ReturnStackOp;
+use super::branch::{control_target, ReturnStackOp};
 
 ///////////////////////////////////////////////////////////////////////////////
 //// STRUCTS
@@ -27,6 +27,11 @@ pub struct ReorderBuffer {
     pub back: usize,
     /// The amount of items in the circular buffer.
     pub count: usize,
+    /// The amount of committed items at the start of the circular buffer,
+    /// that are yet to be cleaned up. This tells apart the buffer being
+    /// entirely committed from entirely uncommitted, when `front_fin` meets
+    /// `front`.
+    pub committed: usize,
     /// The capacity of the circular buffer.
     pub capacity: usize,
 }
@@ -56,6 +61,9 @@ pub struct ReorderEntry {
     pub act_rd: Option<i32>,
     /// The pre-renamed `rd` result register.
     pub reg_rd: Option<Register>,
+    /// The physical register allocated to hold the result, if the instruction
+    /// writes to a register.
+    pub phys_rd: Option<usize>,
     /// Either the first source register name, or value. If this argument is
     /// unused, it will be set as 0.
     pub rs1: Either<i32, usize>,
@@ -64,6 +72,29 @@ pub struct ReorderEntry {
     pub rs2: Either<i32, usize>,
     /// The immediate of the pending instruction, if applicable.
     pub imm: Option<i32>,
+    /// The address in memory that the instruction accesses, if it was known
+    /// when the instruction was fetched.
+    pub mem_addr: Option<usize>,
+    /// The cycle in which the instruction was fetched.
+    pub cycle_fetched: u64,
+    /// The cycle in which this entry was reserved in the _decode_ stage.
+    pub cycle_reserved: u64,
+    /// The cycle in which this entry was issued to an execute unit.
+    pub cycle_issued: Option<u64>,
+    /// The index of the execute unit that this entry was issued to.
+    pub execute_unit: Option<usize>,
+    /// The cycle in which this entry finished execution.
+    pub cycle_finished: Option<u64>,
+    /// The cycle in which this entry was committed.
+    pub cycle_committed: Option<u64>,
+    /// The reorder buffer entry of a load whose result this entry was waiting
+    /// on when it was decoded, if any.
+    pub load_dep: Option<usize>,
+    /// The number of cycles from when the load in `load_dep` was issued until
+    /// this entry was issued, once it has been.
+    pub load_use: Option<u64>,
+    /// The address and value that a store wrote to memory, once committed.
+    pub mem_write: Option<(usize, u32)>,
 }
 
 ///////////////////////////////////////////////////////////////////////////////
@@ -78,6 +109,7 @@ impl ReorderBuffer {
             front: 0,
             back: 0,
             count: 0,
+            committed: 0,
             capacity,
         }
     }
@@ -88,6 +120,12 @@ impl ReorderBuffer {
         self.count < self.capacity
     }
 
+    /// Returns the number of entries that are yet to be committed. This
+    /// excludes committed entries that are still waiting to be cleaned up.
+    pub fn in_flight(&self) -> usize {
+        self.count - self.committed
+    }
+
     /// If available, reserves a slot for a given reorder buffer entry.
     pub fn reserve_entry(&mut self, entry: ReorderEntry) -> Option<usize> {
         // Check we have space
@@ -131,47 +169,29 @@ impl ReorderBuffer {
         new_rob: &mut ReorderBuffer,
         limit: usize,
     ) -> Vec<usize> {
-        if self.count == 0 {
-            return vec![]
-        }
-
         let mut popped = vec![];
-        let unfinished_count = if self.back < self.front_fin {
-            self.back + self.capacity - self.front_fin
-        } else if self.front_fin < self.back {
-            self.back - self.front_fin
-        } else { // self.front_fin == self.back
-            self.count
-        };
-        for i in 0..min(limit, unfinished_count) {
-            if self.rob[(self.front_fin + i) % self.capacity].finished {
-                new_rob.front_fin = (new_rob.front_fin + 1) % new_rob.capacity;
-                new_rob.cleanup();
-                popped.push((self.front_fin + i) % self.capacity)
-            } else {
-                for _ in i .. min(limit, unfinished_count) {
-                    new_rob.cleanup();
-                }
+        for i in 0..min(limit, self.in_flight()) {
+            let entry = (self.front_fin + i) % self.capacity;
+            if !self.rob[entry].finished {
                 break;
             }
+            new_rob.front_fin = (new_rob.front_fin + 1) % new_rob.capacity;
+            new_rob.committed += 1;
+            popped.push(entry);
         }
+        new_rob.cleanup();
 
         popped
     }
 
-    /// Cleans up any straggling entries that are finished _and_ have a zero
-    /// reference count.
+    /// Cleans up the committed entries at the front that are no longer
+    /// referenced, stopping at the first that still is, so that their slots
+    /// can be reused.
     fn cleanup(&mut self) {
-        if self.rob[self.front].finished && self.rob[self.front].ref_count == 0 {
-            let new_front = if self.front != self.front_fin {
-                (self.front + 1) % self.capacity
-            } else {
-                self.front_fin
-            };
-            if new_front != self.front_fin {
-                self.count -= 1;
-                self.front = new_front;
-            }
+        while self.committed != 0 && self.rob[self.front].ref_count == 0 {
+            self.front = (self.front + 1) % self.capacity;
+            self.count -= 1;
+            self.committed -= 1;
         }
     }
 
@@ -182,6 +202,7 @@ impl ReorderBuffer {
         self.front = 0;
         self.back = 0;
         self.count = 0;
+        self.committed = 0;
     }
 }
 
@@ -215,9 +236,20 @@ impl Default for ReorderEntry {
             act_pc: 0,
             act_rd: None,
             reg_rd: None,
+            phys_rd: None,
             rs1: Left(0),
             rs2: Left(0),
             imm: None,
+            mem_addr: None,
+            cycle_fetched: 0,
+            cycle_reserved: 0,
+            cycle_issued: None,
+            execute_unit: None,
+            cycle_finished: None,
+            cycle_committed: None,
+            load_dep: None,
+            load_use: None,
+            mem_write: None,
         }
     }
 }
@@ -246,6 +278,198 @@ impl Display for ReorderEntry {
             Right(rob) => write!(f, " r{}", rob)?,
         }
         write!(f, " {}", format_option!("{}", self.imm))?;
+        // Branches and jumps also show their target, to compare with the pc.
+        // Finished jumps may have already let go of their base register.
+        let target = match self.op {
+            Operation::JALR if self.finished => Some(self.act_pc),
+            _ => control_target(self.op, self.pc, self.rs1, self.imm),
+        };
+        if let Some(target) = target {
+            write!(f, "→{:08x}", target)?;
+        }
         Ok(())
     }
 }
+
+///////////////////////////////////////////////////////////////////////////////
+//// TESTS
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::builder::StateBuilder;
+    use crate::simulator::step_cycle;
+    use crate::util::config::Config;
+
+    #[test]
+    fn timestamps_are_in_stage_order() {
+        let mut config = Config::default();
+        config.n_way = 2;
+        let mut state = StateBuilder::new()
+            .config(config)
+            .origin(0x1000)
+            .word(0x2000, 6)
+            .reg(Register::X5, 0x2000)
+            .asm("lw x6 x5 0")
+            .asm("mul x7 x6 x6")
+            .asm("addi x8 x7 1")
+            .asm("sw x5 x8 4")
+            .asm("div x9 x8 x6")
+            .asm("jalr x0 x1 0")
+            .build();
+        let mut committed = 0;
+        while !step_cycle(&mut state) {
+            assert!(state.stats.cycles < 200);
+            for entry in state.reorder_buffer.rob.iter() {
+                let stages = [
+                    Some(entry.cycle_fetched),
+                    Some(entry.cycle_reserved),
+                    entry.cycle_issued,
+                    entry.cycle_finished,
+                    entry.cycle_committed,
+                ];
+                let reached: Vec<u64> = stages
+                    .iter()
+                    .take_while(|c| c.is_some())
+                    .flatten()
+                    .cloned()
+                    .collect();
+                assert!(reached.windows(2).all(|w| w[0] <= w[1]), "{:?}", stages);
+                assert_eq!(
+                    reached.len(),
+                    stages.iter().flatten().count(),
+                    "{:?}",
+                    stages
+                );
+                if entry.cycle_committed.is_some() {
+                    committed += 1;
+                }
+            }
+        }
+        assert_eq!(state.register[Register::X9].data, 37 / 6);
+        assert!(committed > 0);
+    }
+
+    #[test]
+    fn displays_target_of_branches_and_jumps() {
+        let jump = ReorderEntry {
+            op: Operation::JAL,
+            pc: 0x100,
+            imm: Some(-0x10),
+            ..ReorderEntry::default()
+        };
+        assert!(jump.to_string().ends_with(" -16→000000f0"));
+
+        // A finished jump shows where it went, as its base register may have
+        // since been cleared
+        let jump = ReorderEntry {
+            op: Operation::JALR,
+            pc: 0x100,
+            rs1: Right(3),
+            imm: Some(4),
+            ..ReorderEntry::default()
+        };
+        assert!(!jump.to_string().contains('→'));
+        let jump = ReorderEntry {
+            finished: true,
+            act_pc: 0x204,
+            rs1: Left(0),
+            ..jump
+        };
+        assert!(jump.to_string().ends_with(" 4→00000204"));
+    }
+
+    /// Reserves the given number of default entries, returning where they
+    /// were placed.
+    fn reserve(rob: &mut ReorderBuffer, n: usize) -> Vec<usize> {
+        (0..n)
+            .map(|_| rob.reserve_entry(ReorderEntry::default()).unwrap())
+            .collect()
+    }
+
+    /// Commits up to the given number of finished entries, as the commit
+    /// stage does, returning those committed.
+    fn commit(rob: &mut ReorderBuffer, limit: usize) -> Vec<usize> {
+        let mut new_rob = rob.clone();
+        let popped = rob.pop_finished_entries(&mut new_rob, limit);
+        *rob = new_rob;
+        popped
+    }
+
+    #[test]
+    fn wraps_around_with_back_before_front() {
+        let mut rob = ReorderBuffer::new(4);
+        assert_eq!(reserve(&mut rob, 3), vec![0, 1, 2]);
+        rob[0].finished = true;
+        rob[1].finished = true;
+        assert_eq!(commit(&mut rob, 4), vec![0, 1]);
+        assert_eq!((rob.front, rob.front_fin, rob.count), (2, 2, 1));
+
+        // The back wraps around to before the front, with entry 0 waiting on
+        // entry 3 across the end of the buffer
+        assert_eq!(reserve(&mut rob, 2), vec![3, 0]);
+        assert_eq!((rob.front, rob.back, rob.in_flight()), (2, 1, 3));
+        rob[3].ref_count = 1;
+        rob[0].rs1 = Right(3);
+        rob.execute_bypass(3, 42);
+        assert_eq!(rob[0].rs1, Left(42));
+        assert_eq!(rob[3].ref_count, 0);
+
+        // Committing past the end leaves the buffer empty, where it stopped
+        rob[2].finished = true;
+        rob[3].finished = true;
+        assert_eq!(commit(&mut rob, 2), vec![2, 3]);
+        rob[0].finished = true;
+        assert_eq!(commit(&mut rob, 2), vec![0]);
+        assert_eq!((rob.front, rob.front_fin, rob.back), (1, 1, 1));
+        assert_eq!((rob.count, rob.committed), (0, 0));
+        assert_eq!(reserve(&mut rob, 4), vec![1, 2, 3, 0]);
+        assert!(!rob.free_capacity());
+    }
+
+    #[test]
+    fn keeps_committed_entries_until_unreferenced() {
+        let mut rob = ReorderBuffer::new(4);
+        reserve(&mut rob, 3);
+        rob[0].finished = true;
+        rob[0].ref_count = 1;
+        rob[1].finished = true;
+
+        // Both commit, but neither slot is freed while the front entry is
+        // still referenced
+        assert_eq!(commit(&mut rob, 4), vec![0, 1]);
+        assert_eq!((rob.front, rob.front_fin), (0, 2));
+        assert_eq!((rob.count, rob.committed, rob.in_flight()), (3, 2, 1));
+        assert_eq!(reserve(&mut rob, 1), vec![3]);
+        assert!(!rob.free_capacity());
+        assert_eq!(rob.reserve_entry(ReorderEntry::default()), None);
+
+        // Once let go of, the next commit frees both, even with nothing new
+        // to commit
+        rob[0].ref_count = 0;
+        assert_eq!(commit(&mut rob, 4), vec![]);
+        assert_eq!((rob.front, rob.front_fin), (2, 2));
+        assert_eq!((rob.count, rob.committed, rob.in_flight()), (2, 0, 2));
+    }
+
+    #[test]
+    fn bypass_drops_reference_counts() {
+        let mut rob = ReorderBuffer::new(4);
+        reserve(&mut rob, 3);
+        rob[0].ref_count = 3;
+        rob[1].rs1 = Right(0);
+        rob[2].rs1 = Right(0);
+        rob[2].rs2 = Right(0);
+        rob.execute_bypass(0, 7);
+        assert_eq!(rob[0].ref_count, 0);
+        assert_eq!(
+            (rob[1].rs1, rob[2].rs1, rob[2].rs2),
+            (Left(7), Left(7), Left(7))
+        );
+
+        // So the producer is freed as soon as it commits
+        rob[0].finished = true;
+        assert_eq!(commit(&mut rob, 1), vec![0]);
+        assert_eq!((rob.front, rob.count, rob.committed), (1, 2, 0));
+    }
+}
@@ -0,0 +1,254 @@
+use std::fs;
+use std::sync::Arc;
+
+use crate::isa::Instruction;
+
+use super::state::State;
+
+///////////////////////////////////////////////////////////////////////////////
+//// STRUCTS
+
+/// A trace of committed instructions, as printed by the `trace` subcommand,
+/// that the simulator is checked against as it commits instructions. The
+/// trace may or may not include the cycle numbers, so that traces from other
+/// configurations (or simulators) can be replayed.
+#[derive(Clone)]
+pub struct Replay {
+    /// The instructions expected to commit, in order. These are shared between
+    /// every clone of the state.
+    entries: Arc<Vec<ReplayEntry>>,
+    /// The number of expected instructions that have been committed so far.
+    pub pos: usize,
+    /// Whether the simulation has diverged from the trace, and was stopped.
+    pub diverged: bool,
+}
+
+/// A single committed instruction within a trace.
+#[derive(Clone, Debug)]
+struct ReplayEntry {
+    /// The line in the trace file that the instruction is on.
+    line: usize,
+    /// The program counter of the instruction.
+    pc: usize,
+    /// Where the instruction wrote to, either a register or memory, and the
+    /// value written, if anything.
+    effect: Option<(String, u32)>,
+    /// The instruction as it appears in the trace, without the cycle number.
+    text: String,
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// IMPLEMENTATIONS
+
+impl Replay {
+    /// Loads the trace to replay from the given file, quitting if it could
+    /// not be read or parsed.
+    pub fn load(path: &str) -> Replay {
+        let contents = match fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) => error!(format!("Failed to load trace to replay:\n{}", e)),
+        };
+        match parse_trace(&contents) {
+            Ok(entries) => Replay {
+                entries: Arc::new(entries),
+                pos: 0,
+                diverged: false,
+            },
+            Err(message) => error!(format!("Failed to parse trace to replay:\n{}", message)),
+        }
+    }
+
+    /// Returns the number of instructions in the trace.
+    pub fn count(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Checks that the given reorder buffer entry, which has just been
+    /// committed, is the next instruction in the trace, and wrote the same
+    /// value to the same register or memory. Returns a description of the
+    /// divergence if not.
+    pub fn check(&mut self, state: &State, entry: usize) -> Result<(), Vec<String>> {
+        let pc = state.reorder_buffer[entry].pc;
+        let effect = commit_effect(state, entry);
+        let expected = match self.entries.get(self.pos) {
+            Some(e) if e.pc == pc && e.effect == effect => {
+                self.pos += 1;
+                return Ok(());
+            }
+            Some(e) => format!("line {}: {}", e.line, e.text),
+            None => String::from("the end of the trace"),
+        };
+        Err(vec![
+            format!("Replay diverged at instruction {}!", self.pos + 1),
+            format!("  expected  {}", expected),
+            format!("  committed {}", format_commit(state, entry)),
+        ])
+    }
+
+    /// Checks that every instruction in the trace was committed, once the
+    /// program has finished. Returns a description of the divergence if not.
+    pub fn check_finished(&self) -> Result<(), Vec<String>> {
+        match self.entries.get(self.pos) {
+            Some(e) => Err(vec![
+                format!("Replay diverged at instruction {}!", self.pos + 1),
+                format!("  expected  line {}: {}", e.line, e.text),
+                String::from("  committed the end of the program"),
+            ]),
+            None => Ok(()),
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// FUNCTIONS
+
+/// Formats a committed reorder buffer entry as it appears in a trace, without
+/// the cycle number: its program counter, the disassembled instruction and
+/// the value written to the destination register or memory (if any).
+pub fn format_commit(state: &State, entry: usize) -> String {
+    let rob_entry = &state.reorder_buffer[entry];
+    let asm = match Instruction::decode(state.memory.read_i32(rob_entry.pc).word) {
+        Some(i) => i.disassemble(),
+        None => String::from("<illegal>"),
+    };
+    match commit_effect(state, entry) {
+        Some((dest, val)) => format!("{:08x}  {:<24} {} <- {:08x}", rob_entry.pc, asm, dest, val),
+        None => format!("{:08x}  {}", rob_entry.pc, asm),
+    }
+}
+
+/// Returns where a committed reorder buffer entry wrote to, as it appears in
+/// a trace, and the value written, if anything. Stores are written as the
+/// address in memory, e.g. `mem[00011000]`.
+fn commit_effect(state: &State, entry: usize) -> Option<(String, u32)> {
+    let rob_entry = &state.reorder_buffer[entry];
+    match (rob_entry.reg_rd, rob_entry.act_rd, rob_entry.mem_write) {
+        (Some(rd), Some(val), _) => Some((format!("{:#}", rd), val as u32)),
+        (_, _, Some((addr, val))) => Some((format!("mem[{:08x}]", addr), val)),
+        _ => None,
+    }
+}
+
+/// Parses the committed instructions from a trace. Each line is the
+/// (optional) cycle number, the program counter and the instruction, followed
+/// by `<rd> <- <value>` if it wrote to a register, or by
+/// `mem[<address>] <- <value>` if it wrote to memory. The instructions end at
+/// the first line without a program counter, where the program output
+/// follows.
+fn parse_trace(contents: &str) -> Result<Vec<ReplayEntry>, String> {
+    let mut entries = vec![];
+    for (n, line) in contents.lines().enumerate() {
+        let mut tokens = line.split_whitespace();
+        let mut pc_str = tokens.next().unwrap_or("");
+        // Cycle numbers are never as wide as a program counter
+        if pc_str.len() != 8 {
+            pc_str = tokens.next().unwrap_or("");
+        }
+        let pc = match usize::from_str_radix(pc_str, 16) {
+            Ok(pc) if pc_str.len() == 8 => pc,
+            _ => break,
+        };
+        let effect = match line.rfind(" <- ") {
+            Some(i) => {
+                let reg = line[..i].split_whitespace().last().unwrap_or("");
+                match u32::from_str_radix(line[i + 4..].trim(), 16) {
+                    Ok(val) => Some((String::from(reg), val)),
+                    Err(_) => return Err(format!("Line {}: invalid value in '{}'.", n + 1, line)),
+                }
+            }
+            None => None,
+        };
+        let text = &line[line.find(pc_str).unwrap_or(0)..];
+        entries.push(ReplayEntry {
+            line: n + 1,
+            pc,
+            effect,
+            text: String::from(text.trim_end()),
+        });
+    }
+    if entries.is_empty() {
+        return Err(String::from("No instructions found."));
+    }
+    Ok(entries)
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// TESTS
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::builder::{run, StateBuilder};
+    use crate::simulator::step_cycle;
+
+    /// A program that stores a byte, a half and a word, then finishes.
+    fn stores() -> StateBuilder {
+        StateBuilder::new().program(
+            "addi x5 x0 0x400
+             addi x6 x0 -2
+             sb x5 x6 0
+             sh x5 x6 4
+             sw x5 x6 8
+             jalr x0 x1 0",
+        )
+    }
+
+    /// Runs the program from the given builder, returning its trace as the
+    /// `trace` subcommand prints it, without the cycle numbers.
+    fn trace(builder: StateBuilder) -> String {
+        let mut state = builder.build();
+        let mut trace = String::new();
+        let mut front = 0;
+        loop {
+            let finished = step_cycle(&mut state);
+            let cycle = state.stats.cycles - 1;
+            // Entries are committed in order from the front of the buffer
+            while state.reorder_buffer[front].cycle_committed == Some(cycle) {
+                trace += &format!("{}\n", format_commit(&state, front));
+                front = (front + 1) % state.reorder_buffer.capacity;
+            }
+            if finished {
+                return trace;
+            }
+        }
+    }
+
+    /// Replays the given trace against the program from the given builder,
+    /// returning the replay once the simulation has stopped, and its output.
+    fn replay(builder: StateBuilder, trace: &str) -> (Replay, Vec<String>) {
+        let mut state = builder.build();
+        state.replay = Some(Replay {
+            entries: Arc::new(parse_trace(trace).unwrap()),
+            pos: 0,
+            diverged: false,
+        });
+        run(&mut state, 1000);
+        (state.replay.take().unwrap(), state.out)
+    }
+
+    #[test]
+    fn traces_memory_written_by_stores() {
+        let trace = trace(stores());
+        assert!(trace.contains("mem[00000400] <- 000000fe"));
+        assert!(trace.contains("mem[00000404] <- 0000fffe"));
+        assert!(trace.contains("mem[00000408] <- fffffffe"));
+    }
+
+    #[test]
+    fn replays_own_trace() {
+        let trace = trace(stores());
+        let (replay, _) = replay(stores(), &trace);
+        assert!(!replay.diverged);
+        assert_eq!(replay.pos, replay.count());
+    }
+
+    #[test]
+    fn diverges_at_store_of_different_value() {
+        let trace =
+            trace(stores()).replace("mem[00000404] <- 0000fffe", "mem[00000404] <- 0000fffd");
+        let (replay, out) = replay(stores(), &trace);
+        assert!(replay.diverged);
+        assert_eq!(replay.pos, 3);
+        assert!(out.iter().any(|l| l == "Replay diverged at instruction 4!"));
+    }
+}
@@ -1,5 +1,5 @@
-use std::cmp::min;
-use std::collections::VecDeque;
+use std::cmp::{min, Reverse};
+use std::collections::{HashMap, VecDeque};
 use std::fmt::{Display, Formatter, Result as FmtResult};
 
 use either::{Either, Left, Right};
@@ -7,9 +7,27 @@ use either::{Either, Left, Right};
 use crate::isa::op_code::Operation;
 use crate::isa::operand::Register;
 
-use super::execute::{ExecuteUnit, ExecutionLen, UnitType};
+use super::branch::control_target;
+use super::execute::ExecuteUnit;
+use super::load::MemoryDisambiguation;
 use super::reorder::ReorderBuffer;
 
+///////////////////////////////////////////////////////////////////////////////
+//// ENUMS
+
+/// The policy used to choose between the reservations that are ready to be
+/// issued to an execute unit.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum IssuePolicy {
+    /// Issue the ready reservation that is oldest in program order.
+    Oldest,
+    /// Issue the ready reservation that is first in the reservation station.
+    Position,
+    /// Issue the ready reservation with the longest chain of instructions
+    /// depending on it, i.e. the one most likely on the critical path.
+    Critical,
+}
+
 ///////////////////////////////////////////////////////////////////////////////
 //// STRUCTS
 
@@ -20,6 +38,8 @@ use super::reorder::ReorderBuffer;
 pub struct ResvStation {
     /// The amount of reservations the Reservation Station can hold.
     pub capacity: usize,
+    /// The policy used to choose which ready reservation is issued next.
+    pub policy: IssuePolicy,
     /// The contents of the Reservation Station.
     pub contents: VecDeque<Reservation>,
 }
@@ -44,16 +64,21 @@ pub struct Reservation {
     pub rs2: Either<i32, usize>,
     /// The immediate of the pending instruction, if applicable.
     pub imm: Option<i32>,
+    /// The length of the longest chain of decoded instructions that depend on
+    /// this one, as an estimate of how critical it is to issue.
+    pub depth: usize,
 }
 
 ///////////////////////////////////////////////////////////////////////////////
 //// IMPLEMENTATIONS
 
 impl ResvStation {
-    /// Creates a new empty reservation station with given capacity.
-    pub fn new(capacity: usize) -> ResvStation {
+    /// Creates a new empty reservation station with given capacity, that
+    /// issues according to the given policy.
+    pub fn new(capacity: usize, policy: IssuePolicy) -> ResvStation {
         ResvStation {
             capacity,
+            policy,
             contents: VecDeque::with_capacity(capacity),
         }
     }
@@ -76,56 +101,71 @@ impl ResvStation {
     }
 
     /// Consumes the next reservation station entry that is ready for
-    /// execution, and is supported by the given execution unit type. The limit
+    /// execution, and is supported by the given execution unit type. Routed
+    /// operations are only consumed by the unit they are routed to, which is
+    /// `unit` in the execute units of the state. The limit
     /// field reduces how many entries of the reservation station will be
-    /// checked.
+    /// checked, so a limit of 0 will never consume anything. Memory accesses
+    /// are only consumed if there is a free memory port, and loads only once
+    /// the memory disambiguation policy allows. If several entries are ready,
+    /// the issue policy decides.
+    ///
+    /// Entries are chosen from and removed from `new_rs`, so once consumed an
+    /// entry can never be issued to a second unit in the same cycle. As the
+    /// entries after it shift down, the remaining limit still only covers the
+    /// entries that were within the original limit.
+    ///
+    /// Returns the consumed reservation (if any), and the limit that remains
+    /// for any further entries consumed in the same cycle.
     pub fn consume_next(
         &self,
         new_rs: &mut ResvStation,
-        eu: &ExecuteUnit,
+        eu: &dyn ExecuteUnit,
+        unit: usize,
+        routes: &HashMap<Operation, usize>,
         rob: &ReorderBuffer,
         limit: usize,
+        memory_port_free: bool,
+        disambiguation: MemoryDisambiguation,
     ) -> (Option<Reservation>, usize) {
         let act_limit = if self.contents.len() != 0 {
-            if limit == 0 {
-                self.contents.len()
-            } else {
-                min(limit, self.contents.len())
-            }
+            min(limit, self.contents.len())
         } else {
             // Enable bypassing of reservation station if empty
-            if limit == 0 {
-                new_rs.contents.len()
-            } else {
-                min(limit, new_rs.contents.len())
-            }
+            min(limit, new_rs.contents.len())
         };
-        let unit_type = eu.get_type();
-        let next_valid = new_rs
+        let mut ready = new_rs
             .contents
             .iter()
-            .cloned()
             .take(act_limit)
             .enumerate()
-            .find(|(_, r)| {
-                // Check operation is supported by execute unit type
-                unit_type == UnitType::from(r.op)
+            .filter(|(_, r)| {
+                // Check operation is supported by execute unit
+                eu.handles(r.op)
+                &&
+                // Check operation is not routed to another execute unit
+                routes.get(&r.op).map_or(true, |u| *u == unit)
                 &&
                 // Check execute unit is free
-                eu.is_free(ExecutionLen::from(r.op))
+                eu.is_free(r.op)
                 &&
-                // Check rs1 is ready
-                match r.rs1 {
-                    Left(_)  => true,
-                    Right(n) => rob[n].act_rd.is_some(),
-                }
-                // Check rs2 is ready
+                // Check a memory port is free, if needed
+                (memory_port_free || !r.op.is_memory())
                 &&
-                match r.rs2 {
-                    Left(_)  => true,
-                    Right(n) => rob[n].act_rd.is_some(),
-                }
+                // Check rs1 and rs2 are ready
+                r.operands_ready(rob)
+                &&
+                // Check loads are not waiting on older stores
+                disambiguation.may_issue(r, rob)
             });
+        let next_valid = match self.policy {
+            IssuePolicy::Position => ready.next(),
+            IssuePolicy::Oldest => {
+                ready.min_by_key(|(_, r)| (r.rob_entry + rob.capacity - rob.front) % rob.capacity)
+            }
+            // Ties go to the first in the reservation station
+            IssuePolicy::Critical => ready.min_by_key(|(idx, r)| (Reverse(r.depth), *idx)),
+        };
 
         // Consume the reservation, if a valid one was found.
         match next_valid {
@@ -134,6 +174,25 @@ impl ResvStation {
         }
     }
 
+    /// Records that a chain of `depth` decoded instructions depends on the
+    /// result of the given reorder buffer entry, deepening the chains of its
+    /// reservation (if still waiting) and every reservation it depends on.
+    pub fn deepen(&mut self, entry: usize, depth: usize) {
+        let (rs1, rs2) = match self.contents.iter_mut().find(|r| r.rob_entry == entry) {
+            Some(r) if r.depth < depth => {
+                r.depth = depth;
+                (r.rs1, r.rs2)
+            }
+            _ => return,
+        };
+        if let Right(n) = rs1 {
+            self.deepen(n, depth + 1);
+        }
+        if let Right(n) = rs2 {
+            self.deepen(n, depth + 1);
+        }
+    }
+
     /// Recieves a bypass result from an execute unit and then adds it to the
     /// relevant reservation station entries.
     pub fn execute_bypass(&mut self, entry: usize, result: i32) {
@@ -151,6 +210,14 @@ impl ResvStation {
         }
     }
 
+    /// Returns whether any reservation that accesses memory has its operands
+    /// ready, and so is only waiting for an execute unit or memory port.
+    pub fn memory_access_ready(&self, rob: &ReorderBuffer) -> bool {
+        self.contents
+            .iter()
+            .any(|r| r.op.is_memory() && r.operands_ready(rob))
+    }
+
     /// Flushes the reservation station, this would happen when the pipeline is
     /// invalidated and needs to be restarted from scratch.
     pub fn flush(&mut self) {
@@ -158,6 +225,25 @@ impl ResvStation {
     }
 }
 
+impl Reservation {
+    /// Returns whether both of the reservation's operands are ready, either
+    /// as values or as results in the reorder buffer.
+    pub fn operands_ready(&self, rob: &ReorderBuffer) -> bool {
+        let ready = |operand| match operand {
+            Left(_) => true,
+            Right(n) => rob[n].act_rd.is_some(),
+        };
+        ready(self.rs1) && ready(self.rs2)
+    }
+}
+
+impl Default for IssuePolicy {
+    /// Defaults to the first ready reservation in the reservation station.
+    fn default() -> IssuePolicy {
+        IssuePolicy::Position
+    }
+}
+
 impl Display for Reservation {
     fn fmt(&self, f: &mut Formatter) -> FmtResult {
         write!(f, "{:2}", self.rob_entry)?;
@@ -173,6 +259,165 @@ impl Display for Reservation {
             Right(rob) => write!(f, " r{}", rob)?,
         }
         write!(f, " {}", format_option!("{}", self.imm))?;
+        // Branches and jumps also show their target, to compare with the pc
+        if let Some(target) = control_target(self.op, self.pc, self.rs1, self.imm) {
+            write!(f, "→{:08x}", target)?;
+        }
         Ok(())
     }
 }
+
+///////////////////////////////////////////////////////////////////////////////
+//// TESTS
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::execute::{new_unit, UnitType};
+
+    /// Returns a reservation for an addition with both operands ready.
+    fn ready_add(rob_entry: usize) -> Reservation {
+        Reservation {
+            rob_entry,
+            op: Operation::ADD,
+            pc: 4 * rob_entry,
+            reg_rd: Some(Register::X10),
+            rs1: Left(1),
+            rs2: Left(2),
+            imm: None,
+            depth: 0,
+        }
+    }
+
+    /// Issues the next reservation from the station to an ALU, returning
+    /// its reorder buffer entry (if any issued).
+    fn issue_next(rs: &mut ResvStation, rob: &ReorderBuffer) -> Option<usize> {
+        let eu = new_unit(UnitType::ALU, 1, 1);
+        let rs_p = rs.clone();
+        let (r, _) = rs_p.consume_next(
+            rs,
+            eu.as_ref(),
+            0,
+            &HashMap::new(),
+            rob,
+            8,
+            true,
+            MemoryDisambiguation::default(),
+        );
+        r.map(|r| r.rob_entry)
+    }
+
+    /// Issues every reservation in the station one at a time, with the given
+    /// policy and front of the reorder buffer, returning the reorder buffer
+    /// entries in the order that they issued.
+    fn issue_all(policy: IssuePolicy, front: usize, entries: &[usize]) -> Vec<usize> {
+        let mut rob = ReorderBuffer::new(8);
+        rob.front = front;
+        rob.back = front;
+        rob.count = 8;
+        let mut rs = ResvStation::new(8, policy);
+        for &entry in entries.iter() {
+            rs.reserve(ready_add(entry)).unwrap();
+        }
+        let mut issued = vec![];
+        while !rs.contents.is_empty() {
+            issued.push(issue_next(&mut rs, &rob).unwrap());
+        }
+        issued
+    }
+
+    #[test]
+    fn oldest_first_issues_in_program_order() {
+        let order = [3, 0, 5, 1, 7, 2, 6, 4];
+        for front in 0..8 {
+            let entries: Vec<usize> = order.iter().map(|n| (front + n) % 8).collect();
+            let ages: Vec<usize> = issue_all(IssuePolicy::Oldest, front, &entries)
+                .iter()
+                .map(|e| (e + 8 - front) % 8)
+                .collect();
+            assert_eq!(ages, (0..8).collect::<Vec<usize>>(), "front {}", front);
+        }
+    }
+
+    #[test]
+    fn position_first_issues_in_station_order() {
+        let entries = [5, 6, 7, 0, 1];
+        assert_eq!(issue_all(IssuePolicy::Position, 7, &entries), entries);
+    }
+
+    #[test]
+    fn critical_issues_deepest_chain_first() {
+        let mut rob = ReorderBuffer::new(8);
+        rob.count = 8;
+        let mut rs = ResvStation::new(8, IssuePolicy::Critical);
+        rs.reserve(ready_add(0)).unwrap();
+        rs.reserve(ready_add(1)).unwrap();
+        // Entry 3 waits on 2, which waits on 1, so 1 heads the longest chain
+        let mut waiting = ready_add(2);
+        waiting.rs1 = Right(1);
+        rs.reserve(waiting).unwrap();
+        rs.deepen(1, 1);
+        let mut waiting = ready_add(3);
+        waiting.rs1 = Right(2);
+        rs.reserve(waiting).unwrap();
+        rs.deepen(2, 1);
+
+        let depths: Vec<usize> = rs.contents.iter().map(|r| r.depth).collect();
+        assert_eq!(depths, vec![0, 2, 1, 0]);
+        assert_eq!(issue_next(&mut rs, &rob), Some(1));
+        assert_eq!(issue_next(&mut rs, &rob), Some(0));
+        assert_eq!(issue_next(&mut rs, &rob), None);
+
+        // Under the default policy the older, shallower entry goes first
+        let mut rs = ResvStation::new(8, IssuePolicy::Position);
+        rs.reserve(ready_add(0)).unwrap();
+        rs.reserve(ready_add(1)).unwrap();
+        rs.deepen(1, 2);
+        assert_eq!(issue_next(&mut rs, &rob), Some(0));
+    }
+
+    #[test]
+    fn critical_breaks_ties_by_position() {
+        let entries = [5, 6, 7, 0, 1];
+        assert_eq!(issue_all(IssuePolicy::Critical, 7, &entries), entries);
+
+        // Equally deep chains also go in station order
+        let mut rob = ReorderBuffer::new(8);
+        rob.count = 8;
+        let mut rs = ResvStation::new(8, IssuePolicy::Critical);
+        for &entry in [4, 2, 6, 3].iter() {
+            rs.reserve(ready_add(entry)).unwrap();
+        }
+        rs.deepen(2, 3);
+        rs.deepen(3, 3);
+        let issued: Vec<Option<usize>> = (0..4).map(|_| issue_next(&mut rs, &rob)).collect();
+        assert_eq!(issued, vec![Some(2), Some(3), Some(4), Some(6)]);
+    }
+
+    #[test]
+    fn displays_target_of_branches_and_jumps() {
+        let add = ready_add(4);
+        assert!(!add.to_string().contains('→'));
+
+        let branch = Reservation {
+            op: Operation::BNE,
+            imm: Some(-8),
+            ..ready_add(4)
+        };
+        assert!(branch.to_string().ends_with(" -8→00000008"));
+
+        // Only once the base register of a jump is known
+        let jump = Reservation {
+            op: Operation::JALR,
+            rs1: Right(3),
+            imm: Some(5),
+            ..ready_add(4)
+        };
+        assert!(!jump.to_string().contains('→'));
+        let jump = Reservation {
+            rs1: Left(0x100),
+            ..jump
+        };
+        assert!(jump.to_string().ends_with(" 5→00000104"));
+    }
+}
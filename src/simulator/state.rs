@@ -1,16 +1,41 @@
+use std::collections::{BTreeMap, HashMap};
 use std::default::Default;
+use std::sync::Arc;
 
+use crate::isa::op_code::Operation;
 use crate::isa::operand::Register;
 use crate::util::config::Config;
-use crate::util::loader::load_elf;
+use crate::util::loader::{load_args, load_elf, load_input};
+use crate::util::log::{Log, LogLevel, LOG_CAPACITY};
 
-use super::branch::BranchPredictor;
-use super::execute::{ExecuteUnit, UnitType};
+use super::branch::{BranchPredictor, ShadowPredictor};
+use super::csr::CsrFile;
+use super::execute::{new_unit, ExecuteUnit, UnalignedAccess, UnitType};
+use super::fault::FaultInjector;
 use super::fetch::LatchFetch;
-use super::memory::{Memory, INIT_MEMORY_SIZE};
-use super::register::RegisterFile;
+use super::load::{LoadQueue, MemoryDisambiguation};
+use super::memory::{
+    initial_stack_pointer, Memory, DEFAULT_MEM_SIZE, DEFAULT_STACK_ALIGN, INIT_MEMORY_SIZE,
+    STACK_GUARD_SIZE,
+};
+use super::register::{PhysRegFile, RegisterFile};
 use super::reorder::ReorderBuffer;
-use super::reservation::ResvStation;
+use super::replay::Replay;
+use super::reservation::{IssuePolicy, ResvStation};
+
+///////////////////////////////////////////////////////////////////////////////
+//// ENUMS
+
+/// The kinds of memory access that are counted when profiling memory.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AccessKind {
+    /// An instruction fetched by the _fetch_ stage.
+    Fetch,
+    /// A committed load, or the read of an atomic memory operation.
+    Load,
+    /// A committed store, or the write of an atomic memory operation.
+    Store,
+}
 
 ///////////////////////////////////////////////////////////////////////////////
 //// STRUCTS
@@ -22,26 +47,120 @@ pub struct State {
     pub stats: Stats,
     /// Program out, essentially a virtual UART but with output only.
     pub out: Vec<String>,
-    /// The _n-way-ness_ of the superscalar _fetch_, _decode_ and _commit_
-    /// stages in the pipeline. (Note: _execute_ is always
-    /// `exec_units.len()`-way superscalar.
-    pub n_way: usize,
-    /// The limit to the number of instructions that can be issueed at once.
+    /// Messages from the simulator about notable events, such as stalls and
+    /// flushes, filtered by the configured log level.
+    pub log: Log,
+    /// Program input, read by the program through the read system call.
+    pub input: Vec<u8>,
+    /// The number of bytes of the program input that have been read so far.
+    pub input_pos: usize,
+    /// Whether the program input continues from the host's stdin once the
+    /// input read so far runs out, as when there is no input file and the
+    /// display is not using stdin.
+    pub host_input: bool,
+    /// The number of instructions fetched per cycle by the superscalar _fetch_
+    /// stage. (Note: _execute_ is always `exec_units.len()`-way superscalar.)
+    pub fetch_width: usize,
+    /// The size in bytes of the aligned blocks of memory that a single fetch
+    /// cannot cross, or 0 for no limit.
+    pub fetch_block: usize,
+    /// The number of instructions decoded per cycle by the superscalar
+    /// _decode_ stage, which may differ from the fetch width.
+    pub decode_width: usize,
+    /// The limit to the number of instructions that can be issued at once,
+    /// which is also the number of reservation station entries that are
+    /// considered for issue in a cycle. This is never 0.
     pub issue_limit: usize,
-    /// Flag to halt decoding of the instructions in the reservation station.
-    /// This would be caused by a pipeline stall due to lack of resources.
-    pub decode_halt: bool,
+    /// The limit to the number of instructions that can be committed at once.
+    pub commit_width: usize,
+    /// The number of memory accesses that can execute at once, or 0 for no
+    /// limit.
+    pub mem_ports: usize,
+    /// The number of results that can be written back at once, or 0 for no
+    /// limit.
+    pub result_buses: usize,
+    /// The number of branches and jumps that can be in flight at once, or 0
+    /// for no limit.
+    pub spec_depth: usize,
+    /// The policy used to decide when loads may read from memory, given the
+    /// older stores that are yet to be committed.
+    pub mem_disambig: MemoryDisambiguation,
+    /// The policy used for loads and stores to addresses that are not aligned
+    /// to their size.
+    pub unaligned: UnalignedAccess,
+    /// The number of extra cycles that a misaligned load or store takes, when
+    /// they are slow.
+    pub unaligned_penalty: u8,
+    /// The operations that may only be issued to one execute unit, mapped to
+    /// the index of that unit in `execute_units`.
+    pub routes: HashMap<Operation, usize>,
+    /// Whether or not to skip over illegal instructions, rather than halting.
+    pub trap_continue: bool,
+    /// Whether or not to treat breakpoints as no-ops, rather than stopping.
+    pub ignore_ebreak: bool,
+    /// The program counter of a breakpoint that has just been committed, for
+    /// whatever is running the simulator to stop at and then clear.
+    pub breakpoint: Option<usize>,
+    /// The exit code of the program, once it has finished with the exit system
+    /// call (or 0 by returning to address -1), rather than by trapping.
+    pub exit_code: Option<i32>,
+    /// The number of instructions to retire before stopping the simulation,
+    /// or 0 for no limit.
+    pub max_insns: u64,
+    /// The number of cycles at the start of the simulation that are left out
+    /// of the statistics.
+    pub warmup: u64,
+    /// The number of cycles without retiring an instruction after which the
+    /// pipeline is taken to be deadlocked, or 0 to never.
+    pub deadlock_cycles: u64,
+    /// The cycle in which an instruction last retired.
+    pub cycle_retired: u64,
+    /// Whether or not the simulation was halted as the pipeline deadlocked.
+    pub deadlocked: bool,
+    /// The number of idle cycles the _fetch_ stage waits for after a pipeline
+    /// flush, modelling the latency of resolving the mispredicted branch.
+    pub flush_penalty: usize,
+    /// The number of cycles it takes to decode an instruction, from when it
+    /// was fetched. This is never 0.
+    pub decode_latency: usize,
+    /// The number of idle cycles the _fetch_ stage has left to wait for,
+    /// following the last pipeline flush.
+    pub flush_stall_remaining: usize,
     /// The virtual memory module, holding data and instructions in the
     /// simulated machine.
     pub memory: Memory,
+    /// The lowest address the stack may grow down to, if stack overflow
+    /// detection is enabled.
+    pub stack_limit: Option<usize>,
+    /// The address ranges, as `(start, end)`, that stores trap in, as they
+    /// hold code loaded from read-only segments of the elf file.
+    pub protected: Vec<(usize, usize)>,
+    /// The size of the address space that programs may access, beyond which
+    /// loads and stores trap rather than growing memory.
+    pub mem_size: usize,
+    /// The base address of the memory-mapped UART console, if enabled. Stores
+    /// to this go to the program output, rather than memory.
+    pub uart_addr: Option<usize>,
+    /// The address of the `tohost` symbol, if running a RISC-V test. A
+    /// non-zero word stored here finishes the test.
+    pub tohost: Option<usize>,
+    /// The size of the pages that memory accesses are counted against, if
+    /// memory is being profiled, otherwise 0.
+    pub profile_page: usize,
     /// The virtual register file, holding both architectural and physical
     /// registers for the simulated machine.
     pub register: RegisterFile,
+    /// The control and status registers, used for handling traps.
+    pub csr: CsrFile,
     /// The virtual branch predict unit, that is used to select the instruction
     /// that is loaded in the _fetch_ stage.
     pub branch_predictor: BranchPredictor,
-    /// The virtual latch after the fetch unit, holding the data that is
-    /// fetched after the _fetch_ stage in the pipeline.
+    /// The shadow branch predictors, one for every mode, if comparing branch
+    /// predictors. These observe committed branches but do not affect
+    /// execution.
+    pub shadow_predictors: Vec<ShadowPredictor>,
+    /// The virtual instruction queue after the fetch unit, holding the data
+    /// that is fetched by the _fetch_ stage until it can be decoded.
     pub latch_fetch: LatchFetch,
     /// The virtual reservation station, that holding instructions pending
     /// execution.
@@ -49,24 +168,124 @@ pub struct State {
     /// The virtual reorder buffer, holding the pending results ready for
     /// in-order _commitment_ at the writeback stage.
     pub reorder_buffer: ReorderBuffer,
+    /// The free list of the physical registers, that the results of renamed
+    /// instructions are allocated to until they are committed.
+    pub phys_regs: PhysRegFile,
     /// The virtual execute units, used to execute instructions out of order in
     /// the _execute_ stage.
-    pub execute_units: Vec<Box<ExecuteUnit>>,
+    pub execute_units: Vec<Box<dyn ExecuteUnit>>,
+    /// The virtual load queue, holding the loads that have speculatively read
+    /// from memory but are yet to be committed.
+    pub load_queue: LoadQueue,
+    /// The source of injected faults, if fault injection is enabled.
+    pub fault_injector: Option<FaultInjector>,
+    /// The source of injected _decode_ stage stalls, if bubble injection is
+    /// enabled.
+    pub bubble_injector: Option<FaultInjector>,
+    /// The trace that committed instructions are checked against, if
+    /// replaying one.
+    pub replay: Option<Replay>,
+    /// The names of the functions and labels in the elf file's symbol table,
+    /// by address, if loaded. This is shared between every clone of the
+    /// state.
+    pub symbols: Arc<BTreeMap<usize, String>>,
+    /// The state as it was once created, with the program loaded, for
+    /// resetting to. This is shared between every clone of the state.
+    pub pristine: Option<Arc<State>>,
 }
 
-/// Container for simulation statistics.
+/// Container for simulation statistics. Apart from the number of cycles,
+/// these only count from the end of the warmup period, if there is one.
 #[derive(Clone, Default)]
 pub struct Stats {
     /// The number of cycles that have passed.
     pub cycles: u64,
+    /// The number of cycles in the warmup period, once it has ended.
+    pub warmup_cycles: u64,
+    /// The number of instructions retired during the warmup period, once it
+    /// has ended.
+    pub warmup_executed: u64,
     /// The number of successfully executed instructions.
     pub executed: u64,
+    /// The number of successfully executed instructions of each operation.
+    pub op_counts: HashMap<Operation, u64>,
+    /// The number of mispredictions, and the total number, of the branches
+    /// and jumps committed at each program counter.
+    pub branch_counts: HashMap<usize, (u64, u64)>,
+    /// The number of fetches, loads and stores made to each page of memory,
+    /// indexed by `AccessKind`, when memory is being profiled.
+    pub page_counts: HashMap<usize, [u64; 3]>,
     /// The number of pipeline stalls/bubbles that have occured.
     pub stalls: u64,
+    /// The number of stalls caused by the reservation station being full.
+    pub stalls_rsv: u64,
+    /// The number of stalls caused by the reorder buffer being full.
+    pub stalls_rob: u64,
+    /// The number of stalls caused by every physical register being in use.
+    pub stalls_prf: u64,
+    /// The number of stalls caused by too many branches and jumps being in
+    /// flight.
+    pub stalls_spec: u64,
+    /// The number of cycles that a memory access was ready to issue, but left
+    /// waiting as every memory port was in use.
+    pub mem_port_stalls: u64,
+    /// The number of times a finished result was held in its execute unit
+    /// for a cycle, as every result bus was in use.
+    pub bus_stalls: u64,
+    /// The number of times a group of fetched instructions was cut short by a
+    /// predicted branch, discarding the rest until the branch predictor
+    /// catches up.
+    pub fetch_redirects: u64,
+    /// The number of times a group of fetched instructions was cut short at
+    /// the end of a fetch block.
+    pub fetch_truncations: u64,
     /// The number of branch predictions that were successful.
     pub bp_success: u64,
     /// The number of branch predictions that failed.
     pub bp_failure: u64,
+    /// The number of return addresses dropped from the full return address
+    /// stack, to make room for another.
+    pub ras_overflows: u64,
+    /// The number of returns made whilst the return address stack was empty,
+    /// which could not be predicted from it.
+    pub ras_underflows: u64,
+    /// The number of branches that the oracle branch predictor could not
+    /// resolve, as a register value they depended on could not be known
+    /// ahead of the pipeline (such as one read from program input).
+    pub oracle_fallbacks: u64,
+    /// The number of memory ordering violations, where a load read memory
+    /// before an older store to the same address and had to be replayed.
+    pub load_violations: u64,
+    /// The number of committed instructions that were waiting on the result
+    /// of a load when they were decoded.
+    pub load_uses: u64,
+    /// The number of those instructions that immediately followed the load.
+    pub load_uses_adjacent: u64,
+    /// The total number of cycles from the load issuing until each of those
+    /// instructions issued.
+    pub load_use_cycles: u64,
+    /// The number of faults that have been deliberately injected.
+    pub faults_injected: u64,
+    /// The number of cycles that the _decode_ stage was deliberately stalled
+    /// for.
+    pub bubbles_injected: u64,
+    /// The number of illegal instructions that have been trapped.
+    pub illegal_instructions: u64,
+    /// The number of loads and stores to out of range addresses that have
+    /// been trapped.
+    pub access_faults: u64,
+    /// The number of branches and jumps to targets that are not aligned to an
+    /// instruction that have been trapped.
+    pub misaligned_targets: u64,
+    /// The number of traps that vectored to the trap handler, rather than
+    /// halting.
+    pub traps_taken: u64,
+    /// The number of cycles that the _fetch_ stage spent idle as a penalty
+    /// for pipeline flushes.
+    pub flush_penalty_cycles: u64,
+    /// The number of cycles that the _decode_ stage had fetched instructions
+    /// waiting, but none that had finished decoding.
+    pub decode_wait_cycles: u64,
 }
 
 ///////////////////////////////////////////////////////////////////////////////
@@ -79,62 +298,348 @@ impl State {
         let mut register = RegisterFile::default();
         // Initialise return address to -1 (for detecting exit)
         register[Register::X1].data = -1;
-        // Initialise stack pointer to the end of memory.
-        register[Register::X2].data = INIT_MEMORY_SIZE as i32 - 4;
-        register[Register::X8].data = INIT_MEMORY_SIZE as i32 - 4;
+        // Initialise stack pointer to the end of memory, aligned as asked
+        register[Register::X2].data = initial_stack_pointer(config.stack_align);
+        register[Register::X8].data = initial_stack_pointer(config.stack_align);
 
         // Create execution unit(s)
-        let mut execute_units = vec![
-            Box::new(ExecuteUnit::new(UnitType::ALU, 3));
-            config.alu_units
-        ];
-        execute_units
-            .append(&mut vec![Box::new(ExecuteUnit::new(UnitType::BLU, 1)); config.blu_units]);
-        execute_units
-            .append(&mut vec![Box::new(ExecuteUnit::new(UnitType::MCU, 1)); config.mcu_units]);
+        let forward_at_step = if config.early_forward { 1 } else { 0 };
+        let mut execute_units = vec![new_unit(UnitType::ALU, 3, forward_at_step); config.alu_units];
+        execute_units.append(&mut vec![
+            new_unit(UnitType::BLU, 1, forward_at_step);
+            config.blu_units
+        ]);
+        execute_units.append(&mut vec![
+            new_unit(UnitType::MCU, 1, forward_at_step);
+            config.mcu_units
+        ]);
+
+        // Find the unit that each routed operation must issue to, counting
+        // only the units that can execute the operation
+        let mut routes = HashMap::new();
+        for (op, index) in config.routes.iter() {
+            match execute_units
+                .iter()
+                .enumerate()
+                .filter(|(_, eu)| eu.handles(*op))
+                .nth(*index)
+            {
+                Some((n, _)) => routes.insert(*op, n),
+                None => error!(format!(
+                    "Cannot route {} to unit {} of those that execute it, as there are only {}.",
+                    op,
+                    index,
+                    execute_units.iter().filter(|eu| eu.handles(*op)).count()
+                )),
+            };
+        }
+
+        // Issue to every execute unit at once, unless told otherwise
+        let issue_limit = match config.issue_limit {
+            0 => execute_units.len(),
+            n => n,
+        };
+
+        // Commit as many instructions as can be issued, unless told otherwise
+        let commit_width = match config.commit_width {
+            0 => issue_limit,
+            n => n,
+        };
+
+        // Fetch and decode the n-way-ness, unless told otherwise
+        let fetch_width = match config.fetch_width {
+            0 => config.n_way,
+            n => n,
+        };
+        let decode_width = match config.decode_width {
+            0 => config.n_way,
+            n => n,
+        };
+
+        // Hold a single fetch group in the queue for every cycle of decoding,
+        // unless told otherwise
+        let fetch_queue = match config.fetch_queue {
+            0 => fetch_width * config.decode_latency,
+            n => n,
+        };
+
+        // Give every reorder buffer entry a physical register, unless told
+        // otherwise
+        let prf_size = match config.prf_size {
+            0 => config.rob_size,
+            n => n,
+        };
 
         // Create state
         let mut state = State {
             stats: Stats::default(),
             out: vec![String::new()],
-            n_way: config.n_way,
-            issue_limit: config.issue_limit,
-            decode_halt: false,
+            log: Log::new(config.log_level, LOG_CAPACITY),
+            input: Vec::new(),
+            input_pos: 0,
+            host_input: false,
+            fetch_width,
+            fetch_block: config.fetch_block,
+            decode_width,
+            issue_limit,
+            commit_width,
+            mem_ports: config.mem_ports,
+            result_buses: config.result_buses,
+            spec_depth: config.spec_depth,
+            mem_disambig: config.mem_disambig,
+            unaligned: config.unaligned,
+            unaligned_penalty: config.unaligned_penalty,
+            routes,
+            trap_continue: config.trap_continue,
+            ignore_ebreak: config.ignore_ebreak,
+            breakpoint: None,
+            exit_code: None,
+            max_insns: config.max_insns,
+            warmup: config.warmup,
+            deadlock_cycles: config.deadlock_cycles,
+            cycle_retired: 0,
+            deadlocked: false,
+            flush_penalty: config.flush_penalty,
+            decode_latency: config.decode_latency,
+            flush_stall_remaining: 0,
             memory: Memory::create_empty(INIT_MEMORY_SIZE),
+            stack_limit: config.stack_limit,
+            protected: vec![],
+            mem_size: config.mem_size,
+            uart_addr: config.uart_addr,
+            tohost: None,
+            profile_page: config.profile_memory,
             register,
+            csr: CsrFile::default(),
             branch_predictor: BranchPredictor::new(config),
-            latch_fetch: LatchFetch::default(),
-            resv_station: ResvStation::new(config.rsv_size),
+            shadow_predictors: if config.compare_predictors {
+                ShadowPredictor::new_all(config)
+            } else {
+                vec![]
+            },
+            latch_fetch: LatchFetch::new(fetch_queue),
+            resv_station: ResvStation::new(config.rsv_size, config.issue_policy),
             reorder_buffer: ReorderBuffer::new(config.rob_size),
+            phys_regs: PhysRegFile::new(prf_size),
             execute_units,
+            load_queue: LoadQueue::default(),
+            fault_injector: config
+                .inject_faults
+                .map(|(seed, rate)| FaultInjector::new(seed, rate)),
+            bubble_injector: config
+                .inject_bubbles
+                .map(|(seed, rate)| FaultInjector::new(seed, rate)),
+            replay: config.replay.as_ref().map(|path| Replay::load(path)),
+            symbols: Arc::new(BTreeMap::new()),
+            pristine: None,
         };
 
-        // Load ELF file into the new state
-        load_elf(&mut state, &config);
+        // Load ELF file into the new state (the REPL starts with empty memory)
+        if !config.repl {
+            load_elf(&mut state, &config);
+        }
+        load_args(&mut state, &config);
+        load_input(&mut state, &config);
+        for (reg, value) in &config.set_regs {
+            state.register[*reg].data = *value;
+        }
 
+        state.pristine = Some(Arc::new(state.clone()));
         state
     }
 
-    /// Flushes the entire pipeline, restarting from the given Program Counter.
+    /// Resets the state to how it was once created, restoring the registers,
+    /// memory and statistics, and emptying the pipeline, without reading the
+    /// elf file again.
+    pub fn reset(&mut self) {
+        if let Some(pristine) = self.pristine.take() {
+            *self = (*pristine).clone();
+            self.pristine = Some(pristine);
+        }
+    }
+
+    /// Flushes the entire pipeline, restarting from the given Program Counter
+    /// once the flush penalty has passed.
     pub fn flush_pipeline(&mut self, actual_pc: usize) {
         self.stats.bp_failure += 1;
+        self.log(
+            LogLevel::Info,
+            format!("Branch mispredicted, flushing to {:08x}.", actual_pc),
+        );
+        self.clear_pipeline(actual_pc);
+        self.flush_stall_remaining = self.flush_penalty;
+    }
+
+    /// Discards every in flight instruction from the pipeline, restarting from
+    /// the given Program Counter. Unlike `flush_pipeline`, this is not
+    /// recorded as a failed branch prediction.
+    pub fn clear_pipeline(&mut self, pc: usize) {
         self.register.flush();
-        self.branch_predictor.force_update(actual_pc);
-        self.latch_fetch.data = vec![];
+        self.branch_predictor.force_update(pc);
+        self.latch_fetch.flush();
         self.resv_station.flush();
         self.reorder_buffer.flush();
+        self.phys_regs.flush();
+        self.load_queue.flush();
         for eu in self.execute_units.iter_mut() {
-            eu.flush();
+            eu.pipeline_mut().flush();
         }
     }
 
-    /// Stalls the _fetch_ stage of the pipeline to the given Program Counter.
-    pub fn stall(&mut self, pc: usize) {
-        self.branch_predictor.force_update(pc);
-        self.decode_halt = true;
+    /// Replays execution after a load read memory before an older store to the
+    /// same address was committed. As the pipeline can only be flushed as a
+    /// whole, this restarts from the given Program Counter following the
+    /// store, which will re-execute the offending load.
+    pub fn replay_load(&mut self, store_next_pc: usize) {
+        self.stats.load_violations += 1;
+        self.log(
+            LogLevel::Info,
+            format!("Load ordering violation, replaying from {:08x}.", store_next_pc),
+        );
+        self.resync_pipeline(store_next_pc);
+    }
+
+    /// Restarts every in flight instruction from the given Program Counter,
+    /// after an instruction committed changes that they may have missed. The
+    /// speculative return address stack is restored to the committed one.
+    pub fn resync_pipeline(&mut self, pc: usize) {
+        self.branch_predictor.return_stack_d = self.branch_predictor.return_stack_c.clone();
+        self.clear_pipeline(pc);
+    }
+
+    /// Checks whether any instruction younger than the given reorder buffer
+    /// entry, including those still in the fetch queue, was fetched from
+    /// memory overlapping the `size` bytes at `addr`.
+    pub fn fetched_after(&self, entry: usize, addr: usize, size: usize) -> bool {
+        let overlaps = |pc: usize| pc < addr.saturating_add(size) && addr < pc + 4;
+        let rob = &self.reorder_buffer;
+        let mut i = (entry + 1) % rob.capacity;
+        while i != rob.back {
+            if overlaps(rob[i].pc) {
+                return true;
+            }
+            i = (i + 1) % rob.capacity;
+        }
+        self.latch_fetch.queue.iter().any(|e| overlaps(e.pc))
+    }
+
+    /// Checks whether an access of `size` bytes to `addr` touches the guard
+    /// region below the stack limit, indicating that the stack has
+    /// overflowed. Always false if there is no stack limit.
+    pub fn in_stack_guard(&self, addr: usize, size: usize) -> bool {
+        match self.stack_limit {
+            Some(limit) => {
+                limit.saturating_sub(STACK_GUARD_SIZE) < addr.saturating_add(size) && addr < limit
+            }
+            None => false,
+        }
+    }
+
+    /// Checks whether an access of `size` bytes to `addr` touches any of the
+    /// write-protected address ranges.
+    pub fn in_protected(&self, addr: usize, size: usize) -> bool {
+        let end = addr.saturating_add(size);
+        self.protected.iter().any(|&(start, stop)| start < end && addr < stop)
+    }
+
+    /// Counts a memory access of the given kind against the page holding
+    /// `addr`, if memory is being profiled.
+    pub fn profile_access(&mut self, addr: usize, kind: AccessKind) {
+        if self.profile_page > 0 {
+            let page = addr / self.profile_page;
+            self.stats.page_counts.entry(page).or_insert([0; 3])[kind as usize] += 1;
+        }
+    }
+
+    /// Prints a message from the simulator to the program output, on a line of
+    /// its own.
+    pub fn report(&mut self, message: String) {
+        if !self.out[self.out.len() - 1].is_empty() {
+            self.out.push(String::new());
+        }
+        let last = self.out.len() - 1;
+        self.out[last] = message;
+    }
+
+    /// Records a message in the log, against the current cycle.
+    pub fn log(&mut self, level: LogLevel, message: String) {
+        self.log.record(level, self.stats.cycles, message);
+    }
+
+    /// Records a stall of the _decode_ stage of the pipeline, for the given
+    /// reason. The stalled instructions remain in the fetch queue, so are
+    /// retried next cycle.
+    pub fn stall(&mut self, reason: &str) {
         self.stats.stalls += 1;
+        self.log(LogLevel::Debug, format!("Decode stalled: {}.", reason));
     }
+}
 
+impl Stats {
+    /// Returns the number of cycles that the statistics have been counted
+    /// over, which excludes the warmup period once it has ended.
+    pub fn measured_cycles(&self) -> u64 {
+        self.cycles - self.warmup_cycles
+    }
+
+    /// Returns the number of instructions retired in total, including those
+    /// in the warmup period.
+    pub fn retired(&self) -> u64 {
+        self.warmup_executed + self.executed
+    }
+
+    /// Returns every statistic that is a single number by name, along with
+    /// the instructions executed per cycle and the branch prediction success
+    /// rate (as `NaN` if there were no branches).
+    #[rustfmt::skip]
+    pub fn summary(&self) -> Vec<(&'static str, f64)> {
+        let branches = self.bp_success + self.bp_failure;
+        vec![
+            ("cycles",               self.cycles as f64),
+            ("warmup_cycles",        self.warmup_cycles as f64),
+            ("warmup_executed",      self.warmup_executed as f64),
+            ("executed",             self.executed as f64),
+            ("ipc",                  self.executed as f64 / self.measured_cycles() as f64),
+            ("stalls",               self.stalls as f64),
+            ("stalls_rsv",           self.stalls_rsv as f64),
+            ("stalls_rob",           self.stalls_rob as f64),
+            ("stalls_prf",           self.stalls_prf as f64),
+            ("stalls_spec",          self.stalls_spec as f64),
+            ("mem_port_stalls",      self.mem_port_stalls as f64),
+            ("bus_stalls",           self.bus_stalls as f64),
+            ("fetch_redirects",      self.fetch_redirects as f64),
+            ("fetch_truncations",    self.fetch_truncations as f64),
+            ("bp_success",           self.bp_success as f64),
+            ("bp_failure",           self.bp_failure as f64),
+            ("bp_rate",              self.bp_success as f64 / branches as f64),
+            ("ras_overflows",        self.ras_overflows as f64),
+            ("ras_underflows",       self.ras_underflows as f64),
+            ("oracle_fallbacks",     self.oracle_fallbacks as f64),
+            ("load_violations",      self.load_violations as f64),
+            ("load_uses",            self.load_uses as f64),
+            ("load_uses_adjacent",   self.load_uses_adjacent as f64),
+            ("load_use_cycles",      self.load_use_cycles as f64),
+            ("faults_injected",      self.faults_injected as f64),
+            ("bubbles_injected",     self.bubbles_injected as f64),
+            ("illegal_instructions", self.illegal_instructions as f64),
+            ("access_faults",        self.access_faults as f64),
+            ("misaligned_targets",   self.misaligned_targets as f64),
+            ("traps_taken",          self.traps_taken as f64),
+            ("flush_penalty_cycles", self.flush_penalty_cycles as f64),
+            ("decode_wait_cycles",   self.decode_wait_cycles as f64),
+        ]
+    }
+
+    /// Ends the warmup period, setting every statistic back to 0 apart from
+    /// the number of cycles, so that they only count from here on.
+    pub fn end_warmup(&mut self) {
+        *self = Stats {
+            cycles: self.cycles,
+            warmup_cycles: self.cycles,
+            warmup_executed: self.executed,
+            ..Stats::default()
+        };
+    }
 }
 
 impl Default for State {
@@ -143,21 +648,146 @@ impl Default for State {
         // Initialise return address to -1 (for detecting exit)
         register[Register::X1].data = -1;
         // Initialise stack pointer to the end of memory.
-        register[Register::X2].data = INIT_MEMORY_SIZE as i32 - 4;
-        register[Register::X8].data = INIT_MEMORY_SIZE as i32 - 4;
+        register[Register::X2].data = initial_stack_pointer(DEFAULT_STACK_ALIGN);
+        register[Register::X8].data = initial_stack_pointer(DEFAULT_STACK_ALIGN);
         State {
             stats: Stats::default(),
             out: vec![String::new()],
-            n_way: 1,
+            log: Log::default(),
+            input: Vec::new(),
+            input_pos: 0,
+            host_input: false,
+            fetch_width: 1,
+            fetch_block: 0,
+            decode_width: 1,
             issue_limit: 1,
-            decode_halt: false,
+            commit_width: 1,
+            mem_ports: 0,
+            result_buses: 0,
+            spec_depth: 0,
+            mem_disambig: MemoryDisambiguation::default(),
+            unaligned: UnalignedAccess::default(),
+            unaligned_penalty: 0,
+            routes: HashMap::new(),
+            trap_continue: false,
+            ignore_ebreak: false,
+            breakpoint: None,
+            exit_code: None,
+            max_insns: 0,
+            warmup: 0,
+            deadlock_cycles: 0,
+            cycle_retired: 0,
+            deadlocked: false,
+            flush_penalty: 0,
+            decode_latency: 1,
+            flush_stall_remaining: 0,
             memory: Memory::create_empty(INIT_MEMORY_SIZE),
+            stack_limit: None,
+            protected: vec![],
+            mem_size: DEFAULT_MEM_SIZE,
+            uart_addr: None,
+            tohost: None,
+            profile_page: 0,
             register,
+            csr: CsrFile::default(),
             branch_predictor: BranchPredictor::default(),
-            latch_fetch: LatchFetch::default(),
-            resv_station: ResvStation::new(16),
+            shadow_predictors: vec![],
+            latch_fetch: LatchFetch::new(1),
+            resv_station: ResvStation::new(16, IssuePolicy::default()),
             reorder_buffer: ReorderBuffer::new(32),
+            phys_regs: PhysRegFile::new(32),
             execute_units: Vec::new(),
+            load_queue: LoadQueue::default(),
+            fault_injector: None,
+            bubble_injector: None,
+            replay: None,
+            symbols: Arc::new(BTreeMap::new()),
+            pristine: None,
         }
     }
 }
+
+///////////////////////////////////////////////////////////////////////////////
+//// TESTS
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::builder::{run, StateBuilder};
+
+    #[test]
+    fn reset_restores_initial_state() {
+        let mut state = StateBuilder::new()
+            .origin(0x1000)
+            .word(0x200, 7)
+            .reg(Register::X5, 3)
+            .asm("addi x5 x5 1")
+            .asm("addi x10 x0 42")
+            .asm("sw x0 x5 0x200")
+            .asm("jalr x0 x1 0")
+            .build();
+        run(&mut state, 100);
+        assert_eq!(state.register[Register::X10].data, 42);
+        assert_eq!(state.memory.read_i32(0x200).word, 4);
+
+        state.reset();
+        assert_eq!(state.register[Register::PC].data, 0x1000);
+        assert_eq!(state.register[Register::X1].data, -1);
+        assert_eq!(state.register[Register::X5].data, 3);
+        assert_eq!(state.register[Register::X10].data, 0);
+        assert_eq!(state.memory.read_i32(0x200).word, 7);
+        assert_eq!(state.stats.cycles, 0);
+        assert_eq!(state.stats.retired(), 0);
+        assert_eq!(state.reorder_buffer.in_flight(), 0);
+
+        // And it runs again just the same
+        run(&mut state, 100);
+        assert_eq!(state.register[Register::X5].data, 4);
+        assert_eq!(state.memory.read_i32(0x200).word, 4);
+    }
+
+    #[test]
+    fn profiles_accesses_per_page() {
+        let mut config = Config::default();
+        config.profile_memory = 0x100;
+        let mut state = StateBuilder::new()
+            .config(config)
+            .asm("addi x5 x0 0x400")
+            .asm("sw x5 x5 0")
+            .asm("sw x5 x5 4")
+            .asm("lw x6 x5 0")
+            .asm("lw x7 x5 0x300")
+            .asm("jalr x0 x1 0")
+            .build();
+        run(&mut state, 100);
+        let counts = &state.stats.page_counts;
+        assert_eq!(counts[&4], [0, 1, 2]);
+        assert_eq!(counts[&7], [0, 1, 0]);
+        assert!(counts[&0][AccessKind::Fetch as usize] >= 6);
+        assert_eq!(counts[&0][AccessKind::Load as usize], 0);
+        assert_eq!(counts.len(), 3);
+    }
+
+    #[test]
+    fn aligns_initial_stack_pointer() {
+        let stack = |align: usize| {
+            let mut config = Config::default();
+            config.stack_align = align;
+            let state = StateBuilder::new().config(config).build();
+            (
+                state.register[Register::X2].data,
+                state.register[Register::X8].data,
+            )
+        };
+        let (sp, fp) = stack(DEFAULT_STACK_ALIGN);
+        assert_eq!(sp % 16, 0);
+        assert_eq!(fp, sp);
+        assert!(INIT_MEMORY_SIZE as i32 - sp <= 16);
+
+        let (sp, fp) = stack(256);
+        assert_eq!(sp % 256, 0);
+        assert_eq!(fp, sp);
+        assert!(INIT_MEMORY_SIZE as i32 - sp <= 256);
+        assert_eq!(stack(4).0, INIT_MEMORY_SIZE as i32 - 4);
+    }
+}
@@ -0,0 +1,143 @@
+use std::fmt::{Display, Formatter, Result};
+
+use crate::isa::operand::Register;
+use crate::util::log::LogLevel;
+
+use super::state::State;
+
+///////////////////////////////////////////////////////////////////////////////
+//// ENUMS
+
+/// The exceptions that an instruction may raise, which either vector to the
+/// trap handler in `mtvec`, or halt the simulation if there is none.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Trap {
+    /// A jump or branch to an address that is not aligned to an instruction.
+    InstructionMisaligned,
+    /// An instruction that could not be decoded, or accessed a CSR that does
+    /// not exist.
+    IllegalInstruction,
+    /// A breakpoint (`ebreak`) instruction.
+    Breakpoint,
+    /// A misaligned load reserved (`lr.w`). Other loads may be misaligned,
+    /// unless unaligned accesses trap.
+    LoadMisaligned,
+    /// A load from an address out of range.
+    LoadAccessFault,
+    /// A misaligned store conditional (`sc.w`) or atomic memory operation.
+    /// Other stores may be misaligned, unless unaligned accesses trap.
+    StoreMisaligned,
+    /// A store, store conditional or atomic memory operation to an address
+    /// out of range.
+    StoreAccessFault,
+    /// An environment call (`ecall`) instruction.
+    EnvironmentCall,
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// IMPLEMENTATIONS
+
+impl Trap {
+    /// Returns the exception code that is written to `mcause` for the trap,
+    /// as given by the RISC-V privileged specification for machine mode.
+    #[rustfmt::skip]
+    pub fn cause(self) -> u32 {
+        match self {
+            Trap::InstructionMisaligned => 0,
+            Trap::IllegalInstruction    => 2,
+            Trap::Breakpoint            => 3,
+            Trap::LoadMisaligned        => 4,
+            Trap::LoadAccessFault       => 5,
+            Trap::StoreMisaligned       => 6,
+            Trap::StoreAccessFault      => 7,
+            Trap::EnvironmentCall       => 11,
+        }
+    }
+}
+
+impl Display for Trap {
+    #[rustfmt::skip]
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            Trap::InstructionMisaligned => f.pad("instruction address misaligned"),
+            Trap::IllegalInstruction    => f.pad("illegal instruction"),
+            Trap::Breakpoint            => f.pad("breakpoint"),
+            Trap::LoadMisaligned        => f.pad("load address misaligned"),
+            Trap::LoadAccessFault       => f.pad("load access fault"),
+            Trap::StoreMisaligned       => f.pad("store address misaligned"),
+            Trap::StoreAccessFault      => f.pad("store access fault"),
+            Trap::EnvironmentCall       => f.pad("environment call"),
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// FUNCTIONS
+
+/// Takes a trap raised by the instruction at `pc`, where `tval` is the
+/// faulting address or instruction (or 0), and `message` describes what
+/// happened.
+///
+/// If a trap handler is installed in `mtvec`, the trap is recorded in
+/// `mepc`, `mcause` and `mtval`, and the pipeline is restarted from the
+/// handler. Otherwise the message is reported on the console output and the
+/// simulation halts. Either way, every instruction in flight is discarded.
+pub fn take_trap(state: &mut State, trap: Trap, pc: usize, tval: u32, message: String) {
+    if !state.csr.has_handler() {
+        state.log(LogLevel::Warn, message.clone());
+        state.report(message);
+        state.register[Register::PC].data = -1;
+        return;
+    }
+    state.stats.traps_taken += 1;
+    state.csr.mepc = pc as u32;
+    state.csr.mcause = trap.cause();
+    state.csr.mtval = tval;
+    let handler = state.csr.mtvec as usize;
+    state.log(
+        LogLevel::Info,
+        format!("{} Trapping to handler at {:08x}.", message, handler),
+    );
+    state.register[Register::PC].data = handler as i32;
+    state.resync_pipeline(handler);
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// TESTS
+
+#[cfg(test)]
+mod tests {
+    use crate::isa::operand::Register;
+    use crate::simulator::builder::{run, StateBuilder};
+
+    #[test]
+    fn illegal_instruction_vectors_to_handler() {
+        // The handler records the cause, and returns past the instruction
+        let mut state = StateBuilder::new()
+            .program(
+                "
+                    addi x5 x0 24
+                    csrrw x0 x5 0x305
+                    addi x10 x0 1
+                    .word 0xffffffff
+                    addi x11 x0 2
+                    jalr x0 x1 0
+                handler:
+                    csrrs x6 x0 0x342
+                    csrrs x7 x0 0x341
+                    addi x7 x7 4
+                    csrrw x0 x7 0x341
+                    mret
+                ",
+            )
+            .build();
+        run(&mut state, 1000);
+        assert_eq!(state.stats.traps_taken, 1);
+        assert_eq!(state.register[Register::X6].data, 2);
+        assert_eq!(state.csr.mepc, 16);
+        assert_eq!(state.csr.mtval, 0xffff_ffff);
+        assert_eq!(state.register[Register::X10].data, 1);
+        assert_eq!(state.register[Register::X11].data, 2);
+        assert!(state.out.iter().all(|l| !l.contains("Illegal instruction")));
+    }
+}
@@ -0,0 +1,266 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use crate::isa::Instruction;
+use crate::simulator::state::State;
+
+///////////////////////////////////////////////////////////////////////////////
+//// CONST/STATIC
+
+/// The lane of the _fetch_ stage.
+const LANE_FETCH: usize = 0;
+
+/// The lane of the _decode & rename_ stage.
+const LANE_DECODE: usize = 1;
+
+/// The lane of instructions waiting in the reservation station to issue.
+const LANE_ISSUE: usize = 2;
+
+/// The lane of the first execute unit, followed by the rest in order.
+const LANE_EXECUTE: usize = 3;
+
+///////////////////////////////////////////////////////////////////////////////
+//// STRUCTS
+
+/// A timeline of every committed instruction's trip through the pipeline,
+/// written to a JSON file in the Chrome trace event format, for viewing in
+/// Perfetto or `chrome://tracing`.
+///
+/// Each stage is a lane (or "thread"), with one for every execute unit, and
+/// each instruction is a duration event in the lane of every stage it spent
+/// time in, joined together by a flow. One cycle is shown as one microsecond.
+/// Instructions that are flushed before they commit are not shown.
+pub struct ChromeTrace {
+    /// The buffered writer for the JSON file.
+    writer: BufWriter<File>,
+    /// The front of the reorder buffer at the end of the last cycle, where
+    /// the instructions committed in the latest one start from.
+    front: usize,
+    /// The number of instructions traced so far, which identifies the flow
+    /// of the next.
+    traced: u64,
+    /// The lane of the _commit_ stage, after those of the execute units.
+    lane_commit: usize,
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// IMPLEMENTATIONS
+
+impl ChromeTrace {
+    /// Creates the JSON file at the given path, and writes the names of the
+    /// lanes for the execute units of the given state. Quits the program if
+    /// the file cannot be created.
+    pub fn create(path: &str, state: &State) -> ChromeTrace {
+        let file = match File::create(path) {
+            Ok(f) => f,
+            Err(e) => error!(format!("Failed to create Chrome trace file:\n{}", e)),
+        };
+        let mut trace = ChromeTrace {
+            writer: BufWriter::new(file),
+            front: 0,
+            traced: 0,
+            lane_commit: LANE_EXECUTE + state.execute_units.len(),
+        };
+
+        let mut lanes = vec![
+            (LANE_FETCH, String::from("Fetch")),
+            (LANE_DECODE, String::from("Decode")),
+            (LANE_ISSUE, String::from("Issue")),
+        ];
+        for (n, eu) in state.execute_units.iter().enumerate() {
+            let index = state.execute_units[..n]
+                .iter()
+                .filter(|e| e.name() == eu.name())
+                .count();
+            lanes.push((LANE_EXECUTE + n, format!("{} {}", eu.name(), index)));
+        }
+        lanes.push((trace.lane_commit, String::from("Commit")));
+
+        write!(trace.writer, "[").expect("Failed to write to Chrome trace file");
+        for (n, (lane, name)) in lanes.iter().enumerate() {
+            write!(
+                trace.writer,
+                "{}\n{{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":0,\"tid\":{},\"args\":{{\"name\":\"{}\"}}}}",
+                if n == 0 { "" } else { "," },
+                lane,
+                name
+            )
+            .expect("Failed to write to Chrome trace file");
+        }
+        trace
+    }
+
+    /// Writes the events of every instruction that committed in the cycle
+    /// that has just finished in the given state.
+    pub fn record(&mut self, state: &State) {
+        // Everything committed this cycle lies from where the front of the
+        // reorder buffer was, as the commit stage takes from the front
+        let rob = &state.reorder_buffer;
+        let cycle = state.stats.cycles - 1;
+        for i in 0..rob.capacity {
+            if rob[self.front + i].cycle_committed != Some(cycle) {
+                break;
+            }
+            self.record_entry(state, self.front + i);
+        }
+        self.front = rob.front_fin;
+    }
+
+    /// Writes the events of a single committed reorder buffer entry, one for
+    /// each stage, and the flow between them.
+    fn record_entry(&mut self, state: &State, entry: usize) {
+        let e = &state.reorder_buffer[entry];
+        let name = match Instruction::decode(state.memory.read_i32(e.pc).word) {
+            Some(i) => i.disassemble(),
+            None => String::from("<illegal>"),
+        };
+        // Each stage lasts from the end of the cycle in which the last one
+        // finished, until the end of the cycle in which it finished
+        let fetched = e.cycle_fetched + 1;
+        let decoded = (e.cycle_reserved + 1).max(fetched);
+        let issued = e.cycle_issued.map_or(decoded, |c| (c + 1).max(decoded));
+        let finished = e.cycle_finished.map_or(issued, |c| (c + 1).max(issued));
+        let committed = e.cycle_committed.map_or(finished, |c| (c + 1).max(finished));
+
+        let mut stages = vec![
+            ("fetch", LANE_FETCH, e.cycle_fetched, fetched),
+            ("decode", LANE_DECODE, fetched, decoded),
+            ("issue", LANE_ISSUE, decoded, issued),
+        ];
+        if let Some(n) = e.execute_unit {
+            stages.push(("execute", LANE_EXECUTE + n, issued, finished));
+        }
+        stages.push(("commit", self.lane_commit, finished, committed));
+
+        let last = stages.len() - 1;
+        for (n, (stage, lane, start, end)) in stages.into_iter().enumerate() {
+            let flow = match n {
+                0 => "s",
+                n if n == last => "f",
+                _ => "t",
+            };
+            write!(
+                self.writer,
+                ",\n{{\"name\":\"{}\",\"cat\":\"{}\",\"ph\":\"X\",\"pid\":0,\"tid\":{},\"ts\":{},\"dur\":{},\"args\":{{\"pc\":\"{:08x}\"}}}}\
+                 ,\n{{\"name\":\"instruction\",\"cat\":\"flow\",\"ph\":\"{}\",\"bp\":\"e\",\"id\":{},\"pid\":0,\"tid\":{},\"ts\":{}}}",
+                name,
+                stage,
+                lane,
+                start,
+                end - start,
+                e.pc,
+                flow,
+                self.traced,
+                lane,
+                start
+            )
+            .expect("Failed to write to Chrome trace file");
+        }
+        self.traced += 1;
+    }
+
+    /// Ends the list of events, and flushes it out to the file.
+    pub fn finish(&mut self) {
+        writeln!(self.writer, "\n]").expect("Failed to write to Chrome trace file");
+        self.writer.flush().expect("Failed to write to Chrome trace file");
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// TESTS
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::builder::StateBuilder;
+    use crate::simulator::step_cycle;
+
+    /// Parses a JSON value from the start of the given text, skipping any
+    /// leading whitespace, enough to check that the trace is well formed.
+    /// Returns the text after the value, and the text of each element in it
+    /// if it is an array (or of each value, if it is an object).
+    fn parse_value(text: &str) -> Result<(&str, Vec<&str>), String> {
+        let text = text.trim_start();
+        let mut items = vec![];
+        let rest = match text.chars().next() {
+            Some(open) if open == '[' || open == '{' => {
+                let close = if open == '[' { ']' } else { '}' };
+                let mut rest = text[1..].trim_start();
+                if rest.starts_with(close) {
+                    return Ok((&rest[1..], items));
+                }
+                loop {
+                    if open == '{' {
+                        let (after_key, _) = parse_value(rest)?;
+                        rest = after_key.trim_start();
+                        if !rest.starts_with(':') {
+                            return Err(format!("Expected ':' at '{}'", rest));
+                        }
+                        rest = &rest[1..];
+                    }
+                    let (after, _) = parse_value(rest)?;
+                    items.push(rest[..rest.len() - after.len()].trim());
+                    rest = after.trim_start();
+                    match rest.chars().next() {
+                        Some(',') => rest = &rest[1..],
+                        Some(c) if c == close => break &rest[1..],
+                        _ => return Err(format!("Expected ',' or '{}' at '{}'", close, rest)),
+                    }
+                }
+            }
+            Some('"') => match text[1..].find('"') {
+                Some(end) => &text[end + 2..],
+                None => return Err(String::from("Unterminated string")),
+            },
+            Some(_) => {
+                let end = text
+                    .find(|c: char| !c.is_ascii_alphanumeric() && c != '-' && c != '.')
+                    .unwrap_or_else(|| text.len());
+                if end == 0 {
+                    return Err(format!("Unexpected '{}'", text));
+                }
+                &text[end..]
+            }
+            None => return Err(String::from("Unexpected end of input")),
+        };
+        Ok((rest, items))
+    }
+
+    #[test]
+    fn writes_events_for_every_stage_of_each_instruction() {
+        let path = std::env::temp_dir().join("daybreak_chrome_trace.json");
+        let mut state = StateBuilder::new()
+            .asm("addi x5 x0 1")
+            .asm("addi x6 x5 2")
+            .asm("jalr x0 x1 0")
+            .build();
+        let mut trace = ChromeTrace::create(path.to_str().unwrap(), &state);
+        loop {
+            let finished = step_cycle(&mut state);
+            trace.record(&state);
+            if finished {
+                break;
+            }
+        }
+        trace.finish();
+
+        let json = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(parse_value("[{\"a\":1},]").is_err());
+        assert!(parse_value("[{\"a\" 1}]").is_err());
+        let (rest, events) = parse_value(&json).unwrap();
+        assert_eq!(rest.trim(), "");
+        let count = |phase: &str| {
+            let field = format!("\"ph\":\"{}\"", phase);
+            events.iter().filter(|e| e.contains(&field)).count()
+        };
+        // A lane for each stage and each of the three execute units, and
+        // every instruction is executed, so spends time in all five stages
+        assert_eq!(count("M"), 7);
+        assert_eq!(count("X"), 3 * 5);
+        assert_eq!(count("s") + count("t") + count("f"), 3 * 5);
+        assert_eq!(count("s"), 3);
+        assert_eq!(count("f"), 3);
+        assert_eq!(events.len(), 7 + 3 * 5 * 2);
+    }
+}
@@ -1,48 +1,318 @@
-use clap::{App, Arg};
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
 
+use clap::{App, AppSettings, Arg, SubCommand};
+
+use crate::io::theme::ThemeMode;
+use crate::isa::assembly::parse_immediate;
+use crate::isa::op_code::Operation;
+use crate::isa::operand::Register;
 use crate::simulator::branch::BranchPredictorMode;
+use crate::simulator::execute::UnalignedAccess;
+use crate::simulator::load::MemoryDisambiguation;
+use crate::simulator::memory::{DEFAULT_MEM_SIZE, DEFAULT_STACK_ALIGN, INIT_MEMORY_SIZE};
+use crate::simulator::reservation::IssuePolicy;
+use crate::util::log::LogLevel;
+
+/// The parameters that can be swept over when benchmarking, by their command
+/// line names.
+pub const SWEEP_PARAMS: [&str; 24] = [
+    "n-way", "fetch-width", "decode-width", "issue-limit", "commit-width", "fetch-queue",
+    "fetch-block", "decode-latency", "flush-penalty", "alu", "blu", "mcu", "mem-ports", "mem-disambig",
+    "unaligned", "unaligned-penalty", "result-buses", "rsv", "rob", "prf-size", "ras-depth",
+    "spec-depth", "branch-prediction", "issue-policy",
+];
 
 /// Encapsulates the settings for the simulator to run with.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Config {
-    /// The path of the elf-file to run in the simulator.
+    /// What the simulator has been asked to do, as given by the subcommand.
+    pub command: Command,
+    /// The path of the elf-file to run in the simulator, or to write the
+    /// assembled program to.
     pub elf_file: String,
-    /// The _n-way-ness_ of the _fetch_, _decode_ and _commit_ stages in the
-    /// processor pipeline.
+    /// The path of the assembly source file to assemble, for the `asm`
+    /// subcommand.
+    pub asm_file: String,
+    /// The address that assembled programs are placed and start executing at.
+    pub origin: usize,
+    /// The _n-way-ness_ of the _fetch_ and _decode_ stages in the processor
+    /// pipeline.
     pub n_way: usize,
-    /// The amount of instructions that can be issued every cycle, and
-    /// subsequently the number that can be commited. If this is 0, it will be
-    /// assumed to be the number of execute units in the simulator.
+    /// The amount of instructions that can be fetched every cycle. If this is
+    /// 0, it will be assumed to be the _n-way-ness_.
+    pub fetch_width: usize,
+    /// The amount of instructions that can be decoded every cycle. If this is
+    /// 0, it will be assumed to be the _n-way-ness_.
+    pub decode_width: usize,
+    /// The amount of instructions that can be issued every cycle. If this is
+    /// 0, it will be assumed to be the number of execute units in the
+    /// simulator.
     pub issue_limit: usize,
+    /// The amount of instructions that can be commited every cycle. If this is
+    /// 0, it will be assumed to be the same as the issue limit.
+    pub commit_width: usize,
+    /// The number of fetched instructions that can be held waiting to be
+    /// decoded. If this is 0, it will be assumed to be the fetch width times
+    /// the decode latency.
+    pub fetch_queue: usize,
+    /// The size in bytes of the aligned blocks of memory that a single fetch
+    /// cannot cross, or 0 for no limit.
+    pub fetch_block: usize,
+    /// The number of cycles it takes to decode an instruction, from when it
+    /// was fetched. This is never 0.
+    pub decode_latency: usize,
+    /// The number of idle cycles before fetching resumes after a pipeline
+    /// flush.
+    pub flush_penalty: usize,
     /// The number of Arithmetic Logic Units the simulator should have.
     pub alu_units: usize,
     /// The number of Branch Logic Units the simulator should have.
     pub blu_units: usize,
     /// The number of Memory Control Units the simulator should have.
     pub mcu_units: usize,
+    /// The number of memory accesses that can execute at once, where 0 is
+    /// interpreted as unlimited.
+    pub mem_ports: usize,
+    /// The policy used to decide when loads may read from memory.
+    pub mem_disambig: MemoryDisambiguation,
+    /// The policy used for loads and stores to addresses that are not aligned
+    /// to their size.
+    pub unaligned: UnalignedAccess,
+    /// The number of extra cycles that a misaligned load or store takes, when
+    /// they are slow.
+    pub unaligned_penalty: u8,
+    /// The number of results that can be written back every cycle, where 0
+    /// is interpreted as unlimited.
+    pub result_buses: usize,
     /// The number of entries in the reservation station.
     pub rsv_size: usize,
     /// The number of entries in the reorder buffer.
     pub rob_size: usize,
+    /// The number of physical registers for the results of renamed
+    /// instructions. If this is 0, it will be assumed to be the same as the
+    /// number of entries in the reorder buffer.
+    pub prf_size: usize,
+    /// The policy used to choose which ready instruction is issued next.
+    pub issue_policy: IssuePolicy,
+    /// The operations that may only be issued to one execute unit, given by
+    /// its index among the units that execute the operation.
+    pub routes: HashMap<Operation, usize>,
     /// Whether or not branch prediction is enabled.
     pub branch_prediction: BranchPredictorMode,
     /// Whether or not a return address stack is being used.
     pub return_address_stack: bool,
+    /// The number of entries in the return address stack, where 0 is
+    /// unbounded.
+    pub ras_depth: usize,
+    /// The number of branches and jumps that may be in flight at once, where
+    /// 0 is interpreted as unlimited.
+    pub spec_depth: usize,
+    /// Whether or not execute units forward their results a cycle before they
+    /// are written back.
+    pub early_forward: bool,
+    /// Whether or not to run a shadow branch predictor for every mode, to
+    /// compare their accuracy.
+    pub compare_predictors: bool,
+    /// Whether or not to report how many of each operation were committed,
+    /// once the simulator exits.
+    pub op_histogram: bool,
+    /// The number of most mispredicted branches to report once the simulator
+    /// exits, where 0 reports none.
+    pub hot_branches: usize,
+    /// The size of the pages to report memory accesses against once the
+    /// simulator exits, where 0 reports none.
+    pub profile_memory: usize,
+    /// Whether or not to skip over illegal instructions, rather than halting.
+    pub trap_continue: bool,
+    /// Whether or not to treat breakpoints as no-ops, rather than stopping.
+    pub ignore_ebreak: bool,
+    /// Whether or not to run the interactive assembly prompt instead of an
+    /// elf file.
+    pub repl: bool,
+    /// The number of cycles that a bare `step` advances by in the headless
+    /// command prompt, if it is to be run instead of the display.
+    pub interactive_step: Option<u64>,
+    /// Whether or not to check that the elf file's sections read back from
+    /// memory correctly once loaded.
+    pub verify_load: bool,
+    /// Whether or not to run the elf file as a RISC-V test, which reports
+    /// whether it passed by writing to its `tohost` symbol.
+    pub riscv_test: bool,
+    /// Whether or not to load the elf file's symbol table, to name the
+    /// functions and labels in disassembled instructions.
+    pub symbols: bool,
+    /// Whether or not to only report the resolved machine configuration and
+    /// any problems with it, without simulating.
+    pub dry_run: bool,
+    /// Whether or not to only print the elf file's headers and sections, and
+    /// any problems with them, without loading it.
+    pub elf_info: bool,
+    /// The most verbose level of simulator message that is logged.
+    pub log_level: LogLevel,
+    /// The number of past simulator states to keep for rewinding in the UI.
+    pub history: usize,
+    /// The number of cycles simulated per second with the display, where 0 is
+    /// as fast as possible. Without the display, there is no limit.
+    pub speed: u64,
+    /// The number of instructions to retire before stopping the simulation,
+    /// where 0 is unlimited.
+    pub max_insns: u64,
+    /// The number of cycles at the start of the simulation that are left out
+    /// of the statistics.
+    pub warmup: u64,
+    /// The number of cycles without retiring an instruction after which the
+    /// pipeline is taken to be deadlocked, where 0 never does.
+    pub deadlock_cycles: u64,
+    /// Whether or not the UI's instruction memory is initially centred on the
+    /// committed program counter, rather than the load counter.
+    pub follow_pc: bool,
+    /// Whether or not the simulation is initially paused with the display,
+    /// waiting to be started by the user.
+    pub start_paused: bool,
+    /// The colour scheme that the UI is drawn in.
+    pub theme: ThemeMode,
+    /// The seed and per-cycle rate of fault injection, if enabled.
+    pub inject_faults: Option<(u64, f64)>,
+    /// The seed and per-cycle rate of stalling the _decode_ stage, if
+    /// enabled.
+    pub inject_bubbles: Option<(u64, f64)>,
+    /// The number of bytes that the initial stack and frame pointers are
+    /// aligned to, which is a power of two.
+    pub stack_align: usize,
+    /// The lowest address the stack may grow down to, if stack overflow
+    /// detection is enabled.
+    pub stack_limit: Option<usize>,
+    /// Whether or not stores to the code loaded from read-only segments of
+    /// the elf file trap, rather than overwriting it.
+    pub protect_text: bool,
+    /// The size of the address space that programs may access, in bytes,
+    /// beyond which loads and stores trap. This is at least the initial size
+    /// of memory.
+    pub mem_size: usize,
+    /// The base address of the memory-mapped UART console, if enabled.
+    pub uart_addr: Option<usize>,
+    /// The path of the file to use as the program's input, if any.
+    pub stdin: Option<String>,
+    /// The arguments passed to the program's entry point in `argc` and
+    /// `argv`, which may be none.
+    pub args: Vec<String>,
+    /// The registers given initial values, in order, once the program is
+    /// loaded.
+    pub set_regs: Vec<(Register, i32)>,
+    /// The path of the file to write cycle by cycle metrics to, if any.
+    pub metrics_csv: Option<String>,
+    /// The path of the file to write a Chrome trace of the pipeline to, if
+    /// any.
+    pub chrome_trace: Option<String>,
+    /// The path of the trace file to check committed instructions against,
+    /// if any.
+    pub replay: Option<String>,
+    /// Whether the `trace` subcommand prints the cycle that each instruction
+    /// committed in, before the instruction.
+    pub trace_cycles: bool,
+    /// The path of the file to dump the memory image to once the simulation
+    /// has finished, if any.
+    pub mem_dump: Option<String>,
+    /// The parameter to sweep over and the values to sweep it through, when
+    /// benchmarking.
+    pub sweep: Option<(String, Vec<String>)>,
+    /// The path of a file of options to benchmark against, side by side with
+    /// these, if any.
+    pub compare: Option<String>,
+}
+
+/// The subcommands of the simulator, which decide what it does.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Command {
+    /// Runs the simulator with the interactive display, which is the default.
+    Run,
+    /// Prints the disassembled instructions of the elf file.
+    Disasm,
+    /// Runs the simulator without a display, printing every instruction as it
+    /// commits.
+    Trace,
+    /// Runs the simulator without a display, printing how it performed.
+    Bench,
+    /// Assembles a source file into an elf file.
+    Asm,
 }
 
 impl Default for Config {
     fn default() -> Config {
         Config {
+            command: Command::Run,
             elf_file: String::from(""),
+            asm_file: String::from(""),
+            origin: 0x1_0000,
             n_way: 1,
+            fetch_width: 0,
+            decode_width: 0,
             issue_limit: 1,
+            commit_width: 0,
+            fetch_queue: 0,
+            fetch_block: 0,
+            decode_latency: 1,
+            flush_penalty: 0,
             alu_units: 1,
             blu_units: 1,
             mcu_units: 1,
+            mem_ports: 0,
+            result_buses: 0,
+            mem_disambig: MemoryDisambiguation::default(),
+            unaligned: UnalignedAccess::default(),
+            unaligned_penalty: 8,
             rsv_size: 16,
             rob_size: 32,
+            prf_size: 0,
+            issue_policy: IssuePolicy::default(),
+            routes: HashMap::new(),
             branch_prediction: BranchPredictorMode::default(),
             return_address_stack: false,
+            ras_depth: 8,
+            spec_depth: 0,
+            early_forward: false,
+            compare_predictors: false,
+            op_histogram: false,
+            hot_branches: 0,
+            profile_memory: 0,
+            trap_continue: false,
+            ignore_ebreak: false,
+            repl: false,
+            interactive_step: None,
+            verify_load: false,
+            riscv_test: false,
+            symbols: false,
+            dry_run: false,
+            elf_info: false,
+            log_level: LogLevel::default(),
+            history: 100,
+            speed: 40,
+            max_insns: 0,
+            warmup: 0,
+            deadlock_cycles: 0,
+            follow_pc: false,
+            start_paused: true,
+            theme: ThemeMode::default(),
+            inject_faults: None,
+            inject_bubbles: None,
+            stack_align: DEFAULT_STACK_ALIGN,
+            stack_limit: None,
+            protect_text: false,
+            mem_size: DEFAULT_MEM_SIZE,
+            uart_addr: None,
+            stdin: None,
+            args: vec![],
+            set_regs: vec![],
+            metrics_csv: None,
+            chrome_trace: None,
+            replay: None,
+            trace_cycles: true,
+            mem_dump: None,
+            sweep: None,
+            compare: None,
         }
     }
 }
@@ -50,120 +320,157 @@ impl Default for Config {
 impl Config {
     /// Generates a new Config for the assembler program given the arguments
     pub fn create_from_args() -> Config {
-        let matches = App::new("Project Daybreak")
+        Config::create_from(env::args_os())
+    }
+
+    /// Generates a new Config for benchmarking the given elf file, with the
+    /// options in the file at the given path. These are separated by
+    /// whitespace, as they would be on the command line (without quoting),
+    /// and lines starting with `#` are ignored.
+    pub fn create_from_file(path: &str, elf_file: &str) -> Config {
+        let options = match fs::read_to_string(path) {
+            Ok(s) => s,
+            Err(e) => error!(format!("Failed to read options file {}:\n{}", path, e)),
+        };
+        let mut args = vec!["daybreak", "bench"];
+        args.extend(
+            options
+                .lines()
+                .filter(|l| !l.trim_start().starts_with('#'))
+                .flat_map(|l| l.split_whitespace()),
+        );
+        args.push(elf_file);
+        Config::create_from(args)
+    }
+
+    /// Generates a new Config from the given arguments, the first of which is
+    /// the name of the program.
+    pub fn create_from<I, T>(args: I) -> Config
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let app_matches = App::new("Project Daybreak")
                           .version("0.1.0")
                           .author("Anthony W. <a.wharton.2015@bristol.ac.uk>")
                           .about("A superscalar, out of order, riscv32im simulator.")
                           .max_term_width(100)
-                          .arg(Arg::with_name("elf-file")
-                               .takes_value(true)
-                               .value_name("FILE")
-                               .required(true)
-                               .help("Specifies a path to elf file to execute in the simulator."))
-                          .arg(Arg::with_name("n-way")
-                               .short("n")
-                               .long("n-way")
-                               .takes_value(true)
-                               .value_name("N")
-                               .default_value("1")
-                               .validator(|s| match s.parse::<usize>() {
-                                   Ok(_) => Ok(()),
-                                   Err(_) => Err(String::from("Not a valid number!"))
-                               })
-                               .required(false)
-                               .help("Sets the 'n-way-ness' of the fetch, decode and commit stages."))
-                          .arg(Arg::with_name("issue-limit")
-                               .short("i")
-                               .long("issue-limit")
-                               .takes_value(true)
-                               .value_name("N")
-                               .default_value("1")
-                               .validator(|s| match s.parse::<usize>() {
-                                   Ok(_) => Ok(()),
-                                   Err(_) => Err(String::from("Not a valid number!"))
-                               })
-                               .required(false)
-                               .help("Sets a limit to the number of instructions issued and committed per cycle. Setting this to 0 is interpreted as the number of execute units."))
-                          .arg(Arg::with_name("alu-units")
-                               .long("alu")
-                               .takes_value(true)
-                               .value_name("N")
-                               .default_value("1")
-                               .validator(|s| match s.parse::<usize>() {
-                                   Ok(_) => Ok(()),
-                                   Err(_) => Err(String::from("Not a valid number!"))
-                               })
-                               .required(false)
-                               .help("Sets the number of Arithmetic Logic Units."))
-                          .arg(Arg::with_name("blu-units")
-                               .long("blu")
-                               .takes_value(true)
-                               .value_name("N")
-                               .default_value("1")
-                               .validator(|s| match s.parse::<usize>() {
-                                   Ok(_) => Ok(()),
-                                   Err(_) => Err(String::from("Not a valid number!"))
-                               })
-                               .required(false)
-                               .help("Sets the number of Branch Logic Units."))
-                          .arg(Arg::with_name("mcu-units")
-                               .long("mcu")
-                               .takes_value(true)
-                               .value_name("N")
-                               .default_value("1")
-                               .validator(|s| match s.parse::<usize>() {
-                                   Ok(_) => Ok(()),
-                                   Err(_) => Err(String::from("Not a valid number!"))
-                               })
-                               .required(false)
-                               .help("Sets the number of Memory Control Units."))
-                          .arg(Arg::with_name("rsv-size")
-                               .long("rsv")
-                               .takes_value(true)
-                               .value_name("N")
-                               .default_value("16")
-                               .validator(|s| match s.parse::<usize>() {
-                                   Ok(_) => Ok(()),
-                                   Err(_) => Err(String::from("Not a valid number!"))
-                               })
-                               .required(false)
-                               .help("Sets the number of entries in the reservation station."))
-                          .arg(Arg::with_name("rob-size")
-                               .long("rob")
-                               .takes_value(true)
-                               .value_name("N")
-                               .default_value("32")
-                               .validator(|s| match s.parse::<usize>() {
-                                   Ok(_) => Ok(()),
-                                   Err(_) => Err(String::from("Not a valid number!"))
-                               })
-                               .required(false)
-                               .help("Sets the number of entries in the reorder buffer."))
-                          .arg(Arg::with_name("branch-prediction")
-                               .short("b")
-                               .long("branch-prediction")
-                               .takes_value(true)
-                               .possible_values(&["off", "onebit", "twobit", "twolevel"])
-                               .default_value("twobit")
-                               .case_insensitive(true)
-                               .required(false)
-                               .help("Sets the branch prediction mode."))
-                          .arg(Arg::with_name("return-stack")
-                               .short("r")
-                               .long("return-stack")
-                               .required(false)
-                               .requires("branch-prediction")
-                               .help("Enables the Return Address Stack."))
-                          .get_matches();
+                          .setting(AppSettings::SubcommandsNegateReqs)
+                          .setting(AppSettings::ArgsNegateSubcommands)
+                          .arg(elf_file_arg().required_unless("repl"))
+                          .args(&simulator_args())
+                          .arg(repl_arg())
+                          .arg(interactive_step_arg())
+                          .subcommand(SubCommand::with_name("run")
+                                      .about("Runs an elf file in the simulator, with the interactive display. This is the default.")
+                                      .arg(elf_file_arg().required_unless("repl"))
+                                      .args(&simulator_args())
+                                      .arg(repl_arg())
+                                      .arg(interactive_step_arg()))
+                          .subcommand(SubCommand::with_name("disasm")
+                                      .about("Disassembles the instructions in an elf file.")
+                                      .arg(elf_file_arg().required(true))
+                                      .arg(symbols_arg()))
+                          .subcommand(SubCommand::with_name("trace")
+                                      .about("Runs an elf file in the simulator without the display, printing every instruction as it commits.")
+                                      .arg(elf_file_arg().required(true))
+                                      .args(&simulator_args())
+                                      .arg(Arg::with_name("no-cycles")
+                                           .long("no-cycles")
+                                           .required(false)
+                                           .help("Leaves out the cycle that each instruction committed in, so that only the architectural behaviour is printed, as for the golden traces.")))
+                          .subcommand(SubCommand::with_name("bench")
+                                      .about("Runs an elf file in the simulator without the display, and tabulates how it performed.")
+                                      .arg(elf_file_arg().required(true))
+                                      .args(&simulator_args())
+                                      .arg(Arg::with_name("sweep")
+                                           .long("sweep")
+                                           .takes_value(true)
+                                           .value_name("PARAM:VALUES")
+                                           .validator(|s| match parse_sweep_spec(&s) {
+                                               Some(_) => Ok(()),
+                                               None => Err(format!("Expected a parameter ({}) and comma separated values or ranges, e.g. n-way:1,2,4 or rob:8..12!", SWEEP_PARAMS.join(", ")))
+                                           })
+                                           .required(false)
+                                           .help("Runs the simulation once for each of the given values of a parameter, and tabulates how each performed. Values are comma separated, and may include inclusive ranges such as 1..4."))
+                                      .arg(Arg::with_name("compare")
+                                           .long("compare")
+                                           .takes_value(true)
+                                           .value_name("FILE")
+                                           .conflicts_with("sweep")
+                                           .required(false)
+                                           .help("Also runs the simulation with the options in the given file instead, separated by whitespace as on the command line, and tabulates every statistic of both side by side with the change between them.")))
+                          .subcommand(SubCommand::with_name("asm")
+                                      .about("Assembles a source file into an elf file that can be run in the simulator.")
+                                      .arg(Arg::with_name("asm-file")
+                                           .takes_value(true)
+                                           .value_name("SOURCE")
+                                           .required(true)
+                                           .help("Specifies a path to the assembly source file, in the syntax of the interactive prompt, with labels, comments and the .text, .data, .word and .byte directives."))
+                                      .arg(elf_file_arg()
+                                           .required(true)
+                                           .help("Specifies a path to write the assembled elf file to."))
+                                      .arg(Arg::with_name("origin")
+                                           .long("origin")
+                                           .takes_value(true)
+                                           .value_name("ADDR")
+                                           .default_value("0x10000")
+                                           .validator(|s| match parse_address(&s) {
+                                               Some(a) if a % 4 == 0 => Ok(()),
+                                               _ => Err(String::from("Not a valid, word aligned address!"))
+                                           })
+                                           .required(false)
+                                           .help("Sets the address (decimal or 0x prefixed hexadecimal) that the program is placed at, and starts executing from. The data section follows the instructions.")))
+                          .get_matches_from(args);
+
+        // Without a subcommand, the simulator runs as normal
+        let (command, matches) = match app_matches.subcommand() {
+            ("run", Some(m)) => (Command::Run, m),
+            ("disasm", Some(m)) => (Command::Disasm, m),
+            ("trace", Some(m)) => (Command::Trace, m),
+            ("bench", Some(m)) => (Command::Bench, m),
+            ("asm", Some(m)) => (Command::Asm, m),
+            _ => (Command::Run, &app_matches),
+        };
 
         let mut config = Config::default();
-        config.elf_file = String::from(matches.value_of("elf-file").unwrap());
+        config.command = command;
+        if let Some(s) = matches.value_of("elf-file") {
+            config.elf_file = String::from(s);
+        }
+        if let Some(s) = matches.value_of("asm-file") {
+            config.asm_file = String::from(s);
+        }
+        if let Some(s) = matches.value_of("origin") {
+            config.origin = parse_address(s).unwrap();
+        }
         if let Some(s) = matches.value_of("n-way") {
             config.n_way = s.parse::<usize>().unwrap();
         }
+        if let Some(s) = matches.value_of("fetch-width") {
+            config.fetch_width = s.parse::<usize>().unwrap();
+        }
+        if let Some(s) = matches.value_of("decode-width") {
+            config.decode_width = s.parse::<usize>().unwrap();
+        }
         if let Some(s) = matches.value_of("issue-limit") {
             config.issue_limit= s.parse::<usize>().unwrap();
         }
+        if let Some(s) = matches.value_of("commit-width") {
+            config.commit_width = s.parse::<usize>().unwrap();
+        }
+        if let Some(s) = matches.value_of("fetch-queue") {
+            config.fetch_queue = s.parse::<usize>().unwrap();
+        }
+        if let Some(s) = matches.value_of("fetch-block") {
+            config.fetch_block = s.parse::<usize>().unwrap();
+        }
+        if let Some(s) = matches.value_of("decode-latency") {
+            config.decode_latency = s.parse::<usize>().unwrap();
+        }
+        if let Some(s) = matches.value_of("flush-penalty") {
+            config.flush_penalty = s.parse::<usize>().unwrap();
+        }
         if let Some(s) = matches.value_of("alu-units") {
             config.alu_units = s.parse::<usize>().unwrap();
         }
@@ -173,25 +480,1072 @@ impl Config {
         if let Some(s) = matches.value_of("mcu-units") {
             config.mcu_units = s.parse::<usize>().unwrap();
         }
+        if let Some(s) = matches.value_of("mem-ports") {
+            config.mem_ports = s.parse::<usize>().unwrap();
+        }
+        if let Some(s) = matches.value_of("result-buses") {
+            config.result_buses = s.parse::<usize>().unwrap();
+        }
+        if let Some(s) = matches.value_of("mem-disambig") {
+            match s.to_lowercase().as_str() {
+                "conservative" => config.mem_disambig = MemoryDisambiguation::Conservative,
+                "perfect" => config.mem_disambig = MemoryDisambiguation::Perfect,
+                "optimistic" => config.mem_disambig = MemoryDisambiguation::Optimistic,
+                "speculative" => config.mem_disambig = MemoryDisambiguation::Speculative,
+                _ => (),
+            }
+        }
+        if let Some(s) = matches.value_of("unaligned") {
+            match s.to_lowercase().as_str() {
+                "trap" => config.unaligned = UnalignedAccess::Trap,
+                "slow" => config.unaligned = UnalignedAccess::Slow,
+                "fast" => config.unaligned = UnalignedAccess::Fast,
+                _ => (),
+            }
+        }
+        if let Some(s) = matches.value_of("unaligned-penalty") {
+            config.unaligned_penalty = s.parse::<u8>().unwrap();
+        }
         if let Some(s) = matches.value_of("rsv-size") {
             config.rsv_size = s.parse::<usize>().unwrap();
         }
         if let Some(s) = matches.value_of("rob-size") {
             config.rob_size = s.parse::<usize>().unwrap();
         }
+        if let Some(s) = matches.value_of("prf-size") {
+            config.prf_size = s.parse::<usize>().unwrap();
+        }
+        if let Some(s) = matches.value_of("history") {
+            config.history = s.parse::<usize>().unwrap();
+        }
+        if let Some(s) = matches.value_of("speed") {
+            config.speed = s.parse::<u64>().unwrap();
+        }
+        if let Some(s) = matches.value_of("max-insns") {
+            config.max_insns = s.parse::<u64>().unwrap();
+        }
+        if let Some(s) = matches.value_of("warmup") {
+            config.warmup = s.parse::<u64>().unwrap();
+        }
+        if let Some(s) = matches.value_of("dump-rob-on-deadlock") {
+            config.deadlock_cycles = s.parse::<u64>().unwrap();
+        }
+        if matches.is_present("follow-pc") {
+            config.follow_pc = true;
+        }
+        if matches.is_present("start-running") {
+            config.start_paused = false;
+        }
+        if let Some(s) = matches.value_of("theme") {
+            match s.to_lowercase().as_str() {
+                "default" => config.theme = ThemeMode::Default,
+                "plain" => config.theme = ThemeMode::Plain,
+                _ => (),
+            }
+        }
+        if matches.is_present("no-color") {
+            config.theme = ThemeMode::Plain;
+        }
+        if let Some(s) = matches.value_of("inject-faults") {
+            config.inject_faults = parse_fault_spec(s);
+        }
+        if let Some(s) = matches.value_of("inject-bubbles") {
+            config.inject_bubbles = parse_fault_spec(s);
+        }
+        if let Some(s) = matches.value_of("stack-align") {
+            config.stack_align = s.parse::<usize>().unwrap();
+        }
+        if let Some(s) = matches.value_of("stack-limit") {
+            config.stack_limit = parse_address(s);
+        }
+        if matches.is_present("protect-text") {
+            config.protect_text = true;
+        }
+        if let Some(s) = matches.value_of("mem-size") {
+            config.mem_size = parse_address(s).unwrap();
+        }
+        if let Some(s) = matches.value_of("uart-addr") {
+            config.uart_addr = parse_address(s);
+        }
+        if let Some(s) = matches.value_of("stdin") {
+            config.stdin = Some(String::from(s));
+        }
+        if let Some(values) = matches.values_of("args") {
+            config.args = values.map(String::from).collect();
+        }
+        if let Some(specs) = matches.values_of("set-reg") {
+            config.set_regs = specs.filter_map(parse_reg_spec).collect();
+        }
+        if let Some(s) = matches.value_of("metrics-csv") {
+            config.metrics_csv = Some(String::from(s));
+        }
+        if let Some(s) = matches.value_of("chrome-trace") {
+            config.chrome_trace = Some(String::from(s));
+        }
+        if let Some(s) = matches.value_of("replay") {
+            config.replay = Some(String::from(s));
+        }
+        if matches.is_present("no-cycles") {
+            config.trace_cycles = false;
+        }
+        if let Some(s) = matches.value_of("mem-dump") {
+            config.mem_dump = Some(String::from(s));
+        }
         if let Some(s) = matches.value_of("branch-prediction") {
             match s.to_lowercase().as_str() {
                 "off" => config.branch_prediction = BranchPredictorMode::Off,
                 "onebit" => config.branch_prediction = BranchPredictorMode::OneBit,
                 "twobit" => config.branch_prediction = BranchPredictorMode::TwoBit,
                 "twolevel" => config.branch_prediction = BranchPredictorMode::TwoLevel,
+                "oracle" => config.branch_prediction = BranchPredictorMode::Oracle,
                 _ => (),
             }
         }
+        if let Some(s) = matches.value_of("issue-policy") {
+            match s.to_lowercase().as_str() {
+                "oldest" => config.issue_policy = IssuePolicy::Oldest,
+                "position" => config.issue_policy = IssuePolicy::Position,
+                "critical" => config.issue_policy = IssuePolicy::Critical,
+                _ => (),
+            }
+        }
+        if let Some(specs) = matches.values_of("route") {
+            config.routes = specs.filter_map(parse_route_spec).collect();
+        }
         if matches.is_present("return-stack") {
             config.return_address_stack = true;
         }
+        if let Some(s) = matches.value_of("ras-depth") {
+            config.ras_depth = s.parse::<usize>().unwrap();
+        }
+        if let Some(s) = matches.value_of("spec-depth") {
+            config.spec_depth = s.parse::<usize>().unwrap();
+        }
+        if matches.is_present("early-forward") {
+            config.early_forward = true;
+        }
+        if matches.is_present("compare-predictors") {
+            config.compare_predictors = true;
+        }
+        if matches.is_present("op-histogram") {
+            config.op_histogram = true;
+        }
+        if let Some(s) = matches.value_of("hot-branches") {
+            config.hot_branches = s.parse::<usize>().unwrap();
+        }
+        if let Some(s) = matches.value_of("profile-memory") {
+            config.profile_memory = s.parse::<usize>().unwrap();
+        }
+        if matches.is_present("trap-continue") {
+            config.trap_continue = true;
+        }
+        if matches.is_present("ignore-ebreak") {
+            config.ignore_ebreak = true;
+        }
+        if let Some(s) = matches.value_of("log-level") {
+            match s.to_lowercase().as_str() {
+                "off" => config.log_level = LogLevel::Off,
+                "warn" => config.log_level = LogLevel::Warn,
+                "info" => config.log_level = LogLevel::Info,
+                "debug" => config.log_level = LogLevel::Debug,
+                _ => (),
+            }
+        }
+        if matches.is_present("quiet") {
+            config.log_level = LogLevel::Off;
+        }
+        if matches.is_present("repl") {
+            config.repl = true;
+        }
+        if let Some(s) = matches.value_of("interactive-step") {
+            config.interactive_step = Some(s.parse::<u64>().unwrap());
+        }
+        if matches.is_present("verify-load") {
+            config.verify_load = true;
+        }
+        if matches.is_present("riscv-test") {
+            config.riscv_test = true;
+        }
+        if matches.is_present("symbols") {
+            config.symbols = true;
+        }
+        if matches.is_present("dry-run") {
+            config.dry_run = true;
+        }
+        if matches.is_present("elf-info") {
+            config.elf_info = true;
+        }
+        if let Some(s) = matches.value_of("sweep") {
+            config.sweep = parse_sweep_spec(s);
+        }
+        if let Some(s) = matches.value_of("compare") {
+            config.compare = Some(String::from(s));
+        }
 
         config
     }
+
+    /// Sets one of the parameters that can be swept over when benchmarking,
+    /// given by its command line name, to the given value. Returns an error
+    /// if the parameter or value is not recognised.
+    pub fn set_param(&mut self, param: &str, value: &str) -> Result<(), String> {
+        let number = || match value.parse::<usize>() {
+            Ok(n) => Ok(n),
+            Err(_) => Err(format!("{} is not a valid number!", value)),
+        };
+        match param {
+            "n-way" => self.n_way = number()?,
+            "fetch-width" => self.fetch_width = number()?,
+            "decode-width" => self.decode_width = number()?,
+            "issue-limit" => self.issue_limit = number()?,
+            "commit-width" => self.commit_width = number()?,
+            "fetch-queue" => self.fetch_queue = number()?,
+            "fetch-block" => match number()? {
+                n if n == 0 || (n >= 4 && n.is_power_of_two()) => self.fetch_block = n,
+                _ => return Err(format!("{} is not a power of two of at least 4!", value)),
+            },
+            "decode-latency" => match number()? {
+                0 => return Err(String::from("Decode takes at least 1 cycle!")),
+                n => self.decode_latency = n,
+            },
+            "flush-penalty" => self.flush_penalty = number()?,
+            "alu" => self.alu_units = number()?,
+            "blu" => self.blu_units = number()?,
+            "mcu" => self.mcu_units = number()?,
+            "mem-ports" => self.mem_ports = number()?,
+            "result-buses" => self.result_buses = number()?,
+            "mem-disambig" => {
+                self.mem_disambig = match value.to_lowercase().as_str() {
+                    "conservative" => MemoryDisambiguation::Conservative,
+                    "perfect" => MemoryDisambiguation::Perfect,
+                    "optimistic" => MemoryDisambiguation::Optimistic,
+                    "speculative" => MemoryDisambiguation::Speculative,
+                    _ => return Err(format!("{} is not a memory disambiguation policy!", value)),
+                }
+            }
+            "unaligned" => {
+                self.unaligned = match value.to_lowercase().as_str() {
+                    "trap" => UnalignedAccess::Trap,
+                    "slow" => UnalignedAccess::Slow,
+                    "fast" => UnalignedAccess::Fast,
+                    _ => return Err(format!("{} is not an unaligned access policy!", value)),
+                }
+            }
+            "unaligned-penalty" => match value.parse::<u8>() {
+                Ok(n) => self.unaligned_penalty = n,
+                Err(_) => return Err(format!("{} is not a number of cycles up to 255!", value)),
+            },
+            "rsv" => self.rsv_size = number()?,
+            "rob" => self.rob_size = number()?,
+            "prf-size" => self.prf_size = number()?,
+            "ras-depth" => self.ras_depth = number()?,
+            "spec-depth" => self.spec_depth = number()?,
+            "branch-prediction" => {
+                self.branch_prediction = match value.to_lowercase().as_str() {
+                    "off" => BranchPredictorMode::Off,
+                    "onebit" => BranchPredictorMode::OneBit,
+                    "twobit" => BranchPredictorMode::TwoBit,
+                    "twolevel" => BranchPredictorMode::TwoLevel,
+                    "oracle" => BranchPredictorMode::Oracle,
+                    _ => return Err(format!("{} is not a branch prediction mode!", value)),
+                }
+            }
+            "issue-policy" => {
+                self.issue_policy = match value.to_lowercase().as_str() {
+                    "oldest" => IssuePolicy::Oldest,
+                    "position" => IssuePolicy::Position,
+                    "critical" => IssuePolicy::Critical,
+                    _ => return Err(format!("{} is not an issue policy!", value)),
+                }
+            }
+            _ => return Err(format!("{} is not a parameter that can be swept!", param)),
+        }
+        Ok(())
+    }
+}
+
+/// The arguments that configure the simulated machine, shared by every
+/// subcommand that runs the simulator.
+fn simulator_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("n-way")
+            .short("n")
+            .long("n-way")
+            .takes_value(true)
+            .value_name("N")
+            .default_value("1")
+            .validator(|s| match s.parse::<usize>() {
+                Ok(_) => Ok(()),
+                Err(_) => Err(String::from("Not a valid number!"))
+            })
+            .required(false)
+            .help("Sets the 'n-way-ness' of the fetch and decode stages, unless their widths are set separately."),
+        Arg::with_name("fetch-width")
+            .long("fetch-width")
+            .takes_value(true)
+            .value_name("N")
+            .default_value("0")
+            .validator(|s| match s.parse::<usize>() {
+                Ok(_) => Ok(()),
+                Err(_) => Err(String::from("Not a valid number!"))
+            })
+            .required(false)
+            .help("Sets the number of instructions fetched per cycle. Setting this to 0 is interpreted as the n-way-ness."),
+        Arg::with_name("decode-width")
+            .long("decode-width")
+            .takes_value(true)
+            .value_name("N")
+            .default_value("0")
+            .validator(|s| match s.parse::<usize>() {
+                Ok(_) => Ok(()),
+                Err(_) => Err(String::from("Not a valid number!"))
+            })
+            .required(false)
+            .help("Sets the number of instructions decoded per cycle. Setting this to 0 is interpreted as the n-way-ness."),
+        Arg::with_name("issue-limit")
+            .short("i")
+            .long("issue-limit")
+            .takes_value(true)
+            .value_name("N")
+            .default_value("1")
+            .validator(|s| match s.parse::<usize>() {
+                Ok(_) => Ok(()),
+                Err(_) => Err(String::from("Not a valid number!"))
+            })
+            .required(false)
+            .help("Sets a limit to the number of instructions issued per cycle. Setting this to 0 is interpreted as the number of execute units."),
+        Arg::with_name("commit-width")
+            .long("commit-width")
+            .takes_value(true)
+            .value_name("N")
+            .default_value("0")
+            .validator(|s| match s.parse::<usize>() {
+                Ok(_) => Ok(()),
+                Err(_) => Err(String::from("Not a valid number!"))
+            })
+            .required(false)
+            .help("Sets a limit to the number of instructions committed per cycle. Setting this to 0 is interpreted as the issue limit."),
+        Arg::with_name("fetch-queue")
+            .long("fetch-queue")
+            .takes_value(true)
+            .value_name("N")
+            .default_value("0")
+            .validator(|s| match s.parse::<usize>() {
+                Ok(_) => Ok(()),
+                Err(_) => Err(String::from("Not a valid number!"))
+            })
+            .required(false)
+            .help("Sets the number of fetched instructions that can be queued waiting to be decoded. Setting this to 0 is interpreted as the fetch width times the decode latency."),
+        Arg::with_name("fetch-block")
+            .long("fetch-block")
+            .takes_value(true)
+            .value_name("BYTES")
+            .default_value("0")
+            .validator(|s| match s.parse::<usize>() {
+                Ok(n) if n == 0 || (n >= 4 && n.is_power_of_two()) => Ok(()),
+                _ => Err(String::from("Not a power of two of at least 4!"))
+            })
+            .required(false)
+            .help("Sets the size of the aligned blocks of memory (such as cache lines) that a single fetch cannot cross, so fewer instructions are fetched when near the end of one. Setting this to 0 is interpreted as unlimited."),
+        Arg::with_name("decode-latency")
+            .long("decode-latency")
+            .takes_value(true)
+            .value_name("N")
+            .default_value("1")
+            .validator(|s| match s.parse::<usize>() {
+                Ok(0) => Err(String::from("Decode takes at least 1 cycle!")),
+                Ok(_) => Ok(()),
+                Err(_) => Err(String::from("Not a valid number!"))
+            })
+            .required(false)
+            .help("Sets the number of cycles it takes to decode an instruction after it is fetched. Decoding is pipelined, so this adds to the depth of the front end rather than limiting its throughput."),
+        Arg::with_name("flush-penalty")
+            .long("flush-penalty")
+            .takes_value(true)
+            .value_name("N")
+            .default_value("0")
+            .validator(|s| match s.parse::<usize>() {
+                Ok(_) => Ok(()),
+                Err(_) => Err(String::from("Not a valid number!"))
+            })
+            .required(false)
+            .help("Sets the number of idle cycles before fetching resumes after a pipeline flush from a branch misprediction."),
+        Arg::with_name("alu-units")
+            .long("alu")
+            .takes_value(true)
+            .value_name("N")
+            .default_value("1")
+            .validator(|s| match s.parse::<usize>() {
+                Ok(_) => Ok(()),
+                Err(_) => Err(String::from("Not a valid number!"))
+            })
+            .required(false)
+            .help("Sets the number of Arithmetic Logic Units."),
+        Arg::with_name("blu-units")
+            .long("blu")
+            .takes_value(true)
+            .value_name("N")
+            .default_value("1")
+            .validator(|s| match s.parse::<usize>() {
+                Ok(_) => Ok(()),
+                Err(_) => Err(String::from("Not a valid number!"))
+            })
+            .required(false)
+            .help("Sets the number of Branch Logic Units."),
+        Arg::with_name("mcu-units")
+            .long("mcu")
+            .takes_value(true)
+            .value_name("N")
+            .default_value("1")
+            .validator(|s| match s.parse::<usize>() {
+                Ok(_) => Ok(()),
+                Err(_) => Err(String::from("Not a valid number!"))
+            })
+            .required(false)
+            .help("Sets the number of Memory Control Units."),
+        Arg::with_name("mem-ports")
+            .long("mem-ports")
+            .takes_value(true)
+            .value_name("N")
+            .default_value("0")
+            .validator(|s| match s.parse::<usize>() {
+                Ok(_) => Ok(()),
+                Err(_) => Err(String::from("Not a valid number!"))
+            })
+            .required(false)
+            .help("Sets the number of memory ports, limiting how many loads, stores and atomic operations can execute at once. Setting this to 0 is interpreted as unlimited."),
+        Arg::with_name("result-buses")
+            .long("result-buses")
+            .takes_value(true)
+            .value_name("N")
+            .default_value("0")
+            .validator(|s| match s.parse::<usize>() {
+                Ok(_) => Ok(()),
+                Err(_) => Err(String::from("Not a valid number!"))
+            })
+            .required(false)
+            .help("Sets the number of result buses, limiting how many execute units can write back a result each cycle, oldest first. The rest wait with their result. Setting this to 0 is interpreted as unlimited."),
+        Arg::with_name("mem-disambig")
+            .long("mem-disambig")
+            .takes_value(true)
+            .possible_values(&["conservative", "perfect", "optimistic", "speculative"])
+            .default_value("speculative")
+            .case_insensitive(true)
+            .required(false)
+            .help("Sets when loads may read from memory: once every older store has committed, once every older store that truly writes to the same memory has committed (knowing every address up front), as perfect but only for addresses already calculated, or straight away. Loads are replayed if an older store then writes to the memory they read."),
+        Arg::with_name("unaligned")
+            .long("unaligned")
+            .takes_value(true)
+            .possible_values(&["trap", "slow", "fast"])
+            .default_value("fast")
+            .case_insensitive(true)
+            .required(false)
+            .help("Sets what happens to loads and stores to addresses that are not aligned to their size: they raise an address misaligned exception, they complete but take --unaligned-penalty extra cycles in the Memory Control Unit, or they complete as if aligned."),
+        Arg::with_name("unaligned-penalty")
+            .long("unaligned-penalty")
+            .takes_value(true)
+            .value_name("N")
+            .default_value("8")
+            .validator(|s| match s.parse::<u8>() {
+                Ok(_) => Ok(()),
+                Err(_) => Err(String::from("Not a number up to 255!"))
+            })
+            .required(false)
+            .help("Sets the number of extra cycles that misaligned loads and stores take, with --unaligned=slow."),
+        Arg::with_name("rsv-size")
+            .long("rsv")
+            .takes_value(true)
+            .value_name("N")
+            .default_value("16")
+            .validator(|s| match s.parse::<usize>() {
+                Ok(_) => Ok(()),
+                Err(_) => Err(String::from("Not a valid number!"))
+            })
+            .required(false)
+            .help("Sets the number of entries in the reservation station."),
+        Arg::with_name("rob-size")
+            .long("rob")
+            .takes_value(true)
+            .value_name("N")
+            .default_value("32")
+            .validator(|s| match s.parse::<usize>() {
+                Ok(_) => Ok(()),
+                Err(_) => Err(String::from("Not a valid number!"))
+            })
+            .required(false)
+            .help("Sets the number of entries in the reorder buffer."),
+        Arg::with_name("prf-size")
+            .long("prf-size")
+            .takes_value(true)
+            .value_name("N")
+            .default_value("0")
+            .validator(|s| match s.parse::<usize>() {
+                Ok(_) => Ok(()),
+                Err(_) => Err(String::from("Not a valid number!"))
+            })
+            .required(false)
+            .help("Sets the number of physical registers that hold the results of instructions until they are committed, stalling decode when every one is in use. Setting this to 0 is interpreted as the number of reorder buffer entries."),
+        Arg::with_name("issue-policy")
+            .long("issue-policy")
+            .takes_value(true)
+            .possible_values(&["oldest", "position", "critical"])
+            .default_value("position")
+            .case_insensitive(true)
+            .required(false)
+            .help("Sets how to choose between instructions that are ready to issue: the oldest, the first in the reservation station, or the one with the longest chain of dependent instructions."),
+        Arg::with_name("route")
+            .long("route")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("OP:UNIT")
+            .validator(|s| match parse_route_spec(&s) {
+                Some(_) => Ok(()),
+                None => Err(String::from("Expected an operation and the index of a unit that executes it, e.g. mul:0!"))
+            })
+            .required(false)
+            .help("Only issues the given operation to one execute unit, given by its index among the units that execute it, regardless of which are free. May be given several times, for different operations."),
+        Arg::with_name("branch-prediction")
+            .short("b")
+            .long("branch-prediction")
+            .takes_value(true)
+            .possible_values(&["off", "onebit", "twobit", "twolevel", "oracle"])
+            .default_value("twobit")
+            .case_insensitive(true)
+            .required(false)
+            .help("Sets the branch prediction mode."),
+        Arg::with_name("return-stack")
+            .short("r")
+            .long("return-stack")
+            .required(false)
+            .requires("branch-prediction")
+            .help("Enables the Return Address Stack."),
+        Arg::with_name("ras-depth")
+            .long("ras-depth")
+            .takes_value(true)
+            .value_name("N")
+            .default_value("8")
+            .validator(|s| match s.parse::<usize>() {
+                Ok(_) => Ok(()),
+                Err(_) => Err(String::from("Not a valid number!"))
+            })
+            .required(false)
+            .help("Sets the number of entries in the Return Address Stack, dropping the oldest when full. Setting this to 0 is interpreted as unbounded."),
+        Arg::with_name("spec-depth")
+            .long("spec-depth")
+            .takes_value(true)
+            .value_name("N")
+            .default_value("0")
+            .validator(|s| match s.parse::<usize>() {
+                Ok(_) => Ok(()),
+                Err(_) => Err(String::from("Not a valid number!"))
+            })
+            .required(false)
+            .help("Sets the number of branches and jumps that may be in flight before they are committed, stalling decode at the next one, to limit how deeply the pipeline speculates. Setting this to 0 is interpreted as unlimited."),
+        Arg::with_name("early-forward")
+            .long("early-forward")
+            .required(false)
+            .help("Forwards the results of executing instructions to the instructions waiting on them a cycle before they are written back, rather than as they are written back."),
+        Arg::with_name("compare-predictors")
+            .long("compare-predictors")
+            .required(false)
+            .help("Runs a shadow branch predictor for every mode alongside the real one, and reports how accurate each would have been once the simulator exits."),
+        Arg::with_name("op-histogram")
+            .long("op-histogram")
+            .required(false)
+            .help("Reports how many of each operation were committed, most frequent first, once the simulator exits."),
+        Arg::with_name("hot-branches")
+            .long("hot-branches")
+            .takes_value(true)
+            .value_name("N")
+            .validator(|s| match s.parse::<usize>() {
+                Ok(_) => Ok(()),
+                Err(_) => Err(String::from("Not a valid number!"))
+            })
+            .required(false)
+            .help("Reports the N branches and jumps that were mispredicted most, with their instructions and misprediction rates, once the simulator exits."),
+        Arg::with_name("profile-memory")
+            .long("profile-memory")
+            .takes_value(true)
+            .value_name("BYTES")
+            .validator(|s| match s.parse::<usize>() {
+                Ok(_) => Ok(()),
+                Err(_) => Err(String::from("Not a valid number!"))
+            })
+            .required(false)
+            .help("Reports how many instruction fetches, loads and stores were made to each page of memory of the given size, as a histogram, once the simulator exits."),
+        Arg::with_name("ignore-ebreak")
+            .long("ignore-ebreak")
+            .required(false)
+            .help("Treats breakpoint (ebreak) instructions as no-ops, instead of pausing the simulation, or stopping it when running without the display."),
+        Arg::with_name("trap-continue")
+            .long("trap-continue")
+            .required(false)
+            .help("Skips over illegal instructions after reporting them, instead of halting. An all-zero word (uninitialised memory) always halts."),
+        Arg::with_name("verify-load")
+            .long("verify-load")
+            .required(false)
+            .help("Checks that every section of the elf file reads back from memory correctly once loaded, quitting if any were clobbered."),
+        Arg::with_name("riscv-test")
+            .long("riscv-test")
+            .required(false)
+            .conflicts_with("repl")
+            .help("Runs the elf file as a test from the riscv-tests suite, which finishes by writing to its tohost symbol: 1 for a pass, or otherwise the number of the failing test shifted left by 1. The exit code is 0 for a pass, or the number of the failing test. Tests linked at 0x80000000, as the suite links them, are moved down to address 0 to fit in memory, so must only address memory relative to the pc (such as with la, not lui)."),
+        symbols_arg(),
+        Arg::with_name("dry-run")
+            .long("dry-run")
+            .required(false)
+            .conflicts_with("repl")
+            .help("Reports the machine that the given options resolve to, and checks it can run the program, without simulating."),
+        Arg::with_name("elf-info")
+            .long("elf-info")
+            .required(false)
+            .conflicts_with("repl")
+            .help("Prints the elf file's header, program headers and section headers, along with any reasons it cannot be run, without loading it."),
+        Arg::with_name("log-level")
+            .long("log-level")
+            .takes_value(true)
+            .possible_values(&["off", "warn", "info", "debug"])
+            .default_value("warn")
+            .case_insensitive(true)
+            .required(false)
+            .help("Sets the most verbose level of simulator message that is logged: warnings for illegal instructions, access faults and stack overflows, info for pipeline flushes, replays and faults, or debug for every stall."),
+        Arg::with_name("quiet")
+            .short("q")
+            .long("quiet")
+            .required(false)
+            .help("Disables logging of simulator messages, overriding --log-level."),
+        Arg::with_name("history")
+            .long("history")
+            .takes_value(true)
+            .value_name("N")
+            .default_value("100")
+            .validator(|s| match s.parse::<usize>() {
+                Ok(0) => Err(String::from("Must keep at least 1 state!")),
+                Ok(_) => Ok(()),
+                Err(_) => Err(String::from("Not a valid number!"))
+            })
+            .required(false)
+            .help("Sets the number of past states kept for rewinding the simulation. Each state only keeps the memory that changed in its cycle."),
+        Arg::with_name("speed")
+            .long("speed")
+            .takes_value(true)
+            .value_name("HZ")
+            .default_value("40")
+            .validator(|s| match s.parse::<u64>() {
+                Ok(_) => Ok(()),
+                Err(_) => Err(String::from("Not a valid number!"))
+            })
+            .required(false)
+            .help("Sets the number of cycles simulated per second with the display. Setting this to 0 runs the simulation as fast as possible. Without the display, there is no limit."),
+        Arg::with_name("max-insns")
+            .long("max-insns")
+            .takes_value(true)
+            .value_name("N")
+            .default_value("0")
+            .validator(|s| match s.parse::<u64>() {
+                Ok(_) => Ok(()),
+                Err(_) => Err(String::from("Not a valid number!"))
+            })
+            .required(false)
+            .help("Stops the simulation once N instructions have been retired, for sampling a window of a long program. Setting this to 0 is interpreted as unlimited."),
+        Arg::with_name("dump-rob-on-deadlock")
+            .long("dump-rob-on-deadlock")
+            .takes_value(true)
+            .value_name("CYCLES")
+            .default_value("0")
+            .validator(|s| match s.parse::<u64>() {
+                Ok(_) => Ok(()),
+                Err(_) => Err(String::from("Not a valid number!"))
+            })
+            .required(false)
+            .help("Halts the simulation once no instruction has retired for the given number of cycles, printing the reorder buffer, reservation station and execute units to stderr. Setting this to 0 is interpreted as never."),
+        Arg::with_name("warmup")
+            .long("warmup")
+            .takes_value(true)
+            .value_name("N")
+            .default_value("0")
+            .validator(|s| match s.parse::<u64>() {
+                Ok(_) => Ok(()),
+                Err(_) => Err(String::from("Not a valid number!"))
+            })
+            .required(false)
+            .help("Leaves the first N cycles out of the statistics, so that they measure the steady state once the branch predictor has trained. The instructions retired during them are still counted towards --max-insns."),
+        Arg::with_name("follow-pc")
+            .long("follow-pc")
+            .required(false)
+            .help("Centres the instruction memory on the committed program counter, instead of the load counter, until toggled with 'F'."),
+        Arg::with_name("start-running")
+            .long("start-running")
+            .required(false)
+            .help("Starts running the simulation as soon as the display opens, instead of waiting paused for it to be started."),
+        Arg::with_name("theme")
+            .long("theme")
+            .takes_value(true)
+            .possible_values(&["default", "plain"])
+            .default_value("default")
+            .case_insensitive(true)
+            .required(false)
+            .help("Sets the colour scheme of the display: the default colours, or plain text that tells things apart by bold, faint, underlined and inverted text instead."),
+        Arg::with_name("no-color")
+            .long("no-color")
+            .required(false)
+            .help("Draws the display without colours, the same as --theme plain."),
+        Arg::with_name("inject-faults")
+            .long("inject-faults")
+            .takes_value(true)
+            .value_name("SEED:RATE")
+            .validator(|s| match parse_fault_spec(&s) {
+                Some(_) => Ok(()),
+                None => Err(String::from("Expected a seed and a rate between 0 and 1, e.g. 42:0.001!"))
+            })
+            .required(false)
+            .help("Randomly flips a bit in a register, reorder buffer result or memory byte, with the given probability every cycle. The same seed always injects the same faults."),
+        Arg::with_name("inject-bubbles")
+            .long("inject-bubbles")
+            .takes_value(true)
+            .value_name("SEED:RATE")
+            .validator(|s| match parse_fault_spec(&s) {
+                Some(_) => Ok(()),
+                None => Err(String::from("Expected a seed and a rate between 0 and 1, e.g. 42:0.1!"))
+            })
+            .required(false)
+            .help("Randomly stalls the decode stage for a cycle with the given probability every cycle, to watch bubbles form in the pipeline. This only affects timing, never the results of the program. The same seed always injects the same bubbles."),
+        Arg::with_name("stack-align")
+            .long("stack-align")
+            .takes_value(true)
+            .value_name("BYTES")
+            .default_value("16")
+            .validator(|s| match s.parse::<usize>() {
+                Ok(n) if n.is_power_of_two() => Ok(()),
+                _ => Err(String::from("Not a power of two!"))
+            })
+            .required(false)
+            .help("Aligns the initial stack and frame pointers, at the end of memory, down to the given number of bytes. The RISC-V calling convention requires 16."),
+        Arg::with_name("stack-limit")
+            .long("stack-limit")
+            .takes_value(true)
+            .value_name("ADDR")
+            .validator(|s| match parse_address(&s) {
+                Some(_) => Ok(()),
+                None => Err(String::from("Not a valid address!"))
+            })
+            .required(false)
+            .help("Enables stack overflow detection, stopping the simulation if a store is made just below the given address (decimal or 0x prefixed hexadecimal)."),
+        Arg::with_name("protect-text")
+            .long("protect-text")
+            .required(false)
+            .help("Write-protects the code of the elf file, in the sections of instructions loaded from segments that are not writable, so that stores to it trap with access faults."),
+        Arg::with_name("mem-size")
+            .long("mem-size")
+            .takes_value(true)
+            .value_name("BYTES")
+            .validator(|s| match parse_address(&s) {
+                Some(n) if n >= INIT_MEMORY_SIZE => Ok(()),
+                Some(_) => Err(format!("Must be at least {} bytes!", INIT_MEMORY_SIZE)),
+                None => Err(String::from("Not a valid size!"))
+            })
+            .required(false)
+            .help("Sets the size of the address space that the program may access (decimal or 0x prefixed hexadecimal), at least 1000000 bytes. Loads and stores beyond it trap with access faults rather than growing memory. Defaults to 0x10000000."),
+        Arg::with_name("uart-addr")
+            .long("uart-addr")
+            .takes_value(true)
+            .value_name("ADDR")
+            .validator(|s| match parse_address(&s) {
+                Some(_) => Ok(()),
+                None => Err(String::from("Not a valid address!"))
+            })
+            .required(false)
+            .help("Enables a memory-mapped UART console at the given address (decimal or 0x prefixed hexadecimal). Bytes stored to the address are printed to the console output, and loads from 4 bytes above it read 1 for ready."),
+        Arg::with_name("stdin")
+            .long("stdin")
+            .takes_value(true)
+            .value_name("FILE")
+            .required(false)
+            .help("Specifies a file to use as the program's input, for the read system call. Without this the program reads stdin, except with the display, where its input is empty."),
+        Arg::with_name("args")
+            .long("args")
+            .takes_value(true)
+            .value_name("ARGS")
+            .multiple(true)
+            .allow_hyphen_values(true)
+            .conflicts_with("repl")
+            .required(false)
+            .help("Passes the given arguments to the program, with argc in a0 and argv in a1 (as well as on the stack). The first is conventionally the program's name. This takes every value up to the end, so must come after the elf file."),
+        Arg::with_name("set-reg")
+            .long("set-reg")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("REG=VALUE")
+            .validator(|s| match parse_reg_spec(&s) {
+                Some(_) => Ok(()),
+                None => Err(String::from("Expected a register other than zero or pc, and a value, e.g. gp=0x11800!"))
+            })
+            .required(false)
+            .help("Sets the initial value of a register by its numeric or ABI name, once the elf file (and any arguments) are loaded, such as gp or tp, which the simulator otherwise leaves as 0. The value may be decimal or 0x prefixed hexadecimal. May be given several times, for different registers."),
+        Arg::with_name("metrics-csv")
+            .long("metrics-csv")
+            .takes_value(true)
+            .value_name("FILE")
+            .required(false)
+            .help("Writes key metrics to the given CSV file, with one row per cycle."),
+        Arg::with_name("chrome-trace")
+            .long("chrome-trace")
+            .takes_value(true)
+            .value_name("FILE")
+            .required(false)
+            .help("Writes a timeline of every committed instruction's trip through the pipeline to the given file, in the Chrome trace event format, for viewing in Perfetto or chrome://tracing."),
+        Arg::with_name("replay")
+            .long("replay")
+            .takes_value(true)
+            .value_name("FILE")
+            .required(false)
+            .help("Checks every committed instruction, and the register or memory it wrote to, against a trace from the trace subcommand (with or without its cycle numbers), stopping at the first that differs."),
+        Arg::with_name("mem-dump")
+            .long("mem-dump")
+            .takes_value(true)
+            .value_name("FILE")
+            .required(false)
+            .help("Writes the memory image to the given file when the simulation ends, up to the highest address written to. This is also where the 'M' key dumps memory to."),
+    ]
+}
+
+/// The argument for the elf file to run in the simulator.
+fn elf_file_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("elf-file")
+        .takes_value(true)
+        .value_name("FILE")
+        .help("Specifies a path to elf file to execute in the simulator.")
+}
+
+/// The argument for naming the functions and labels in disassembled
+/// instructions, from the elf file's symbol table.
+fn symbols_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("symbols")
+        .long("symbols")
+        .required(false)
+        .help("Labels the disassembled instructions with the names of the functions and labels in the elf file's symbol table, unless it has been stripped.")
+}
+
+/// The argument for running the interactive assembly prompt, rather than an
+/// elf file.
+fn repl_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("repl")
+        .long("repl")
+        .required(false)
+        .conflicts_with("elf-file")
+        .help("Starts an interactive prompt that executes single instructions typed on stdin, instead of running an elf file.")
+}
+
+/// The argument that runs the headless command prompt instead of the display.
+fn interactive_step_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("interactive-step")
+        .long("interactive-step")
+        .takes_value(true)
+        .value_name("N")
+        .validator(|s| match s.parse::<u64>() {
+            Ok(n) if n > 0 => Ok(()),
+            _ => Err(String::from("Not a valid number of cycles!"))
+        })
+        .required(false)
+        .conflicts_with("repl")
+        .help("Runs the elf file without the display, taking commands from stdin to step through it and inspect the state, where a bare `step` advances N cycles.")
+}
+
+/// Parses a sweep specification of the form `PARAM:VALUES`, where the values
+/// are comma separated, and numeric values may be given as inclusive ranges
+/// of the form `FROM..TO`. Every value is checked against the parameter.
+fn parse_sweep_spec(spec: &str) -> Option<(String, Vec<String>)> {
+    let mut parts = spec.splitn(2, ':');
+    let param = parts.next()?;
+    let mut values = vec![];
+    for value in parts.next()?.split(',') {
+        let mut range = value.splitn(2, "..");
+        let from = range.next()?;
+        match range.next() {
+            Some(to) => {
+                let (from, to) = (from.parse::<usize>().ok()?, to.parse::<usize>().ok()?);
+                values.extend((from..=to).map(|n| n.to_string()));
+            }
+            None => values.push(String::from(from)),
+        }
+    }
+
+    let mut config = Config::default();
+    if values.is_empty() || values.iter().any(|v| config.set_param(param, v).is_err()) {
+        return None;
+    }
+    Some((String::from(param), values))
+}
+
+/// Parses a routing specification of the form `OP:UNIT`, where the operation
+/// is given by its mnemonic, and the unit by its index among the execute
+/// units that execute the operation.
+fn parse_route_spec(spec: &str) -> Option<(Operation, usize)> {
+    let mut parts = spec.splitn(2, ':');
+    let op = parts.next()?.parse::<Operation>().ok()?;
+    let unit = parts.next()?.parse::<usize>().ok()?;
+    Some((op, unit))
+}
+
+/// Parses an initial register value specification of the form `REG=VALUE`,
+/// where the register is given by either of its names, and may be neither
+/// the zero register nor the program counter.
+fn parse_reg_spec(spec: &str) -> Option<(Register, i32)> {
+    let mut parts = spec.splitn(2, '=');
+    let reg = Register::from_name(parts.next()?.trim())?;
+    let value = parse_immediate(parts.next()?.trim()).ok()?;
+    match reg {
+        Register::X0 | Register::PC => None,
+        _ => Some((reg, value)),
+    }
+}
+
+/// Parses a fault injection specification of the form `SEED:RATE`, where the
+/// rate is the probability of a fault in any cycle.
+fn parse_fault_spec(spec: &str) -> Option<(u64, f64)> {
+    let mut parts = spec.splitn(2, ':');
+    let seed = parts.next()?.parse::<u64>().ok()?;
+    let rate = parts.next()?.parse::<f64>().ok()?;
+    if 0.0 <= rate && rate <= 1.0 {
+        Some((seed, rate))
+    } else {
+        None
+    }
+}
+
+/// Parses a memory address, given in either decimal or (`0x` prefixed)
+/// hexadecimal.
+pub fn parse_address(addr: &str) -> Option<usize> {
+    if addr.starts_with("0x") || addr.starts_with("0X") {
+        usize::from_str_radix(&addr[2..], 16).ok()
+    } else {
+        addr.parse::<usize>().ok()
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// TESTS
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::state::State;
+
+    const ELF_FILE: &str = "resources/programs/hello_world/a.out";
+
+    #[test]
+    fn runs_without_subcommand() {
+        let config = Config::create_from(vec!["daybreak", "-n=4", ELF_FILE]);
+        assert_eq!(config.command, Command::Run);
+        assert_eq!(config.elf_file, ELF_FILE);
+        assert_eq!(config.n_way, 4);
+
+        let config = Config::create_from(vec!["daybreak", "--repl"]);
+        assert_eq!(config.command, Command::Run);
+        assert!(config.repl);
+    }
+
+    #[test]
+    fn parses_run_subcommand() {
+        let config = Config::create_from(vec!["daybreak", "run", "-n=2", ELF_FILE]);
+        assert_eq!(config.command, Command::Run);
+        assert_eq!(config.elf_file, ELF_FILE);
+        assert_eq!(config.n_way, 2);
+    }
+
+    #[test]
+    fn parses_disasm_subcommand() {
+        let config = Config::create_from(vec!["daybreak", "disasm", "--symbols", ELF_FILE]);
+        assert_eq!(config.command, Command::Disasm);
+        assert_eq!(config.elf_file, ELF_FILE);
+        assert!(config.symbols);
+    }
+
+    #[test]
+    fn parses_asm_subcommand() {
+        let config = Config::create_from(vec!["daybreak", "asm", "prog.s", "prog.elf"]);
+        assert_eq!(config.command, Command::Asm);
+        assert_eq!(config.asm_file, "prog.s");
+        assert_eq!(config.elf_file, "prog.elf");
+        assert_eq!(config.origin, 0x1_0000);
+
+        let config =
+            Config::create_from(vec!["daybreak", "asm", "--origin=0x8000", "prog.s", "prog.elf"]);
+        assert_eq!(config.origin, 0x8000);
+    }
+
+    #[test]
+    fn parses_trace_subcommand() {
+        let config = Config::create_from(vec!["daybreak", "trace", "--rob=8", ELF_FILE]);
+        assert_eq!(config.command, Command::Trace);
+        assert_eq!(config.elf_file, ELF_FILE);
+        assert_eq!(config.rob_size, 8);
+        assert!(config.trace_cycles);
+
+        let config = Config::create_from(vec!["daybreak", "trace", "--no-cycles", ELF_FILE]);
+        assert!(!config.trace_cycles);
+    }
+
+    #[test]
+    fn parses_bench_subcommand() {
+        let config = Config::create_from(vec![
+            "daybreak",
+            "bench",
+            "--sweep",
+            "n-way:1,2,4",
+            ELF_FILE,
+        ]);
+        assert_eq!(config.command, Command::Bench);
+        assert_eq!(config.elf_file, ELF_FILE);
+        let values = vec![String::from("1"), String::from("2"), String::from("4")];
+        assert_eq!(config.sweep, Some((String::from("n-way"), values)));
+    }
+
+    #[test]
+    fn parses_routes() {
+        let config = Config::create_from(vec![
+            "daybreak", "--route", "mul:1", "--route", "lw:0", ELF_FILE,
+        ]);
+        assert_eq!(config.routes.len(), 2);
+        assert_eq!(config.routes[&Operation::MUL], 1);
+        assert_eq!(config.routes[&Operation::LW], 0);
+        assert_eq!(parse_route_spec("mul"), None);
+        assert_eq!(parse_route_spec("mul:x"), None);
+        assert_eq!(parse_route_spec("nop:0"), None);
+    }
+
+    #[test]
+    fn starts_running_when_asked() {
+        let config = Config::create_from(vec!["daybreak", ELF_FILE]);
+        assert!(config.start_paused);
+        let config = Config::create_from(vec!["daybreak", "--start-running", ELF_FILE]);
+        assert!(!config.start_paused);
+    }
+
+    #[test]
+    fn sets_initial_register_values() {
+        let args = vec![
+            "daybreak",
+            "--set-reg",
+            "a0=5",
+            "--set-reg",
+            "x3=0x11800",
+            ELF_FILE,
+        ];
+        let config = Config::create_from(args);
+        assert_eq!(
+            config.set_regs,
+            vec![(Register::X10, 5), (Register::X3, 0x11800)]
+        );
+        let state = State::new(&config);
+        assert_eq!(state.register[Register::X10].data, 5);
+        assert_eq!(state.register[Register::X3].data, 0x11800);
+
+        assert_eq!(parse_reg_spec("gp=-1"), Some((Register::X3, -1)));
+        assert_eq!(parse_reg_spec("zero=1"), None);
+        assert_eq!(parse_reg_spec("pc=1"), None);
+        assert_eq!(parse_reg_spec("a0"), None);
+    }
 }
@@ -1,20 +1,256 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Cursor, Write};
+use std::sync::Arc;
+
 use elf::types::{
     FileHeader, Machine, ProgramHeader, ELFCLASS32, ELFDATA2LSB, ELFOSABI_SYSV, ET_EXEC,
-    EV_CURRENT, PT_LOAD, PT_NOTE, PT_NULL, PT_PHDR,
+    EV_CURRENT, PF_R, PF_W, PF_X, PT_LOAD, PT_NOTE, PT_NULL, PT_PHDR, SHF_ALLOC, SHF_EXECINSTR,
+    SHF_WRITE, SHT_PROGBITS, SHT_STRTAB, SHT_SYMTAB, STB_LOCAL, STT_FUNC, STT_NOTYPE,
 };
 use elf::{File, ParseError};
 
+use crate::isa::assembly::Program;
+use crate::isa::Instruction;
 use crate::isa::operand::Register;
 use crate::simulator::state::State;
 
-use super::config::Config;
+use super::config::{Command, Config};
+
+///////////////////////////////////////////////////////////////////////////////
+//// CONST/STATIC
+
+/// The address that the riscv-tests suite links its tests at, the start of
+/// RAM on its reference platform. Tests loaded there are rebased down to 0.
+const RISCV_TEST_BASE: u64 = 0x8000_0000;
 
 ///////////////////////////////////////////////////////////////////////////////
 //// FUNCTIONS
 
 /// Loads the elf file into a Memory data structure.
 pub fn load_elf(state: &mut State, config: &Config) {
-    let file: File = match File::open_path(&config.elf_file) {
+    let (raw, file) = read_elf(config);
+
+    // Initialise and load in memory, from the loadable segments
+    for h in file.phdrs.iter().filter(|h| h.progtype == PT_LOAD) {
+        if (h.vaddr + h.memsz) as usize > state.mem_size {
+            error!(format!(
+                "Elf file segment at {:08x} does not fit in the {} byte memory.",
+                h.vaddr, state.mem_size
+            ));
+        }
+        state.memory.load_elf_segment(h, segment_data(&raw, h));
+    }
+    if config.protect_text {
+        state.protected = read_only_code(&file);
+    }
+
+    if config.verify_load {
+        verify_load(state, &file);
+    }
+    if config.symbols {
+        state.symbols = Arc::new(read_symbols(&file));
+    }
+    if config.riscv_test {
+        match find_symbol(&file, "tohost") {
+            Some(addr) => state.tohost = Some(addr),
+            None => error!("Elf file has no tohost symbol, so cannot be run as a RISC-V test."),
+        }
+    }
+
+    verify_entry(state, file.ehdr.entry as usize);
+
+    // Load in initial program counter
+    state.register[Register::PC].data = file.ehdr.entry as i32;
+    state.branch_predictor.force_update(file.ehdr.entry as usize);
+}
+
+/// Returns the address ranges, as `(start, end)`, of the executable loadable
+/// segments in the elf file. These hold the program's instructions.
+pub fn executable_segments(config: &Config) -> Vec<(usize, usize)> {
+    let (_, file) = read_elf(config);
+    file.phdrs
+        .iter()
+        .filter(|h| h.progtype == PT_LOAD && h.flags.0 & PF_X.0 != 0)
+        .map(|h| (h.vaddr as usize, (h.vaddr + h.filesz) as usize))
+        .collect()
+}
+
+/// Returns the names and address ranges, as `(name, start, end)`, of the
+/// sections in the elf file that hold instructions. Unlike the executable
+/// segments, these exclude the elf headers and any padding.
+pub fn executable_sections(config: &Config) -> Vec<(String, usize, usize)> {
+    let (_, file) = read_elf(config);
+    file.sections
+        .iter()
+        .filter(|s| s.shdr.flags.0 & SHF_EXECINSTR.0 != 0)
+        .map(|s| {
+            let start = s.shdr.addr as usize;
+            (s.shdr.name.clone(), start, start + s.shdr.size as usize)
+        })
+        .collect()
+}
+
+/// Prints the elf file's header, program headers and section headers, along
+/// with any problems that stop it from being run in the simulator, without
+/// loading it. Quits with an error if there were any problems.
+pub fn print_elf_info(config: &Config) {
+    let (_, file) = parse_elf(config);
+    write_elf_info(&file, &mut io::stdout()).expect("Failed to print elf info.");
+
+    let problems: Vec<String> = check_file_header(&file.ehdr)
+        .map(String::from)
+        .into_iter()
+        .chain(file.phdrs.iter().filter_map(check_prog_header))
+        .collect();
+    if !problems.is_empty() {
+        error!(format!("Elf file cannot be run:\n{}", problems.join("\n")));
+    }
+}
+
+/// Writes the elf file's header, program headers and section headers to the
+/// given output, as printed by `--elf-info`.
+fn write_elf_info<W: Write>(file: &File, out: &mut W) -> io::Result<()> {
+    let header = &file.ehdr;
+    let machine = match header.machine {
+        Machine(0xf3) => String::from("RISC-V"),
+        m => format!("{} ({:?})", m, m),
+    };
+    writeln!(out, "File header:")?;
+    writeln!(out, "  class:    {}", header.class)?;
+    writeln!(out, "  data:     {}", header.data)?;
+    writeln!(out, "  type:     {}", header.elftype)?;
+    writeln!(out, "  machine:  {}", machine)?;
+    writeln!(out, "  entry:    {:#010x}", header.entry)?;
+
+    writeln!(out, "Program headers:")?;
+    writeln!(
+        out,
+        "  {:<12} {:<10} {:<10} {:<10} {:<10} flags",
+        "type", "offset", "vaddr", "filesz", "memsz"
+    )?;
+    for h in file.phdrs.iter() {
+        // The elf crate's types ignore padding, so are padded as strings
+        writeln!(
+            out,
+            "  {:<12} {:#010x} {:#010x} {:#010x} {:#010x} {}",
+            h.progtype.to_string(),
+            h.offset,
+            h.vaddr,
+            h.filesz,
+            h.memsz,
+            h.flags
+        )?;
+    }
+
+    writeln!(out, "Section headers:")?;
+    writeln!(out, "  {:<20} {:<10} {:<10} type", "name", "addr", "size")?;
+    for s in file.sections.iter() {
+        let shtype = s.shdr.shtype.to_string();
+        writeln!(
+            out,
+            "  {:<20} {:#010x} {:#010x} {}",
+            s.shdr.name,
+            s.shdr.addr,
+            s.shdr.size,
+            shtype.trim_start_matches("SHT_")
+        )?;
+    }
+    Ok(())
+}
+
+/// Returns the address ranges, as `(start, end)`, of the sections in the elf
+/// file that hold instructions and are loaded from segments that are not
+/// writable. Any read-only data sharing those segments is left out.
+fn read_only_code(file: &File) -> Vec<(usize, usize)> {
+    let read_only: Vec<&ProgramHeader> = file
+        .phdrs
+        .iter()
+        .filter(|h| h.progtype == PT_LOAD && h.flags.0 & PF_W.0 == 0)
+        .collect();
+    file.sections
+        .iter()
+        .filter(|s| s.shdr.flags.0 & SHF_EXECINSTR.0 != 0 && s.shdr.size > 0)
+        .map(|s| (s.shdr.addr, s.shdr.addr + s.shdr.size))
+        .filter(|&(start, end)| {
+            read_only
+                .iter()
+                .any(|h| h.vaddr <= start && end <= h.vaddr + h.memsz)
+        })
+        .map(|(start, end)| (start as usize, end as usize))
+        .collect()
+}
+
+/// Reads and parses the elf file, returning both its raw contents and the
+/// parsed file. The headers are verified, quitting if they are invalid.
+fn read_elf(config: &Config) -> (Vec<u8>, File) {
+    let (raw, mut file) = parse_elf(config);
+
+    // Verify headers, these will quit the program on a failure.
+    verify_file_header(&file.ehdr);
+    for h in file.phdrs.iter() {
+        verify_prog_header(h);
+    }
+    if config.riscv_test {
+        rebase_riscv_test(&mut file);
+    }
+    (raw, file)
+}
+
+/// Moves a RISC-V test linked at `RISCV_TEST_BASE` (as the riscv-tests suite
+/// links them) down to address 0, so that it fits in the simulated memory.
+/// The segments, sections, entry point and symbols are all rebased, however
+/// addresses that the test builds itself from absolute values (such as with
+/// `lui`) are not, as they cannot be told apart from other constants. Tests
+/// that only address memory relative to the `pc` (such as with `la`) are
+/// unaffected by the move.
+///
+/// Files with any loadable segment below `RISCV_TEST_BASE` are left as is.
+fn rebase_riscv_test(file: &mut File) {
+    let loaded = file.phdrs.iter().filter(|h| h.progtype == PT_LOAD && h.memsz > 0);
+    if loaded.clone().count() == 0 || loaded.clone().any(|h| h.vaddr < RISCV_TEST_BASE) {
+        return;
+    }
+    let rebase = |addr: &mut u64| {
+        if *addr >= RISCV_TEST_BASE {
+            *addr -= RISCV_TEST_BASE;
+        }
+    };
+    rebase(&mut file.ehdr.entry);
+    for h in file.phdrs.iter_mut() {
+        rebase(&mut h.vaddr);
+        rebase(&mut h.paddr);
+    }
+    for section in file.sections.iter_mut() {
+        rebase(&mut section.shdr.addr);
+        if section.shdr.shtype != SHT_SYMTAB {
+            continue;
+        }
+        // Every (32 bit) symbol is 16 bytes, with its value at offset 4, and
+        // its section index at offset 14, where 0 is undefined and indices
+        // from 0xff00 are reserved (such as for absolute values)
+        for symbol in section.data.chunks_mut(16).filter(|s| s.len() == 16) {
+            let shndx = u16::from_le_bytes([symbol[14], symbol[15]]);
+            if shndx != 0 && shndx < 0xff00 {
+                let mut value = u64::from(u32::from_le_bytes([
+                    symbol[4], symbol[5], symbol[6], symbol[7],
+                ]));
+                rebase(&mut value);
+                symbol[4..8].copy_from_slice(&(value as u32).to_le_bytes());
+            }
+        }
+    }
+}
+
+/// Reads and parses the elf file, returning both its raw contents and the
+/// parsed file, quitting if it cannot be parsed. The headers are not checked
+/// for compatibility with the simulator.
+fn parse_elf(config: &Config) -> (Vec<u8>, File) {
+    let raw = match fs::read(&config.elf_file) {
+        Ok(r) => r,
+        Err(e) => error!(format!("Failed to load elf file:\n{}", e)),
+    };
+    let file: File = match File::open_stream(&mut Cursor::new(&raw)) {
         Ok(f) => f,
         Err(e) => match e {
             ParseError::IoError(ee) => error!(format!("Failed to load elf file:\n{}", ee)),
@@ -23,44 +259,280 @@ pub fn load_elf(state: &mut State, config: &Config) {
             ParseError::NotImplemented => error!("Something went wrong loading the elf file."),
         },
     };
+    (raw, file)
+}
 
-    // Verify headers, these will quit the program on a failure.
-    verify_file_header(&file.ehdr);
-    for h in file.phdrs.iter() {
-        verify_prog_header(h);
+/// Reads the names of the functions and labels from the symbol table of the
+/// elf file (if it has not been stripped), by address. Where several share an
+/// address, a function's name is preferred over a label's.
+fn read_symbols(file: &File) -> BTreeMap<usize, String> {
+    let mut symbols = BTreeMap::new();
+    for section in file.sections.iter().filter(|s| s.shdr.shtype == SHT_SYMTAB) {
+        let table = match file.get_symbols(section) {
+            Ok(t) => t,
+            Err(_) => error!("Elf file contained a malformed symbol table."),
+        };
+        // Undefined symbols (section 0) have no address
+        for symbol in table.into_iter().filter(|s| s.shndx != 0 && !s.name.is_empty()) {
+            let addr = symbol.value as usize;
+            if symbol.symtype == STT_FUNC {
+                symbols.insert(addr, symbol.name);
+            } else if symbol.symtype == STT_NOTYPE {
+                symbols.entry(addr).or_insert(symbol.name);
+            }
+        }
     }
+    symbols
+}
 
-    // Initialise and load in memory
-    for s in file.sections.iter() {
-        state.memory.load_elf_section(s);
+/// Returns the address of the symbol with the given name in the symbol table
+/// of the elf file, of any type, if there is one.
+fn find_symbol(file: &File, name: &str) -> Option<usize> {
+    for section in file.sections.iter().filter(|s| s.shdr.shtype == SHT_SYMTAB) {
+        let table = match file.get_symbols(section) {
+            Ok(t) => t,
+            Err(_) => error!("Elf file contained a malformed symbol table."),
+        };
+        if let Some(symbol) = table.into_iter().find(|s| s.shndx != 0 && s.name == name) {
+            return Some(symbol.value as usize);
+        }
     }
+    None
+}
 
-    // Load in initial program counter
-    state.register[Register::PC].data = file.ehdr.entry as i32;
-    state.branch_predictor.force_update(file.ehdr.entry as usize);
+/// Returns the contents of an elf file that loads the given assembled program
+/// at its origin, and starts executing it there. The text and data sections
+/// are each given a loadable segment, with the text section read-only, and
+/// every label is written to the symbol table.
+pub fn write_elf(program: &Program) -> Vec<u8> {
+    let u16s = |values: &[u16]| -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect()
+    };
+    let u32s = |values: &[u32]| -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect()
+    };
+    let origin = program.origin as u32;
+    let text_size = program.text_size as u32;
+    let data_size = (program.bytes.len() - program.text_size) as u32;
+    let has_data = data_size > 0;
+    let phnum: u32 = if has_data { 2 } else { 1 };
+    let text_off = 0x34 + 0x20 * phnum;
+
+    // Program headers, for the text and data segments
+    let mut phdrs = u32s(&[
+        PT_LOAD.0, text_off, origin, origin, text_size, text_size, PF_R.0 | PF_X.0, 4,
+    ]);
+    if has_data {
+        let (off, addr) = (text_off + text_size, origin + text_size);
+        phdrs.extend(u32s(&[
+            PT_LOAD.0, off, addr, addr, data_size, data_size, PF_R.0 | PF_W.0, 4,
+        ]));
+    }
+
+    // Symbols for the labels, in order of address, each in the section that
+    // holds it (labels at the very end are kept in the section they end)
+    let (text_index, data_index) = (1u16, 2u16);
+    let mut labels: Vec<(&String, &usize)> = program.labels.iter().collect();
+    labels.sort_by_key(|(name, addr)| (**addr, name.as_str()));
+    let mut strtab = vec![0u8];
+    let mut symtab = vec![0u8; 16];
+    for (name, addr) in labels.iter() {
+        let index = if has_data && **addr >= program.origin + program.text_size {
+            data_index
+        } else {
+            text_index
+        };
+        symtab.extend(u32s(&[strtab.len() as u32, **addr as u32, 0]));
+        symtab.extend(&[STB_LOCAL.0 << 4 | STT_NOTYPE.0, 0]);
+        symtab.extend(u16s(&[index]));
+        strtab.extend(name.as_bytes());
+        strtab.push(0);
+    }
+    let shstrtab = b"\0.text\0.data\0.symtab\0.strtab\0.shstrtab\0";
+
+    // The program, followed by the tables, each word aligned
+    let mut raw = vec![0u8; text_off as usize];
+    raw.extend(&program.bytes);
+    let table = |raw: &mut Vec<u8>, data: &[u8]| {
+        while raw.len() % 4 != 0 {
+            raw.push(0);
+        }
+        let offset = raw.len() as u32;
+        raw.extend(data);
+        (offset, data.len() as u32)
+    };
+    let (sym_off, sym_size) = table(&mut raw, &symtab);
+    let (str_off, str_size) = table(&mut raw, &strtab);
+    let (shstr_off, shstr_size) = table(&mut raw, shstrtab);
+
+    // Section headers, of which the string tables follow the symbol table
+    let mut shdrs = vec![0u8; 40];
+    let alloc_exec = SHF_ALLOC.0 as u32 | SHF_EXECINSTR.0 as u32;
+    let alloc_write = SHF_ALLOC.0 as u32 | SHF_WRITE.0 as u32;
+    shdrs.extend(u32s(&[
+        1, SHT_PROGBITS.0, alloc_exec, origin, text_off, text_size, 0, 0, 4, 0,
+    ]));
+    if has_data {
+        shdrs.extend(u32s(&[
+            7,
+            SHT_PROGBITS.0,
+            alloc_write,
+            origin + text_size,
+            text_off + text_size,
+            data_size,
+            0,
+            0,
+            4,
+            0,
+        ]));
+    }
+    let symtab_index = u32::from(if has_data { data_index } else { text_index }) + 1;
+    let nsyms = symtab.len() as u32 / 16;
+    shdrs.extend(u32s(&[
+        13, SHT_SYMTAB.0, 0, 0, sym_off, sym_size, symtab_index + 1, nsyms, 4, 16,
+    ]));
+    shdrs.extend(u32s(&[21, SHT_STRTAB.0, 0, 0, str_off, str_size, 0, 0, 1, 0]));
+    shdrs.extend(u32s(&[29, SHT_STRTAB.0, 0, 0, shstr_off, shstr_size, 0, 0, 1, 0]));
+    let (sh_off, _) = table(&mut raw, &shdrs);
+    let shnum = shdrs.len() as u16 / 40;
+
+    // The file header, at the start of the file
+    let mut ehdr = vec![0x7f, b'E', b'L', b'F', ELFCLASS32.0, ELFDATA2LSB.0, 1, ELFOSABI_SYSV.0];
+    ehdr.resize(16, 0);
+    ehdr.extend(u16s(&[ET_EXEC.0, 0xf3]));
+    ehdr.extend(u32s(&[EV_CURRENT.0, origin, 0x34, sh_off, 0]));
+    ehdr.extend(u16s(&[0x34, 0x20, phnum as u16, 40, shnum, shnum - 1]));
+    ehdr.extend(phdrs);
+    raw[..text_off as usize].copy_from_slice(&ehdr);
+    raw
 }
 
-/// Verifies the given ELF file header is compatible with the simulator, and
-/// quits if invalid. If this function returns, it can be assumed that the
-/// header is good to go!
-fn verify_file_header(header: &FileHeader) {
-    if header.class != ELFCLASS32 {
-        error!("Found 64 bit ELF file, expected 32 bit.");
+/// Places the program's arguments (if any) on the stack, and passes them to
+/// its entry point. The argument strings go at the top of the stack, below
+/// which `argc` is followed by the `argv` array of pointers to them, ending
+/// with a null pointer. The stack and frame pointers are moved down to `argc`
+/// keeping their alignment, and `a0` and `a1` are set to `argc` and `argv`.
+pub fn load_args(state: &mut State, config: &Config) {
+    if config.args.is_empty() {
+        return;
+    }
+    let mut addr = state.register[Register::X2].data as usize;
+    let mut argv = vec![];
+    for arg in config.args.iter().rev() {
+        addr -= arg.len() + 1;
+        for (n, byte) in arg.bytes().chain(Some(0)).enumerate() {
+            state.memory.write_u8(addr + n, byte);
+        }
+        argv.push(addr);
+    }
+    argv.reverse();
+
+    let sp = (addr - (4 * (argv.len() + 2))) & !(config.stack_align - 1);
+    state.memory.write_i32(sp, argv.len() as i32);
+    for (n, arg) in argv.iter().chain(Some(&0)).enumerate() {
+        state.memory.write_i32(sp + 4 + (4 * n), *arg as i32);
+    }
+    state.register[Register::X2].data = sp as i32;
+    state.register[Register::X8].data = sp as i32;
+    state.register[Register::X10].data = argv.len() as i32;
+    state.register[Register::X11].data = (sp + 4) as i32;
+}
+
+/// Loads the program input file (if any) into the state, to be read through
+/// the read system call. Without one, the program reads the host's stdin
+/// instead, unless the display is using it for key presses.
+pub fn load_input(state: &mut State, config: &Config) {
+    match &config.stdin {
+        Some(path) => match fs::read(path) {
+            Ok(input) => state.input = input,
+            Err(e) => error!(format!("Failed to load program input file:\n{}", e)),
+        },
+        None => {
+            state.host_input =
+                config.command != Command::Run || config.repl || config.interactive_step.is_some()
+        }
+    }
+}
+
+/// Checks that every section of the elf file that occupies memory reads back
+/// from memory with the same contents as in the elf file (or zeroes, for
+/// sections such as `.bss`), and quits reporting any that do not. This would
+/// catch segments that clobber each other when loaded.
+fn verify_load(state: &State, file: &File) {
+    let mismatches = load_mismatches(state, file);
+    if !mismatches.is_empty() {
+        error!(format!("Elf file did not load correctly:\n{}", mismatches.join("\n")));
     }
-    if header.data != ELFDATA2LSB {
-        error!("Found Big Endian ELF file, expected Little Endian.");
+}
+
+/// Returns a description of the first byte of each section of the elf file
+/// that occupies memory which does not read back from memory as expected.
+fn load_mismatches(state: &State, file: &File) -> Vec<String> {
+    let mut mismatches = vec![];
+    for section in file.sections.iter().filter(|s| s.shdr.flags.0 & SHF_ALLOC.0 != 0) {
+        let start = section.shdr.addr as usize;
+        let mismatch = section
+            .data
+            .iter()
+            .enumerate()
+            .map(|(i, &byte)| (start + i, byte, state.memory.get(start + i).cloned()))
+            .find(|(_, expected, found)| Some(*expected) != *found);
+        if let Some((addr, expected, found)) = mismatch {
+            mismatches.push(format!(
+                "{} at {:08x}: expected {:02x}, found {}",
+                section.shdr.name,
+                addr,
+                expected,
+                format_option!("{:02x}", found)
+            ));
+        }
     }
-    if header.version != EV_CURRENT {
-        error!("Incompatible ELF file version, expected 1.");
+    mismatches
+}
+
+/// Checks that the entry point of the elf file holds an instruction once
+/// loaded, and quits if not. An entry point in memory that was never loaded
+/// (or was zero filled) would otherwise only trap once simulated.
+fn verify_entry(state: &State, entry: usize) {
+    if let Some(problem) = entry_problem(state, entry) {
+        error!(problem);
     }
-    if header.osabi != ELFOSABI_SYSV {
-        error!("Incompatible OS ABI in ELF file header, expected Unix - System V.");
+}
+
+/// Returns why the given entry point does not hold an instruction once the
+/// elf file is loaded, if it does not.
+fn entry_problem(state: &State, entry: usize) -> Option<String> {
+    let word = state.memory.read_i32(entry).word;
+    if word == 0 {
+        Some(format!("Elf file's entry point ({:08x}) is in uninitialised memory.", entry))
+    } else if entry % 4 != 0 || Instruction::decode(word).is_none() {
+        Some(format!(
+            "Elf file's entry point ({:08x}) does not hold a valid instruction, found {:08x}.",
+            entry, word
+        ))
+    } else {
+        None
     }
-    if header.elftype != ET_EXEC {
-        error!("Incompatible object file type in ELF file header, expected EXEC.");
+}
+
+/// Returns the contents of the segment described by the given program header
+/// from the raw elf file, and quits if the segment lies outside of the file
+/// or is larger in the file than in memory.
+fn segment_data<'a>(raw: &'a [u8], header: &ProgramHeader) -> &'a [u8] {
+    let start = header.offset as usize;
+    let end = start.saturating_add(header.filesz as usize);
+    if raw.len() < end || header.memsz < header.filesz {
+        error!("Elf file contained a malformed loadable segment.");
     }
-    if header.machine != Machine(0xf3) {
-        error!("Incompatible ISA in ELF file header, expected RISC-V.");
+    &raw[start..end]
+}
+
+/// Verifies the given ELF file header is compatible with the simulator, and
+/// quits if invalid. If this function returns, it can be assumed that the
+/// header is good to go!
+fn verify_file_header(header: &FileHeader) {
+    if let Some(problem) = check_file_header(header) {
+        error!(problem);
     }
 }
 
@@ -69,8 +541,408 @@ fn verify_file_header(header: &FileHeader) {
 /// and quits the simulator if invalid. If this function returns, it can be
 /// assumed that the header is good to go!
 fn verify_prog_header(header: &ProgramHeader) {
+    if let Some(problem) = check_prog_header(header) {
+        error!(problem);
+    }
+}
+
+/// Returns why the given ELF file header is incompatible with the simulator,
+/// if it is.
+fn check_file_header(header: &FileHeader) -> Option<&'static str> {
+    if header.class != ELFCLASS32 {
+        Some("Found 64 bit ELF file, expected 32 bit.")
+    } else if header.data != ELFDATA2LSB {
+        Some("Found Big Endian ELF file, expected Little Endian.")
+    } else if header.version != EV_CURRENT {
+        Some("Incompatible ELF file version, expected 1.")
+    } else if header.osabi != ELFOSABI_SYSV {
+        Some("Incompatible OS ABI in ELF file header, expected Unix - System V.")
+    } else if header.elftype != ET_EXEC {
+        Some("Incompatible object file type in ELF file header, expected EXEC.")
+    } else if header.machine != Machine(0xf3) {
+        Some("Incompatible ISA in ELF file header, expected RISC-V.")
+    } else {
+        None
+    }
+}
+
+/// Returns why the given program header is unsupported by the simulator, if
+/// it is.
+fn check_prog_header(header: &ProgramHeader) -> Option<String> {
     match header.progtype {
-        PT_NULL | PT_LOAD | PT_NOTE | PT_PHDR => (),
-        _ => error!("Elf file contained unsupported program header type."),
+        PT_NULL | PT_LOAD | PT_NOTE | PT_PHDR => None,
+        t => Some(format!("Elf file contained unsupported program header type ({}).", t)),
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// TESTS
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isa::assembly::assemble_program;
+    use crate::simulator::builder::{run, StateBuilder};
+
+    /// Returns whether a program run with the given config reads the host's
+    /// stdin for its input.
+    fn reads_host_input(config: Config) -> bool {
+        let mut state = State::default();
+        load_input(&mut state, &config);
+        state.host_input
+    }
+
+    #[test]
+    fn reads_host_input_only_without_display() {
+        let mut config = Config::default();
+        assert!(!reads_host_input(config.clone()));
+        config.command = Command::Trace;
+        assert!(reads_host_input(config.clone()));
+        config.command = Command::Bench;
+        assert!(reads_host_input(config.clone()));
+        config.command = Command::Run;
+        config.repl = true;
+        assert!(reads_host_input(config.clone()));
+        config.repl = false;
+        config.interactive_step = Some(1);
+        assert!(reads_host_input(config.clone()));
+        config.stdin = Some(String::from("/dev/null"));
+        assert!(!reads_host_input(config));
+    }
+
+    #[test]
+    fn zero_fills_bss_beyond_file_data() {
+        // The data segment of this program has 0xa8 bytes in the file, and
+        // 0xfc in memory, the rest being .bss
+        let mut config = Config::default();
+        config.elf_file = String::from("resources/programs/vec_add/a.out");
+        let mut state = State::default();
+        for addr in 0x1_1000..0x1_1300 {
+            state.memory.write_u8(addr, 0xaa);
+        }
+        load_elf(&mut state, &config);
+
+        let raw = fs::read(&config.elf_file).unwrap();
+        assert_eq!(state.memory[0x1_1134..0x1_11dc], raw[0x134..0x1dc]);
+        assert!(state.memory[0x1_11dc..0x1_1230].iter().all(|b| *b == 0));
+        assert_eq!(state.memory[0x1_1230], 0xaa);
+    }
+
+    #[test]
+    fn detects_clobbered_section() {
+        let mut config = Config::default();
+        config.elf_file = String::from("resources/programs/vec_add/a.out");
+        let mut state = State::default();
+        load_elf(&mut state, &config);
+        let (_, file) = read_elf(&config);
+        assert!(load_mismatches(&state, &file).is_empty());
+
+        // As if an overlapping section had been loaded over the data
+        let data = file.get_section(".data").unwrap();
+        let addr = data.shdr.addr as usize + 4;
+        let byte = state.memory[addr];
+        state.memory.write_u8(addr, !byte);
+        assert_eq!(
+            load_mismatches(&state, &file),
+            vec![format!(
+                ".data at {:08x}: expected {:02x}, found {:02x}",
+                addr, byte, !byte
+            )]
+        );
+    }
+
+    #[test]
+    fn rejects_entry_point_in_zero_filled_memory() {
+        let mut config = Config::default();
+        config.elf_file = String::from("resources/programs/vec_add/a.out");
+        let mut state = State::default();
+        load_elf(&mut state, &config);
+        let (_, file) = read_elf(&config);
+        assert_eq!(entry_problem(&state, file.ehdr.entry as usize), None);
+
+        // As if the entry point were moved into the .bss section, by writing
+        // it over the e_entry field of the file header
+        let mut raw = fs::read(&config.elf_file).unwrap();
+        raw[0x18..0x1c].copy_from_slice(&0x1_11e0u32.to_le_bytes());
+        let path = std::env::temp_dir().join("daybreak_zero_entry");
+        fs::write(&path, raw).unwrap();
+        config.elf_file = String::from(path.to_str().unwrap());
+        let (_, file) = read_elf(&config);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            entry_problem(&state, file.ehdr.entry as usize),
+            Some(String::from(
+                "Elf file's entry point (000111e0) is in uninitialised memory."
+            ))
+        );
+        assert_eq!(
+            entry_problem(&state, 0x1_0002),
+            Some(format!(
+                "Elf file's entry point (00010002) does not hold a valid instruction, found {:08x}.",
+                state.memory.read_i32(0x1_0002).word
+            ))
+        );
+    }
+
+    /// Writes a copy of the given (stripped) elf file to a temporary file of
+    /// the given name, with a symbol table holding the given functions, as
+    /// `(name, address)`. Returns the path written to.
+    fn with_symbols(elf_file: &str, name: &str, functions: &[(&str, u32)]) -> String {
+        let mut raw = fs::read(elf_file).unwrap();
+        let u16_at = |raw: &[u8], i: usize| u16::from_le_bytes([raw[i], raw[i + 1]]) as usize;
+        let u32_at = |raw: &[u8], i: usize| {
+            u32::from_le_bytes([raw[i], raw[i + 1], raw[i + 2], raw[i + 3]]) as usize
+        };
+        let (shoff, shnum, shstrndx) = (u32_at(&raw, 0x20), u16_at(&raw, 0x30), u16_at(&raw, 0x32));
+        let mut headers = raw[shoff..shoff + 40 * shnum].to_vec();
+
+        // The names of the new sections are added to a copy of the section
+        // name table, as are the symbol names to a new string table
+        let shstr = &headers[40 * shstrndx..40 * (shstrndx + 1)];
+        let (old_off, old_size) = (u32_at(shstr, 0x10), u32_at(shstr, 0x14));
+        let mut shstrtab = raw[old_off..old_off + old_size].to_vec();
+        let symtab_name = shstrtab.len() as u32;
+        shstrtab.extend(b".symtab\0.strtab\0");
+        let mut strtab = vec![0u8];
+        let mut symtab = vec![0u8; 16];
+        for (name, addr) in functions {
+            symtab.extend(&(strtab.len() as u32).to_le_bytes());
+            symtab.extend(&addr.to_le_bytes());
+            // A global function of no size, in the first section
+            symtab.extend(&[0, 0, 0, 0, 0x10 | STT_FUNC.0, 0, 1, 0]);
+            strtab.extend(name.as_bytes());
+            strtab.push(0);
+        }
+
+        let section = |raw: &mut Vec<u8>, data: &[u8]| {
+            while raw.len() % 4 != 0 {
+                raw.push(0);
+            }
+            let offset = raw.len() as u32;
+            raw.extend(data);
+            (offset, data.len() as u32)
+        };
+        let (shstr_off, shstr_size) = section(&mut raw, &shstrtab);
+        let (sym_off, sym_size) = section(&mut raw, &symtab);
+        let (str_off, str_size) = section(&mut raw, &strtab);
+        let header = |name: u32, shtype: u32, off: u32, size: u32, link: u32, entsize: u32| {
+            let fields = [name, shtype, 0, 0, off, size, link, 1, 4, entsize];
+            fields
+                .iter()
+                .flat_map(|f| f.to_le_bytes().to_vec())
+                .collect::<Vec<u8>>()
+        };
+        let shstr = 40 * shstrndx;
+        headers[shstr + 0x10..shstr + 0x18]
+            .copy_from_slice(&[shstr_off.to_le_bytes(), shstr_size.to_le_bytes()].concat());
+        let strtab_index = shnum as u32 + 1;
+        headers.extend(header(
+            symtab_name,
+            SHT_SYMTAB.0,
+            sym_off,
+            sym_size,
+            strtab_index,
+            16,
+        ));
+        headers.extend(header(
+            symtab_name + 8,
+            SHT_STRTAB.0,
+            str_off,
+            str_size,
+            0,
+            0,
+        ));
+        let (shoff, _) = section(&mut raw, &headers);
+        raw[0x20..0x24].copy_from_slice(&shoff.to_le_bytes());
+        raw[0x30..0x32].copy_from_slice(&(shnum as u16 + 2).to_le_bytes());
+
+        let path = std::env::temp_dir().join(name);
+        fs::write(&path, raw).unwrap();
+        String::from(path.to_str().unwrap())
+    }
+
+    #[test]
+    fn names_symbols_at_their_address() {
+        let mut config = Config::default();
+        config.elf_file = with_symbols(
+            "resources/programs/hello_world/a.out",
+            "daybreak_symbols",
+            &[("main", 0x1_0074), ("putchar", 0x1_00c8)],
+        );
+        let state = State::new(&config);
+        assert!(state.symbols.is_empty());
+
+        config.symbols = true;
+        let state = State::new(&config);
+        fs::remove_file(&config.elf_file).unwrap();
+        assert_eq!(
+            state.symbols.get(&0x1_0074).map(|s| s.as_str()),
+            Some("main")
+        );
+        assert_eq!(
+            state.symbols.get(&0x1_00c8).map(|s| s.as_str()),
+            Some("putchar")
+        );
+        assert_eq!(state.symbols.len(), 2);
+    }
+
+    #[test]
+    fn writes_assembled_program_as_elf() {
+        let source = "
+            main:
+                auipc x5 0          # x5 = main
+                lw x6 x5 16         # x6 = value
+                sw x5 x6 20         # result = x6
+                jalr x0 x1 0
+            .data
+            value:  .word 42
+            result: .word 0
+        ";
+        let program = assemble_program(source, 0x2_0000).unwrap();
+        let path = std::env::temp_dir().join("daybreak_write_elf");
+        fs::write(&path, write_elf(&program)).unwrap();
+
+        let mut config = Config::default();
+        config.elf_file = String::from(path.to_str().unwrap());
+        config.symbols = true;
+        let mut state = State::new(&config);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(state.register[Register::PC].data, 0x2_0000);
+        assert_eq!(state.symbols.get(&0x2_0000).map(|s| s.as_str()), Some("main"));
+        assert_eq!(state.symbols.get(&0x2_0010).map(|s| s.as_str()), Some("value"));
+        assert_eq!(state.symbols.get(&0x2_0014).map(|s| s.as_str()), Some("result"));
+
+        run(&mut state, 1000);
+        assert_eq!(state.exit_code, Some(0));
+        assert_eq!(state.register[Register::X6].data, 42);
+        assert_eq!(state.memory.read_i32(0x2_0014).word, 42);
+    }
+
+    /// Runs a RISC-V test that writes the given value to its `tohost` symbol,
+    /// linked at `RISCV_TEST_BASE` as the riscv-tests suite links them.
+    fn run_riscv_test(value: i32) -> State {
+        let source = format!(
+            "
+                addi x5 x0 {}
+                la x6 tohost
+                sw x6 x5 0
+            loop:
+                jal x0 loop
+            .data
+            tohost: .word 0
+            ",
+            value
+        );
+        let program = assemble_program(&source, RISCV_TEST_BASE as usize).unwrap();
+        let path = std::env::temp_dir().join(format!("daybreak_riscv_test_{}", value));
+        fs::write(&path, write_elf(&program)).unwrap();
+
+        let mut config = Config::default();
+        config.riscv_test = true;
+        config.elf_file = String::from(path.to_str().unwrap());
+        let mut state = State::new(&config);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(state.tohost, Some(program.labels["tohost"] - RISCV_TEST_BASE as usize));
+        run(&mut state, 1000);
+        state
+    }
+
+    #[test]
+    fn reports_riscv_test_result_written_to_tohost() {
+        let state = run_riscv_test(1);
+        assert_eq!(state.out.last().unwrap(), "PASS");
+        assert_eq!(state.exit_code, Some(0));
+
+        // Failures give the number of the failing test above the lowest bit
+        let state = run_riscv_test(3 << 1 | 1);
+        assert_eq!(state.out.last().unwrap(), "FAIL: test 3");
+        assert_eq!(state.exit_code, Some(3));
+    }
+
+    #[test]
+    fn rebases_riscv_test_linked_at_suite_address() {
+        let mut config = Config::default();
+        config.elf_file = String::from("tests/fixtures/riscv_test.elf");
+        config.riscv_test = true;
+        config.symbols = true;
+        let mut state = State::new(&config);
+        assert_eq!(state.register[Register::PC].data, 0);
+        assert_eq!(state.symbols.get(&0).map(|s| s.as_str()), Some("_start"));
+        assert_eq!(state.tohost, Some(0x54));
+
+        run(&mut state, 1000);
+        assert_eq!(state.out.last().unwrap(), "PASS");
+        assert_eq!(state.exit_code, Some(0));
+
+        // Tests linked anywhere else are left where they are
+        config.elf_file = String::from("resources/programs/hello_world/a.out");
+        let (_, file) = read_elf(&config);
+        assert_eq!(file.ehdr.entry, 0x1_0074);
+    }
+
+    #[test]
+    fn passes_args_to_program() {
+        // The program reads argc from the stack, and the first character of
+        // argv[1] and the null pointer ending argv through a1
+        let mut state = StateBuilder::new()
+            .reg(Register::X2, 0x1000)
+            .asm("lw x5 x2 0")
+            .asm("lw x6 x11 4")
+            .asm("lbu x7 x6 0")
+            .asm("lw x12 x11 8")
+            .asm("jalr x0 x1 0")
+            .build();
+        let mut config = Config::default();
+        config.args = vec![String::from("prog"), String::from("hello")];
+        load_args(&mut state, &config);
+
+        let sp = state.register[Register::X2].data as usize;
+        assert_eq!(sp % config.stack_align, 0);
+        assert!(sp < 0x1000 - 11);
+        assert_eq!(state.register[Register::X8].data as usize, sp);
+        assert_eq!(state.register[Register::X11].data as usize, sp + 4);
+        run(&mut state, 1000);
+        assert_eq!(state.register[Register::X10].data, 2);
+        assert_eq!(state.register[Register::X5].data, 2);
+        assert_eq!(state.register[Register::X7].data, i32::from(b'h'));
+        assert_eq!(state.register[Register::X12].data, 0);
+    }
+
+    #[test]
+    fn prints_entry_address_of_elf_file() {
+        let config = Config::create_from(vec![
+            "daybreak",
+            "--elf-info",
+            "resources/programs/hello_world/a.out",
+        ]);
+        assert!(config.elf_info);
+        let (_, file) = parse_elf(&config);
+        let mut out = Vec::new();
+        write_elf_info(&file, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("File header:\n"));
+        assert!(out.contains("  machine:  RISC-V\n"));
+        assert!(out.contains("  entry:    0x00010074\n"));
+        assert!(out.contains("  .text "));
+    }
+
+    #[test]
+    fn protects_only_read_only_code() {
+        let args = vec![
+            "daybreak",
+            "--protect-text",
+            "resources/programs/hello_world/a.out",
+        ];
+        let state = State::new(&Config::create_from(args));
+        assert_eq!(state.protected, vec![(0x1_0074, 0x1_00e4)]);
+        assert!(state.in_protected(0x1_00e0, 4));
+        assert!(!state.in_protected(0x1_00e4, 4));
+        assert!(!state.in_protected(0x1_10f4, 4));
+
+        let unprotected = State::new(&Config::create_from(vec![
+            "daybreak",
+            "resources/programs/hello_world/a.out",
+        ]));
+        assert!(unprotected.protected.is_empty());
     }
 }
@@ -0,0 +1,135 @@
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter, Result};
+
+///////////////////////////////////////////////////////////////////////////////
+//// CONST/STATIC
+
+/// The number of log entries that are retained, older entries are discarded.
+pub const LOG_CAPACITY: usize = 64;
+
+///////////////////////////////////////////////////////////////////////////////
+//// ENUMS
+
+/// The level of a log message, or the most verbose level being retained. Each
+/// level includes all of the levels before it.
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+pub enum LogLevel {
+    /// Nothing is logged.
+    Off,
+    /// Events that stop or corrupt the simulated program.
+    Warn,
+    /// Notable events in the pipeline, such as flushes.
+    Info,
+    /// Everything, including every stall.
+    Debug,
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// STRUCTS
+
+/// A bounded log of simulator messages, keeping only the most recent entries
+/// at or above the configured level.
+#[derive(Clone, Debug)]
+pub struct Log {
+    /// The most verbose level of message that is retained.
+    pub level: LogLevel,
+    /// The maximum number of entries retained.
+    pub capacity: usize,
+    /// The retained entries, oldest first, as the cycle they were logged in,
+    /// their level and their message.
+    pub entries: VecDeque<(u64, LogLevel, String)>,
+    /// The total number of entries ever logged, including those discarded.
+    pub total: u64,
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// IMPLEMENTATIONS
+
+impl Display for LogLevel {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            LogLevel::Off => f.pad("off"),
+            LogLevel::Warn => f.pad("warn"),
+            LogLevel::Info => f.pad("info"),
+            LogLevel::Debug => f.pad("debug"),
+        }
+    }
+}
+
+impl Default for LogLevel {
+    /// Defaults to warnings only.
+    fn default() -> LogLevel {
+        LogLevel::Warn
+    }
+}
+
+impl Log {
+    /// Creates a new empty log, retaining up to `capacity` entries at or
+    /// above the given level.
+    pub fn new(level: LogLevel, capacity: usize) -> Log {
+        Log {
+            level,
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+            total: 0,
+        }
+    }
+
+    /// Records a message from the given cycle, if its level is retained. The
+    /// oldest entry is discarded if the log is full.
+    pub fn record(&mut self, level: LogLevel, cycle: u64, message: String) {
+        if level == LogLevel::Off || self.level < level || self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((cycle, level, message));
+        self.total += 1;
+    }
+
+    /// Returns the entries logged since the given total, i.e. those that are
+    /// new since `total` was last read, if they are still retained.
+    pub fn since(&self, total: u64) -> impl Iterator<Item = &(u64, LogLevel, String)> {
+        let new = (self.total - total.min(self.total)) as usize;
+        self.entries.iter().skip(self.entries.len().saturating_sub(new))
+    }
+}
+
+impl Default for Log {
+    fn default() -> Log {
+        Log::new(LogLevel::default(), LOG_CAPACITY)
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// TESTS
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caps_at_capacity() {
+        let mut log = Log::new(LogLevel::Debug, 4);
+        for cycle in 0..10 {
+            log.record(LogLevel::Info, cycle, format!("message {}", cycle));
+        }
+        assert_eq!(log.entries.len(), 4);
+        assert_eq!(log.total, 10);
+        let cycles: Vec<u64> = log.entries.iter().map(|(c, _, _)| *c).collect();
+        assert_eq!(cycles, vec![6, 7, 8, 9]);
+        assert_eq!(log.since(8).count(), 2);
+        assert_eq!(log.since(0).count(), 4);
+    }
+
+    #[test]
+    fn filters_by_level() {
+        let mut log = Log::new(LogLevel::Warn, 4);
+        log.record(LogLevel::Debug, 0, String::from("stall"));
+        log.record(LogLevel::Info, 1, String::from("flush"));
+        log.record(LogLevel::Warn, 2, String::from("overflow"));
+        assert_eq!(log.total, 1);
+        assert_eq!(log.entries[0].2, "overflow");
+    }
+}
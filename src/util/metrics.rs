@@ -0,0 +1,99 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use crate::simulator::state::State;
+
+///////////////////////////////////////////////////////////////////////////////
+//// STRUCTS
+
+/// A compact time series of key metrics, written to a CSV file with one row
+/// per simulated cycle.
+pub struct MetricsCsv {
+    /// The buffered writer for the CSV file.
+    writer: BufWriter<File>,
+    /// The number of executed instructions at the end of the last cycle, used
+    /// to work out how many were committed in the latest one.
+    last_executed: u64,
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// IMPLEMENTATIONS
+
+impl MetricsCsv {
+    /// Creates the CSV file at the given path and writes the header row.
+    /// Quits the program if the file cannot be created.
+    pub fn create(path: &str) -> MetricsCsv {
+        let file = match File::create(path) {
+            Ok(f) => f,
+            Err(e) => error!(format!("Failed to create metrics CSV file:\n{}", e)),
+        };
+        let mut metrics = MetricsCsv {
+            writer: BufWriter::new(file),
+            last_executed: 0,
+        };
+        writeln!(
+            metrics.writer,
+            "cycle,committed_this_cycle,rob_occupancy,rsv_occupancy,stalls_total,bp_success,bp_failure"
+        )
+        .expect("Failed to write to metrics CSV file");
+        metrics
+    }
+
+    /// Writes a row for the cycle that has just finished in the given state.
+    pub fn record(&mut self, state: &State) {
+        writeln!(
+            self.writer,
+            "{},{},{},{},{},{},{}",
+            state.stats.cycles,
+            state.stats.retired() - self.last_executed,
+            state.reorder_buffer.count,
+            state.resv_station.contents.len(),
+            state.stats.stalls,
+            state.stats.bp_success,
+            state.stats.bp_failure
+        )
+        .expect("Failed to write to metrics CSV file");
+        self.last_executed = state.stats.retired();
+    }
+
+    /// Flushes any buffered rows out to the file.
+    pub fn flush(&mut self) {
+        self.writer.flush().expect("Failed to write to metrics CSV file");
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+//// TESTS
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::builder::StateBuilder;
+    use crate::simulator::step_cycle;
+
+    #[test]
+    fn writes_header_and_row_per_cycle() {
+        let path = std::env::temp_dir().join("daybreak_metrics.csv");
+        let mut metrics = MetricsCsv::create(path.to_str().unwrap());
+        let mut state = StateBuilder::new()
+            .asm("addi x5 x0 1")
+            .asm("addi x6 x5 2")
+            .asm("jalr x0 x1 0")
+            .build();
+        while !step_cycle(&mut state) {
+            metrics.record(&state);
+        }
+        metrics.record(&state);
+        metrics.flush();
+
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len() as u64, state.stats.cycles + 1);
+        let committed: u64 = lines[1..]
+            .iter()
+            .map(|l| l.split(',').nth(1).unwrap().parse::<u64>().unwrap())
+            .sum();
+        assert_eq!(committed, state.stats.retired());
+    }
+}
@@ -70,11 +70,21 @@ macro_rules! format_option {
 ///////////////////////////////////////////////////////////////////////////////
 //// EXTERNAL MODULES
 
+/// Chrome trace event format export of every instruction's trip through the
+/// pipeline.
+pub mod chrome_trace;
+
 /// Command line config parsing and option structs.
 pub mod config;
 
 /// The ELF file loader and utilities.
 pub mod loader;
 
+/// A bounded, level filtered log of messages from the simulator.
+pub mod log;
+
+/// Cycle by cycle CSV export of key simulator metrics.
+pub mod metrics;
+
 /// Helper functions for a panic that deals better with raw terminals.
 pub mod panic;
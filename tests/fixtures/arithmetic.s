@@ -0,0 +1,24 @@
+# Arithmetic: runs a mix of operations on the ALU, and stores some of the
+# results to memory.
+main:
+    addi x5 x0 100          # t0 = 100
+    addi x6 x0 -7           # t1 = -7
+    add x7 x5 x6            # t2 = 93
+    sub x28 x5 x6           # t3 = 107
+    mul x29 x5 x6           # t4 = -700
+    div x30 x5 x6           # t5 = -14
+    rem x31 x5 x6           # t6 = 2
+    slli x11 x5 4           # a1 = 1600
+    srai x12 x6 1           # a2 = -4
+    slt x13 x6 x5           # a3 = 1
+    xor x14 x5 x6           # a4 = -99
+    la x15 results
+    sw x15 x7 0
+    sw x15 x28 4
+    sw x15 x29 8
+    sw x15 x30 12
+    sw x15 x31 16
+    jalr x0 x1 0
+
+.data
+results: .word 0, 0, 0, 0, 0
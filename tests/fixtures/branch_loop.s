@@ -0,0 +1,22 @@
+# Branch loop: sums the numbers from 1 to 20, and separately the odd ones, so
+# that the branch inside the loop alternates between taken and not taken.
+main:
+    addi x5 x0 1            # t0 = i
+    addi x6 x0 21           # t1 = limit
+    addi x10 x0 0           # a0 = sum
+    addi x11 x0 0           # a1 = sum of the odd i
+loop:
+    add x10 x10 x5
+    andi x7 x5 1
+    beq x7 x0 even
+    add x11 x11 x5
+even:
+    addi x5 x5 1
+    blt x5 x6 loop
+    la x28 sums
+    sw x28 x10 0
+    sw x28 x11 4
+    jalr x0 x1 0
+
+.data
+sums: .word 0, 0
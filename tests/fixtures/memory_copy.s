@@ -0,0 +1,38 @@
+# Memory copy: copies a block of words and then a string of bytes to other
+# buffers, and sums the copied words as read back from memory, so that loads
+# depend on the stores before them.
+main:
+    la x5 source
+    la x6 dest
+    addi x7 x0 8            # t2 = words left
+copy_words:
+    lw x28 x5 0
+    sw x6 x28 0
+    addi x5 x5 4
+    addi x6 x6 4
+    addi x7 x7 -1
+    bne x7 x0 copy_words
+    la x5 text
+    la x6 text_copy
+copy_bytes:
+    lbu x28 x5 0
+    sb x6 x28 0
+    addi x5 x5 1
+    addi x6 x6 1
+    bne x28 x0 copy_bytes
+    la x5 dest
+    addi x7 x0 8
+    addi x11 x0 0           # a1 = sum of the copied words
+sum_words:
+    lw x28 x5 0
+    add x11 x11 x28
+    addi x5 x5 4
+    addi x7 x7 -1
+    bne x7 x0 sum_words
+    jalr x0 x1 0
+
+.data
+source:    .word 1, -2, 3, -4, 0x12345678, 5, 6, 7
+dest:      .word 0, 0, 0, 0, 0, 0, 0, 0
+text:      .byte 0x68, 0x69, 0x21, 0
+text_copy: .byte 0xff, 0xff, 0xff, 0xff
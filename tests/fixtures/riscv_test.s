@@ -0,0 +1,30 @@
+# A test in the style of the riscv-tests suite, and linked where they are
+# (assembled with --origin=0x80000000). gp holds the number of the test that
+# is running, and tohost is written 1 for a pass, or gp << 1 | 1 for a fail.
+_start:
+    addi x3 x0 2            # test 2: 3 + 4 = 7
+    addi x5 x0 3
+    addi x6 x0 4
+    add x7 x5 x6
+    addi x28 x0 7
+    bne x7 x28 fail
+    addi x3 x0 3            # test 3: loads the expected value
+    la x5 expected
+    lw x7 x5 0
+    addi x28 x0 -5
+    bne x7 x28 fail
+pass:
+    addi x10 x0 1
+    jal x0 write_tohost
+fail:
+    slli x10 x3 1
+    ori x10 x10 1
+write_tohost:
+    la x5 tohost
+    sw x5 x10 0
+loop:
+    jal x0 loop
+
+.data
+expected: .word -5
+tohost:   .word 0